@@ -1563,7 +1563,10 @@ bool option2bool(String option, String value) {
   } else if (option.startsWith("allow-") ||
       option == kOptionStopService ||
       option == kOptionDirectServer ||
-      option == kOptionForceAlwaysRelay) {
+      option == kOptionDirectAccessTls ||
+      option == kOptionForceAlwaysRelay ||
+      option == kOptionStrictEncryption ||
+      option == kOptionUseSystemProxy) {
     res = value == "Y";
   } else {
     assert(false);
@@ -1581,7 +1584,10 @@ String bool2option(String option, bool b) {
   } else if (option.startsWith('allow-') ||
       option == kOptionStopService ||
       option == kOptionDirectServer ||
-      option == kOptionForceAlwaysRelay) {
+      option == kOptionDirectAccessTls ||
+      option == kOptionForceAlwaysRelay ||
+      option == kOptionStrictEncryption ||
+      option == kOptionUseSystemProxy) {
     res = b ? 'Y' : defaultOptionNo;
   } else {
     if (option != kOptionEnableUdpPunch && option != kOptionEnableIpv6Punch) {
@@ -3939,8 +3945,8 @@ String getConnectionText(bool secure, bool direct, String streamType) {
   } else {
     connectionText = translate("Relayed and unencrypted connection");
   }
-  if (streamType == 'Relay') {
-    streamType = 'TCP';
+  if (streamType == 'Relay' || streamType.startsWith('Relay, ')) {
+    streamType = streamType.replaceFirst('Relay', 'TCP');
   }
   if (streamType.isEmpty) {
     return connectionText;
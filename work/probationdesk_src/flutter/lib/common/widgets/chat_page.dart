@@ -123,6 +123,21 @@ class ChatPage extends StatelessWidget implements PageShape {
                           ),
                         ),
                       ),
+                      leading: [
+                        IconButton(
+                          tooltip: translate('Attach files'),
+                          icon: Icon(Icons.attach_file, size: 18),
+                          onPressed: readOnly ? null : chatModel.sendAttachments,
+                        ),
+                        IconButton(
+                          tooltip: translate('Export chat history'),
+                          icon: Icon(Icons.download_rounded, size: 18),
+                          onPressed: chatModel.currentKey.peerId.isEmpty
+                              ? null
+                              : () => chatModel
+                                  .exportHistory(chatModel.currentKey.peerId),
+                        ),
+                      ],
                       sendButtonBuilder: defaultSendButton(
                         padding:
                             EdgeInsets.symmetric(horizontal: 6, vertical: 0),
@@ -137,15 +152,30 @@ class ChatPage extends StatelessWidget implements PageShape {
                       maxWidth: constraints.maxWidth * 0.7,
                       messageTextBuilder: (message, _, __) {
                         final isOwnMessage = message.user.id.isBlank!;
+                        final attachments = List<String>.from(
+                            message.customProperties?['attachments'] ?? []);
+                        final status = message.customProperties?['status'];
+                        final receipt = !isOwnMessage
+                            ? ''
+                            : status == 'read'
+                                ? ' ✓✓'
+                                : status == 'delivered'
+                                    ? ' ✓'
+                                    : '';
                         return Column(
                           crossAxisAlignment: isOwnMessage
                               ? CrossAxisAlignment.end
                               : CrossAxisAlignment.start,
                           children: <Widget>[
-                            Text(message.text,
-                                style: TextStyle(color: Colors.white)),
+                            if (message.text.isNotEmpty)
+                              Text(message.text,
+                                  style: TextStyle(color: Colors.white)),
+                            for (final path in attachments)
+                              Text('📎 $path',
+                                  style: TextStyle(
+                                      color: Colors.white, fontSize: 12)),
                             Text(
-                              "${message.createdAt.hour}:${message.createdAt.minute.toString().padLeft(2, '0')}",
+                              "${message.createdAt.hour}:${message.createdAt.minute.toString().padLeft(2, '0')}$receipt",
                               style: TextStyle(
                                 color: Colors.white,
                                 fontSize: 8,
@@ -177,6 +177,55 @@ void changeIdDialog() {
   });
 }
 
+/// The caps in KB/s, 0 for unlimited.
+void changeBandwidthLimit(String title, int upload, int download,
+    Future<void> Function(int upload, int download) onSubmit,
+    {bool enabled = true}) {
+  final uploadController =
+      TextEditingController(text: upload > 0 ? upload.toString() : '');
+  final downloadController =
+      TextEditingController(text: download > 0 ? download.toString() : '');
+  Widget field(String label, TextEditingController controller) {
+    return TextField(
+      controller: controller,
+      enabled: enabled,
+      inputFormatters: [FilteringTextInputFormatter.digitsOnly],
+      decoration: InputDecoration(
+        labelText: '${translate(label)} (KB/s)',
+        hintText: translate('Unlimited'),
+      ),
+    ).workaroundFreezeLinuxMint();
+  }
+
+  gFFI.dialogManager.show((setState, close, context) {
+    submit() async {
+      await onSubmit(int.tryParse(uploadController.text) ?? 0,
+          int.tryParse(downloadController.text) ?? 0);
+      close();
+    }
+
+    return CustomAlertDialog(
+      title: Text(translate(title)),
+      content: Column(
+        crossAxisAlignment: CrossAxisAlignment.start,
+        mainAxisSize: MainAxisSize.min,
+        children: [
+          Text(translate('bandwidth_limit_tip')),
+          const SizedBox(height: 8.0),
+          field('Upload', uploadController),
+          field('Download', downloadController),
+        ],
+      ),
+      actions: [
+        dialogButton("Cancel", onPressed: close, isOutline: true),
+        if (enabled) dialogButton("OK", onPressed: submit),
+      ],
+      onSubmit: enabled ? submit : null,
+      onCancel: close,
+    );
+  });
+}
+
 void changeWhiteList({Function()? callback}) async {
   final curWhiteList = await bind.mainGetOption(key: kOptionWhitelist);
   var newWhiteListField = curWhiteList == defaultOptionWhitelist
@@ -1569,6 +1618,179 @@ showAuditDialog(FFI ffi) async {
   });
 }
 
+showSaveInputMacroDialog(
+    SessionID sessionId, OverlayDialogManager dialogManager) async {
+  final controller = TextEditingController();
+  String? errorText;
+  dialogManager.show((setState, close, context) {
+    submit() async {
+      final name = controller.text.trim();
+      if (name.isEmpty) return;
+      final err = await bind.sessionStopInputMacroRecording(
+          sessionId: sessionId, name: name);
+      if (err.isNotEmpty) {
+        setState(() => errorText = translate(err));
+        return;
+      }
+      close();
+    }
+
+    cancel() {
+      bind.sessionStopInputMacroRecording(sessionId: sessionId, name: '');
+      close();
+    }
+
+    return CustomAlertDialog(
+      title: Text(translate('Save macro')),
+      content: DialogTextField(
+        title: translate('Name'),
+        controller: controller,
+        errorText: errorText,
+      ),
+      actions: [
+        dialogButton('Discard', onPressed: cancel, isOutline: true),
+        dialogButton('Save', onPressed: submit),
+      ],
+      onSubmit: submit,
+      onCancel: cancel,
+    );
+  });
+}
+
+showInsertInputMacroTextDialog(
+    SessionID sessionId, OverlayDialogManager dialogManager) async {
+  final controller = TextEditingController();
+  dialogManager.show((setState, close, context) {
+    submit() {
+      final text = controller.text;
+      if (text.isNotEmpty) {
+        bind.sessionInsertInputMacroText(sessionId: sessionId, text: text);
+      }
+      close();
+    }
+
+    return CustomAlertDialog(
+      title: Text(translate('Insert macro text')),
+      content: DialogTextField(
+        title: translate('Text'),
+        helperText: translate('input_macro_variable_tip'),
+        controller: controller,
+      ),
+      actions: [
+        dialogButton('Cancel', onPressed: close, isOutline: true),
+        dialogButton('OK', onPressed: submit),
+      ],
+      onSubmit: submit,
+      onCancel: close,
+    );
+  });
+}
+
+showReplayInputMacroDialog(FFI ffi) async {
+  List<Map<String, dynamic>> macros = [];
+  try {
+    macros = (jsonDecode(await bind.mainGetInputMacros()) as List<dynamic>)
+        .map((e) => Map<String, dynamic>.from(e))
+        .toList();
+  } catch (e) {
+    debugPrint('Failed to parse input macros: $e');
+  }
+  if (macros.isEmpty) {
+    showToast(translate('No macros'));
+    return;
+  }
+  String name = macros.first['name'];
+  double speed = 1.0;
+  bool allSessions = false;
+  final variables = <String, TextEditingController>{};
+  ffi.dialogManager.show((setState, close, context) {
+    final selected = macros.firstWhere((m) => m['name'] == name);
+    final names = List<String>.from(selected['variables'] ?? []);
+    for (final v in names) {
+      variables.putIfAbsent(v, () => TextEditingController());
+    }
+
+    submit() {
+      bind.sessionReplayInputMacro(
+          sessionId: ffi.sessionId,
+          name: name,
+          speed: speed,
+          variables: jsonEncode(
+              {for (final v in names) v: variables[v]!.text}),
+          allSessions: allSessions);
+      close();
+    }
+
+    remove() {
+      bind.mainRemoveInputMacro(name: name);
+      macros.removeWhere((m) => m['name'] == name);
+      if (macros.isEmpty) {
+        close();
+      } else {
+        setState(() => name = macros.first['name']);
+      }
+    }
+
+    return CustomAlertDialog(
+      title: Text(translate('Replay macro')),
+      content: Column(
+        mainAxisSize: MainAxisSize.min,
+        crossAxisAlignment: CrossAxisAlignment.start,
+        children: [
+          Row(children: [
+            Expanded(
+              child: DropdownButton<String>(
+                value: name,
+                isExpanded: true,
+                items: macros
+                    .map((m) => DropdownMenuItem<String>(
+                        value: m['name'],
+                        child: Text('${m['name']} (${m['steps']})')))
+                    .toList(),
+                onChanged: (v) {
+                  if (v != null) setState(() => name = v);
+                },
+              ),
+            ),
+            IconButton(
+                icon: Icon(Icons.delete_outline),
+                tooltip: translate('Delete'),
+                onPressed: remove),
+          ]),
+          ...names.map((v) => DialogTextField(
+              title: v, controller: variables[v]!).marginOnly(top: 8)),
+          Row(children: [
+            Text('${translate('Speed')}: ${speed.toStringAsFixed(2)}x'),
+            Expanded(
+              child: Slider(
+                value: speed,
+                min: 0.25,
+                max: 4.0,
+                divisions: 15,
+                onChanged: (v) => setState(() => speed = v),
+              ),
+            ),
+          ]).marginOnly(top: 8),
+          CheckboxListTile(
+            contentPadding: EdgeInsets.zero,
+            value: allSessions,
+            title: Text(translate('Replay on all sessions')),
+            onChanged: (v) => setState(() => allSessions = v == true),
+          ),
+          Text(translate('input_macro_abort_tip'),
+              style: Theme.of(context).textTheme.bodySmall),
+        ],
+      ),
+      actions: [
+        dialogButton('Cancel', onPressed: close, isOutline: true),
+        dialogButton('OK', onPressed: submit),
+      ],
+      onSubmit: submit,
+      onCancel: close,
+    );
+  });
+}
+
 void showConfirmSwitchSidesDialog(
     SessionID sessionId, String id, OverlayDialogManager dialogManager) async {
   dialogManager.show((setState, close, context) {
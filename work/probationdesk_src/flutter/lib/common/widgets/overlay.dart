@@ -596,6 +596,22 @@ class QualityMonitor extends StatelessWidget {
                       _row(
                           "Codec", qualityMonitorModel.data.codecFormat ?? '-'),
                       _row("Chroma", qualityMonitorModel.data.chroma ?? '-'),
+                      if (qualityMonitorModel.data.audioBuffer != null) ...[
+                        _row("Audio Buffer",
+                            "${qualityMonitorModel.data.audioBuffer}ms"),
+                        _row("Audio Jitter",
+                            "${qualityMonitorModel.data.audioJitter}ms"),
+                        _row("Concealed",
+                            qualityMonitorModel.data.audioConcealed ?? '-'),
+                      ],
+                      if (qualityMonitorModel.data.captureIdle != null) ...[
+                        _row("Idle Frames",
+                            qualityMonitorModel.data.captureIdle ?? '-'),
+                        _row("Damaged",
+                            "${qualityMonitorModel.data.captureDamage}%"),
+                        _row("Encode",
+                            "${qualityMonitorModel.data.captureEncode}ms"),
+                      ],
                     ],
                   ),
                 )
@@ -256,6 +256,31 @@ List<TTextMenu> toolbarControls(BuildContext context, String id, FFI ffi) {
           blockInput.value = !blockInput.value;
         }));
   }
+  // inventory
+  if (isDefaultConn &&
+      isDesktop &&
+      perms['inventory'] != false &&
+      pi.platform != kPeerPlatformAndroid) {
+    v.add(TTextMenu(
+        child: Text(translate('System inventory')),
+        onPressed: () => bind.sessionRequestInventory(
+            sessionId: sessionId, includePackages: true)));
+  }
+  // control token
+  if (isDefaultConn && (ffiModel.controlToken['controllers'] ?? 0) > 1) {
+    final hasControl = ffiModel.controlToken['has_control'] == true;
+    final requested = ffiModel.controlToken['requested'] == true;
+    v.add(TTextMenu(
+        child: Text(translate(hasControl
+            ? 'Release control'
+            : requested
+                ? 'Cancel control request'
+                : 'Request control')),
+        onPressed: () => bind.sessionControlTokenAction(
+            sessionId: sessionId,
+            action: hasControl || requested ? 'release' : 'request',
+            connId: 0)));
+  }
   // switchSides
   if (isDefaultConn &&
       isDesktop &&
@@ -332,6 +357,33 @@ List<TTextMenu> toolbarControls(BuildContext context, String id, FFI ffi) {
       ));
     }
   }
+  // input macro
+  if (isDefaultConn && isDesktop && !ffiModel.viewOnly && ffiModel.keyboard) {
+    final recording = ffiModel.inputMacroRecording;
+    v.add(TTextMenu(
+        child: Text(translate(recording.value
+            ? 'Stop macro recording'
+            : 'Start macro recording')),
+        onPressed: () {
+          if (recording.value) {
+            recording.value = false;
+            showSaveInputMacroDialog(sessionId, ffi.dialogManager);
+          } else {
+            recording.value = true;
+            bind.sessionStartInputMacroRecording(sessionId: sessionId);
+          }
+        }));
+    if (recording.value) {
+      v.add(TTextMenu(
+          child: Text(translate('Insert macro text')),
+          onPressed: () =>
+              showInsertInputMacroTextDialog(sessionId, ffi.dialogManager)));
+    } else {
+      v.add(TTextMenu(
+          child: Text(translate('Replay macro')),
+          onPressed: () => showReplayInputMacroDialog(ffi)));
+    }
+  }
   // fingerprint
   if (!(isDesktop || isWebDesktop)) {
     v.add(TTextMenu(
@@ -604,6 +656,17 @@ Future<List<TToggleMenu>> toolbarDisplayToggle(
           bind.sessionToggleOption(sessionId: sessionId, value: option);
         },
         child: Text(translate('Mute'))));
+    // audio sources, mixed if several are selected
+    for (final source in ffiModel.audioSources) {
+      final sourceId = source['id'] as String;
+      v.add(TToggleMenu(
+          value: ffiModel.selectedAudioSources.contains(sourceId),
+          onChanged: (value) {
+            if (value == null) return;
+            ffiModel.selectAudioSource(sourceId, value);
+          },
+          child: Text('${translate('Audio source')}: ${source['name']}')));
+    }
   }
   // file copy and paste
   // If the version is less than 1.2.4, file copy and paste is supported on Windows only.
@@ -715,6 +778,20 @@ Future<List<TToggleMenu>> toolbarDisplayToggle(
         child: Text(translate('True color (4:4:4)'))));
   }
 
+  // lossless text
+  if (versionCmp(pi.version, "1.4.2") >= 0) {
+    final option = kOptionTextLossless;
+    final value =
+        bind.sessionGetToggleOptionSync(sessionId: sessionId, arg: option);
+    v.add(TToggleMenu(
+        value: value,
+        onChanged: (value) async {
+          if (value == null) return;
+          await bind.sessionToggleOption(sessionId: sessionId, value: option);
+        },
+        child: Text(translate('Lossless text'))));
+  }
+
   if (isDefaultConn && isMobile) {
     v.addAll(toolbarKeyboardToggles(ffi));
   }
@@ -89,9 +89,16 @@ const String kOptionViewOnly = "view_only";
 const String kOptionEnableLanDiscovery = "enable-lan-discovery";
 const String kOptionWhitelist = "whitelist";
 const String kOptionEnableAbr = "enable-abr";
+const String kOptionUploadLimit = "upload-limit";
+const String kOptionDownloadLimit = "download-limit";
+const String kOptionStrictEncryption = "strict-encryption";
+const String kOptionUseSystemProxy = "use-system-proxy";
+const String kOptionAllowEmbeddedServer = "allow-embedded-server";
+const String kOptionEmbeddedServerKey = "embedded-server-key";
 const String kOptionEnableRecordSession = "enable-record-session";
 const String kOptionDirectServer = "direct-server";
 const String kOptionDirectAccessPort = "direct-access-port";
+const String kOptionDirectAccessTls = "direct-access-tls";
 const String kOptionAllowAutoDisconnect = "allow-auto-disconnect";
 const String kOptionAutoDisconnectTimeout = "auto-disconnect-timeout";
 const String kOptionEnableHwcodec = "enable-hwcodec";
@@ -111,6 +118,7 @@ const String kOptionTerminalPersistent = "terminal-persistent";
 const String kOptionEnableTunnel = "enable-tunnel";
 const String kOptionEnableRemoteRestart = "enable-remote-restart";
 const String kOptionEnableBlockInput = "enable-block-input";
+const String kOptionEnableInventory = "enable-inventory";
 const String kOptionAllowRemoteConfigModification =
     "allow-remote-config-modification";
 const String kOptionVerificationMethod = "verification-method";
@@ -131,6 +139,7 @@ const String kOptionLockAfterSessionEnd = "lock_after_session_end";
 const String kOptionPrivacyMode = "privacy_mode";
 const String kOptionTouchMode = "touch-mode";
 const String kOptionI444 = "i444";
+const String kOptionTextLossless = "text-lossless";
 const String kOptionSwapLeftRightMouse = "swap-left-right-mouse";
 const String kOptionCodecPreference = "codec-preference";
 const String kOptionRemoteMenubarDragLeft = "remote-menubar-drag-left";
@@ -190,6 +199,7 @@ const int kWindowMainId = 0;
 
 const String kPointerEventKindTouch = "touch";
 const String kPointerEventKindMouse = "mouse";
+const String kPointerEventKindPen = "pen";
 
 const String kMouseEventTypeDefault = "";
 const String kMouseEventTypePanStart = "pan_start";
@@ -1028,6 +1028,9 @@ class _SafetyState extends State<_Safety> with AutomaticKeepAliveClientMixin {
               _OptionCheckBox(context, 'Enable blocking user input',
                   kOptionEnableBlockInput,
                   enabled: enabled, fakeValue: fakeValue),
+            _OptionCheckBox(
+                context, 'Enable inventory query', kOptionEnableInventory,
+                enabled: enabled, fakeValue: fakeValue),
             _OptionCheckBox(context, 'Enable remote configuration modification',
                 kOptionAllowRemoteConfigModification,
                 enabled: enabled, fakeValue: fakeValue),
@@ -1198,6 +1201,8 @@ class _SafetyState extends State<_Safety> with AutomaticKeepAliveClientMixin {
       _OptionCheckBox(context, 'Deny LAN discovery', 'enable-lan-discovery',
           reverse: true, enabled: enabled),
       ...directIp(context),
+      _OptionCheckBox(context, 'Require encryption', kOptionStrictEncryption,
+          enabled: enabled),
       whitelist(),
       ...autoDisconnect(context),
       if (bind.mainIsInstalled())
@@ -1298,6 +1303,13 @@ class _SafetyState extends State<_Safety> with AutomaticKeepAliveClientMixin {
 
         return tmpWrapper();
       }(),
+      Offstage(
+        offstage: !option2bool(kOptionDirectServer,
+            bind.mainGetOptionSync(key: kOptionDirectServer)),
+        child: _OptionCheckBox(
+            context, 'Direct IP access over TLS', kOptionDirectAccessTls,
+            update: update, enabled: !locked),
+      ),
     ];
   }
 
@@ -1600,6 +1612,35 @@ class _NetworkState extends State<_Network> with AutomaticKeepAliveClientMixin {
                   title: 'ID/Relay Server',
                   onTap: () => showServerSettings(gFFI.dialogManager),
                 ),
+              if (!hideServer && !isWeb && !bind.isOutgoingOnly()) ...[
+                Divider(height: 1, indent: 16, endIndent: 16),
+                listTile(
+                  icon: Icons.hub_outlined,
+                  title: 'Embedded ID/relay server',
+                  showTooltip: true,
+                  tooltipMessage: 'embedded_server_tip',
+                  onTap: mainGetBoolOptionSync(kOptionAllowEmbeddedServer)
+                      ? () {
+                          final key = bind.mainGetOptionSync(
+                              key: kOptionEmbeddedServerKey);
+                          if (key.isEmpty) return;
+                          Clipboard.setData(ClipboardData(text: key));
+                          showToast(translate('Copied'));
+                        }
+                      : null,
+                  trailing: Switch(
+                    value: mainGetBoolOptionSync(kOptionAllowEmbeddedServer),
+                    onChanged:
+                        locked || isOptionFixed(kOptionAllowEmbeddedServer)
+                            ? null
+                            : (value) {
+                                mainSetBoolOption(
+                                    kOptionAllowEmbeddedServer, value);
+                                setState(() {});
+                              },
+                  ),
+                ),
+              ],
               if (!hideServer && (!hideProxy || !hideWebSocket))
                 Divider(height: 1, indent: 16, endIndent: 16),
               if (!hideProxy)
@@ -1608,6 +1649,22 @@ class _NetworkState extends State<_Network> with AutomaticKeepAliveClientMixin {
                   title: 'Socks5/Http(s) Proxy',
                   onTap: changeSocks5Proxy,
                 ),
+              if (!hideProxy) ...[
+                Divider(height: 1, indent: 16, endIndent: 16),
+                listTile(
+                  icon: Icons.settings_ethernet_rounded,
+                  title: 'Use system proxy',
+                  trailing: Switch(
+                    value: mainGetBoolOptionSync(kOptionUseSystemProxy),
+                    onChanged: locked || isOptionFixed(kOptionUseSystemProxy)
+                        ? null
+                        : (value) {
+                            mainSetBoolOption(kOptionUseSystemProxy, value);
+                            setState(() {});
+                          },
+                  ),
+                ),
+              ],
               if (!hideProxy && !hideWebSocket)
                 Divider(height: 1, indent: 16, endIndent: 16),
               if (!hideWebSocket)
@@ -1626,6 +1683,32 @@ class _NetworkState extends State<_Network> with AutomaticKeepAliveClientMixin {
                           },
                   ),
                 ),
+              if (!bind.isOutgoingOnly()) ...[
+                Divider(height: 1, indent: 16, endIndent: 16),
+                listTile(
+                  icon: Icons.speed_rounded,
+                  title: 'Bandwidth limit',
+                  showTooltip: true,
+                  tooltipMessage: 'bandwidth_limit_tip',
+                  onTap: () => changeBandwidthLimit(
+                      'Bandwidth limit',
+                      int.tryParse(bind.mainGetOptionSync(
+                              key: kOptionUploadLimit)) ??
+                          0,
+                      int.tryParse(bind.mainGetOptionSync(
+                              key: kOptionDownloadLimit)) ??
+                          0, (upload, download) async {
+                    await bind.mainSetOption(
+                        key: kOptionUploadLimit,
+                        value: upload > 0 ? upload.toString() : '');
+                    await bind.mainSetOption(
+                        key: kOptionDownloadLimit,
+                        value: download > 0 ? download.toString() : '');
+                  },
+                      enabled: !isOptionFixed(kOptionUploadLimit) &&
+                          !isOptionFixed(kOptionDownloadLimit)),
+                ),
+              ],
             ],
           ),
         ),
@@ -18,6 +18,7 @@ import 'package:flutter_svg/flutter_svg.dart';
 
 import '../../common.dart';
 import '../../common/widgets/chat_page.dart';
+import '../../common/widgets/dialog.dart';
 import '../../models/file_model.dart';
 import '../../models/platform_model.dart';
 import '../../models/server_model.dart';
@@ -553,7 +554,14 @@ class _CmHeaderState extends State<_CmHeader>
                         ),
                       )
                   ],
-                ))
+                )),
+                if (client.authorized && client.traffic.isNotEmpty)
+                  FittedBox(
+                    child: Text(
+                      "↑ ${readableFileSize(client.bytesSent.toDouble())}  ↓ ${readableFileSize(client.bytesReceived.toDouble())}",
+                      style: TextStyle(color: Colors.white70, fontSize: 12),
+                    ),
+                  ),
               ],
             ),
           ),
@@ -793,7 +801,21 @@ class _PrivilegeBoardState extends State<_PrivilegeBoard> {
                             });
                           },
                           translate('Enable blocking user input'),
-                        )
+                        ),
+                      buildPermissionIcon(
+                        client.inventory,
+                        Icons.inventory_2_outlined,
+                        (enabled) {
+                          bind.cmSwitchPermission(
+                              connId: client.id,
+                              name: "inventory",
+                              enabled: enabled);
+                          setState(() {
+                            client.inventory = enabled;
+                          });
+                        },
+                        translate('Enable inventory query'),
+                      ),
                     ],
             ),
           ),
@@ -956,6 +978,48 @@ class _CmControlPanel extends StatelessWidget {
               text: "Switch Sides",
               textColor: Colors.white),
         ),
+        Offstage(
+          offstage: model.controllerCount < 2 ||
+              client.type_() != ClientType.remote,
+          child: buildButton(
+            context,
+            color: client.hasControl ? Colors.orange : MyTheme.accent,
+            onClick: () => bind.cmSetControlToken(
+                id: client.id, hasControl: !client.hasControl),
+            icon: Icon(
+              client.hasControl
+                  ? Icons.pan_tool_outlined
+                  : Icons.pan_tool_alt_outlined,
+              color: Colors.white,
+              size: 14,
+            ),
+            text: client.hasControl
+                ? 'Revoke control'
+                : client.controlRequested
+                    ? 'Grant requested control'
+                    : 'Grant control',
+            textColor: Colors.white,
+          ),
+        ),
+        buildButton(
+          context,
+          color: MyTheme.accent,
+          onClick: () => changeBandwidthLimit(
+              'Bandwidth limit', client.uploadLimit, client.downloadLimit,
+              (upload, download) async {
+            client.uploadLimit = upload;
+            client.downloadLimit = download;
+            await bind.cmSetBandwidthLimit(
+                id: client.id, upload: upload, download: download);
+          }),
+          icon: Icon(
+            Icons.speed_rounded,
+            color: Colors.white,
+            size: 14,
+          ),
+          text: 'Bandwidth limit',
+          textColor: Colors.white,
+        ),
         Offstage(
           offstage: !showElevation,
           child: buildButton(
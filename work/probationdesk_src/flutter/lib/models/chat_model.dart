@@ -1,8 +1,11 @@
 import 'dart:async';
+import 'dart:convert';
+import 'dart:io';
 
 import 'package:dash_chat_2/dash_chat_2.dart';
 import 'package:desktop_multi_window/desktop_multi_window.dart';
 import 'package:draggable_float_widget/draggable_float_widget.dart';
+import 'package:file_picker/file_picker.dart';
 import 'package:flutter/material.dart';
 import 'package:flutter/services.dart';
 import 'package:flutter_hbb/common/shared_state.dart';
@@ -344,13 +347,16 @@ class ChatModel with ChangeNotifier {
     mobileClearClientUnread(key.connId);
   }
 
-  receive(int id, String text) async {
+  receive(int id, String text,
+      {String msgId = '',
+      int timestamp = 0,
+      List<String> attachments = const []}) async {
     final session = parent.target;
     if (session == null) {
       debugPrint("Failed to receive msg, session state is null");
       return;
     }
-    if (text.isEmpty) return;
+    if (text.isEmpty && attachments.isEmpty) return;
     if (desktopType == DesktopType.cm) {
       await showCmWindow();
     }
@@ -430,8 +436,23 @@ class ChatModel with ChangeNotifier {
       }
       chatUser = ChatUser(id: client.peerId, firstName: client.name);
     }
-    insertMessage(messagekey,
-        ChatMessage(text: text, user: chatUser, createdAt: DateTime.now()));
+    insertMessage(
+        messagekey,
+        ChatMessage(
+            text: text,
+            user: chatUser,
+            createdAt: timestamp > 0
+                ? DateTime.fromMillisecondsSinceEpoch(timestamp)
+                : DateTime.now(),
+            customProperties: {'msg_id': msgId, 'attachments': attachments}));
+    // The chat page is shown above.
+    if (msgId.isNotEmpty) {
+      if (id == clientModeID) {
+        bind.sessionSendChatRead(sessionId: sessionId, msgIds: [msgId]);
+      } else {
+        bind.cmSendChatRead(connId: id, msgIds: [msgId]);
+      }
+    }
     if (id == clientModeID || _currentKey.peerId.isEmpty) {
       // client or invalid
       _currentKey = messagekey;
@@ -441,23 +462,81 @@ class ChatModel with ChangeNotifier {
     notifyListeners();
   }
 
-  send(ChatMessage message) {
+  send(ChatMessage message, {List<String> attachments = const []}) async {
     String trimmedText = message.text.trim();
-    if (trimmedText.isEmpty) {
+    if (trimmedText.isEmpty && attachments.isEmpty) {
       return;
     }
     message.text = trimmedText;
-    insertMessage(_currentKey, message);
-    if (_currentKey.connId == clientModeID && parent.target != null) {
-      bind.sessionSendChat(sessionId: sessionId, text: message.text);
+    final key = _currentKey;
+    final String res;
+    if (key.connId == clientModeID && parent.target != null) {
+      res = await bind.sessionSendChatWithAttachments(
+          sessionId: sessionId, text: message.text, paths: attachments);
     } else {
-      bind.cmSendChat(connId: _currentKey.connId, msg: message.text);
+      res = await bind.cmSendChatWithAttachments(
+          connId: key.connId, msg: message.text, paths: attachments);
+    }
+    final record = jsonDecode(res.isEmpty ? '{}' : res);
+    if (record['error'] != null) {
+      showToast(translate(record['error']));
+      return;
     }
+    message.customProperties = {
+      'msg_id': record['msg_id'] ?? '',
+      'attachments': List<String>.from(record['attachments'] ?? []),
+    };
+    insertMessage(key, message);
 
     notifyListeners();
     inputNode.requestFocus();
   }
 
+  sendAttachments() async {
+    final result = await FilePicker.platform.pickFiles(allowMultiple: true);
+    final paths = result?.paths.whereType<String>().toList() ?? [];
+    if (paths.isEmpty) return;
+    await send(
+        ChatMessage(
+            text: textController.text, user: me, createdAt: DateTime.now()),
+        attachments: paths);
+    textController.clear();
+  }
+
+  /// The peer has got or read the messages, [id] is the connection id in the connection manager.
+  onReceipt(int id, List<String> msgIds, bool read) {
+    final body = _messages.entries
+        .firstWhereOrNull((e) => e.key.connId == id)
+        ?.value;
+    if (body == null) return;
+    for (final message in body.chatMessages) {
+      final props = message.customProperties;
+      if (props == null || !msgIds.contains(props['msg_id'])) continue;
+      if (read || props['status'] != 'read') {
+        props['status'] = read ? 'read' : 'delivered';
+      }
+    }
+    notifyListeners();
+  }
+
+  exportHistory(String peerId) async {
+    String? outputFile = await FilePicker.platform.saveFile(
+      dialogTitle: '${translate('Save as')}...',
+      fileName: 'chat_$peerId.txt',
+      allowedExtensions: ['txt', 'json'],
+      type: FileType.custom,
+    );
+    if (outputFile == null) return;
+    final format = outputFile.toLowerCase().endsWith('.json') ? 'json' : 'txt';
+    final res = jsonDecode(
+        await bind.mainExportChatHistory(id: peerId, format: format));
+    if (res['error'] != null) {
+      showToast(translate(res['error']));
+      return;
+    }
+    await File(outputFile).writeAsString(res['data']);
+  }
+
   insertMessage(MessageKey key, ChatMessage message) {
     updateConnIdOfKey(key);
     if (!_messages.containsKey(key)) {
@@ -878,6 +878,10 @@ class InputModel {
   void onPointHoverImage(PointerHoverEvent e) {
     _stopFling = true;
     if (isViewOnly && !showMyCursor) return;
+    if (_isPen(e)) {
+      handlePenEvent(e);
+      return;
+    }
     if (e.kind != ui.PointerDeviceKind.mouse) return;
     if (!isPhysicalMouse.value) {
       isPhysicalMouse.value = true;
@@ -1040,6 +1044,10 @@ class InputModel {
     _windowRect = null;
     if (isViewOnly && !showMyCursor) return;
     if (isViewCamera) return;
+    if (_isPen(e)) {
+      handlePenEvent(e);
+      return;
+    }
     if (e.kind != ui.PointerDeviceKind.mouse) {
       if (isPhysicalMouse.value) {
         isPhysicalMouse.value = false;
@@ -1054,6 +1062,10 @@ class InputModel {
     if (isDesktop) _queryOtherWindowCoords = false;
     if (isViewOnly && !showMyCursor) return;
     if (isViewCamera) return;
+    if (_isPen(e)) {
+      handlePenEvent(e);
+      return;
+    }
     if (e.kind != ui.PointerDeviceKind.mouse) return;
     if (isPhysicalMouse.value) {
       handleMouse(_getMouseEvent(e, _kMouseEventUp), e.position);
@@ -1063,6 +1075,10 @@ class InputModel {
   void onPointMoveImage(PointerMoveEvent e) {
     if (isViewOnly && !showMyCursor) return;
     if (isViewCamera) return;
+    if (_isPen(e)) {
+      handlePenEvent(e);
+      return;
+    }
     if (e.kind != ui.PointerDeviceKind.mouse) return;
     if (_queryOtherWindowCoords) {
       Future.delayed(Duration.zero, () async {
@@ -1075,6 +1091,49 @@ class InputModel {
     }
   }
 
+  bool _isPen(PointerEvent e) =>
+      peerPlatform != kPeerPlatformAndroid &&
+      (e.kind == ui.PointerDeviceKind.stylus ||
+          e.kind == ui.PointerDeviceKind.invertedStylus);
+
+  /// Send the stylus state to the peer, pressure is in [0, 1024], tilt in degrees.
+  void handlePenEvent(PointerEvent e) {
+    if (isViewOnly) return;
+    final x = e.position.dx;
+    final y = max(0.0, e.position.dy);
+    if (_checkPeerControlProtected(x, y)) {
+      return;
+    }
+    final pos = handlePointerDevicePos(kPointerEventKindPen, x, y, true, '');
+    if (pos == null) {
+      return;
+    }
+    var pressure = 0.0;
+    if (e.down) {
+      final range = e.pressureMax - e.pressureMin;
+      pressure = range > 0 ? (e.pressure - e.pressureMin) / range : 1.0;
+    }
+    final tilt = e.tilt * 180 / pi;
+    var buttons = 0;
+    if (e.buttons & kSecondaryStylusButton != 0) buttons |= 0x01;
+    if (e.buttons & kTertiaryButton != 0) buttons |= 0x02;
+    final Map<String, dynamic> evt = {
+      'k': kPointerEventKindPen,
+      'v': {
+        'x': pos.x.toInt(),
+        'y': pos.y.toInt(),
+        'pressure': (pressure.clamp(0.0, 1.0) * 1024).toInt(),
+        'tilt_x': (tilt * sin(e.orientation)).round(),
+        'tilt_y': (-tilt * cos(e.orientation)).round(),
+        'buttons': buttons,
+        'eraser': e.kind == ui.PointerDeviceKind.invertedStylus,
+        'in_range': true,
+      }
+    };
+    bind.sessionSendPointer(
+        sessionId: sessionId, msg: json.encode(modify(evt)));
+  }
+
   static Future<Rect?> fillRemoteCoordsAndGetCurFrame(
       List<RemoteWindowCoords> remoteWindowCoords) async {
     final coords =
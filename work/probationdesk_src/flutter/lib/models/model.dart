@@ -126,6 +126,14 @@ class FfiModel with ChangeNotifier {
   bool isRefreshing = false;
 
   Timer? timerScreenshot;
+  // The floor control between the controllers of the peer, see `_handleControlToken`.
+  Map<String, dynamic> controlToken = {};
+
+  // Audio sources announced by the peer, {id, name, kind}.
+  final audioSources = <Map<String, dynamic>>[].obs;
+  // Ids of the selected audio sources, empty for the default one.
+  final selectedAudioSources = <String>{}.obs;
+  final inputMacroRecording = false.obs;
 
   Rect? get rect => _rect;
   bool get isOriginalResolutionSet =>
@@ -330,11 +338,26 @@ class FfiModel with ChangeNotifier {
       } else if (name == 'permission') {
         updatePermission(evt, peerId);
       } else if (name == 'chat_client_mode') {
-        parent.target?.chatModel
-            .receive(ChatModel.clientModeID, evt['text'] ?? '');
+        parent.target?.chatModel.receive(
+            ChatModel.clientModeID, evt['text'] ?? '',
+            msgId: evt['msg_id'] ?? '',
+            timestamp: evt['timestamp'] ?? 0,
+            attachments: List<String>.from(evt['attachments'] ?? []));
       } else if (name == 'chat_server_mode') {
-        parent.target?.chatModel
-            .receive(int.parse(evt['id'] as String), evt['text'] ?? '');
+        parent.target?.chatModel.receive(
+            int.parse(evt['id'] as String), evt['text'] ?? '',
+            msgId: evt['msg_id'] ?? '',
+            timestamp: int.tryParse(evt['timestamp'] ?? '') ?? 0,
+            attachments:
+                List<String>.from(jsonDecode(evt['attachments'] ?? '[]')));
+      } else if (name == 'chat_receipt') {
+        // The connection manager sends the fields as strings.
+        final cm = evt['id'] != null;
+        parent.target?.chatModel.onReceipt(
+            cm ? int.parse(evt['id']) : ChatModel.clientModeID,
+            List<String>.from(
+                cm ? jsonDecode(evt['msg_ids']) : evt['msg_ids'] ?? []),
+            cm ? evt['read'] == 'true' : evt['read'] == true);
       } else if (name == 'terminal_response') {
         parent.target?.routeTerminalResponse(evt);
       } else if (name == 'file_dir') {
@@ -398,6 +421,10 @@ class FfiModel with ChangeNotifier {
         parent.target?.chatModel.onVoiceCallIncoming();
       } else if (name == 'update_voice_call_state') {
         parent.target?.serverModel.updateVoiceCallState(evt);
+      } else if (name == 'update_control_token') {
+        parent.target?.serverModel.updateControlToken(evt);
+      } else if (name == 'update_traffic') {
+        parent.target?.serverModel.updateTraffic(evt);
       } else if (name == 'fingerprint') {
         FingerprintState.find(peerId).value = evt['fingerprint'] ?? '';
       } else if (name == 'plugin_manager') {
@@ -446,12 +473,152 @@ class FfiModel with ChangeNotifier {
         _handlePrinterRequest(evt, sessionId, peerId);
       } else if (name == 'screenshot') {
         _handleScreenshot(evt, sessionId, peerId);
+      } else if (name == 'audio_sources') {
+        _handleAudioSources(evt);
+      } else if (name == 'inventory') {
+        _handleInventory(evt, sessionId);
+      } else if (name == 'control_token') {
+        _handleControlToken(evt, sessionId);
+      } else if (name == 'input_macro_replayed') {
+        showToast(
+            '${evt['name']}: ${translate(evt['completed'] == 'true' ? 'Finished' : 'Aborted')}');
       } else {
         debugPrint('Event is not handled in the fixed branch: $name');
       }
     };
   }
 
+  _handleAudioSources(Map<String, dynamic> evt) {
+    try {
+      final sources = jsonDecode(evt['sources']) as List<dynamic>;
+      audioSources.value =
+          sources.map((e) => Map<String, dynamic>.from(e)).toList();
+      selectedAudioSources.removeWhere(
+          (id) => !audioSources.any((e) => e['id'] == id));
+    } catch (e) {
+      debugPrint('Failed to parse audio sources: $e');
+    }
+  }
+
+  selectAudioSource(String id, bool selected) {
+    if (selected) {
+      selectedAudioSources.add(id);
+    } else {
+      selectedAudioSources.remove(id);
+    }
+    bind.sessionSelectAudioSources(
+        sessionId: sessionId,
+        sources: jsonEncode(selectedAudioSources
+            .map((id) => {'id': id, 'gain': 1.0})
+            .toList()));
+  }
+
+  _handleInventory(Map<String, dynamic> evt, SessionID sessionId) {
+    final dialogManager = parent.target!.dialogManager;
+    final String inventory = evt['inventory'] ?? '';
+    Map<String, dynamic> map;
+    try {
+      map = jsonDecode(inventory);
+    } catch (e) {
+      debugPrint('Failed to parse inventory: $e');
+      return;
+    }
+    final String error = map['error'] ?? '';
+    if (error.isNotEmpty) {
+      msgBox(sessionId, 'custom-nook-nocancel-hasclose-error',
+          'System inventory', error, '', dialogManager);
+      return;
+    }
+    String gb(dynamic bytes) =>
+        '${((bytes ?? 0) / 1024 / 1024 / 1024).toStringAsFixed(1)} GB';
+    final lines = <String>[
+      '${translate('Hostname')}: ${map['hostname']}',
+      'OS: ${map['os_name']} (${map['kernel_version']})',
+      'CPU: ${map['cpu_brand']} x${map['cpu_cores']}, ${(map['cpu_usage'] ?? 0).toStringAsFixed(1)}%',
+      '${translate('Memory')}: ${gb(map['used_memory'])} / ${gb(map['total_memory'])}',
+      for (final d in (map['disks'] as List<dynamic>? ?? []))
+        '${d['mount_point']}: ${gb(d['available_space'])} / ${gb(d['total_space'])}',
+      for (final i in (map['network_interfaces'] as List<dynamic>? ?? []))
+        '${i['name']}: ${(i['ips'] as List<dynamic>).join(', ')}',
+      for (final u in (map['users'] as List<dynamic>? ?? []))
+        '${translate('User')}: ${u['name']} ${u['session']}',
+      '${translate('Packages')}: ${(map['packages'] as List<dynamic>? ?? []).length}',
+    ];
+
+    export(String format) {
+      Future.delayed(Duration.zero, () async {
+        String? outputFile = await FilePicker.platform.saveFile(
+          dialogTitle: '${translate('Save as')}...',
+          fileName: 'inventory_${map['hostname']}.$format',
+          allowedExtensions: [format],
+          type: FileType.custom,
+        );
+        if (outputFile == null) return;
+        final res = await bind.mainExportInventory(
+            inventory: inventory, path: outputFile, format: format);
+        if (res.isNotEmpty) {
+          msgBox(sessionId, 'custom-nook-nocancel-hasclose-error',
+              'System inventory', res, '', dialogManager);
+        }
+      });
+    }
+
+    dialogManager.show(
+      (setState, close, context) => CustomAlertDialog(
+        title: Text(translate('System inventory')),
+        content: SizedBox(
+          width: 480,
+          height: 360,
+          child: SingleChildScrollView(
+            child: SelectableText(lines.join('\n')),
+          ),
+        ),
+        actions: [
+          dialogButton('${translate('Export')} JSON',
+              onPressed: () => export('json')),
+          dialogButton('${translate('Export')} CSV',
+              onPressed: () => export('csv')),
+          dialogButton('Close', onPressed: close),
+        ],
+        onCancel: close,
+      ),
+      tag: 'inventory-$sessionId',
+    );
+  }
+
+  _handleControlToken(Map<String, dynamic> evt, SessionID sessionId) {
+    final hadControl = controlToken['has_control'] == true;
+    controlToken = evt;
+    final hasControl = evt['has_control'] == true;
+    if (hadControl != hasControl && (evt['controllers'] ?? 0) > 1) {
+      showToast(translate(hasControl ? 'You have the control' : 'View only'));
+    }
+    final dialogManager = parent.target!.dialogManager;
+    for (final r in (evt['requests'] as List<dynamic>? ?? [])) {
+      final int connId = r['conn_id'];
+      dialogManager.show((setState, close, context) {
+        respond(String action) {
+          bind.sessionControlTokenAction(
+              sessionId: sessionId, action: action, connId: connId);
+          close();
+        }
+
+        return CustomAlertDialog(
+          title: Text(translate('Request control')),
+          content: Text(
+              translate('control-request-tip').replaceAll('{}', r['name'])),
+          actions: [
+            dialogButton('Deny',
+                onPressed: () => respond('deny'), isOutline: true),
+            dialogButton('Grant', onPressed: () => respond('grant')),
+          ],
+          onCancel: close,
+        );
+      }, tag: 'control-request-$sessionId-$connId');
+    }
+    notifyListeners();
+  }
+
   _handleScreenshot(
       Map<String, dynamic> evt, SessionID sessionId, String peerId) {
     timerScreenshot?.cancel();
@@ -473,10 +640,11 @@ class FfiModel with ChangeNotifier {
         close();
         Future.delayed(Duration.zero, () async {
           final ts = DateTime.now().millisecondsSinceEpoch ~/ 1000;
+          final String ext = evt['ext'] ?? 'png';
           String? outputFile = await FilePicker.platform.saveFile(
             dialogTitle: '${translate('Save as')}...',
-            fileName: 'screenshot_$ts.png',
-            allowedExtensions: ['png'],
+            fileName: 'screenshot_$ts.$ext',
+            allowedExtensions: [ext],
             type: FileType.custom,
           );
           if (outputFile == null) {
@@ -856,6 +1024,8 @@ class FfiModel with ChangeNotifier {
       wrongPasswordDialog(sessionId, dialogManager, type, title, text);
     } else if (type == 'input-2fa') {
       enter2FaDialog(sessionId, dialogManager);
+    } else if (type == 'input-access-token') {
+      showAccessTokenDialog(sessionId, type, dialogManager);
     } else if (type == 'input-password') {
       enterPasswordDialog(sessionId, dialogManager);
     } else if (type == 'session-login' || type == 'session-re-login') {
@@ -882,6 +1052,9 @@ class FfiModel with ChangeNotifier {
       showElevationError(sessionId, type, title, text, dialogManager);
     } else if (type == 'relay-hint' || type == 'relay-hint2') {
       showRelayHintDialog(sessionId, type, title, text, dialogManager, peerId);
+    } else if (type == 'peer-key-changed') {
+      showPeerKeyChangedDialog(
+          sessionId, type, title, text, dialogManager, peerId);
     } else if (text == kMsgboxTextWaitingForImage) {
       showConnectedWaitingForImage(dialogManager, sessionId, type, title, text);
     } else if (title == 'Privacy mode') {
@@ -989,6 +1162,69 @@ class FfiModel with ChangeNotifier {
     });
   }
 
+  // The pre-shared token of the direct ip access of the peer, kept in its options.
+  void showAccessTokenDialog(
+      SessionID sessionId, String type, OverlayDialogManager dialogManager) {
+    final controller = TextEditingController();
+    dialogManager.show(tag: '$sessionId-$type', (setState, close, context) {
+      onClose() {
+        closeConnection();
+        close();
+      }
+
+      submit() {
+        bind.sessionPeerOption(
+            sessionId: sessionId,
+            name: 'direct-access-token',
+            value: controller.text.trim());
+        reconnect(dialogManager, sessionId, false);
+      }
+
+      return CustomAlertDialog(
+        title: Text(translate('Wrong access token')),
+        content: DialogTextField(
+          title: translate('Access token'),
+          controller: controller,
+          obscureText: true,
+        ),
+        actions: [
+          dialogButton('Cancel', onPressed: onClose, isOutline: true),
+          dialogButton('OK', onPressed: submit),
+        ],
+        onSubmit: submit,
+        onCancel: onClose,
+      );
+    });
+  }
+
+  void showPeerKeyChangedDialog(SessionID sessionId, String type, String title,
+      String text, OverlayDialogManager dialogManager, String peerId) {
+    dialogManager.show(tag: '$sessionId-$type', (setState, close, context) {
+      onClose() {
+        closeConnection();
+        close();
+      }
+
+      onRetrust() async {
+        await bind.mainRetrustPeerKey(id: peerId);
+        reconnect(dialogManager, sessionId, false);
+      }
+
+      return CustomAlertDialog(
+        title: null,
+        content: msgboxContent('error', title, text),
+        actions: [
+          dialogButton('Close', onPressed: onClose, isOutline: true),
+          dialogButton('Trust new key',
+              onPressed: onRetrust,
+              buttonStyle:
+                  ElevatedButton.styleFrom(backgroundColor: Colors.red[700])),
+        ],
+        onCancel: onClose,
+      );
+    });
+  }
+
   void showConnectedWaitingForImage(OverlayDialogManager dialogManager,
       SessionID sessionId, String type, String title, String text) {
     onClose() {
@@ -2765,6 +3001,14 @@ class QualityMonitorData {
   String? targetBitrate;
   String? codecFormat;
   String? chroma;
+  // Buffered/target ms, jitter ms and the concealed frames of the audio playout.
+  String? audioBuffer;
+  String? audioJitter;
+  String? audioConcealed;
+  // Idle/captured frames, damaged percent and encode ms of the current peer display.
+  String? captureIdle;
+  String? captureDamage;
+  String? captureEncode;
 }
 
 class QualityMonitorModel with ChangeNotifier {
@@ -2827,6 +3071,27 @@ class QualityMonitorModel with ChangeNotifier {
       if (evt.containsKey('chroma') && (evt['chroma'] as String).isNotEmpty) {
         _data.chroma = evt['chroma'];
       }
+      if (evt.containsKey('audio') && (evt['audio'] as String).isNotEmpty) {
+        final audio = jsonDecode(evt['audio']) as Map<String, dynamic>;
+        _data.audioBuffer = '${audio['buffer_ms']}/${audio['target_ms']}';
+        _data.audioJitter = audio['jitter_ms'].toString();
+        _data.audioConcealed = audio['concealed'].toString();
+      }
+      if (evt.containsKey('capture') && (evt['capture'] as String).isNotEmpty) {
+        final capture = jsonDecode(evt['capture']) as Map<String, dynamic>;
+        final currentDisplay = parent.target?.ffiModel.pi.currentDisplay;
+        final stats = capture[currentDisplay.toString()];
+        if (stats != null) {
+          _data.captureIdle = '${stats['idle']}/${stats['captured']}';
+          _data.captureDamage =
+              ((stats['damage_ratio'] as num) * 100).toStringAsFixed(1);
+          _data.captureEncode = stats['encode_ms'].toString();
+        } else {
+          _data.captureIdle = null;
+          _data.captureDamage = null;
+          _data.captureEncode = null;
+        }
+      }
       notifyListeners();
     } catch (e) {
       //
@@ -785,6 +785,39 @@ class ServerModel with ChangeNotifier {
     }
   }
 
+  void updateControlToken(Map<String, dynamic> evt) {
+    try {
+      final client = Client.fromJson(jsonDecode(evt["client"]));
+      final index = _clients.indexWhere((element) => element.id == client.id);
+      if (index != -1) {
+        _clients[index].hasControl = client.hasControl;
+        _clients[index].controlRequested = client.controlRequested;
+        notifyListeners();
+      }
+    } catch (e) {
+      debugPrint("updateControlToken failed: $e");
+    }
+  }
+
+  void updateTraffic(Map<String, dynamic> evt) {
+    try {
+      final client = Client.fromJson(jsonDecode(evt["client"]));
+      final index = _clients.indexWhere((element) => element.id == client.id);
+      if (index != -1) {
+        _clients[index].traffic = client.traffic;
+        notifyListeners();
+      }
+    } catch (e) {
+      debugPrint("updateTraffic failed: $e");
+    }
+  }
+
+  /// The number of the controllers, the control token matters only if there are more than one.
+  int get controllerCount => _clients
+      .where((c) =>
+          !c.disconnected && c.authorized && c.type_() == ClientType.remote)
+      .length;
+
   void androidUpdatekeepScreenOn() async {
     if (!isAndroid) return;
     var floatingWindowDisabled =
@@ -831,10 +864,18 @@ class Client {
   bool restart = false;
   bool recording = false;
   bool blockInput = false;
+  bool inventory = false;
   bool disconnected = false;
   bool fromSwitch = false;
   bool inVoiceCall = false;
   bool incomingVoiceCall = false;
+  bool hasControl = false;
+  bool controlRequested = false;
+  // {"upload": {"video": bytes, ...}, "download": {...}}
+  Map<String, dynamic> traffic = {};
+  // KB/s, 0 for the global caps only.
+  int uploadLimit = 0;
+  int downloadLimit = 0;
 
   RxInt unreadChatMessageCount = 0.obs;
 
@@ -858,10 +899,16 @@ class Client {
     restart = json['restart'];
     recording = json['recording'];
     blockInput = json['block_input'];
+    inventory = json['inventory'] ?? false;
     disconnected = json['disconnected'];
     fromSwitch = json['from_switch'];
     inVoiceCall = json['in_voice_call'];
     incomingVoiceCall = json['incoming_voice_call'];
+    hasControl = json['has_control'] ?? false;
+    controlRequested = json['control_requested'] ?? false;
+    traffic = json['traffic'] ?? {};
+    uploadLimit = json['upload_limit'] ?? 0;
+    downloadLimit = json['download_limit'] ?? 0;
   }
 
   Map<String, dynamic> toJson() {
@@ -881,13 +928,27 @@ class Client {
     data['restart'] = restart;
     data['recording'] = recording;
     data['block_input'] = blockInput;
+    data['inventory'] = inventory;
     data['disconnected'] = disconnected;
     data['from_switch'] = fromSwitch;
     data['in_voice_call'] = inVoiceCall;
     data['incoming_voice_call'] = incomingVoiceCall;
+    data['has_control'] = hasControl;
+    data['control_requested'] = controlRequested;
+    data['traffic'] = traffic;
+    data['upload_limit'] = uploadLimit;
+    data['download_limit'] = downloadLimit;
     return data;
   }
 
+  int _trafficTotal(String direction) {
+    final Map<String, dynamic> bytes = traffic[direction] ?? {};
+    return bytes.values.fold(0, (sum, v) => sum + (v as int));
+  }
+
+  int get bytesSent => _trafficTotal('upload');
+  int get bytesReceived => _trafficTotal('download');
+
   ClientType type_() {
     if (isFileTransfer) {
       return ClientType.file;
@@ -1020,6 +1020,10 @@ class RustdeskImpl {
     return mainSetPeerOption(id: id, key: 'password', value: '');
   }
 
+  Future<void> mainRetrustPeerKey({required String id, dynamic hint}) {
+    throw UnimplementedError("mainRetrustPeerKey");
+  }
+
   Future<bool> mainPeerHasPassword({required String id, dynamic hint}) {
     return Future(() =>
         js.context.callMethod('getByName', ['peer_has_password', id]) ==
@@ -323,6 +323,13 @@ pub struct PeerConfig {
         deserialize_with = "PeerConfig::deserialize_trackpad_speed"
     )]
     pub trackpad_speed: i32,
+    // The signing key of the peer trusted on first use, in base64, like the known_hosts of ssh.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_string",
+        skip_serializing_if = "String::is_empty"
+    )]
+    pub trusted_pk: String,
 
     #[serde(
         default,
@@ -379,6 +386,7 @@ impl Default for PeerConfig {
             use_all_my_displays_for_the_remote_session:
                 Self::default_use_all_my_displays_for_the_remote_session(),
             trackpad_speed: Self::default_trackpad_speed(),
+            trusted_pk: Default::default(),
             custom_resolutions: Default::default(),
             options: Self::default_options(),
             ui_flutter: Default::default(),
@@ -1310,7 +1318,12 @@ impl PeerConfig {
                     decrypt_vec_or_original(&config.password, PASSWORD_ENC_VERSION);
                 config.password = password;
                 store = store || store2;
-                for opt in ["rdp_password", "os-username", "os-password"] {
+                for opt in [
+                    "rdp_password",
+                    "os-username",
+                    "os-password",
+                    keys::OPTION_DIRECT_ACCESS_TOKEN,
+                ] {
                     if let Some(v) = config.options.get_mut(opt) {
                         let (encrypted, _, store2) =
                             decrypt_str_or_original(v, PASSWORD_ENC_VERSION);
@@ -1344,7 +1357,12 @@ impl PeerConfig {
         let mut config = self.clone();
         config.password =
             encrypt_vec_or_original(&config.password, PASSWORD_ENC_VERSION, ENCRYPT_MAX_LEN);
-        for opt in ["rdp_password", "os-username", "os-password"] {
+        for opt in [
+            "rdp_password",
+            "os-username",
+            "os-password",
+            keys::OPTION_DIRECT_ACCESS_TOKEN,
+        ] {
             if let Some(v) = config.options.get_mut(opt) {
                 *v = encrypt_str_or_original(v, PASSWORD_ENC_VERSION, ENCRYPT_MAX_LEN)
             }
@@ -1359,6 +1377,11 @@ impl PeerConfig {
         fs::remove_file(Self::path(id)).ok();
     }
 
+    /// The directory of the chat history with the peer and the received attachments.
+    pub fn chat_dir(id: &str) -> PathBuf {
+        Self::path(id).with_extension("chat")
+    }
+
     fn path(id: &str) -> PathBuf {
         //If the id contains invalid chars, encode it
         let forbidden_paths = Regex::new(r".*[<>:/\\|\?\*].*");
@@ -1614,6 +1637,105 @@ impl PeerConfig {
     }
 }
 
+const INPUT_MACROS: &str = "macros";
+
+/// A recorded sequence of input, replayed on any peer by the controlling side.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+pub struct InputMacro {
+    #[serde(default, deserialize_with = "deserialize_string")]
+    pub name: String,
+    #[serde(default, deserialize_with = "deserialize_vec_macrostep")]
+    pub steps: Vec<MacroStep>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+pub struct MacroStep {
+    // milliseconds since the previous step
+    #[serde(default)]
+    pub delay: u64,
+    pub action: MacroAction,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MacroAction {
+    // base64 of the protobuf `KeyEvent`
+    Key {
+        event: String,
+    },
+    // `x` and `y` are relative to the size of `display` (0.0 - 1.0),
+    // or the deltas of wheel and trackpad events.
+    Mouse {
+        mask: i32,
+        display: i32,
+        x: f64,
+        y: f64,
+        #[serde(default)]
+        modifiers: Vec<i32>,
+    },
+    // Typed text, `${name}` is replaced by the variable `name` on replay.
+    Text {
+        text: String,
+    },
+}
+
+impl Default for MacroAction {
+    fn default() -> Self {
+        Self::Text {
+            text: Default::default(),
+        }
+    }
+}
+
+impl InputMacro {
+    pub fn load(name: &str) -> Option<InputMacro> {
+        let path = Self::path(name);
+        if !path.exists() {
+            return None;
+        }
+        Some(load_path(path))
+    }
+
+    pub fn store(&self) -> crate::ResultType<()> {
+        if self.name.is_empty() {
+            crate::bail!("empty macro name");
+        }
+        store_path(Self::path(&self.name), self)
+    }
+
+    pub fn remove(name: &str) {
+        fs::remove_file(Self::path(name)).ok();
+    }
+
+    pub fn list() -> Vec<InputMacro> {
+        let mut macros: Vec<InputMacro> = Config::path(INPUT_MACROS)
+            .read_dir()
+            .map(|dir| {
+                dir.filter_map(|res| res.ok().map(|e| e.path()))
+                    .filter(|p| p.extension().and_then(|e| e.to_str()) == Some("toml"))
+                    .map(load_path::<InputMacro>)
+                    .filter(|m| !m.name.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+        macros.sort_by(|a, b| a.name.cmp(&b.name));
+        macros
+    }
+
+    fn path(name: &str) -> PathBuf {
+        let name = if name
+            .chars()
+            .any(|c| matches!(c, '<' | '>' | ':' | '/' | '\\' | '|' | '?' | '*' | '.'))
+        {
+            "base64_".to_string() + base64::encode(name, base64::Variant::UrlSafe).as_str()
+        } else {
+            name.to_string()
+        };
+        let path: PathBuf = [INPUT_MACROS, name.as_str()].iter().collect();
+        Config::with_extension(Config::path(path))
+    }
+}
+
 serde_field_bool!(
     ShowRemoteCursor,
     "show_remote_cursor",
@@ -2026,7 +2148,7 @@ impl UserDefaultConfig {
     }
 }
 
-#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
 pub struct AbPeer {
     #[serde(
         default,
@@ -2066,6 +2188,9 @@ pub struct AbPeer {
     pub alias: String,
     #[serde(default, deserialize_with = "deserialize_vec_string")]
     pub tags: Vec<String>,
+    // Fields this version does not know about, e.g. rdp settings, kept for the round trip.
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
 }
 
 #[derive(Debug, Default, Serialize, Deserialize, Clone)]
@@ -2154,6 +2279,81 @@ impl Ab {
     }
 }
 
+/// The address book kept on this device, usable without an API server.
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+pub struct LocalAb {
+    #[serde(default, deserialize_with = "deserialize_vec_abpeer")]
+    pub peers: Vec<AbPeer>,
+    #[serde(default, deserialize_with = "deserialize_vec_string")]
+    pub tags: Vec<String>,
+    #[serde(
+        default,
+        deserialize_with = "deserialize_string",
+        skip_serializing_if = "String::is_empty"
+    )]
+    pub tag_colors: String,
+    // The state of the last sync, the common base of the local and the remote edits.
+    #[serde(default, deserialize_with = "deserialize_vec_abpeer")]
+    pub synced_peers: Vec<AbPeer>,
+    #[serde(default, deserialize_with = "deserialize_vec_string")]
+    pub synced_tags: Vec<String>,
+    #[serde(
+        default,
+        deserialize_with = "deserialize_string",
+        skip_serializing_if = "String::is_empty"
+    )]
+    pub synced_tag_colors: String,
+    #[serde(default, deserialize_with = "deserialize_vec_abconflict")]
+    pub conflicts: Vec<AbConflict>,
+}
+
+/// A peer edited both locally and on the server since the last sync.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+pub struct AbConflict {
+    #[serde(default, deserialize_with = "deserialize_string")]
+    pub id: String,
+    // None if deleted on that side
+    #[serde(default)]
+    pub local: Option<AbPeer>,
+    #[serde(default)]
+    pub remote: Option<AbPeer>,
+}
+
+impl LocalAb {
+    fn path() -> PathBuf {
+        let filename = format!("{}_local_ab", APP_NAME.read().unwrap().clone());
+        Config::path(filename)
+    }
+
+    pub fn store(&self) -> crate::ResultType<()> {
+        let json = serde_json::to_string(self)?;
+        let data = compress(json.as_bytes());
+        let max_len = 64 * 1024 * 1024;
+        if data.len() > max_len {
+            // maxlen of function decompress
+            crate::bail!("local ab data too large, {} > {}", data.len(), max_len);
+        }
+        let Ok(data) = symmetric_crypt(&data, true) else {
+            crate::bail!("failed to encrypt local ab");
+        };
+        std::fs::write(Self::path(), data)?;
+        Ok(())
+    }
+
+    pub fn load() -> LocalAb {
+        if let Ok(data) = std::fs::read(Self::path()) {
+            if let Ok(data) = symmetric_crypt(&data, false) {
+                let data = decompress(&data);
+                if let Ok(ab) = serde_json::from_str::<LocalAb>(&String::from_utf8_lossy(&data)) {
+                    return ab;
+                }
+            }
+            log::error!("failed to load local ab");
+        }
+        LocalAb::default()
+    }
+}
+
 // use default value when field type is wrong
 macro_rules! deserialize_default {
     ($func_name:ident, $return_type:ty) => {
@@ -2303,9 +2503,11 @@ deserialize_default!(deserialize_vec_i32_string_i32, Vec<(i32, String, i32)>);
 deserialize_default!(deserialize_vec_discoverypeer, Vec<DiscoveryPeer>);
 deserialize_default!(deserialize_vec_abpeer, Vec<AbPeer>);
 deserialize_default!(deserialize_vec_abentry, Vec<AbEntry>);
+deserialize_default!(deserialize_vec_abconflict, Vec<AbConflict>);
 deserialize_default!(deserialize_vec_groupuser, Vec<GroupUser>);
 deserialize_default!(deserialize_vec_grouppeer, Vec<GroupPeer>);
 deserialize_default!(deserialize_vec_devicegroup, Vec<DeviceGroup>);
+deserialize_default!(deserialize_vec_macrostep, Vec<MacroStep>);
 deserialize_default!(deserialize_keypair, KeyPair);
 deserialize_default!(deserialize_size, Size);
 deserialize_default!(deserialize_hashmap_string_string, HashMap<String, String>);
@@ -2403,7 +2605,10 @@ pub fn option2bool(option: &str, value: &str) -> bool {
     } else if option.starts_with("allow-")
         || option == "stop-service"
         || option == keys::OPTION_DIRECT_SERVER
+        || option == keys::OPTION_DIRECT_ACCESS_TLS
         || option == "force-always-relay"
+        || option == keys::OPTION_STRICT_ENCRYPTION
+        || option == keys::OPTION_USE_SYSTEM_PROXY
     {
         value == "Y"
     } else {
@@ -2433,6 +2638,7 @@ pub mod keys {
     pub const OPTION_PRIVACY_MODE: &str = "privacy_mode";
     pub const OPTION_TOUCH_MODE: &str = "touch-mode";
     pub const OPTION_I444: &str = "i444";
+    pub const OPTION_TEXT_LOSSLESS: &str = "text-lossless";
     pub const OPTION_REVERSE_MOUSE_WHEEL: &str = "reverse_mouse_wheel";
     pub const OPTION_SWAP_LEFT_RIGHT_MOUSE: &str = "swap-left-right-mouse";
     pub const OPTION_DISPLAYS_AS_INDIVIDUAL_WINDOWS: &str = "displays_as_individual_windows";
@@ -2472,11 +2678,25 @@ pub mod keys {
     pub const OPTION_ENABLE_REMOTE_RESTART: &str = "enable-remote-restart";
     pub const OPTION_ENABLE_RECORD_SESSION: &str = "enable-record-session";
     pub const OPTION_ENABLE_BLOCK_INPUT: &str = "enable-block-input";
+    pub const OPTION_ENABLE_INVENTORY: &str = "enable-inventory";
     pub const OPTION_ALLOW_REMOTE_CONFIG_MODIFICATION: &str = "allow-remote-config-modification";
     pub const OPTION_ALLOW_NUMERNIC_ONE_TIME_PASSWORD: &str = "allow-numeric-one-time-password";
     pub const OPTION_ENABLE_LAN_DISCOVERY: &str = "enable-lan-discovery";
     pub const OPTION_DIRECT_SERVER: &str = "direct-server";
     pub const OPTION_DIRECT_ACCESS_PORT: &str = "direct-access-port";
+    // Comma separated ips of the interfaces to listen on for the direct access, all if empty.
+    pub const OPTION_DIRECT_ACCESS_BIND: &str = "direct-access-bind";
+    // Pre-shared, proven before the login by the direct access clients.
+    // Also per peer with the tls and the cert, to reach its direct access.
+    pub const OPTION_DIRECT_ACCESS_TOKEN: &str = "direct-access-token";
+    pub const OPTION_DIRECT_ACCESS_TLS: &str = "direct-access-tls";
+    // The PEM certificate chain and private key of this side or the path of them,
+    // self-signed if empty.
+    pub const OPTION_DIRECT_ACCESS_CERT: &str = "direct-access-cert";
+    // The PEM CA certificates the direct access clients must present one signed by, or the path.
+    pub const OPTION_DIRECT_ACCESS_CLIENT_CA: &str = "direct-access-client-ca";
+    // Per peer, the sha256 fingerprints of its direct access certificate accepted, comma separated.
+    pub const OPTION_DIRECT_ACCESS_PINS: &str = "direct-access-pins";
     pub const OPTION_WHITELIST: &str = "whitelist";
     pub const OPTION_ALLOW_AUTO_DISCONNECT: &str = "allow-auto-disconnect";
     pub const OPTION_AUTO_DISCONNECT_TIMEOUT: &str = "auto-disconnect-timeout";
@@ -2485,6 +2705,11 @@ pub mod keys {
     pub const OPTION_ALLOW_AUTO_RECORD_OUTGOING: &str = "allow-auto-record-outgoing";
     pub const OPTION_VIDEO_SAVE_DIRECTORY: &str = "video-save-directory";
     pub const OPTION_ENABLE_ABR: &str = "enable-abr";
+    // KB/s, shared by all connections, empty for unlimited.
+    pub const OPTION_UPLOAD_LIMIT: &str = "upload-limit";
+    pub const OPTION_DOWNLOAD_LIMIT: &str = "download-limit";
+    // Refuse the connections without end-to-end encryption, both incoming and outgoing.
+    pub const OPTION_STRICT_ENCRYPTION: &str = "strict-encryption";
     pub const OPTION_ALLOW_REMOVE_WALLPAPER: &str = "allow-remove-wallpaper";
     pub const OPTION_ALLOW_ALWAYS_SOFTWARE_RENDER: &str = "allow-always-software-render";
     pub const OPTION_ALLOW_LINUX_HEADLESS: &str = "allow-linux-headless";
@@ -2496,6 +2721,26 @@ pub mod keys {
     pub const OPTION_API_SERVER: &str = "api-server";
     pub const OPTION_KEY: &str = "key";
     pub const OPTION_ALLOW_WEBSOCKET: &str = "allow-websocket";
+    // The extra CA certificates trusted by the TLS of websocket, e.g. of a TLS inspecting proxy,
+    // a PEM bundle or the path of it.
+    pub const OPTION_WS_CA_CERTS: &str = "ws-ca-certs";
+    // The SHA-256 fingerprints of the only server certificates accepted by the TLS of websocket,
+    // in hex, separated by commas.
+    pub const OPTION_WS_CERT_PINS: &str = "ws-cert-pins";
+    // The https endpoint of the registration if UDP and TCP fail, on 443 of the rendezvous
+    // server under /rendezvous if empty.
+    pub const OPTION_RENDEZVOUS_HTTP_URL: &str = "rendezvous-http-url";
+    // The url of the PAC script, http or file, used if no proxy is set by hand.
+    pub const OPTION_PROXY_PAC_URL: &str = "proxy-pac-url";
+    // The proxy of the environment variables or the desktop settings, used if no proxy is set.
+    pub const OPTION_USE_SYSTEM_PROXY: &str = "use-system-proxy";
+    // The destinations connected without a proxy: ip addresses, CIDRs and domain suffixes,
+    // separated by commas.
+    pub const OPTION_NO_PROXY: &str = "no-proxy";
+    // This peer is also the rendezvous and relay server of its site.
+    pub const OPTION_ALLOW_EMBEDDED_SERVER: &str = "allow-embedded-server";
+    // The public key of the embedded server, set by it, for its peers.
+    pub const OPTION_EMBEDDED_SERVER_KEY: &str = "embedded-server-key";
     pub const OPTION_PRESET_ADDRESS_BOOK_NAME: &str = "preset-address-book-name";
     pub const OPTION_PRESET_ADDRESS_BOOK_TAG: &str = "preset-address-book-tag";
     pub const OPTION_PRESET_ADDRESS_BOOK_ALIAS: &str = "preset-address-book-alias";
@@ -2598,6 +2843,7 @@ pub mod keys {
         OPTION_PRIVACY_MODE,
         OPTION_TOUCH_MODE,
         OPTION_I444,
+        OPTION_TEXT_LOSSLESS,
         OPTION_REVERSE_MOUSE_WHEEL,
         OPTION_SWAP_LEFT_RIGHT_MOUSE,
         OPTION_DISPLAYS_AS_INDIVIDUAL_WINDOWS,
@@ -2665,17 +2911,26 @@ pub mod keys {
         OPTION_ENABLE_REMOTE_RESTART,
         OPTION_ENABLE_RECORD_SESSION,
         OPTION_ENABLE_BLOCK_INPUT,
+        OPTION_ENABLE_INVENTORY,
         OPTION_ALLOW_REMOTE_CONFIG_MODIFICATION,
         OPTION_ALLOW_NUMERNIC_ONE_TIME_PASSWORD,
         OPTION_ENABLE_LAN_DISCOVERY,
         OPTION_DIRECT_SERVER,
         OPTION_DIRECT_ACCESS_PORT,
+        OPTION_DIRECT_ACCESS_BIND,
+        OPTION_DIRECT_ACCESS_TOKEN,
+        OPTION_DIRECT_ACCESS_TLS,
+        OPTION_DIRECT_ACCESS_CERT,
+        OPTION_DIRECT_ACCESS_CLIENT_CA,
         OPTION_WHITELIST,
         OPTION_ALLOW_AUTO_DISCONNECT,
         OPTION_AUTO_DISCONNECT_TIMEOUT,
         OPTION_ALLOW_ONLY_CONN_WINDOW_OPEN,
         OPTION_ALLOW_AUTO_RECORD_INCOMING,
         OPTION_ENABLE_ABR,
+        OPTION_UPLOAD_LIMIT,
+        OPTION_DOWNLOAD_LIMIT,
+        OPTION_STRICT_ENCRYPTION,
         OPTION_ALLOW_REMOVE_WALLPAPER,
         OPTION_ALLOW_ALWAYS_SOFTWARE_RENDER,
         OPTION_ALLOW_LINUX_HEADLESS,
@@ -2690,6 +2945,13 @@ pub mod keys {
         OPTION_API_SERVER,
         OPTION_KEY,
         OPTION_ALLOW_WEBSOCKET,
+        OPTION_WS_CA_CERTS,
+        OPTION_WS_CERT_PINS,
+        OPTION_RENDEZVOUS_HTTP_URL,
+        OPTION_PROXY_PAC_URL,
+        OPTION_USE_SYSTEM_PROXY,
+        OPTION_NO_PROXY,
+        OPTION_ALLOW_EMBEDDED_SERVER,
         OPTION_PRESET_ADDRESS_BOOK_NAME,
         OPTION_PRESET_ADDRESS_BOOK_TAG,
         OPTION_PRESET_ADDRESS_BOOK_ALIAS,
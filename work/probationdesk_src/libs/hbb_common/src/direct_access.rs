@@ -0,0 +1,474 @@
+// Hardening of the direct ip access, to expose it on a management network.
+//
+// The direct port may be wrapped in TLS, with the certificate of `direct-access-cert` or a
+// self-signed one kept in the config directory, so that its fingerprint can be pinned by the
+// clients. With `direct-access-client-ca`, only the clients presenting a certificate signed by
+// it get through the handshake.
+// With `direct-access-token`, the server sends a random nonce in `DirectAccess` before anything
+// else, the client answers with the hash of the nonce and the pre-shared token, the token never
+// goes over the wire and an answer can not be replayed. The `Hash` of the login follows only
+// after the proof.
+// The attempts are limited per source ip, the rest is closed unanswered.
+//
+// The options above are of the listener. To reach the direct access of a peer, the same
+// options are per peer in the options of its `PeerConfig`, with `direct-access-pins` for its
+// certificate and `direct-access-cert` for the certificate of this side. Without pins, the
+// certificate of the first connection is pinned, a changed one is refused after.
+
+use crate::{
+    anyhow::anyhow,
+    bail,
+    config::{keys::*, option2bool, Config, PeerConfig},
+    happy_eyeballs,
+    message_proto::*,
+    protobuf::Message as _,
+    tcp::FramedStream,
+    websocket::{is_pinned, parse_pins, pem_blocks, PEM_BEGIN},
+    ResultType, Stream,
+};
+use rustls::{
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::CryptoProvider,
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer, ServerName, UnixTime},
+    server::WebPkiClientVerifier,
+    DigitallySignedStruct, RootCertStore, SignatureScheme,
+};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::net::TcpStream;
+pub use tokio_rustls::TlsAcceptor;
+use tokio_rustls::TlsConnector;
+
+// The attempts allowed per source ip within the window.
+pub const MAX_ATTEMPTS: u32 = 10;
+pub const ATTEMPT_WINDOW: Duration = Duration::from_secs(60);
+// ms, for the TLS handshake and the answer to the challenge each.
+pub const HANDSHAKE_TIMEOUT: u64 = 5_000;
+const NONCE_LEN: usize = 32;
+const SERVER_NAME: &str = "rustdesk";
+const SELF_SIGNED_FILE: &str = "direct_access.pem";
+pub const WRONG_TOKEN: &str = "Wrong access token";
+
+/// The pre-shared access token of the listener, empty if not required.
+pub fn token() -> String {
+    Config::get_option(OPTION_DIRECT_ACCESS_TOKEN)
+        .trim()
+        .to_owned()
+}
+
+/// Whether the listener is over TLS.
+pub fn is_tls() -> bool {
+    option2bool(
+        OPTION_DIRECT_ACCESS_TLS,
+        &Config::get_option(OPTION_DIRECT_ACCESS_TLS),
+    )
+}
+
+/// The settings to reach the direct access of a peer.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ClientSettings {
+    pub tls: bool,
+    pub token: String,
+    pub pins: Vec<String>,
+    // The PEM certificate chain and private key of this side or the path of them.
+    pub cert: String,
+}
+
+impl ClientSettings {
+    pub fn load(id: &str) -> Self {
+        Self::from_options(&PeerConfig::load(id).options)
+    }
+
+    pub fn from_options(options: &HashMap<String, String>) -> Self {
+        let get = |key: &str| options.get(key).map(|v| v.trim()).unwrap_or_default();
+        Self {
+            tls: option2bool(OPTION_DIRECT_ACCESS_TLS, get(OPTION_DIRECT_ACCESS_TLS)),
+            token: get(OPTION_DIRECT_ACCESS_TOKEN).to_owned(),
+            pins: parse_pins(get(OPTION_DIRECT_ACCESS_PINS)),
+            cert: get(OPTION_DIRECT_ACCESS_CERT).to_owned(),
+        }
+    }
+}
+
+/// The addresses to listen on for `port`, empty for all interfaces.
+pub fn bind_addrs(option: &str, port: u16) -> ResultType<Vec<SocketAddr>> {
+    option
+        .split(',')
+        .map(|ip| ip.trim())
+        .filter(|ip| !ip.is_empty())
+        .map(|ip| match ip.parse::<IpAddr>() {
+            Ok(ip) => Ok(SocketAddr::new(ip, port)),
+            Err(_) => bail!("Invalid ip to bind the direct access: {}", ip),
+        })
+        .collect()
+}
+
+pub fn proof(token: &str, nonce: &[u8]) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(b"direct-access");
+    hasher.update(nonce);
+    hasher.update(token.as_bytes());
+    hasher.finalize().to_vec()
+}
+
+/// In constant time.
+pub fn verify(token: &str, nonce: &[u8], received: &[u8]) -> bool {
+    sodiumoxide::utils::memcmp(&proof(token, nonce), received)
+}
+
+/// The counts of the attempts per source ip.
+#[derive(Default)]
+pub struct RateLimiter(HashMap<IpAddr, (Instant, u32)>);
+
+impl RateLimiter {
+    pub fn allow(&mut self, ip: IpAddr) -> bool {
+        let now = Instant::now();
+        self.0
+            .retain(|_, (start, _)| now.duration_since(*start) < ATTEMPT_WINDOW);
+        let (_, count) = self.0.entry(ip.to_canonical()).or_insert((now, 0));
+        *count += 1;
+        *count <= MAX_ATTEMPTS
+    }
+}
+
+/// Requires the proof of `token` before anything else is sent on `stream`.
+pub async fn challenge(stream: &mut Stream, token: &str) -> ResultType<()> {
+    let nonce = sodiumoxide::randombytes::randombytes(NONCE_LEN);
+    let mut msg_out = Message::new();
+    msg_out.set_direct_access(DirectAccess {
+        nonce: nonce.clone().into(),
+        ..Default::default()
+    });
+    stream.send(&msg_out).await?;
+    let Some(Ok(bytes)) = stream.next_timeout(HANDSHAKE_TIMEOUT).await else {
+        bail!("No answer to the access token challenge");
+    };
+    match Message::parse_from_bytes(&bytes).map(|msg| msg.union) {
+        Ok(Some(message::Union::DirectAccess(da))) if verify(token, &nonce, &da.proof) => Ok(()),
+        _ => {
+            let mut msg_out = Message::new();
+            msg_out.set_login_response(LoginResponse {
+                union: Some(login_response::Union::Error(WRONG_TOKEN.to_owned())),
+                ..Default::default()
+            });
+            stream.send(&msg_out).await.ok();
+            bail!(WRONG_TOKEN);
+        }
+    }
+}
+
+/// The answer of the client to the challenge, with the token set for the peer.
+pub fn answer(da: &DirectAccess, token: &str) -> Message {
+    let mut msg_out = Message::new();
+    msg_out.set_direct_access(DirectAccess {
+        proof: proof(token, &da.nonce).into(),
+        ..Default::default()
+    });
+    msg_out
+}
+
+/// The sha256 fingerprint as the pins are given.
+pub fn fingerprint(der: &[u8]) -> String {
+    Sha256::digest(der)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+// The option itself if it is PEM, else the file it points to.
+fn read_pem(option: &str) -> ResultType<String> {
+    let option = option.trim();
+    if option.is_empty() || option.contains("-----BEGIN") {
+        Ok(option.to_owned())
+    } else {
+        Ok(std::fs::read_to_string(option)?)
+    }
+}
+
+fn parse_certified_key(
+    pem: &str,
+) -> ResultType<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)> {
+    let chain = CertificateDer::pem_slice_iter(pem.as_bytes())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| anyhow!("Invalid certificate: {:?}", e))?;
+    if chain.is_empty() {
+        bail!("No certificate in {}", OPTION_DIRECT_ACCESS_CERT);
+    }
+    let key = PrivateKeyDer::from_pem_slice(pem.as_bytes())
+        .map_err(|e| anyhow!("Invalid private key: {:?}", e))?;
+    Ok((chain, key))
+}
+
+// Kept, so its fingerprint stays the same across restarts.
+fn self_signed() -> ResultType<String> {
+    let path = Config::path(SELF_SIGNED_FILE);
+    if let Ok(pem) = std::fs::read_to_string(&path) {
+        if pem.contains(PEM_BEGIN) {
+            return Ok(pem);
+        }
+    }
+    let cert = rcgen::generate_simple_self_signed(vec![SERVER_NAME.to_owned()])?;
+    let pem = format!("{}{}", cert.cert.pem(), cert.key_pair.serialize_pem());
+    std::fs::write(&path, &pem)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).ok();
+    }
+    Ok(pem)
+}
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(rustls::crypto::ring::default_provider())
+}
+
+pub fn acceptor() -> ResultType<TlsAcceptor> {
+    let mut pem = read_pem(&Config::get_option(OPTION_DIRECT_ACCESS_CERT))?;
+    if pem.is_empty() {
+        pem = self_signed()?;
+    }
+    let (chain, key) = parse_certified_key(&pem)?;
+    log::info!(
+        "Direct access certificate sha256: {}",
+        fingerprint(chain[0].as_ref())
+    );
+    let provider = provider();
+    let builder = rustls::ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()?;
+    let cas = pem_blocks(&read_pem(&Config::get_option(
+        OPTION_DIRECT_ACCESS_CLIENT_CA,
+    ))?);
+    let config = if cas.is_empty() {
+        builder.with_no_client_auth()
+    } else {
+        let mut roots = RootCertStore::empty();
+        for ca in cas {
+            let cert = CertificateDer::from_pem_slice(ca.as_bytes())
+                .map_err(|e| anyhow!("Invalid CA certificate: {:?}", e))?;
+            roots.add(cert)?;
+        }
+        builder.with_client_cert_verifier(
+            WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider).build()?,
+        )
+    }
+    .with_single_cert(chain, key)?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+pub async fn accept(
+    acceptor: &TlsAcceptor,
+    stream: TcpStream,
+    local_addr: SocketAddr,
+) -> ResultType<Stream> {
+    let tls = crate::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await??;
+    Ok(Stream::Tcp(FramedStream::from(tls, local_addr)))
+}
+
+fn connector(settings: &ClientSettings) -> ResultType<TlsConnector> {
+    let provider = provider();
+    let builder = rustls::ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(PinnedCert {
+            pins: settings.pins.clone(),
+            provider,
+        }));
+    let pem = read_pem(&settings.cert)?;
+    let config = if pem.is_empty() {
+        builder.with_no_client_auth()
+    } else {
+        let (chain, key) = parse_certified_key(&pem)?;
+        builder.with_client_auth_cert(chain, key)?
+    };
+    Ok(TlsConnector::from(Arc::new(config)))
+}
+
+/// Connects to the direct access of `addr` over TLS, not through the proxy.
+/// Returns the fingerprint of the certificate of the peer too, to be pinned if there are no pins.
+pub async fn connect(
+    addr: &str,
+    ms_timeout: u64,
+    settings: &ClientSettings,
+) -> ResultType<(Stream, String)> {
+    let addrs = happy_eyeballs::resolve(addr).await?;
+    let stream = happy_eyeballs::connect(&addrs, None, ms_timeout).await?;
+    stream.set_nodelay(true).ok();
+    let local_addr = stream.local_addr()?;
+    let server_name = ServerName::try_from(SERVER_NAME)?;
+    let tls = crate::timeout(
+        ms_timeout,
+        connector(settings)?.connect(server_name, stream),
+    )
+    .await??;
+    let Some(cert) = tls.get_ref().1.peer_certificates().and_then(|c| c.first()) else {
+        bail!("No certificate of the direct access");
+    };
+    let pin = fingerprint(cert.as_ref());
+    Ok((Stream::Tcp(FramedStream::from(tls, local_addr)), pin))
+}
+
+// Any certificate if there are no pins, on the first connection only as it is pinned then.
+// The handshake signatures are still checked.
+#[derive(Debug)]
+struct PinnedCert {
+    pins: Vec<String>,
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for PinnedCert {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if !self.pins.is_empty() && !is_pinned(&self.pins, end_entity) {
+            return Err(rustls::Error::General(
+                "The certificate of the direct access is not pinned".to_owned(),
+            ));
+        }
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_proof() {
+        let nonce = [7u8; NONCE_LEN];
+        let p = proof("secret", &nonce);
+        assert!(verify("secret", &nonce, &p));
+        assert!(!verify("Secret", &nonce, &p));
+        assert!(!verify("secret", &[8u8; NONCE_LEN], &p));
+        assert!(!verify("secret", &nonce, &p[1..]));
+    }
+
+    #[test]
+    fn test_bind_addrs() {
+        assert!(bind_addrs(" ", 21118).unwrap().is_empty());
+        assert_eq!(
+            bind_addrs("10.0.0.2, ::1", 21118).unwrap(),
+            vec![
+                "10.0.0.2:21118".parse().unwrap(),
+                "[::1]:21118".parse().unwrap()
+            ]
+        );
+        assert!(bind_addrs("10.0.0.256", 21118).is_err());
+    }
+
+    #[test]
+    fn test_rate_limiter() {
+        let mut limiter = RateLimiter::default();
+        let ip: IpAddr = "10.0.0.2".parse().unwrap();
+        for _ in 0..MAX_ATTEMPTS {
+            assert!(limiter.allow(ip));
+        }
+        assert!(!limiter.allow(ip));
+        assert!(!limiter.allow("::ffff:10.0.0.2".parse().unwrap()));
+        assert!(limiter.allow("10.0.0.3".parse().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn test_tls() {
+        let cert = rcgen::generate_simple_self_signed(vec![SERVER_NAME.to_owned()]).unwrap();
+        let pem = format!("{}{}", cert.cert.pem(), cert.key_pair.serialize_pem());
+        let (chain, key) = parse_certified_key(&pem).unwrap();
+        let pin = fingerprint(chain[0].as_ref());
+        let settings = ClientSettings {
+            tls: true,
+            token: "secret".to_owned(),
+            pins: parse_pins(&pin),
+            ..Default::default()
+        };
+        let config = rustls::ServerConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(chain, key)
+            .unwrap();
+        let acceptor = TlsAcceptor::from(Arc::new(config));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = accept(&acceptor, stream, addr).await.unwrap();
+            challenge(&mut stream, "secret").await
+        });
+        let (mut stream, peer_pin) = connect(&addr.to_string(), 3000, &settings).await.unwrap();
+        assert_eq!(peer_pin, pin);
+        let bytes = stream.next_timeout(3000).await.unwrap().unwrap();
+        let Some(message::Union::DirectAccess(da)) =
+            Message::parse_from_bytes(&bytes).unwrap().union
+        else {
+            panic!("no challenge");
+        };
+        assert_eq!(da.nonce.len(), NONCE_LEN);
+        stream.send(&answer(&da, &settings.token)).await.unwrap();
+        assert!(server.await.unwrap().is_ok());
+    }
+
+    #[test]
+    fn test_client_settings() {
+        assert_eq!(
+            ClientSettings::from_options(&Default::default()),
+            Default::default()
+        );
+        let options = [
+            (OPTION_DIRECT_ACCESS_TLS, "Y"),
+            (OPTION_DIRECT_ACCESS_TOKEN, " secret "),
+            (OPTION_DIRECT_ACCESS_PINS, "AA:bb"),
+        ]
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+        let settings = ClientSettings::from_options(&options);
+        assert!(settings.tls);
+        assert_eq!(settings.token, "secret");
+        assert_eq!(settings.pins, vec!["aabb".to_owned()]);
+        assert!(settings.cert.is_empty());
+    }
+}
@@ -0,0 +1,352 @@
+// Dual-stack connecting of RFC 8305 (happy eyeballs v2): the addresses of a host are tried
+// alternately by family, IPv6 first, the next one started if the previous has not connected
+// within the connection attempt delay, and the first connected wins.
+// On an IPv6-only host, the IPv4 addresses are synthesized with the NAT64 prefix discovered
+// by the DNS64 of RFC 7050, instead of asking nip.io.
+// Over udp, the same request goes to the addresses alike and the first answering wins.
+
+use crate::{config::Config, tcp::new_socket, ResultType};
+use futures::{stream::FuturesUnordered, Future, StreamExt};
+use std::{
+    collections::VecDeque,
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tokio::net::{lookup_host, TcpStream, ToSocketAddrs, UdpSocket};
+
+// The recommended value of RFC 8305.
+const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+// The discovered NAT64 prefix is kept so long, the network may change.
+const NAT64_TTL: Duration = Duration::from_secs(600);
+// The routes are checked again after this, asked for on each connection.
+const ROUTE_TTL: Duration = Duration::from_secs(60);
+// The well-known name of RFC 7050, resolving to these only by a DNS64.
+const IPV4ONLY_ARPA: &str = "ipv4only.arpa:0";
+const IPV4ONLY_ADDRS: [Ipv4Addr; 2] =
+    [Ipv4Addr::new(192, 0, 0, 170), Ipv4Addr::new(192, 0, 0, 171)];
+
+lazy_static::lazy_static! {
+    static ref NAT64_PREFIX: Mutex<Option<(Instant, Option<[u8; 12]>)>> = Default::default();
+    // IPv4 and IPv6.
+    static ref ROUTES: Mutex<[Option<(Instant, bool)>; 2]> = Default::default();
+}
+
+/// "IPv4" or "IPv6", the family of `addr` as reported.
+pub fn family(addr: &SocketAddr) -> &'static str {
+    match addr.ip() {
+        IpAddr::V6(ip) if ip.to_ipv4_mapped().is_none() => "IPv6",
+        _ => "IPv4",
+    }
+}
+
+/// Whether this host has a route to the internet over IPv6 or IPv4, nothing is sent.
+pub async fn has_route(ipv6: bool) -> bool {
+    if let Some((at, route)) = ROUTES.lock().unwrap()[ipv6 as usize] {
+        if at.elapsed() < ROUTE_TTL {
+            return route;
+        }
+    }
+    let (any, public): (SocketAddr, SocketAddr) = if ipv6 {
+        (
+            (Ipv6Addr::UNSPECIFIED, 0).into(),
+            (
+                Ipv6Addr::new(0x2001, 0x4860, 0x4860, 0, 0, 0, 0, 0x8888),
+                53,
+            )
+                .into(),
+        )
+    } else {
+        (
+            (Ipv4Addr::UNSPECIFIED, 0).into(),
+            (Ipv4Addr::new(8, 8, 8, 8), 53).into(),
+        )
+    };
+    let route = match UdpSocket::bind(any).await {
+        Ok(socket) => socket.connect(public).await.is_ok(),
+        Err(_) => false,
+    };
+    ROUTES.lock().unwrap()[ipv6 as usize] = Some((Instant::now(), route));
+    route
+}
+
+/// The addresses alternately by family, IPv6 first, without duplicates.
+pub fn interleave(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let (mut v6, mut v4): (VecDeque<_>, VecDeque<_>) = addrs.into_iter().partition(|a| a.is_ipv6());
+    let mut res = Vec::with_capacity(v6.len() + v4.len());
+    while !v6.is_empty() || !v4.is_empty() {
+        for addr in v6.pop_front().into_iter().chain(v4.pop_front()) {
+            if !res.contains(&addr) {
+                res.push(addr);
+            }
+        }
+    }
+    res
+}
+
+/// The IPv6 address of `ip` behind the NAT64 of the /96 `prefix`, as RFC 6052.
+pub fn synthesize(prefix: [u8; 12], ip: Ipv4Addr) -> Ipv6Addr {
+    let mut octets = [0u8; 16];
+    octets[..12].copy_from_slice(&prefix);
+    octets[12..].copy_from_slice(&ip.octets());
+    Ipv6Addr::from(octets)
+}
+
+/// The NAT64 prefix of the network, None without DNS64.
+pub async fn nat64_prefix() -> Option<[u8; 12]> {
+    if let Some((at, prefix)) = *NAT64_PREFIX.lock().unwrap() {
+        if at.elapsed() < NAT64_TTL {
+            return prefix;
+        }
+    }
+    let prefix = lookup_host(IPV4ONLY_ARPA).await.ok().and_then(|mut addrs| {
+        addrs.find_map(|addr| match addr.ip() {
+            IpAddr::V6(ip) => {
+                let octets = ip.octets();
+                let v4 = Ipv4Addr::new(octets[12], octets[13], octets[14], octets[15]);
+                if IPV4ONLY_ADDRS.contains(&v4) {
+                    let mut prefix = [0u8; 12];
+                    prefix.copy_from_slice(&octets[..12]);
+                    Some(prefix)
+                } else {
+                    None
+                }
+            }
+            IpAddr::V4(_) => None,
+        })
+    });
+    if let Some(prefix) = prefix {
+        log::info!(
+            "NAT64 prefix: {}/96",
+            synthesize(prefix, Ipv4Addr::UNSPECIFIED)
+        );
+    }
+    *NAT64_PREFIX.lock().unwrap() = Some((Instant::now(), prefix));
+    prefix
+}
+
+/// `addr` behind the NAT64 of the network if it is a public IPv4 address.
+pub async fn nat64_addr(addr: SocketAddr) -> Option<SocketAddr> {
+    match addr.ip() {
+        IpAddr::V4(ip) if is_public(ip) => {
+            let prefix = nat64_prefix().await?;
+            Some(SocketAddr::new(synthesize(prefix, ip).into(), addr.port()))
+        }
+        _ => None,
+    }
+}
+
+fn is_public(ip: Ipv4Addr) -> bool {
+    !(ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast())
+}
+
+/// The addresses of `target` in the order of trying them, with NAT64 on an IPv6-only host.
+pub async fn resolve<T: ToSocketAddrs>(target: T) -> io::Result<Vec<SocketAddr>> {
+    let mut addrs: Vec<SocketAddr> = lookup_host(target).await?.collect();
+    if addrs.iter().any(|a| a.is_ipv4() && !a.ip().is_loopback())
+        && !addrs.iter().any(|a| a.is_ipv6())
+        && !has_route(false).await
+        && has_route(true).await
+    {
+        let mut synthesized = Vec::with_capacity(addrs.len());
+        for addr in addrs {
+            synthesized.push(nat64_addr(addr).await.unwrap_or(addr));
+        }
+        addrs = synthesized;
+    }
+    Ok(interleave(addrs))
+}
+
+async fn attempt(
+    addr: SocketAddr,
+    local: Option<SocketAddr>,
+    ms_timeout: u64,
+) -> ResultType<TcpStream> {
+    let local = local.unwrap_or_else(|| Config::get_any_listen_addr(addr.is_ipv4()));
+    let socket = new_socket(local, true)?;
+    Ok(crate::timeout(ms_timeout, socket.connect(addr)).await??)
+}
+
+async fn attempt_udp(
+    addr: SocketAddr,
+    request: &[u8],
+    ms_timeout: u64,
+) -> ResultType<(UdpSocket, Vec<u8>)> {
+    let socket = UdpSocket::bind(Config::get_any_listen_addr(addr.is_ipv4())).await?;
+    socket.send_to(request, addr).await?;
+    let mut buf = vec![0u8; 64 * 1024];
+    let n = crate::timeout(ms_timeout, async {
+        loop {
+            let (n, from) = socket.recv_from(&mut buf).await?;
+            if from == addr {
+                return Ok::<_, io::Error>(n);
+            }
+        }
+    })
+    .await??;
+    buf.truncate(n);
+    Ok((socket, buf))
+}
+
+/// Races the connections to `addrs` in their order, each within `ms_timeout`.
+/// Bound to `local`, only the addresses of its family are tried.
+pub async fn connect(
+    addrs: &[SocketAddr],
+    local: Option<SocketAddr>,
+    ms_timeout: u64,
+) -> ResultType<TcpStream> {
+    let pending = addrs
+        .iter()
+        .filter(|a| match local {
+            Some(l) => l.is_ipv4() == a.is_ipv4(),
+            None => true,
+        })
+        .cloned()
+        .collect();
+    let (_, stream) = race(pending, |addr| attempt(addr, local, ms_timeout)).await?;
+    Ok(stream)
+}
+
+/// Races `request` over udp to `addrs` in their order, each answered within `ms_timeout`.
+/// The socket and the address of the first answer, with the answer.
+pub async fn race_udp(
+    addrs: &[SocketAddr],
+    request: &[u8],
+    ms_timeout: u64,
+) -> ResultType<(UdpSocket, SocketAddr, Vec<u8>)> {
+    let pending = addrs.iter().cloned().collect();
+    let (addr, (socket, answer)) =
+        race(pending, |addr| attempt_udp(addr, request, ms_timeout)).await?;
+    Ok((socket, addr, answer))
+}
+
+async fn race<T, F, Fut>(
+    mut pending: VecDeque<SocketAddr>,
+    attempt: F,
+) -> ResultType<(SocketAddr, T)>
+where
+    F: Fn(SocketAddr) -> Fut,
+    Fut: Future<Output = ResultType<T>>,
+{
+    let mut attempts = FuturesUnordered::new();
+    let mut last_err = None;
+    let start = Instant::now();
+    let mut next = pending.pop_front();
+    loop {
+        if let Some(addr) = next.take() {
+            let fut = attempt(addr);
+            attempts.push(async move { (addr, fut.await) });
+        }
+        tokio::select! {
+            res = attempts.next() => match res {
+                Some((addr, Ok(res))) => {
+                    log::debug!(
+                        "Reached {} over {} in {:?}",
+                        addr,
+                        family(&addr),
+                        start.elapsed()
+                    );
+                    return Ok((addr, res));
+                }
+                Some((addr, Err(err))) => {
+                    log::debug!("Failed to reach {}: {}", addr, err);
+                    last_err = Some(err);
+                    next = pending.pop_front();
+                }
+                None => break,
+            },
+            _ = tokio::time::sleep(CONNECTION_ATTEMPT_DELAY), if !pending.is_empty() => {
+                next = pending.pop_front();
+            }
+        }
+        if next.is_none() && attempts.is_empty() {
+            break;
+        }
+    }
+    Err(last_err.unwrap_or_else(|| anyhow::anyhow!("No address to connect")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn test_interleave() {
+        let a4: SocketAddr = "1.1.1.1:1".parse().unwrap();
+        let b4: SocketAddr = "2.2.2.2:1".parse().unwrap();
+        let c4: SocketAddr = "3.3.3.3:1".parse().unwrap();
+        let a6: SocketAddr = "[2001:db8::1]:1".parse().unwrap();
+        let b6: SocketAddr = "[2001:db8::2]:1".parse().unwrap();
+        assert_eq!(
+            interleave(vec![a4, b4, a6, c4, b6, a4]),
+            vec![a6, a4, b6, b4, c4]
+        );
+        assert_eq!(interleave(vec![a4, b4]), vec![a4, b4]);
+        assert!(interleave(vec![]).is_empty());
+    }
+
+    #[test]
+    fn test_synthesize() {
+        let prefix = [0, 0x64, 0xff, 0x9b, 0, 0, 0, 0, 0, 0, 0, 0];
+        assert_eq!(
+            synthesize(prefix, Ipv4Addr::new(192, 0, 2, 33)),
+            "64:ff9b::c000:221".parse::<Ipv6Addr>().unwrap()
+        );
+        assert_eq!(family(&"[64:ff9b::c000:221]:1".parse().unwrap()), "IPv6");
+        assert_eq!(family(&"[::ffff:1.1.1.1]:1".parse().unwrap()), "IPv4");
+        assert_eq!(family(&"1.1.1.1:1".parse().unwrap()), "IPv4");
+        assert!(!is_public(Ipv4Addr::new(192, 168, 1, 1)));
+        assert!(is_public(Ipv4Addr::new(1, 1, 1, 1)));
+    }
+
+    #[tokio::test]
+    async fn test_connect() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        // A refused one first, then one not answering within the delay.
+        let refused = {
+            let l = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            l.local_addr().unwrap()
+        };
+        let blackhole: SocketAddr = "192.0.2.1:9".parse().unwrap();
+        let start = Instant::now();
+        let stream = connect(&[refused, blackhole, addr], None, 3000)
+            .await
+            .unwrap();
+        assert_eq!(stream.peer_addr().unwrap(), addr);
+        assert!(start.elapsed() < Duration::from_secs(2));
+        assert!(connect(&[refused], None, 1000).await.is_err());
+        // Bound to IPv6, the IPv4 ones are not tried.
+        let local: SocketAddr = "[::]:0".parse().unwrap();
+        assert!(connect(&[addr], Some(local), 1000).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_race_udp() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 64];
+            while let Ok((n, from)) = server.recv_from(&mut buf).await {
+                server.send_to(&buf[..n], from).await.ok();
+            }
+        });
+        // One not answering within the delay first.
+        let blackhole: SocketAddr = "192.0.2.1:9".parse().unwrap();
+        let start = Instant::now();
+        let (socket, from, answer) = race_udp(&[blackhole, addr], b"register", 3000)
+            .await
+            .unwrap();
+        assert_eq!(from, addr);
+        assert_eq!(answer, b"register");
+        assert!(socket.local_addr().unwrap().is_ipv4());
+        assert!(start.elapsed() < Duration::from_secs(2));
+        assert!(race_udp(&[blackhole], b"register", 500).await.is_err());
+    }
+}
@@ -18,6 +18,7 @@ use std::{
 pub use tokio;
 pub use tokio_util;
 pub mod proxy;
+pub mod proxy_resolver;
 pub mod socket_client;
 pub mod tcp;
 pub mod udp;
@@ -58,8 +59,13 @@ pub use uuid;
 pub mod fingerprint;
 pub use flexi_logger;
 pub mod websocket;
+pub mod quic;
+pub mod happy_eyeballs;
+pub mod direct_access;
 pub mod stream;
 pub use stream::Stream;
+#[cfg(any(test, feature = "netsim"))]
+pub mod netsim;
 pub use whoami;
 
 pub type SessionID = uuid::Uuid;
@@ -0,0 +1,437 @@
+// Simulated bad networks for the tests, enabled by the `netsim` feature.
+//
+// `stream_pair` gives two connected `Stream`s, e.g. a controlling and a controlled side,
+// and `udp_pair` two connected udp sockets, e.g. for kcp, behind the conditions of a `Link`.
+// `wrap_stream` and `wrap_udp` put an existing stream or connected udp socket behind one.
+// The conditions can be changed while the streams are in use, to simulate a short outage
+// or a network getting worse. The random delays and losses of a link are seeded, the runs of
+// a test are alike.
+// The streams go well with the paused time of tokio, the udp sockets need the real time.
+// The streams are reliable like tcp, the data is delayed but neither lost nor reordered,
+// an outage holds it back until the link is up again. The udp packets are lost or reordered.
+
+use crate::{tcp::FramedStream, ResultType, Stream};
+use bytes::Bytes;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{
+    collections::VecDeque,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt, DuplexStream, ReadHalf, WriteHalf},
+    net::UdpSocket,
+    sync::mpsc,
+    time::Instant,
+};
+
+const BUF_SIZE: usize = 16 * 1024;
+// The router drops the udp packets which would wait longer, as a real one with a full queue.
+const MAX_QUEUE_DELAY: Duration = Duration::from_millis(200);
+// The extra delay of a reordered packet, it arrives after the next ones.
+const REORDER_DELAY: Duration = Duration::from_millis(20);
+const DEFAULT_SEED: u64 = 1;
+
+#[derive(Debug, Clone, Default)]
+pub struct NetConditions {
+    /// One-way delay.
+    pub latency: Duration,
+    /// Random extra delay, up to this.
+    pub jitter: Duration,
+    /// Bytes per second, 0 for unlimited.
+    pub bandwidth: u64,
+    /// The probability of losing a udp packet, 0 to 1.
+    pub loss: f64,
+    /// The probability of delaying a udp packet behind the next ones, 0 to 1.
+    pub reorder: f64,
+}
+
+impl NetConditions {
+    pub fn lan() -> Self {
+        Self {
+            latency: Duration::from_millis(1),
+            ..Default::default()
+        }
+    }
+
+    /// A congested mobile network.
+    pub fn mobile() -> Self {
+        Self {
+            latency: Duration::from_millis(60),
+            jitter: Duration::from_millis(150),
+            bandwidth: 256 * 1024,
+            loss: 0.03,
+            reorder: 0.02,
+        }
+    }
+
+    /// A stable long distance link, e.g. satellite.
+    pub fn satellite() -> Self {
+        Self {
+            latency: Duration::from_millis(300),
+            jitter: Duration::from_millis(10),
+            bandwidth: 1024 * 1024,
+            loss: 0.005,
+            reorder: 0.0,
+        }
+    }
+
+    /// A random one-way delay, without the time to send the data.
+    fn sample_delay(&self, rng: &mut StdRng) -> Duration {
+        self.latency + self.jitter.mul_f64(rng.gen::<f64>())
+    }
+
+    fn transmit_time(&self, len: usize) -> Duration {
+        if self.bandwidth == 0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(len as f64 / self.bandwidth as f64)
+        }
+    }
+}
+
+#[derive(Debug)]
+struct LinkState {
+    conditions: NetConditions,
+    outage_until: Option<Instant>,
+    rng: StdRng,
+}
+
+/// The shared conditions of a simulated link, in both directions.
+#[derive(Debug, Clone)]
+pub struct Link(Arc<Mutex<LinkState>>);
+
+impl Link {
+    pub fn new(conditions: NetConditions) -> Self {
+        Self::with_seed(conditions, DEFAULT_SEED)
+    }
+
+    /// Another run of the random delays and losses than `new`.
+    pub fn with_seed(conditions: NetConditions, seed: u64) -> Self {
+        Self(Arc::new(Mutex::new(LinkState {
+            conditions,
+            outage_until: None,
+            rng: StdRng::seed_from_u64(seed),
+        })))
+    }
+
+    pub fn conditions(&self) -> NetConditions {
+        self.0.lock().unwrap().conditions.clone()
+    }
+
+    pub fn set_conditions(&self, conditions: NetConditions) {
+        self.0.lock().unwrap().conditions = conditions;
+    }
+
+    /// Nothing goes through for `duration` from now.
+    pub fn outage(&self, duration: Duration) {
+        self.0.lock().unwrap().outage_until = Some(Instant::now() + duration);
+    }
+
+    fn is_down(&self, now: Instant) -> bool {
+        self.0
+            .lock()
+            .unwrap()
+            .outage_until
+            .is_some_and(|until| until > now)
+    }
+
+    // The time `len` bytes arrive, `free_at` is when the sender has sent the previous data.
+    fn schedule(&self, len: usize, free_at: &mut Instant) -> (Instant, NetConditions) {
+        let mut state = self.0.lock().unwrap();
+        let state = &mut *state;
+        let mut start = Instant::now().max(*free_at);
+        if let Some(until) = state.outage_until {
+            start = start.max(until);
+        }
+        *free_at = start + state.conditions.transmit_time(len);
+        let at = *free_at + state.conditions.sample_delay(&mut state.rng);
+        (at, state.conditions.clone())
+    }
+
+    // Whether an event of the probability `p` happens, e.g. a loss.
+    fn happens(&self, p: f64) -> bool {
+        self.0.lock().unwrap().rng.gen::<f64>() < p
+    }
+}
+
+// The data of one direction waiting to arrive, in order.
+#[derive(Default)]
+struct Queue {
+    data: VecDeque<(Instant, Bytes)>,
+    free_at: Option<Instant>,
+    last: Option<Instant>,
+}
+
+impl Queue {
+    fn push(&mut self, link: &Link, data: Bytes) {
+        let mut free_at = self.free_at.unwrap_or_else(Instant::now);
+        let (at, _) = link.schedule(data.len(), &mut free_at);
+        self.free_at = Some(free_at);
+        // The jitter does not reorder a stream.
+        let at = self.last.map_or(at, |last| last.max(at));
+        self.last = Some(at);
+        self.data.push_back((at, data));
+    }
+
+    fn next_at(&self) -> Option<Instant> {
+        self.data.front().map(|(at, _)| *at)
+    }
+
+    fn pop_due(&mut self, now: Instant) -> Option<Bytes> {
+        if self.next_at()? <= now {
+            self.data.pop_front().map(|(_, data)| data)
+        } else {
+            None
+        }
+    }
+}
+
+/// Two connected streams through a simulated link, `addr` is the local address they report.
+pub fn stream_pair(conditions: NetConditions, addr: SocketAddr) -> (Stream, Stream, Link) {
+    let link = Link::new(conditions);
+    let (a, a_remote) = tokio::io::duplex(BUF_SIZE);
+    let (b, b_remote) = tokio::io::duplex(BUF_SIZE);
+    let (a_read, a_write) = tokio::io::split(a_remote);
+    let (b_read, b_write) = tokio::io::split(b_remote);
+    tokio::spawn(pump(a_read, b_write, link.clone()));
+    tokio::spawn(pump(b_read, a_write, link.clone()));
+    (
+        Stream::Tcp(FramedStream::from(a, addr)),
+        Stream::Tcp(FramedStream::from(b, addr)),
+        link,
+    )
+}
+
+// Forwards the data in one direction, in order.
+async fn pump(mut src: ReadHalf<DuplexStream>, mut dst: WriteHalf<DuplexStream>, link: Link) {
+    let (tx, mut rx) = mpsc::unbounded_channel::<(Instant, Bytes)>();
+    tokio::spawn(async move {
+        while let Some((at, data)) = rx.recv().await {
+            tokio::time::sleep_until(at).await;
+            if dst.write_all(&data).await.is_err() {
+                break;
+            }
+        }
+        dst.shutdown().await.ok();
+    });
+    let mut free_at = Instant::now();
+    let mut last = Instant::now();
+    let mut buf = vec![0; BUF_SIZE];
+    loop {
+        let n = match src.read(&mut buf).await {
+            Ok(n) if n > 0 => n,
+            _ => break,
+        };
+        let (at, _) = link.schedule(n, &mut free_at);
+        // The jitter does not reorder a stream.
+        last = last.max(at);
+        if tx.send((last, Bytes::copy_from_slice(&buf[..n]))).is_err() {
+            break;
+        }
+    }
+}
+
+/// `stream` behind a simulated link, e.g. a connection to a real peer. The messages are
+/// forwarded as they are, wrap it before the key exchange for the encrypted ones.
+pub fn wrap_stream(stream: Stream, link: Link) -> Stream {
+    let addr = stream.local_addr();
+    let (outer, outer_remote) = tokio::io::duplex(BUF_SIZE);
+    tokio::spawn(forward(
+        stream,
+        FramedStream::from(outer_remote, addr),
+        link,
+    ));
+    Stream::Tcp(FramedStream::from(outer, addr))
+}
+
+async fn forward(mut inner: Stream, mut outer: FramedStream, link: Link) {
+    let (mut up, mut down) = (Queue::default(), Queue::default());
+    loop {
+        let next_at = match (up.next_at(), down.next_at()) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        let due = next_at.unwrap_or_else(Instant::now);
+        tokio::select! {
+            res = inner.next() => match res {
+                Some(Ok(data)) => down.push(&link, data.freeze()),
+                _ => break,
+            },
+            res = outer.next() => match res {
+                Some(Ok(data)) => up.push(&link, data.freeze()),
+                _ => break,
+            },
+            _ = tokio::time::sleep_until(due), if next_at.is_some() => {
+                let now = Instant::now();
+                while let Some(data) = up.pop_due(now) {
+                    if inner.send_bytes(data).await.is_err() {
+                        return;
+                    }
+                }
+                while let Some(data) = down.pop_due(now) {
+                    if outer.send_bytes(data).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Two udp sockets on localhost connected to each other through a simulated link.
+pub async fn udp_pair(conditions: NetConditions) -> ResultType<(UdpSocket, UdpSocket, Link)> {
+    let link = Link::new(conditions);
+    let a = UdpSocket::bind("127.0.0.1:0").await?;
+    let b = UdpSocket::bind("127.0.0.1:0").await?;
+    a.connect(b.local_addr()?).await?;
+    b.connect(a.local_addr()?).await?;
+    let a = wrap_udp(a, link.clone()).await?;
+    Ok((a, b, link))
+}
+
+/// The connected `socket` behind a simulated link, the returned socket sends to and receives
+/// from its peer, e.g. a real one.
+pub async fn wrap_udp(socket: UdpSocket, link: Link) -> ResultType<UdpSocket> {
+    let socket = Arc::new(socket);
+    let outer = UdpSocket::bind("127.0.0.1:0").await?;
+    let relay_outer = Arc::new(UdpSocket::bind("127.0.0.1:0").await?);
+    outer.connect(relay_outer.local_addr()?).await?;
+    relay_outer.connect(outer.local_addr()?).await?;
+    tokio::spawn(relay(relay_outer.clone(), socket.clone(), link.clone()));
+    tokio::spawn(relay(socket, relay_outer, link));
+    Ok(outer)
+}
+
+// Forwards the packets received by `from` to the peer of `to`.
+async fn relay(from: Arc<UdpSocket>, to: Arc<UdpSocket>, link: Link) {
+    let mut free_at = Instant::now();
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let n = match from.recv(&mut buf).await {
+            Ok(n) => n,
+            Err(_) => break,
+        };
+        let now = Instant::now();
+        if link.is_down(now) || free_at > now + MAX_QUEUE_DELAY {
+            continue;
+        }
+        let (mut at, conditions) = link.schedule(n, &mut free_at);
+        if link.happens(conditions.loss) {
+            continue;
+        }
+        if link.happens(conditions.reorder) {
+            at += REORDER_DELAY + conditions.jitter;
+        }
+        let to = to.clone();
+        let data = buf[..n].to_vec();
+        tokio::spawn(async move {
+            tokio::time::sleep_until(at).await;
+            to.send(&data).await.ok();
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr() -> SocketAddr {
+        "127.0.0.1:0".parse().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_stream_outage() {
+        let conditions = NetConditions {
+            latency: Duration::from_millis(20),
+            jitter: Duration::from_millis(30),
+            bandwidth: 1024 * 1024,
+            ..Default::default()
+        };
+        let (mut a, mut b, link) = stream_pair(conditions, addr());
+        let start = Instant::now();
+        a.send_bytes(Bytes::from_static(b"hello")).await.unwrap();
+        let msg = b.next_timeout(1000).await.unwrap().unwrap();
+        assert_eq!(&msg[..], b"hello");
+        assert!(start.elapsed() >= Duration::from_millis(20));
+
+        // The session survives a short outage, nothing is lost or reordered.
+        link.outage(Duration::from_millis(300));
+        let start = Instant::now();
+        for i in 0..50u8 {
+            a.send_bytes(Bytes::from(vec![i; 1000])).await.unwrap();
+        }
+        for i in 0..50u8 {
+            let msg = b.next_timeout(3000).await.unwrap().unwrap();
+            assert_eq!(msg[0], i);
+        }
+        assert!(start.elapsed() >= Duration::from_millis(300));
+        b.send_bytes(Bytes::from_static(b"back")).await.unwrap();
+        assert_eq!(&a.next_timeout(1000).await.unwrap().unwrap()[..], b"back");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_wrap_stream() {
+        let (a, mut b, _) = stream_pair(NetConditions::default(), addr());
+        let link = Link::new(NetConditions {
+            latency: Duration::from_millis(50),
+            ..Default::default()
+        });
+        let mut a = wrap_stream(a, link.clone());
+        let start = Instant::now();
+        a.send_bytes(Bytes::from_static(b"ping")).await.unwrap();
+        assert_eq!(&b.next().await.unwrap().unwrap()[..], b"ping");
+        b.send_bytes(Bytes::from_static(b"pong")).await.unwrap();
+        assert_eq!(&a.next().await.unwrap().unwrap()[..], b"pong");
+        assert!(start.elapsed() >= Duration::from_millis(100));
+
+        link.outage(Duration::from_secs(1));
+        a.send_bytes(Bytes::from_static(b"held")).await.unwrap();
+        assert!(b.next_timeout(500).await.is_none());
+        assert_eq!(&b.next().await.unwrap().unwrap()[..], b"held");
+    }
+
+    #[test]
+    fn test_seed() {
+        let sample = |link: &Link| {
+            let mut free_at = Instant::now();
+            (0..10)
+                .map(|_| link.schedule(0, &mut free_at).0 - free_at)
+                .collect::<Vec<_>>()
+        };
+        let conditions = NetConditions::mobile();
+        assert_eq!(
+            sample(&Link::new(conditions.clone())),
+            sample(&Link::new(conditions.clone()))
+        );
+        assert_ne!(
+            sample(&Link::new(conditions.clone())),
+            sample(&Link::with_seed(conditions, 2))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_udp_loss() {
+        let conditions = NetConditions {
+            latency: Duration::from_millis(5),
+            jitter: Duration::from_millis(5),
+            loss: 0.2,
+            reorder: 0.1,
+            ..Default::default()
+        };
+        let (a, b, _link) = udp_pair(conditions).await.unwrap();
+        for i in 0..200u8 {
+            a.send(&[i]).await.unwrap();
+        }
+        let mut received = vec![];
+        let mut buf = [0u8; 16];
+        while let Ok(Ok(n)) =
+            tokio::time::timeout(Duration::from_millis(200), b.recv(&mut buf)).await
+        {
+            received.push(buf[..n][0]);
+        }
+        assert!(received.len() > 120 && received.len() < 190);
+        assert!(received.windows(2).any(|w| w[0] > w[1]));
+    }
+}
@@ -354,6 +354,24 @@ impl Proxy {
         target: T,
         local_addr: Option<SocketAddr>,
     ) -> ResultType<FramedStream>
+    where
+        T: IntoTargetAddr<'t>,
+    {
+        let (stream, addr) = self.connect_raw(target, local_addr).await?;
+        Ok(FramedStream(
+            Framed::new(stream, BytesCodec::new()),
+            addr,
+            None,
+            0,
+        ))
+    }
+
+    /// The tunnel to `target` through the proxy, and the local address of it.
+    pub async fn connect_raw<'t, T>(
+        self,
+        target: T,
+        local_addr: Option<SocketAddr>,
+    ) -> ResultType<(DynTcpStream, SocketAddr)>
     where
         T: IntoTargetAddr<'t>,
     {
@@ -380,23 +398,13 @@ impl Proxy {
                 info!("Connect to remote http proxy server: {}", proxy);
                 let stream =
                     super::timeout(self.ms_timeout, self.http_connect(stream, target)).await??;
-                Ok(FramedStream(
-                    Framed::new(DynTcpStream(Box::new(stream)), BytesCodec::new()),
-                    addr,
-                    None,
-                    0,
-                ))
+                Ok((DynTcpStream(Box::new(stream)), addr))
             }
             ProxyScheme::Https { .. } => {
                 info!("Connect to remote https proxy server: {}", proxy);
                 let stream =
                     super::timeout(self.ms_timeout, self.https_connect(stream, target)).await??;
-                Ok(FramedStream(
-                    Framed::new(DynTcpStream(Box::new(stream)), BytesCodec::new()),
-                    addr,
-                    None,
-                    0,
-                ))
+                Ok((DynTcpStream(Box::new(stream)), addr))
             }
             ProxyScheme::Socks5 { .. } => {
                 info!("Connect to remote socket5 proxy server: {}", proxy);
@@ -418,12 +426,7 @@ impl Proxy {
                     )
                     .await??
                 };
-                Ok(FramedStream(
-                    Framed::new(DynTcpStream(Box::new(stream)), BytesCodec::new()),
-                    addr,
-                    None,
-                    0,
-                ))
+                Ok((DynTcpStream(Box::new(stream)), addr))
             }
         };
     }
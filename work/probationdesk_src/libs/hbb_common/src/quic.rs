@@ -0,0 +1,709 @@
+// QUIC transport over a punched udp socket, an alternative to kcp.
+//
+// The messages go on unidirectional streams, the session with the video and audio on the
+// main one, as they govern each other, e.g. `PeerInfo` and `SwitchDisplay` the frames after
+// them. The file transfer and the clipboard, independent bulk data, have their own, so a
+// lost packet of them does not hold back the session.
+// The TLS of QUIC is not trusted, the peer is authenticated by the signed id and the session
+// keys of the handshake on top as on tcp, every channel is sealed with its own keys.
+// The session tickets are kept for the process, a reconnection to the same peer resumes
+// without the full handshake (0-RTT). The early data is sealed by the keys of the handshake
+// on top, a replay to another connection can not be opened.
+// The controlled side listens on an unconnected socket, so the controlling side migrates to
+// a new local address, e.g. from wifi to ethernet, as far as the NAT of the peer lets it in.
+
+use crate::{bail, bytes_codec::BytesCodec, tcp::Encrypt, ResultType};
+use bytes::{Bytes, BytesMut};
+use futures::{ready, FutureExt, SinkExt, StreamExt};
+use quinn::{
+    crypto::rustls::{QuicClientConfig, QuicServerConfig},
+    udp::{RecvMeta, Transmit},
+    AsyncUdpSocket, UdpPoller,
+};
+use rustls::{
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::CryptoProvider,
+    pki_types::{CertificateDer, PrivatePkcs8KeyDer, ServerName, UnixTime},
+    DigitallySignedStruct, SignatureScheme,
+};
+use sodiumoxide::crypto::secretbox::Key;
+use std::{
+    io::{self, Error, IoSliceMut},
+    net::{IpAddr, SocketAddr},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::{io::ReadBuf, net::UdpSocket, sync::mpsc};
+use tokio_util::codec::{FramedRead, FramedWrite};
+
+const SERVER_NAME: &str = "rustdesk";
+const ALPN: &[u8] = b"rustdesk";
+const KEEP_ALIVE: Duration = Duration::from_secs(5);
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+const RECV_QUEUE: usize = 64;
+const ROUTE_CHECK: Duration = Duration::from_secs(2);
+
+const CHANNELS: usize = 3;
+const MAIN: u8 = 0;
+const FILE: u8 = 1;
+const CLIPBOARD: u8 = 2;
+
+lazy_static::lazy_static! {
+    // Shared by the connections.
+    static ref CLIENT_CONFIG: Option<quinn::ClientConfig> = client_config()
+        .map_err(|e| log::error!("Failed to create QUIC client config: {}", e))
+        .ok();
+    static ref SERVER_CONFIG: Option<quinn::ServerConfig> = server_config()
+        .map_err(|e| log::error!("Failed to create QUIC server config: {}", e))
+        .ok();
+}
+
+pub struct QuicFramedStream {
+    _endpoint: quinn::Endpoint,
+    connection: quinn::Connection,
+    local_addr: SocketAddr,
+    senders: Vec<Option<FramedWrite<quinn::SendStream, BytesCodec>>>,
+    // The frames sent as 0-RTT data, to be sent again if the peer rejects it.
+    early: Option<(quinn::ZeroRttAccepted, Vec<(u8, Bytes)>)>,
+    receiver: mpsc::Receiver<(u8, Result<BytesMut, Error>)>,
+    encrypt: Option<Vec<Encrypt>>,
+    raw: bool,
+    send_timeout: u64,
+}
+
+impl QuicFramedStream {
+    /// The controlling side, `socket` is connected to the peer `peer_id`.
+    pub async fn connect(
+        socket: Arc<UdpSocket>,
+        peer_id: &str,
+        ms_timeout: u64,
+    ) -> ResultType<Self> {
+        let Some(config) = CLIENT_CONFIG.clone() else {
+            bail!("QUIC is not available");
+        };
+        let peer = socket.peer_addr()?;
+        let endpoint = new_endpoint(socket, None)?;
+        let connecting = endpoint.connect_with(config, peer, &server_name(peer_id))?;
+        let (connection, early) = match connecting.into_0rtt() {
+            Ok((connection, accepted)) => (connection, Some((accepted, Vec::new()))),
+            Err(connecting) => (crate::timeout(ms_timeout, connecting).await??, None),
+        };
+        tokio::spawn(watch_route(endpoint.clone(), connection.clone()));
+        let mut stream = Self::new(endpoint, connection);
+        stream.early = early;
+        Ok(stream)
+    }
+
+    /// The controlled side, `socket` is not connected, only `peer` is accepted.
+    pub async fn accept(
+        socket: Arc<UdpSocket>,
+        peer: SocketAddr,
+        ms_timeout: u64,
+    ) -> ResultType<Self> {
+        let Some(config) = SERVER_CONFIG.clone() else {
+            bail!("QUIC is not available");
+        };
+        let endpoint = new_endpoint(socket, Some(config))?;
+        let connecting = crate::timeout(ms_timeout, accept_from(&endpoint, peer)).await??;
+        // The server takes the 0-RTT data, its own goes before the handshake completes.
+        let connection = match connecting.into_0rtt() {
+            Ok((connection, _)) => connection,
+            Err(connecting) => crate::timeout(ms_timeout, connecting).await??,
+        };
+        Ok(Self::new(endpoint, connection))
+    }
+
+    fn new(endpoint: quinn::Endpoint, connection: quinn::Connection) -> Self {
+        let (tx, receiver) = mpsc::channel(RECV_QUEUE);
+        tokio::spawn(accept_streams(connection.clone(), tx));
+        let local_addr = endpoint
+            .local_addr()
+            .unwrap_or_else(|_| crate::config::Config::get_any_listen_addr(true));
+        Self {
+            _endpoint: endpoint,
+            connection,
+            local_addr,
+            senders: (0..CHANNELS).map(|_| None).collect(),
+            early: None,
+            receiver,
+            encrypt: None,
+            raw: false,
+            send_timeout: 0,
+        }
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    pub fn set_send_timeout(&mut self, ms: u64) {
+        self.send_timeout = ms;
+    }
+
+    // The raw data, e.g. of port forwarding, is not protobuf, it all goes on the main channel.
+    pub fn set_raw(&mut self) {
+        self.raw = true;
+        self.encrypt = None;
+    }
+
+    pub fn is_secured(&self) -> bool {
+        self.encrypt.is_some()
+    }
+
+    pub fn set_key(&mut self, key: Key) {
+        self.set_encrypt(Encrypt::new(key));
+    }
+
+    pub fn set_encrypt(&mut self, encrypt: Encrypt) {
+        self.encrypt = Some((0..CHANNELS as u8).map(|ch| encrypt.channel(ch)).collect());
+    }
+
+    #[inline]
+    pub async fn send(&mut self, msg: &impl protobuf::Message) -> ResultType<()> {
+        self.send_raw(msg.write_to_bytes()?).await
+    }
+
+    #[inline]
+    pub async fn send_raw(&mut self, msg: Vec<u8>) -> ResultType<()> {
+        let ch = if self.raw { MAIN } else { channel_of(&msg) };
+        let mut msg = msg;
+        let mut rekey = None;
+        if let Some(encrypt) = self.encrypt.as_mut() {
+            let key = &mut encrypt[ch as usize];
+            rekey = key.rekey_frame();
+            msg = key.enc(&msg);
+        }
+        if let Some(frame) = rekey {
+            self.send_on(ch, Bytes::from(frame)).await?;
+        }
+        self.send_on(ch, Bytes::from(msg)).await
+    }
+
+    #[inline]
+    pub async fn send_bytes(&mut self, bytes: Bytes) -> ResultType<()> {
+        self.send_on(MAIN, bytes).await
+    }
+
+    async fn send_on(&mut self, ch: u8, bytes: Bytes) -> ResultType<()> {
+        if self.send_timeout > 0 {
+            crate::timeout(self.send_timeout, self.send_on_stream(ch, bytes)).await??;
+        } else {
+            self.send_on_stream(ch, bytes).await?;
+        }
+        Ok(())
+    }
+
+    async fn send_on_stream(&mut self, ch: u8, bytes: Bytes) -> ResultType<()> {
+        if let Some(accepted) = self
+            .early
+            .as_mut()
+            .and_then(|(accepted, _)| accepted.now_or_never())
+        {
+            self.settle_early(accepted).await?;
+        }
+        if let Some((_, frames)) = self.early.as_mut() {
+            frames.push((ch, bytes.clone()));
+        }
+        self.write_on_stream(ch, bytes).await
+    }
+
+    // The 0-RTT data is confirmed, or rejected and sent again on new streams.
+    async fn settle_early(&mut self, accepted: bool) -> ResultType<()> {
+        let Some((_, frames)) = self.early.take() else {
+            return Ok(());
+        };
+        if !accepted {
+            log::debug!(
+                "QUIC 0-RTT is rejected, {} frames are sent again",
+                frames.len()
+            );
+            self.senders.iter_mut().for_each(|sender| *sender = None);
+            for (ch, bytes) in frames {
+                self.write_on_stream(ch, bytes).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn write_on_stream(&mut self, ch: u8, bytes: Bytes) -> ResultType<()> {
+        let i = ch as usize;
+        if self.senders[i].is_none() {
+            let mut stream = self.connection.open_uni().await?;
+            stream.write_all(&[ch]).await?;
+            self.senders[i] = Some(FramedWrite::new(stream, BytesCodec::new()));
+        }
+        if let Some(sender) = self.senders[i].as_mut() {
+            sender.send(bytes).await?;
+        }
+        Ok(())
+    }
+
+    #[inline]
+    pub async fn next(&mut self) -> Option<Result<BytesMut, Error>> {
+        loop {
+            let res = match self.early.as_mut() {
+                Some((accepted, _)) => tokio::select! {
+                    accepted = accepted => Err(accepted),
+                    res = self.receiver.recv() => Ok(res),
+                },
+                None => Ok(self.receiver.recv().await),
+            };
+            let (ch, mut res) = match res {
+                Ok(res) => res?,
+                Err(accepted) => {
+                    if let Err(err) = self.settle_early(accepted).await {
+                        return Some(Err(Error::new(io::ErrorKind::Other, err.to_string())));
+                    }
+                    continue;
+                }
+            };
+            if let (Ok(bytes), Some(encrypt)) = (res.as_mut(), self.encrypt.as_mut()) {
+                match encrypt[ch as usize].dec(bytes) {
+                    Ok(true) => {}
+                    // A rekeying frame, not for the caller.
+                    Ok(false) => continue,
+                    Err(err) => return Some(Err(err)),
+                }
+            }
+            return Some(res);
+        }
+    }
+
+    #[inline]
+    pub async fn next_timeout(&mut self, ms: u64) -> Option<Result<BytesMut, Error>> {
+        if let Ok(res) = crate::timeout(ms, self.next()).await {
+            res
+        } else {
+            None
+        }
+    }
+}
+
+impl Drop for QuicFramedStream {
+    fn drop(&mut self) {
+        self.connection.close(0u32.into(), b"");
+    }
+}
+
+// The channel by the field number of `Message.union`, the first tag of the message.
+fn channel_of(data: &[u8]) -> u8 {
+    let mut tag = 0u64;
+    for (i, b) in data.iter().take(10).enumerate() {
+        tag |= ((b & 0x7f) as u64) << (7 * i);
+        if b & 0x80 == 0 {
+            return match tag >> 3 {
+                17 | 18 => FILE,
+                16 | 20 | 28 => CLIPBOARD,
+                _ => MAIN,
+            };
+        }
+    }
+    MAIN
+}
+
+/// Whether `data` is the first packet of a QUIC v1 connection, a long header Initial.
+pub fn is_initial(data: &[u8]) -> bool {
+    data.len() > 5 && data[0] & 0xf0 == 0xc0 && data[1..5] == [0, 0, 0, 1]
+}
+
+// The session tickets are kept by the server name, one per peer.
+fn server_name(peer_id: &str) -> String {
+    let id: String = peer_id
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .take(48)
+        .collect();
+    format!("p{}.{}", id.to_ascii_lowercase(), SERVER_NAME)
+}
+
+// Other addresses may reach the unconnected socket, e.g. the rendezvous server.
+async fn accept_from(
+    endpoint: &quinn::Endpoint,
+    peer: SocketAddr,
+) -> ResultType<quinn::Connecting> {
+    loop {
+        let Some(incoming) = endpoint.accept().await else {
+            bail!("QUIC endpoint is closed");
+        };
+        if incoming.remote_address() == peer {
+            return Ok(incoming.accept()?);
+        }
+        log::debug!("Refused QUIC connection from {}", incoming.remote_address());
+        incoming.refuse();
+    }
+}
+
+// Moves the connection to a new socket when the route to the peer goes out another address.
+async fn watch_route(endpoint: quinn::Endpoint, connection: quinn::Connection) {
+    let mut local = route_ip(connection.remote_address()).await;
+    loop {
+        tokio::select! {
+            _ = connection.closed() => break,
+            _ = tokio::time::sleep(ROUTE_CHECK) => {}
+        }
+        let ip = route_ip(connection.remote_address()).await;
+        if ip.is_none() || ip == local {
+            continue;
+        }
+        if let Some(ip) = ip {
+            match rebind(&endpoint, ip).await {
+                Ok(()) => log::info!("QUIC connection migrated to {}", ip),
+                Err(err) => {
+                    log::debug!("Failed to migrate QUIC connection to {}: {}", ip, err);
+                    continue;
+                }
+            }
+        }
+        local = ip;
+    }
+}
+
+// The local address of the route to `peer`, nothing is sent.
+async fn route_ip(peer: SocketAddr) -> Option<IpAddr> {
+    let any = crate::config::Config::get_any_listen_addr(peer.is_ipv4());
+    let socket = UdpSocket::bind(any).await.ok()?;
+    socket.connect(peer).await.ok()?;
+    Some(socket.local_addr().ok()?.ip())
+}
+
+async fn rebind(endpoint: &quinn::Endpoint, ip: IpAddr) -> ResultType<()> {
+    let socket = UdpSocket::bind(SocketAddr::new(ip, 0)).await?;
+    endpoint.rebind_abstract(Arc::new(PunchedSocket(Arc::new(socket))))?;
+    Ok(())
+}
+
+// The first byte of a stream is its channel.
+async fn accept_streams(
+    connection: quinn::Connection,
+    tx: mpsc::Sender<(u8, Result<BytesMut, Error>)>,
+) {
+    while let Ok(mut recv) = connection.accept_uni().await {
+        let tx = tx.clone();
+        tokio::spawn(async move {
+            let mut ch = [0u8; 1];
+            if let Err(e) = recv.read_exact(&mut ch).await {
+                log::debug!("Failed to read QUIC stream header: {}", e);
+                return;
+            }
+            if ch[0] as usize >= CHANNELS {
+                log::debug!("Invalid QUIC channel {}", ch[0]);
+                return;
+            }
+            let mut framed = FramedRead::new(recv, BytesCodec::new());
+            while let Some(res) = framed.next().await {
+                let failed = res.is_err();
+                if tx.send((ch[0], res)).await.is_err() || failed {
+                    break;
+                }
+            }
+        });
+    }
+}
+
+fn new_endpoint(
+    socket: Arc<UdpSocket>,
+    server_config: Option<quinn::ServerConfig>,
+) -> ResultType<quinn::Endpoint> {
+    Ok(quinn::Endpoint::new_with_abstract_socket(
+        quinn::EndpointConfig::default(),
+        server_config,
+        Arc::new(PunchedSocket(socket)),
+        Arc::new(quinn::TokioRuntime),
+    )?)
+}
+
+fn transport_config() -> Arc<quinn::TransportConfig> {
+    let mut config = quinn::TransportConfig::default();
+    config.keep_alive_interval(Some(KEEP_ALIVE));
+    config.max_idle_timeout(IDLE_TIMEOUT.try_into().ok());
+    Arc::new(config)
+}
+
+fn client_config() -> ResultType<quinn::ClientConfig> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let mut crypto = rustls::ClientConfig::builder_with_provider(provider.clone())
+        .with_protocol_versions(&[&rustls::version::TLS13])?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(AnyCert(provider)))
+        .with_no_client_auth();
+    crypto.alpn_protocols = vec![ALPN.to_vec()];
+    crypto.enable_early_data = true;
+    let mut config = quinn::ClientConfig::new(Arc::new(QuicClientConfig::try_from(crypto)?));
+    config.transport_config(transport_config());
+    Ok(config)
+}
+
+fn server_config() -> ResultType<quinn::ServerConfig> {
+    let cert = rcgen::generate_simple_self_signed(vec![SERVER_NAME.to_owned()])?;
+    let key = PrivatePkcs8KeyDer::from(cert.key_pair.serialize_der());
+    let mut crypto = rustls::ServerConfig::builder_with_provider(Arc::new(
+        rustls::crypto::ring::default_provider(),
+    ))
+    .with_protocol_versions(&[&rustls::version::TLS13])?
+    .with_no_client_auth()
+    .with_single_cert(vec![cert.cert.der().clone()], key.into())?;
+    crypto.alpn_protocols = vec![ALPN.to_vec()];
+    // QUIC takes any 0-RTT data or none.
+    crypto.max_early_data_size = u32::MAX;
+    let mut config =
+        quinn::ServerConfig::with_crypto(Arc::new(QuicServerConfig::try_from(crypto)?));
+    config.transport_config(transport_config());
+    config.migration(true);
+    Ok(config)
+}
+
+// Any certificate, the peer is authenticated on top, see above.
+// The handshake signatures are still checked, they prove the key of the certificate.
+#[derive(Debug)]
+struct AnyCert(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for AnyCert {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+// The punched socket of the controlling side is connected to the peer, it can not `send_to`
+// on some platforms. The other sockets are not.
+#[derive(Debug)]
+struct PunchedSocket(Arc<UdpSocket>);
+
+impl AsyncUdpSocket for PunchedSocket {
+    fn create_io_poller(self: Arc<Self>) -> Pin<Box<dyn UdpPoller>> {
+        Box::pin(Poller(self.0.clone()))
+    }
+
+    fn try_send(&self, transmit: &Transmit) -> io::Result<()> {
+        if self.0.peer_addr().is_ok() {
+            self.0.try_send(transmit.contents)?;
+        } else {
+            self.0
+                .try_send_to(transmit.contents, transmit.destination)?;
+        }
+        Ok(())
+    }
+
+    fn poll_recv(
+        &self,
+        cx: &mut Context,
+        bufs: &mut [IoSliceMut<'_>],
+        meta: &mut [RecvMeta],
+    ) -> Poll<io::Result<usize>> {
+        let mut buf = ReadBuf::new(&mut bufs[0][..]);
+        let addr = ready!(self.0.poll_recv_from(cx, &mut buf))?;
+        let len = buf.filled().len();
+        meta[0] = RecvMeta {
+            addr,
+            len,
+            stride: len,
+            ..Default::default()
+        };
+        Poll::Ready(Ok(1))
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.0.local_addr()
+    }
+}
+
+#[derive(Debug)]
+struct Poller(Arc<UdpSocket>);
+
+impl UdpPoller for Poller {
+    fn poll_writable(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        self.0.poll_send_ready(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        message_proto::{message, Message, TestDelay, VideoFrame},
+        netsim::{udp_pair, NetConditions},
+    };
+    use protobuf::Message as _;
+    use sodiumoxide::crypto::secretbox;
+
+    fn video(display: i32) -> Message {
+        Message {
+            union: Some(message::Union::VideoFrame(VideoFrame {
+                display,
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+    }
+
+    fn test_delay(time: i64) -> Message {
+        Message {
+            union: Some(message::Union::TestDelay(TestDelay {
+                time,
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+    }
+
+    async fn pair(
+        server: UdpSocket,
+        client: UdpSocket,
+        peer_id: &str,
+    ) -> (QuicFramedStream, QuicFramedStream) {
+        let peer = server.peer_addr().unwrap_or(client.local_addr().unwrap());
+        let (server, client) = tokio::join!(
+            QuicFramedStream::accept(Arc::new(server), peer, 5000),
+            QuicFramedStream::connect(Arc::new(client), peer_id, 5000)
+        );
+        let (mut server, mut client) = (server.unwrap(), client.unwrap());
+        let key = secretbox::gen_key();
+        server.set_key(key.clone());
+        client.set_key(key);
+        (server, client)
+    }
+
+    async fn local_pair(peer_id: &str) -> (QuicFramedStream, QuicFramedStream) {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.connect(server.local_addr().unwrap()).await.unwrap();
+        pair(server, client, peer_id).await
+    }
+
+    async fn assert_delay(from: &mut QuicFramedStream, to: &mut QuicFramedStream, time: i64) {
+        from.send(&test_delay(time)).await.unwrap();
+        let bytes = to.next_timeout(5000).await.unwrap().unwrap();
+        match Message::parse_from_bytes(&bytes).unwrap().union {
+            Some(message::Union::TestDelay(td)) => assert_eq!(td.time, time),
+            _ => panic!("unexpected message"),
+        }
+    }
+
+    #[test]
+    fn test_channel_of() {
+        assert_eq!(channel_of(&video(1).write_to_bytes().unwrap()), MAIN);
+        assert_eq!(channel_of(&test_delay(1).write_to_bytes().unwrap()), MAIN);
+        // file_action = 17.
+        assert_eq!(channel_of(&[0x8a, 0x01, 0x00]), FILE);
+        // multi_clipboards = 28, a two-byte tag.
+        assert_eq!(channel_of(&[0xe2, 0x01, 0x00]), CLIPBOARD);
+        assert_eq!(channel_of(&[]), MAIN);
+        assert_eq!(channel_of(&[0xff; 12]), MAIN);
+    }
+
+    #[test]
+    fn test_is_initial() {
+        assert!(is_initial(&[0xc3, 0, 0, 0, 1, 8]));
+        // A handshake packet, not the initial.
+        assert!(!is_initial(&[0xe3, 0, 0, 0, 1, 8]));
+        // Another version.
+        assert!(!is_initial(&[0xc3, 0, 0, 0, 2, 8]));
+        // A kcp segment, the command is the fifth byte.
+        assert!(!is_initial(&[0xc0, 0, 0, 0, 81, 0]));
+        assert!(!is_initial(&[]));
+    }
+
+    #[test]
+    fn test_server_name() {
+        assert_eq!(server_name("123456789"), "p123456789.rustdesk");
+        assert_eq!(server_name("Ab-c_d"), "pabcd.rustdesk");
+        assert!(ServerName::try_from(server_name("").as_str()).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_0rtt() {
+        let (mut server, mut client) = local_pair("test_0rtt").await;
+        assert!(client.early.is_none());
+        assert_delay(&mut server, &mut client, 1).await;
+        assert_delay(&mut client, &mut server, 2).await;
+        drop((server, client));
+        // The ticket of the first connection resumes the second one.
+        let (mut server, mut client) = local_pair("test_0rtt").await;
+        assert!(client.early.is_some());
+        assert_delay(&mut client, &mut server, 3).await;
+        assert_delay(&mut server, &mut client, 4).await;
+    }
+
+    #[tokio::test]
+    async fn test_migration() {
+        let (mut server, mut client) = local_pair("test_migration").await;
+        assert_delay(&mut client, &mut server, 1).await;
+        let old = server.connection.remote_address();
+        rebind(&client._endpoint, "127.0.0.1".parse().unwrap())
+            .await
+            .unwrap();
+        assert_delay(&mut client, &mut server, 2).await;
+        assert_delay(&mut server, &mut client, 3).await;
+        assert_ne!(server.connection.remote_address(), old);
+    }
+
+    #[tokio::test]
+    async fn test_lossy_link() {
+        let conditions = NetConditions {
+            latency: Duration::from_millis(10),
+            loss: 0.05,
+            ..Default::default()
+        };
+        let (a, b, _link) = udp_pair(conditions).await.unwrap();
+        let (mut server, mut client) = pair(b, a, "test_lossy_link").await;
+
+        for i in 0..100 {
+            server.send(&test_delay(i as _)).await.unwrap();
+            server.send(&video(i)).await.unwrap();
+        }
+        // The session and the video stay in order between them.
+        for i in 0..100 {
+            let bytes = client.next_timeout(5000).await.unwrap().unwrap();
+            match Message::parse_from_bytes(&bytes).unwrap().union {
+                Some(message::Union::TestDelay(td)) => assert_eq!(td.time, i as i64),
+                _ => panic!("unexpected message"),
+            }
+            let bytes = client.next_timeout(5000).await.unwrap().unwrap();
+            match Message::parse_from_bytes(&bytes).unwrap().union {
+                Some(message::Union::VideoFrame(vf)) => assert_eq!(vf.display, i),
+                _ => panic!("unexpected message"),
+            }
+        }
+        assert_delay(&mut client, &mut server, 1).await;
+    }
+}
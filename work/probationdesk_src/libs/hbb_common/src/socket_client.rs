@@ -1,5 +1,8 @@
 use crate::{
-    config::{Config, NetworkType},
+    config::{Config, NetworkType, Socks5Server},
+    happy_eyeballs,
+    proxy::Proxy,
+    proxy_resolver::proxy_for_async,
     tcp::FramedStream,
     udp::FramedSocket,
     websocket::{self, check_ws, is_ws_endpoint},
@@ -131,8 +134,10 @@ pub async fn connect_tcp<
 ) -> ResultType<crate::Stream> {
     let target_str = check_ws(&target.to_string());
     if is_ws_endpoint(&target_str) {
+        let proxy_conf = proxy_for_async(target_str.clone()).await;
         return Ok(Stream::WebSocket(
-            websocket::WsFramedStream::new(target_str, None, None, ms_timeout).await?,
+            websocket::WsFramedStream::new(target_str, None, proxy_conf.as_ref(), ms_timeout)
+                .await?,
         ));
     }
     connect_tcp_local(target, None, ms_timeout).await
@@ -147,7 +152,7 @@ pub async fn connect_tcp_local<
     local: Option<SocketAddr>,
     ms_timeout: u64,
 ) -> ResultType<Stream> {
-    if let Some(conf) = Config::get_socks() {
+    if let Some(conf) = proxy_for_async(target.to_string()).await {
         return Ok(Stream::Tcp(
             FramedStream::connect(target, local, &conf, ms_timeout).await?,
         ));
@@ -156,7 +161,10 @@ pub async fn connect_tcp_local<
     if let Some(target_addr) = target.resolve() {
         if let Some(local_addr) = local {
             if local_addr.is_ipv6() && target_addr.is_ipv4() {
-                let resolved_target = query_nip_io(target_addr).await?;
+                let resolved_target = match happy_eyeballs::nat64_addr(*target_addr).await {
+                    Some(addr) => addr,
+                    None => query_nip_io(target_addr).await?,
+                };
                 return Ok(Stream::Tcp(
                     FramedStream::new(resolved_target, Some(local_addr), ms_timeout).await?,
                 ));
@@ -195,15 +203,17 @@ pub fn ipv4_to_ipv6(addr: String, ipv4: bool) -> String {
     addr
 }
 
+// The address of the family answering first over tcp, or the first one.
 async fn test_target(target: &str) -> ResultType<SocketAddr> {
-    if let Ok(Ok(s)) = super::timeout(1000, tokio::net::TcpStream::connect(target)).await {
+    let addrs = happy_eyeballs::resolve(target).await?;
+    if let Ok(s) = happy_eyeballs::connect(&addrs, None, 1000).await {
         if let Ok(addr) = s.peer_addr() {
             return Ok(addr);
         }
     }
-    tokio::net::lookup_host(target)
-        .await?
-        .next()
+    addrs
+        .first()
+        .cloned()
         .context(format!("Failed to look up host for {target}"))
 }
 
@@ -220,20 +230,58 @@ pub async fn new_udp_for(
     target: &str,
     ms_timeout: u64,
 ) -> ResultType<(FramedSocket, TargetAddr<'static>)> {
-    let (ipv4, target) = if NetworkType::Direct == Config::get_network_type() {
+    let proxy_conf = udp_proxy_for(target).await;
+    let (ipv4, target) = if proxy_conf.is_none() {
         let addr = test_target(target).await?;
         (addr.is_ipv4(), addr.into_target_addr()?)
     } else {
         (true, target.into_target_addr()?)
     };
     Ok((
-        new_udp(Config::get_any_listen_addr(ipv4), ms_timeout).await?,
+        new_udp(Config::get_any_listen_addr(ipv4), proxy_conf, ms_timeout).await?,
         target.to_owned(),
     ))
 }
 
-async fn new_udp<T: ToSocketAddrs>(local: T, ms_timeout: u64) -> ResultType<FramedSocket> {
-    match Config::get_socks() {
+/// Like `new_udp_for`, the family is of the address answering `request` first over udp,
+/// with the answer. Without any answer or over a proxy, it is left to `new_udp_for`.
+pub async fn race_udp_for(
+    target: &str,
+    request: &[u8],
+    ms_timeout: u64,
+) -> ResultType<(FramedSocket, TargetAddr<'static>, Option<Vec<u8>>)> {
+    if udp_proxy_for(target).await.is_none() {
+        let addrs = happy_eyeballs::resolve(target).await?;
+        match happy_eyeballs::race_udp(&addrs, request, ms_timeout).await {
+            Ok((socket, addr, answer)) => {
+                return Ok((
+                    socket.into(),
+                    addr.into_target_addr()?.to_owned(),
+                    Some(answer),
+                ));
+            }
+            Err(err) => log::debug!("No answer of {} over udp: {}", target, err),
+        }
+    }
+    let (socket, addr) = new_udp_for(target, ms_timeout).await?;
+    Ok((socket, addr, None))
+}
+
+// Only socks5 relays udp, it goes direct beside an http proxy.
+async fn udp_proxy_for(target: &str) -> Option<Socks5Server> {
+    let conf = proxy_for_async(target.to_owned()).await?;
+    match Proxy::from_conf(&conf, None) {
+        Ok(proxy) if !proxy.is_http_or_https() => Some(conf),
+        _ => None,
+    }
+}
+
+async fn new_udp<T: ToSocketAddrs>(
+    local: T,
+    proxy_conf: Option<Socks5Server>,
+    ms_timeout: u64,
+) -> ResultType<FramedSocket> {
+    match proxy_conf {
         None => Ok(FramedSocket::new(local).await?),
         Some(conf) => {
             let socket = FramedSocket::new_proxy(
@@ -252,7 +300,7 @@ async fn new_udp<T: ToSocketAddrs>(local: T, ms_timeout: u64) -> ResultType<Fram
 pub async fn rebind_udp_for(
     target: &str,
 ) -> ResultType<Option<(FramedSocket, TargetAddr<'static>)>> {
-    if Config::get_network_type() != NetworkType::Direct {
+    if udp_proxy_for(target).await.is_some() {
         return Ok(None);
     }
     let addr = test_target(target).await?;
@@ -1,12 +1,13 @@
-use crate::{config, tcp, websocket, ResultType};
+use crate::{config, quic, tcp, websocket, ResultType};
 use sodiumoxide::crypto::secretbox::Key;
 use std::net::SocketAddr;
 use tokio::net::TcpStream;
 
-// support Websocket and tcp.
+// support Websocket, tcp and QUIC.
 pub enum Stream {
     WebSocket(websocket::WsFramedStream),
     Tcp(tcp::FramedStream),
+    Quic(quic::QuicFramedStream),
 }
 
 impl Stream {
@@ -15,6 +16,7 @@ impl Stream {
         match self {
             Stream::WebSocket(s) => s.set_send_timeout(ms),
             Stream::Tcp(s) => s.set_send_timeout(ms),
+            Stream::Quic(s) => s.set_send_timeout(ms),
         }
     }
 
@@ -23,6 +25,7 @@ impl Stream {
         match self {
             Stream::WebSocket(s) => s.set_raw(),
             Stream::Tcp(s) => s.set_raw(),
+            Stream::Quic(s) => s.set_raw(),
         }
     }
 
@@ -31,6 +34,7 @@ impl Stream {
         match self {
             Stream::WebSocket(s) => s.send_bytes(bytes).await,
             Stream::Tcp(s) => s.send_bytes(bytes).await,
+            Stream::Quic(s) => s.send_bytes(bytes).await,
         }
     }
 
@@ -39,6 +43,7 @@ impl Stream {
         match self {
             Stream::WebSocket(s) => s.send_raw(bytes).await,
             Stream::Tcp(s) => s.send_raw(bytes).await,
+            Stream::Quic(s) => s.send_raw(bytes).await,
         }
     }
 
@@ -47,6 +52,16 @@ impl Stream {
         match self {
             Stream::WebSocket(s) => s.set_key(key),
             Stream::Tcp(s) => s.set_key(key),
+            Stream::Quic(s) => s.set_key(key),
+        }
+    }
+
+    #[inline]
+    pub fn set_encrypt(&mut self, encrypt: tcp::Encrypt) {
+        match self {
+            Stream::WebSocket(s) => s.set_encrypt(encrypt),
+            Stream::Tcp(s) => s.set_encrypt(encrypt),
+            Stream::Quic(s) => s.set_encrypt(encrypt),
         }
     }
 
@@ -55,6 +70,7 @@ impl Stream {
         match self {
             Stream::WebSocket(s) => s.is_secured(),
             Stream::Tcp(s) => s.is_secured(),
+            Stream::Quic(s) => s.is_secured(),
         }
     }
 
@@ -66,6 +82,7 @@ impl Stream {
         match self {
             Stream::WebSocket(s) => s.next_timeout(timeout).await,
             Stream::Tcp(s) => s.next_timeout(timeout).await,
+            Stream::Quic(s) => s.next_timeout(timeout).await,
         }
     }
 
@@ -89,6 +106,7 @@ impl Stream {
         match self {
             Self::WebSocket(ws) => ws.send(msg).await,
             Self::Tcp(tcp) => tcp.send(msg).await,
+            Self::Quic(quic) => quic.send(msg).await,
         }
     }
 
@@ -98,6 +116,7 @@ impl Stream {
         match self {
             Self::WebSocket(ws) => ws.next().await,
             Self::Tcp(tcp) => tcp.next().await,
+            Self::Quic(quic) => quic.next().await,
         }
     }
 
@@ -106,6 +125,7 @@ impl Stream {
         match self {
             Self::WebSocket(ws) => ws.local_addr(),
             Self::Tcp(tcp) => tcp.local_addr(),
+            Self::Quic(quic) => quic.local_addr(),
         }
     }
 
@@ -3,8 +3,10 @@ use anyhow::Context as AnyhowCtx;
 use bytes::{BufMut, Bytes, BytesMut};
 use futures::{SinkExt, StreamExt};
 use protobuf::Message;
+use sha2::{Digest, Sha256};
 use sodiumoxide::crypto::{
     box_,
+    scalarmult::curve25519,
     secretbox::{self, Key, Nonce},
 };
 use std::{
@@ -13,6 +15,7 @@ use std::{
     ops::{Deref, DerefMut},
     pin::Pin,
     task::{Context, Poll},
+    time::{Duration, Instant},
 };
 use tokio::{
     io::{AsyncRead, AsyncWrite, ReadBuf},
@@ -24,8 +27,25 @@ use tokio_util::codec::Framed;
 pub trait TcpStreamTrait: AsyncRead + AsyncWrite + Unpin {}
 pub struct DynTcpStream(pub Box<dyn TcpStreamTrait + Send + Sync>);
 
+/// The key exchange of `PublicKey` with the ephemeral X25519 keys of both sides and in-band rekeying.
+pub const KEY_EXCHANGE_FORWARD_SECRET: u32 = 1;
+// The sending key is rotated after so many bytes or so long, whichever comes first.
+const REKEY_BYTES: u64 = 256 * 1024 * 1024;
+const REKEY_INTERVAL: Duration = Duration::from_secs(10 * 60);
+// The first byte of the plaintext of the forward secret frames.
+const FRAME_DATA: u8 = 0;
+const FRAME_REKEY: u8 = 1;
+
 #[derive(Clone)]
-pub struct Encrypt(pub Key, pub u64, pub u64);
+pub struct Encrypt {
+    // The same key in both directions for the legacy key exchange.
+    send_key: Key,
+    recv_key: Key,
+    send_seq: u64,
+    recv_seq: u64,
+    // The bytes sent and the time since the last rekeying, None for the legacy key exchange.
+    rekey: Option<(u64, Instant)>,
+}
 
 pub struct FramedStream(
     pub Framed<DynTcpStream, BytesCodec>,
@@ -86,26 +106,16 @@ impl FramedStream {
         local_addr: Option<SocketAddr>,
         ms_timeout: u64,
     ) -> ResultType<Self> {
-        for remote_addr in lookup_host(&remote_addr).await? {
-            let local = if let Some(addr) = local_addr {
-                addr
-            } else {
-                crate::config::Config::get_any_listen_addr(remote_addr.is_ipv4())
-            };
-            if let Ok(socket) = new_socket(local, true) {
-                if let Ok(Ok(stream)) =
-                    super::timeout(ms_timeout, socket.connect(remote_addr)).await
-                {
-                    stream.set_nodelay(true).ok();
-                    let addr = stream.local_addr()?;
-                    return Ok(Self(
-                        Framed::new(DynTcpStream(Box::new(stream)), BytesCodec::new()),
-                        addr,
-                        None,
-                        0,
-                    ));
-                }
-            }
+        let addrs = crate::happy_eyeballs::resolve(&remote_addr).await?;
+        if let Ok(stream) = crate::happy_eyeballs::connect(&addrs, local_addr, ms_timeout).await {
+            stream.set_nodelay(true).ok();
+            let addr = stream.local_addr()?;
+            return Ok(Self(
+                Framed::new(DynTcpStream(Box::new(stream)), BytesCodec::new()),
+                addr,
+                None,
+                0,
+            ));
         }
         bail!(format!("Failed to connect to {remote_addr}"));
     }
@@ -157,9 +167,14 @@ impl FramedStream {
     #[inline]
     pub async fn send_raw(&mut self, msg: Vec<u8>) -> ResultType<()> {
         let mut msg = msg;
+        let mut rekey = None;
         if let Some(key) = self.2.as_mut() {
+            rekey = key.rekey_frame();
             msg = key.enc(&msg);
         }
+        if let Some(frame) = rekey {
+            self.send_bytes(bytes::Bytes::from(frame)).await?;
+        }
         self.send_bytes(bytes::Bytes::from(msg)).await?;
         Ok(())
     }
@@ -176,15 +191,20 @@ impl FramedStream {
 
     #[inline]
     pub async fn next(&mut self) -> Option<Result<BytesMut, Error>> {
-        let mut res = self.0.next().await;
-        if let Some(Ok(bytes)) = res.as_mut() {
-            if let Some(key) = self.2.as_mut() {
-                if let Err(err) = key.dec(bytes) {
-                    return Some(Err(err));
+        loop {
+            let mut res = self.0.next().await;
+            if let Some(Ok(bytes)) = res.as_mut() {
+                if let Some(key) = self.2.as_mut() {
+                    match key.dec(bytes) {
+                        Ok(true) => {}
+                        // A rekeying frame, not for the caller.
+                        Ok(false) => continue,
+                        Err(err) => return Some(Err(err)),
+                    }
                 }
             }
+            return res;
         }
-        res
     }
 
     #[inline]
@@ -200,6 +220,10 @@ impl FramedStream {
         self.2 = Some(Encrypt::new(key));
     }
 
+    pub fn set_encrypt(&mut self, encrypt: Encrypt) {
+        self.2 = Some(encrypt);
+    }
+
     fn get_nonce(seqnum: u64) -> Nonce {
         let mut nonce = Nonce([0u8; secretbox::NONCEBYTES]);
         nonce.0[..std::mem::size_of_val(&seqnum)].copy_from_slice(&seqnum.to_le_bytes());
@@ -294,30 +318,154 @@ impl AsyncWrite for DynTcpStream {
 impl<R: AsyncRead + AsyncWrite + Unpin> TcpStreamTrait for R {}
 
 impl Encrypt {
+    /// The session key of the legacy key exchange, sealed by the controlling side.
     pub fn new(key: Key) -> Self {
-        Self(key, 0, 0)
+        Self {
+            send_key: key.clone(),
+            recv_key: key,
+            send_seq: 0,
+            recv_seq: 0,
+            rekey: None,
+        }
+    }
+
+    /// The session keys of the forward secret key exchange, derived from the ephemeral keys of
+    /// both sides, `initiator` is the controlling side.
+    pub fn forward_secret(
+        our_sk_b: &box_::SecretKey,
+        our_pk_b: &box_::PublicKey,
+        their_pk_b: &[u8],
+        initiator: bool,
+    ) -> ResultType<Self> {
+        if their_pk_b.len() != box_::PUBLICKEYBYTES {
+            anyhow::bail!("Handshake failed: pk length {}", their_pk_b.len());
+        }
+        let mut pk_ = [0u8; box_::PUBLICKEYBYTES];
+        pk_[..].copy_from_slice(their_pk_b);
+        let shared = curve25519::scalarmult(
+            &curve25519::Scalar(our_sk_b.0),
+            &curve25519::GroupElement(pk_),
+        )
+        .map_err(|_| anyhow::anyhow!("Handshake failed: invalid public key from peer"))?;
+        let (initiator_pk, responder_pk) = if initiator {
+            (&our_pk_b.0, &pk_)
+        } else {
+            (&pk_, &our_pk_b.0)
+        };
+        let derive = |label: &[u8]| {
+            let mut hasher = Sha256::new();
+            hasher.update(label);
+            hasher.update(shared.0);
+            hasher.update(initiator_pk);
+            hasher.update(responder_pk);
+            let mut key = [0u8; secretbox::KEYBYTES];
+            key.copy_from_slice(&hasher.finalize());
+            Key(key)
+        };
+        let (initiator_key, responder_key) = (derive(b"initiator"), derive(b"responder"));
+        let (send_key, recv_key) = if initiator {
+            (initiator_key, responder_key)
+        } else {
+            (responder_key, initiator_key)
+        };
+        Ok(Self {
+            send_key,
+            recv_key,
+            send_seq: 0,
+            recv_seq: 0,
+            rekey: Some((0, Instant::now())),
+        })
+    }
+
+    /// The keys of the channel `ch` of a multiplexed transport, e.g. QUIC,
+    /// the channels are sealed independently so they can not share the nonces.
+    pub fn channel(&self, ch: u8) -> Self {
+        let derive = |key: &Key| {
+            let mut hasher = Sha256::new();
+            hasher.update(b"channel");
+            hasher.update([ch]);
+            hasher.update(key.0);
+            let mut key = [0u8; secretbox::KEYBYTES];
+            key.copy_from_slice(&hasher.finalize());
+            Key(key)
+        };
+        Self {
+            send_key: derive(&self.send_key),
+            recv_key: derive(&self.recv_key),
+            send_seq: 0,
+            recv_seq: 0,
+            rekey: self.rekey.map(|_| (0, Instant::now())),
+        }
+    }
+
+    // The next key is a hash of the current one, the old keys can not be derived from it.
+    fn next_key(key: &Key) -> Key {
+        let mut hasher = Sha256::new();
+        hasher.update(b"rekey");
+        hasher.update(key.0);
+        let mut key = [0u8; secretbox::KEYBYTES];
+        key.copy_from_slice(&hasher.finalize());
+        Key(key)
     }
 
-    pub fn dec(&mut self, bytes: &mut BytesMut) -> Result<(), Error> {
+    /// Returns whether `bytes` is data, the rekeying frames are consumed here.
+    pub fn dec(&mut self, bytes: &mut BytesMut) -> Result<bool, Error> {
         if bytes.len() <= 1 {
-            return Ok(());
+            return Ok(true);
         }
-        self.2 += 1;
-        let nonce = FramedStream::get_nonce(self.2);
-        match secretbox::open(bytes, &nonce, &self.0) {
-            Ok(res) => {
-                bytes.clear();
-                bytes.put_slice(&res);
-                Ok(())
+        self.recv_seq += 1;
+        let nonce = FramedStream::get_nonce(self.recv_seq);
+        let res = secretbox::open(bytes, &nonce, &self.recv_key)
+            .map_err(|()| Error::new(ErrorKind::Other, "decryption error"))?;
+        let data = if self.rekey.is_none() {
+            &res[..]
+        } else {
+            match res.first() {
+                Some(&FRAME_DATA) => &res[1..],
+                Some(&FRAME_REKEY) => {
+                    self.recv_key = Self::next_key(&self.recv_key);
+                    self.recv_seq = 0;
+                    return Ok(false);
+                }
+                _ => return Err(Error::new(ErrorKind::Other, "invalid frame type")),
             }
-            Err(()) => Err(Error::new(ErrorKind::Other, "decryption error")),
-        }
+        };
+        bytes.clear();
+        bytes.put_slice(data);
+        Ok(true)
     }
 
     pub fn enc(&mut self, data: &[u8]) -> Vec<u8> {
-        self.1 += 1;
-        let nonce = FramedStream::get_nonce(self.1);
-        secretbox::seal(&data, &nonce, &self.0)
+        match self.rekey.as_mut() {
+            Some((sent, _)) => {
+                *sent += data.len() as u64;
+                let mut frame = Vec::with_capacity(data.len() + 1);
+                frame.push(FRAME_DATA);
+                frame.extend_from_slice(data);
+                self.seal(&frame)
+            }
+            None => self.seal(data),
+        }
+    }
+
+    fn seal(&mut self, data: &[u8]) -> Vec<u8> {
+        self.send_seq += 1;
+        let nonce = FramedStream::get_nonce(self.send_seq);
+        secretbox::seal(data, &nonce, &self.send_key)
+    }
+
+    /// The rekeying frame to send before the next data when it is time,
+    /// the data after it is sealed with the next key.
+    pub fn rekey_frame(&mut self) -> Option<Vec<u8>> {
+        let (sent, since) = self.rekey?;
+        if sent < REKEY_BYTES && since.elapsed() < REKEY_INTERVAL {
+            return None;
+        }
+        let frame = self.seal(&[FRAME_REKEY]);
+        self.send_key = Self::next_key(&self.send_key);
+        self.send_seq = 0;
+        self.rekey = Some((0, Instant::now()));
+        Some(frame)
     }
 
     pub fn decode(
@@ -342,3 +490,54 @@ impl Encrypt {
         Ok(Key(key))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(from: &mut Encrypt, to: &mut Encrypt, data: &[u8]) {
+        if let Some(frame) = from.rekey_frame() {
+            assert!(!to.dec(&mut BytesMut::from(&frame[..])).unwrap());
+        }
+        let mut bytes = BytesMut::from(&from.enc(data)[..]);
+        assert!(to.dec(&mut bytes).unwrap());
+        assert_eq!(&bytes[..], data);
+    }
+
+    #[test]
+    fn test_forward_secret() {
+        let (a_pk, a_sk) = box_::gen_keypair();
+        let (b_pk, b_sk) = box_::gen_keypair();
+        let mut a = Encrypt::forward_secret(&a_sk, &a_pk, &b_pk.0, true).unwrap();
+        let mut b = Encrypt::forward_secret(&b_sk, &b_pk, &a_pk.0, false).unwrap();
+        // Different keys in the two directions.
+        assert_ne!(a.send_key.0, a.recv_key.0);
+        round_trip(&mut a, &mut b, b"hello");
+        round_trip(&mut b, &mut a, b"hi");
+        round_trip(&mut a, &mut b, b"");
+
+        let old_key = a.send_key.clone();
+        a.rekey = Some((REKEY_BYTES, Instant::now()));
+        round_trip(&mut a, &mut b, b"rekeyed");
+        assert_ne!(a.send_key.0, old_key.0);
+        assert_eq!(a.send_seq, 1);
+        assert_eq!(b.recv_key.0, a.send_key.0);
+        round_trip(&mut b, &mut a, b"the other direction is not rekeyed");
+        // The frames of the old key are rejected.
+        let mut frame = BytesMut::from(&Encrypt::new(old_key).enc(b"x")[..]);
+        assert!(b.dec(&mut frame).is_err());
+
+        // A low order point.
+        assert!(Encrypt::forward_secret(&a_sk, &a_pk, &[0u8; 32], true).is_err());
+    }
+
+    #[test]
+    fn test_legacy() {
+        let key = secretbox::gen_key();
+        let mut a = Encrypt::new(key.clone());
+        let mut b = Encrypt::new(key);
+        round_trip(&mut a, &mut b, b"hello");
+        assert!(a.rekey_frame().is_none());
+        assert_eq!(a.enc(b"hello").len(), b"hello".len() + secretbox::MACBYTES);
+    }
+}
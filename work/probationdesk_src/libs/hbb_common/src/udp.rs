@@ -169,3 +169,9 @@ impl FramedSocket {
         None
     }
 }
+
+impl From<UdpSocket> for FramedSocket {
+    fn from(socket: UdpSocket) -> Self {
+        Self::Direct(UdpFramed::new(socket, BytesCodec::new()))
+    }
+}
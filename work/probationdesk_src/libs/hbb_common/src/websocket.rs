@@ -1,14 +1,17 @@
 use crate::{
-    config::keys::OPTION_RELAY_SERVER,
+    bail,
+    config::keys::{OPTION_RELAY_SERVER, OPTION_WS_CA_CERTS, OPTION_WS_CERT_PINS},
     config::{use_ws, Config, Socks5Server, RELAY_PORT, RENDEZVOUS_PORT},
     protobuf::Message,
+    proxy::Proxy,
     socket_client::split_host_port,
     sodiumoxide::crypto::secretbox::Key,
-    tcp::Encrypt,
+    tcp::{DynTcpStream, Encrypt},
     ResultType,
 };
 use bytes::{Bytes, BytesMut};
 use futures::{SinkExt, StreamExt};
+use sha2::{Digest, Sha256};
 use std::{
     io::{Error, ErrorKind},
     net::SocketAddr,
@@ -16,45 +19,76 @@ use std::{
 };
 use tokio::{net::TcpStream, time::timeout};
 use tokio_tungstenite::{
-    connect_async, tungstenite::protocol::Message as WsMessage, MaybeTlsStream, WebSocketStream,
+    client_async_tls_with_config, tungstenite::protocol::Message as WsMessage, Connector,
+    MaybeTlsStream, WebSocketStream,
 };
 use tungstenite::client::IntoClientRequest;
 use tungstenite::protocol::Role;
 
+pub(crate) const PEM_BEGIN: &str = "-----BEGIN CERTIFICATE-----";
+const PEM_END: &str = "-----END CERTIFICATE-----";
+
 pub struct WsFramedStream {
-    stream: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    stream: WebSocketStream<MaybeTlsStream<DynTcpStream>>,
     addr: SocketAddr,
     encrypt: Option<Encrypt>,
     send_timeout: u64,
 }
 
 impl WsFramedStream {
+    /// Through the proxy if `proxy_conf` is set, both HTTP CONNECT and SOCKS5.
     pub async fn new<T: AsRef<str>>(
         url: T,
-        _local_addr: Option<SocketAddr>,
-        _proxy_conf: Option<&Socks5Server>,
+        local_addr: Option<SocketAddr>,
+        proxy_conf: Option<&Socks5Server>,
         ms_timeout: u64,
     ) -> ResultType<Self> {
         let url_str = url.as_ref();
 
-        // to-do: websocket proxy.
-
         let request = url_str
             .into_client_request()
             .map_err(|e| Error::new(ErrorKind::Other, e))?;
+        let uri = request.uri();
+        let Some(host) = uri.host() else {
+            bail!("Invalid websocket url: {}", url_str);
+        };
+        // The brackets of IPv6.
+        let host = host
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_owned();
+        let default_port = if uri.scheme_str() == Some("wss") {
+            443
+        } else {
+            80
+        };
+        let port = uri.port_u16().unwrap_or(default_port);
 
-        let (stream, _) =
-            timeout(Duration::from_millis(ms_timeout), connect_async(request)).await??;
-
-        let addr = match stream.get_ref() {
-            MaybeTlsStream::Plain(tcp) => tcp.peer_addr()?,
-            #[cfg(any(target_os = "macos", target_os = "windows"))]
-            MaybeTlsStream::NativeTls(tls) => tls.get_ref().get_ref().get_ref().peer_addr()?,
-            #[cfg(not(any(target_os = "macos", target_os = "windows")))]
-            MaybeTlsStream::Rustls(tls) => tls.get_ref().0.peer_addr()?,
-            _ => return Err(Error::new(ErrorKind::Other, "Unsupported stream type").into()),
+        let (stream, addr) = if let Some(conf) = proxy_conf {
+            Proxy::from_conf(conf, Some(ms_timeout))?
+                .connect_raw((host.as_str(), port), local_addr)
+                .await?
+        } else {
+            let tcp = timeout(
+                Duration::from_millis(ms_timeout),
+                TcpStream::connect((host.as_str(), port)),
+            )
+            .await??;
+            tcp.set_nodelay(true).ok();
+            let addr = tcp.peer_addr()?;
+            (DynTcpStream(Box::new(tcp)), addr)
         };
 
+        let (stream, _) = timeout(
+            Duration::from_millis(ms_timeout),
+            client_async_tls_with_config(request, stream, None, tls_connector()?),
+        )
+        .await??;
+        #[cfg(any(target_os = "macos", target_os = "windows"))]
+        if let MaybeTlsStream::NativeTls(tls) = stream.get_ref() {
+            check_native_pins(tls.get_ref())?;
+        }
+
         let ws = Self {
             stream,
             addr,
@@ -72,9 +106,12 @@ impl WsFramedStream {
 
     #[inline]
     pub async fn from_tcp_stream(stream: TcpStream, addr: SocketAddr) -> ResultType<Self> {
-        let ws_stream =
-            WebSocketStream::from_raw_socket(MaybeTlsStream::Plain(stream), Role::Client, None)
-                .await;
+        let ws_stream = WebSocketStream::from_raw_socket(
+            MaybeTlsStream::Plain(DynTcpStream(Box::new(stream))),
+            Role::Client,
+            None,
+        )
+        .await;
 
         Ok(Self {
             stream: ws_stream,
@@ -99,6 +136,11 @@ impl WsFramedStream {
         self.encrypt = Some(Encrypt::new(key));
     }
 
+    #[inline]
+    pub fn set_encrypt(&mut self, encrypt: Encrypt) {
+        self.encrypt = Some(encrypt);
+    }
+
     #[inline]
     pub fn is_secured(&self) -> bool {
         self.encrypt.is_some()
@@ -112,9 +154,14 @@ impl WsFramedStream {
     #[inline]
     pub async fn send_raw(&mut self, msg: Vec<u8>) -> ResultType<()> {
         let mut msg = msg;
+        let mut rekey = None;
         if let Some(key) = self.encrypt.as_mut() {
+            rekey = key.rekey_frame();
             msg = key.enc(&msg);
         }
+        if let Some(frame) = rekey {
+            self.send_bytes(Bytes::from(frame)).await?;
+        }
         self.send_bytes(Bytes::from(msg)).await
     }
 
@@ -150,8 +197,11 @@ impl WsFramedStream {
                 WsMessage::Binary(data) => {
                     let mut bytes = BytesMut::from(&data[..]);
                     if let Some(key) = self.encrypt.as_mut() {
-                        if let Err(err) = key.dec(&mut bytes) {
-                            return Some(Err(err));
+                        match key.dec(&mut bytes) {
+                            Ok(true) => {}
+                            // A rekeying frame, not for the caller.
+                            Ok(false) => continue,
+                            Err(err) => return Some(Err(err)),
                         }
                     }
                     return Some(Ok(bytes));
@@ -181,6 +231,233 @@ impl WsFramedStream {
     }
 }
 
+// The PEM blocks of the option, the bundle itself or the path of it.
+fn ca_certs() -> ResultType<Vec<String>> {
+    let option = Config::get_option(OPTION_WS_CA_CERTS);
+    let option = option.trim();
+    if option.is_empty() {
+        return Ok(vec![]);
+    }
+    let pem = if option.contains(PEM_BEGIN) {
+        option.to_owned()
+    } else {
+        std::fs::read_to_string(option)?
+    };
+    let blocks = pem_blocks(&pem);
+    if blocks.is_empty() {
+        bail!("No certificate in {}", OPTION_WS_CA_CERTS);
+    }
+    Ok(blocks)
+}
+
+pub(crate) fn pem_blocks(pem: &str) -> Vec<String> {
+    let mut blocks = vec![];
+    let mut rest = pem;
+    while let Some(start) = rest.find(PEM_BEGIN) {
+        let Some(len) = rest[start..].find(PEM_END) else {
+            break;
+        };
+        let end = start + len + PEM_END.len();
+        blocks.push(rest[start..end].to_owned());
+        rest = &rest[end..];
+    }
+    blocks
+}
+
+// As `openssl x509 -fingerprint -sha256` prints them, the colons are optional.
+fn cert_pins() -> Vec<String> {
+    parse_pins(&Config::get_option(OPTION_WS_CERT_PINS))
+}
+
+pub(crate) fn parse_pins(option: &str) -> Vec<String> {
+    option
+        .split(',')
+        .map(|p| p.trim().replace(':', "").to_lowercase())
+        .filter(|p| !p.is_empty())
+        .collect()
+}
+
+pub(crate) fn is_pinned(pins: &[String], der: &[u8]) -> bool {
+    let fingerprint: String = Sha256::digest(der)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    pins.contains(&fingerprint)
+}
+
+// The default of tungstenite if there are neither extra CA certificates nor pins.
+#[cfg(not(any(target_os = "macos", target_os = "windows")))]
+fn tls_connector() -> ResultType<Option<Connector>> {
+    use std::sync::Arc;
+    let (cas, pins) = (ca_certs()?, cert_pins());
+    if cas.is_empty() && pins.is_empty() {
+        return Ok(None);
+    }
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let verifier = verifier::CertVerifier::new(cas, pins, provider.clone())?;
+    let config = rustls::ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(verifier))
+        .with_no_client_auth();
+    Ok(Some(Connector::Rustls(Arc::new(config))))
+}
+
+// The pins are checked after the handshake, see `check_native_pins`.
+#[cfg(any(target_os = "macos", target_os = "windows"))]
+fn tls_connector() -> ResultType<Option<Connector>> {
+    use tokio_native_tls::native_tls;
+    let cas = ca_certs()?;
+    if cas.is_empty() {
+        return Ok(None);
+    }
+    let mut builder = native_tls::TlsConnector::builder();
+    for ca in cas {
+        builder.add_root_certificate(native_tls::Certificate::from_pem(ca.as_bytes())?);
+    }
+    Ok(Some(Connector::NativeTls(builder.build()?)))
+}
+
+// native-tls has no hook in the handshake, the websocket is closed before it is used.
+#[cfg(any(target_os = "macos", target_os = "windows"))]
+fn check_native_pins<S: std::io::Read + std::io::Write>(
+    tls: &tokio_native_tls::native_tls::TlsStream<S>,
+) -> ResultType<()> {
+    let pins = cert_pins();
+    if pins.is_empty() {
+        return Ok(());
+    }
+    match tls.peer_certificate()? {
+        Some(cert) if is_pinned(&pins, &cert.to_der()?) => Ok(()),
+        _ => bail!("The certificate of the websocket server is not pinned"),
+    }
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "windows")))]
+mod verifier {
+    use super::is_pinned;
+    use crate::ResultType;
+    use rustls::{
+        client::{
+            danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+            WebPkiServerVerifier,
+        },
+        crypto::CryptoProvider,
+        pki_types::{pem::PemObject, CertificateDer, ServerName, UnixTime},
+        DigitallySignedStruct, RootCertStore, SignatureScheme,
+    };
+    use std::sync::Arc;
+
+    // The platform roots or the extra CA certificates, and the pins if any.
+    #[derive(Debug)]
+    pub(super) struct CertVerifier {
+        platform: rustls_platform_verifier::Verifier,
+        extra: Option<Arc<WebPkiServerVerifier>>,
+        pins: Vec<String>,
+        provider: Arc<CryptoProvider>,
+    }
+
+    impl CertVerifier {
+        pub(super) fn new(
+            cas: Vec<String>,
+            pins: Vec<String>,
+            provider: Arc<CryptoProvider>,
+        ) -> ResultType<Self> {
+            let mut platform = rustls_platform_verifier::Verifier::new();
+            platform.set_provider(provider.clone());
+            let extra = if cas.is_empty() {
+                None
+            } else {
+                let mut roots = RootCertStore::empty();
+                for ca in cas {
+                    let cert = CertificateDer::from_pem_slice(ca.as_bytes())
+                        .map_err(|e| anyhow::anyhow!("Invalid CA certificate: {:?}", e))?;
+                    roots.add(cert)?;
+                }
+                Some(
+                    WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+                        .build()?,
+                )
+            };
+            Ok(Self {
+                platform,
+                extra,
+                pins,
+                provider,
+            })
+        }
+    }
+
+    impl ServerCertVerifier for CertVerifier {
+        fn verify_server_cert(
+            &self,
+            end_entity: &CertificateDer<'_>,
+            intermediates: &[CertificateDer<'_>],
+            server_name: &ServerName<'_>,
+            ocsp_response: &[u8],
+            now: UnixTime,
+        ) -> Result<ServerCertVerified, rustls::Error> {
+            let res = self.platform.verify_server_cert(
+                end_entity,
+                intermediates,
+                server_name,
+                ocsp_response,
+                now,
+            );
+            let verified = match (res, &self.extra) {
+                (Err(_), Some(extra)) => extra.verify_server_cert(
+                    end_entity,
+                    intermediates,
+                    server_name,
+                    ocsp_response,
+                    now,
+                ),
+                (res, _) => res,
+            }?;
+            if !self.pins.is_empty() && !is_pinned(&self.pins, end_entity) {
+                return Err(rustls::Error::General(
+                    "The certificate of the websocket server is not pinned".to_owned(),
+                ));
+            }
+            Ok(verified)
+        }
+
+        fn verify_tls12_signature(
+            &self,
+            message: &[u8],
+            cert: &CertificateDer<'_>,
+            dss: &DigitallySignedStruct,
+        ) -> Result<HandshakeSignatureValid, rustls::Error> {
+            rustls::crypto::verify_tls12_signature(
+                message,
+                cert,
+                dss,
+                &self.provider.signature_verification_algorithms,
+            )
+        }
+
+        fn verify_tls13_signature(
+            &self,
+            message: &[u8],
+            cert: &CertificateDer<'_>,
+            dss: &DigitallySignedStruct,
+        ) -> Result<HandshakeSignatureValid, rustls::Error> {
+            rustls::crypto::verify_tls13_signature(
+                message,
+                cert,
+                dss,
+                &self.provider.signature_verification_algorithms,
+            )
+        }
+
+        fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+            self.provider
+                .signature_verification_algorithms
+                .supported_schemes()
+        }
+    }
+}
+
 pub fn is_ws_endpoint(endpoint: &str) -> bool {
     endpoint.starts_with("ws://") || endpoint.starts_with("wss://")
 }
@@ -395,4 +672,26 @@ mod tests {
         assert_eq!(check_ws("127.0.0.1:23456"), "ws://127.0.0.1:23458");
         assert_eq!(check_ws("127.0.0.1:34567"), "ws://127.0.0.1:34569");
     }
+
+    #[test]
+    fn test_ca_certs_and_pins() {
+        let pem = format!(
+            "subject=CN = a\n{b}\nMIIB\n{e}\n\n{b}\nMIIC\n{e}\n{b}\nbroken",
+            b = PEM_BEGIN,
+            e = PEM_END
+        );
+        let blocks = pem_blocks(&pem);
+        assert_eq!(blocks.len(), 2);
+        assert!(blocks[1].starts_with(PEM_BEGIN) && blocks[1].contains("MIIC"));
+
+        let pins = parse_pins(" AB:cd:01 ,, ");
+        assert_eq!(pins, vec!["abcd01".to_owned()]);
+        let der = b"certificate";
+        let fingerprint: String = Sha256::digest(der)
+            .iter()
+            .map(|b| format!("{:02X}:", b))
+            .collect();
+        assert!(is_pinned(&parse_pins(&fingerprint), der));
+        assert!(!is_pinned(&pins, der));
+    }
 }
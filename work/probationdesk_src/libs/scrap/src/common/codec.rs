@@ -14,8 +14,9 @@ use crate::vram::*;
 use crate::{
     aom::{self, AomDecoder, AomEncoder, AomEncoderConfig},
     common::GoogleImage,
+    text_tiles::TextTileDecoder,
     vpxcodec::{self, VpxDecoder, VpxDecoderConfig, VpxEncoder, VpxEncoderConfig, VpxVideoCodecId},
-    CodecFormat, EncodeInput, EncodeYuvFormat, ImageRgb, ImageTexture,
+    CodecFormat, DamageRect, EncodeInput, EncodeYuvFormat, ImageRgb, ImageTexture,
 };
 
 #[cfg(any(
@@ -80,6 +81,12 @@ pub trait EncoderApi {
     fn is_hardware(&self) -> bool;
 
     fn disable(&self);
+
+    // Regions changed since the previous frame, empty means the whole frame.
+    // Encoders supporting ROI spend more bits on them.
+    fn set_roi(&mut self, _rects: &[DamageRect]) -> ResultType<()> {
+        Ok(())
+    }
 }
 
 pub struct Encoder {
@@ -120,6 +127,8 @@ pub struct Decoder {
     valid: bool,
     #[cfg(feature = "hwcodec")]
     i420: Vec<u8>,
+    text_tiles: TextTileDecoder,
+    rgb_decoded: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -616,6 +625,8 @@ impl Decoder {
             valid,
             #[cfg(feature = "hwcodec")]
             i420: vec![],
+            text_tiles: Default::default(),
+            rgb_decoded: false,
         }
     }
 
@@ -629,6 +640,33 @@ impl Decoder {
 
     // rgb [in/out] fmt and stride must be set in ImageRgb
     pub fn handle_video_frame(
+        &mut self,
+        frame: &video_frame::Union,
+        rgb: &mut ImageRgb,
+        texture: &mut ImageTexture,
+        pixelbuffer: &mut bool,
+        chroma: &mut Option<Chroma>,
+    ) -> ResultType<bool> {
+        if let video_frame::Union::Rgb(tiles) = frame {
+            // Lossless tiles are composited over the last decoded image, texture is not supported.
+            if !self.text_tiles.update(tiles)? || !self.rgb_decoded {
+                return Ok(false);
+            }
+            self.text_tiles.composite(rgb);
+            return Ok(true);
+        }
+        let res = self.handle_encoded_video_frame(frame, rgb, texture, pixelbuffer, chroma);
+        if res.as_ref().is_ok_and(|x| *x) {
+            self.rgb_decoded = *pixelbuffer;
+            if contains_key_frame(frame) || !self.rgb_decoded {
+                self.text_tiles.clear();
+            }
+            self.text_tiles.composite(rgb);
+        }
+        res
+    }
+
+    fn handle_encoded_video_frame(
         &mut self,
         frame: &video_frame::Union,
         rgb: &mut ImageRgb,
@@ -847,6 +885,14 @@ impl Decoder {
     }
 }
 
+pub fn contains_key_frame(frame: &video_frame::Union) -> bool {
+    use video_frame::Union::*;
+    match frame {
+        Vp8s(f) | Vp9s(f) | Av1s(f) | H264s(f) | H265s(f) => f.frames.iter().any(|e| e.key),
+        _ => false,
+    }
+}
+
 #[cfg(any(feature = "hwcodec", feature = "mediacodec"))]
 pub fn enable_hwcodec_option() -> bool {
     use hbb_common::config::keys::OPTION_ENABLE_HWCODEC;
@@ -0,0 +1,278 @@
+// Damage tracking: which parts of the screen changed since the previous frame.
+//
+// Capturers fill the damage of the last returned frame either from the platform
+// (X11 XDamage, DXGI dirty rects) or from `DamageTracker`, which compares the new
+// buffer against the previous one tile by tile.
+// The video service uses it to skip idle frames, feed ROI hints to the encoder
+// and collect capture statistics.
+
+use std::io;
+
+// Tile edge in pixels used by `DamageTracker`, multiple of 16 so that the tiles
+// line up with encoder macroblocks.
+const TILE_SIZE: usize = 64;
+// Collapse into the bounding box if there are more rects than this.
+pub const MAX_DAMAGE_RECTS: usize = 64;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DamageRect {
+    pub x: i32,
+    pub y: i32,
+    pub w: u32,
+    pub h: u32,
+}
+
+impl DamageRect {
+    pub fn new(x: i32, y: i32, w: u32, h: u32) -> Self {
+        Self { x, y, w, h }
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.w == 0 || self.h == 0
+    }
+
+    #[inline]
+    pub fn area(&self) -> u64 {
+        self.w as u64 * self.h as u64
+    }
+
+    #[inline]
+    fn right(&self) -> i32 {
+        self.x + self.w as i32
+    }
+
+    #[inline]
+    fn bottom(&self) -> i32 {
+        self.y + self.h as i32
+    }
+
+    pub fn union(&self, other: &DamageRect) -> DamageRect {
+        if self.is_empty() {
+            return *other;
+        }
+        if other.is_empty() {
+            return *self;
+        }
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        let r = self.right().max(other.right());
+        let b = self.bottom().max(other.bottom());
+        DamageRect::new(x, y, (r - x) as _, (b - y) as _)
+    }
+
+    pub fn intersect(&self, other: &DamageRect) -> Option<DamageRect> {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let r = self.right().min(other.right());
+        let b = self.bottom().min(other.bottom());
+        if r > x && b > y {
+            Some(DamageRect::new(x, y, (r - x) as _, (b - y) as _))
+        } else {
+            None
+        }
+    }
+
+    // Translate a rect in screen coordinates into a display of the given origin and size.
+    pub fn to_display(
+        &self,
+        origin: (i32, i32),
+        width: usize,
+        height: usize,
+    ) -> Option<DamageRect> {
+        DamageRect::new(self.x - origin.0, self.y - origin.1, self.w, self.h)
+            .intersect(&DamageRect::new(0, 0, width as _, height as _))
+    }
+}
+
+// Merge adjacent rects and collapse them into the bounding box if there are still too many.
+pub fn merge_rects(rects: &mut Vec<DamageRect>) {
+    rects.retain(|r| !r.is_empty());
+    if rects.len() <= 1 {
+        return;
+    }
+    rects.sort_by(|a, b| (a.y, a.x).cmp(&(b.y, b.x)));
+    let mut merged: Vec<DamageRect> = Vec::with_capacity(rects.len());
+    // horizontal neighbours in the same row band
+    for r in rects.drain(..) {
+        if let Some(last) = merged.last_mut() {
+            if last.y == r.y && last.h == r.h && last.right() >= r.x {
+                *last = last.union(&r);
+                continue;
+            }
+        }
+        merged.push(r);
+    }
+    // vertical neighbours with the same horizontal span
+    for r in merged.drain(..) {
+        if let Some(above) = rects
+            .iter_mut()
+            .find(|a| a.x == r.x && a.w == r.w && a.bottom() == r.y)
+        {
+            *above = above.union(&r);
+            continue;
+        }
+        rects.push(r);
+    }
+    if rects.len() > MAX_DAMAGE_RECTS {
+        let bounding = rects
+            .iter()
+            .fold(DamageRect::default(), |acc, r| acc.union(r));
+        rects.clear();
+        rects.push(bounding);
+    }
+}
+
+// Fraction of the frame covered by `rects`, which must not overlap.
+pub fn damage_ratio(rects: &[DamageRect], width: usize, height: usize) -> f32 {
+    let total = width as u64 * height as u64;
+    if total == 0 {
+        return 0.;
+    }
+    let damaged: u64 = rects.iter().map(|r| r.area()).sum();
+    (damaged.min(total) as f64 / total as f64) as f32
+}
+
+/// Compute damage by comparing each frame with the previous one.
+/// It is the fallback for capturers without platform damage reports,
+/// and a drop-in replacement of `would_block_if_equal`.
+#[derive(Default)]
+pub struct DamageTracker {
+    saved: Vec<u8>,
+    width: usize,
+    height: usize,
+    rects: Vec<DamageRect>,
+}
+
+impl DamageTracker {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Returns `WouldBlock` if nothing changed.
+    pub fn update(
+        &mut self,
+        data: &[u8],
+        width: usize,
+        height: usize,
+        stride: usize,
+        bytes_per_pixel: usize,
+    ) -> io::Result<()> {
+        self.rects.clear();
+        if self.saved.len() != data.len()
+            || self.width != width
+            || self.height != height
+            || stride < width * bytes_per_pixel
+            || data.len() < stride * height
+        {
+            self.saved.resize(data.len(), 0);
+            self.saved.copy_from_slice(data);
+            self.width = width;
+            self.height = height;
+            self.rects
+                .push(DamageRect::new(0, 0, width as _, height as _));
+            return Ok(());
+        }
+        for ty in (0..height).step_by(TILE_SIZE) {
+            let th = TILE_SIZE.min(height - ty);
+            for tx in (0..width).step_by(TILE_SIZE) {
+                let tw = TILE_SIZE.min(width - tx);
+                let len = tw * bytes_per_pixel;
+                let changed = (ty..ty + th).any(|y| {
+                    let offset = y * stride + tx * bytes_per_pixel;
+                    data[offset..offset + len] != self.saved[offset..offset + len]
+                });
+                if changed {
+                    for y in ty..ty + th {
+                        let offset = y * stride + tx * bytes_per_pixel;
+                        self.saved[offset..offset + len]
+                            .copy_from_slice(&data[offset..offset + len]);
+                    }
+                    self.rects
+                        .push(DamageRect::new(tx as _, ty as _, tw as _, th as _));
+                }
+            }
+        }
+        if self.rects.is_empty() {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        merge_rects(&mut self.rects);
+        Ok(())
+    }
+
+    #[inline]
+    pub fn rects(&self) -> &[DamageRect] {
+        &self.rects
+    }
+
+    /// The last frame passed to `update`.
+    #[inline]
+    pub fn data(&self) -> &[u8] {
+        &self.saved
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_rects() {
+        let mut rects = vec![
+            DamageRect::new(0, 0, 64, 64),
+            DamageRect::new(64, 0, 64, 64),
+            DamageRect::new(0, 64, 128, 64),
+            DamageRect::new(512, 512, 10, 10),
+        ];
+        merge_rects(&mut rects);
+        assert_eq!(
+            rects,
+            vec![
+                DamageRect::new(0, 0, 128, 128),
+                DamageRect::new(512, 512, 10, 10)
+            ]
+        );
+
+        let mut rects = (0..MAX_DAMAGE_RECTS + 1)
+            .map(|i| DamageRect::new(i as i32 * 20, i as i32 * 20, 10, 10))
+            .collect::<Vec<_>>();
+        merge_rects(&mut rects);
+        assert_eq!(rects.len(), 1);
+        assert_eq!(rects[0].x, 0);
+        assert_eq!(rects[0].right(), MAX_DAMAGE_RECTS as i32 * 20 + 10);
+    }
+
+    #[test]
+    fn test_to_display() {
+        let r = DamageRect::new(1900, 100, 100, 50);
+        assert_eq!(
+            r.to_display((0, 0), 1920, 1080),
+            Some(DamageRect::new(1900, 100, 20, 50))
+        );
+        assert_eq!(
+            r.to_display((1920, 0), 1920, 1080),
+            Some(DamageRect::new(0, 100, 80, 50))
+        );
+        assert_eq!(r.to_display((0, 1080), 1920, 1080), None);
+    }
+
+    #[test]
+    fn test_damage_tracker() {
+        let (w, h, bpp) = (200, 100, 4);
+        let stride = w * bpp;
+        let mut data = vec![0u8; stride * h];
+        let mut tracker = DamageTracker::new();
+        tracker.update(&data, w, h, stride, bpp).unwrap();
+        assert_eq!(tracker.rects(), &[DamageRect::new(0, 0, 200, 100)]);
+        assert_eq!(
+            tracker.update(&data, w, h, stride, bpp).unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+        // one pixel in the last tile column of the second tile row
+        data[70 * stride + 150 * bpp] = 1;
+        tracker.update(&data, w, h, stride, bpp).unwrap();
+        assert_eq!(tracker.rects(), &[DamageRect::new(128, 64, 64, 36)]);
+        assert!((damage_ratio(tracker.rects(), w, h) - 0.1152).abs() < 1e-4);
+        assert!(tracker.update(&data, w, h, stride, bpp).is_err());
+    }
+}
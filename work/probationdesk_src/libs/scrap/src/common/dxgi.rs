@@ -1,6 +1,6 @@
 #[cfg(feature = "vram")]
 use crate::AdapterDevice;
-use crate::{common::TraitCapturer, dxgi, Frame, Pixfmt};
+use crate::{common::TraitCapturer, dxgi, DamageRect, Frame, Pixfmt};
 use std::{
     io::{
         self,
@@ -57,6 +57,10 @@ impl TraitCapturer for Capturer {
         self.inner.set_gdi()
     }
 
+    fn damage(&self) -> Option<Vec<DamageRect>> {
+        self.inner.damage()
+    }
+
     #[cfg(feature = "vram")]
     fn device(&self) -> AdapterDevice {
         self.inner.device()
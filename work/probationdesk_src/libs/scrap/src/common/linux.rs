@@ -4,7 +4,7 @@ use crate::{
         x11::{self},
         TraitCapturer,
     },
-    Frame,
+    DamageRect, Frame,
 };
 use std::{io, time::Duration};
 
@@ -43,6 +43,13 @@ impl TraitCapturer for Capturer {
             Capturer::WAYLAND(d) => d.frame(timeout),
         }
     }
+
+    fn damage(&self) -> Option<Vec<DamageRect>> {
+        match self {
+            Capturer::X11(d) => d.damage(),
+            Capturer::WAYLAND(d) => d.damage(),
+        }
+    }
 }
 
 pub enum Display {
@@ -51,8 +51,11 @@ pub const HW_STRIDE_ALIGN: usize = 0; // recommended by av_frame_get_buffer
 pub mod aom;
 #[cfg(not(any(target_os = "ios")))]
 pub mod camera;
+pub mod damage;
+pub mod text_tiles;
 pub mod record;
 mod vpx;
+pub use self::damage::{DamageRect, DamageTracker};
 
 #[repr(usize)]
 #[derive(Debug, Copy, Clone)]
@@ -141,6 +144,12 @@ pub trait TraitCapturer {
 
     #[cfg(feature = "vram")]
     fn set_output_texture(&mut self, texture: bool);
+
+    // Damage of the last returned frame, in capturer pixel coordinates.
+    // None means unknown, the whole frame should be treated as changed.
+    fn damage(&self) -> Option<Vec<DamageRect>> {
+        None
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -188,7 +197,7 @@ impl Frame<'_> {
     }
 
     pub fn to<'a>(
-        &'a self,
+        &self,
         yuvfmt: EncodeYuvFormat,
         yuv: &'a mut Vec<u8>,
         mid_data: &mut Vec<u8>,
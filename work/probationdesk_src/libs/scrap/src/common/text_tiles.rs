@@ -0,0 +1,382 @@
+// Lossless tiles for screen content.
+//
+// Small text is blurred by lossy codecs even at high quality. When the text
+// lossless mode is on, the server keeps encoding the whole screen as usual, and
+// in addition sends the tiles that have been static for a while and look like
+// text as zstd-compressed BGRA in `RGB` video frames. The client composites them
+// over every decoded frame until they are damaged again or a key frame arrives.
+
+use crate::{DamageRect, ImageFormat, ImageRgb, Pixfmt};
+use hbb_common::{
+    bail,
+    compress::{compress, decompress},
+    message_proto::{RGBTile, VideoFrame, RGB},
+    ResultType,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant},
+};
+
+// Same as the damage tracker tile, so that damage rects map to whole tiles.
+const TILE_SIZE: usize = 64;
+// A tile must be unchanged for this long before it is sent lossless.
+const STATIC_DURATION: Duration = Duration::from_millis(500);
+// Text has a few colors: foreground, background and anti-aliasing shades.
+// Photos and gradients have much more, and a single color tile is cheap for the encoder anyway.
+const MIN_TEXT_COLORS: usize = 2;
+const MAX_TEXT_COLORS: usize = 48;
+// Limit the burst after a big static change, the rest is sent with the next frames.
+const MAX_TILES_PER_FRAME: usize = 32;
+
+#[derive(Debug, Clone, Copy)]
+struct TileState {
+    static_since: Instant,
+    checked: bool,
+    sent: bool,
+}
+
+impl TileState {
+    fn new(now: Instant) -> Self {
+        Self {
+            static_since: now,
+            checked: false,
+            sent: false,
+        }
+    }
+}
+
+/// Server side: decide which tiles are sent lossless and which are dropped.
+#[derive(Default)]
+pub struct TextTileEncoder {
+    // The last frame, the tiles are cut from it.
+    data: Vec<u8>,
+    width: usize,
+    height: usize,
+    stride: usize,
+    rgba: bool,
+    cols: usize,
+    tiles: Vec<TileState>,
+}
+
+impl TextTileEncoder {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Feed a captured frame, `on_damage` follows with its damage from the capturer.
+    /// Returns false if the frame can't be kept.
+    pub fn on_frame(
+        &mut self,
+        data: &[u8],
+        width: usize,
+        height: usize,
+        stride: usize,
+        pixfmt: Pixfmt,
+    ) -> bool {
+        let rgba = match pixfmt {
+            Pixfmt::BGRA => false,
+            Pixfmt::RGBA => true,
+            _ => return false,
+        };
+        if stride < width * 4 || data.len() < stride * height {
+            return false;
+        }
+        if width != self.width || height != self.height || stride != self.stride {
+            self.width = width;
+            self.height = height;
+            self.stride = stride;
+            self.cols = (width + TILE_SIZE - 1) / TILE_SIZE;
+            let rows = (height + TILE_SIZE - 1) / TILE_SIZE;
+            self.tiles = vec![TileState::new(Instant::now()); self.cols * rows];
+        }
+        self.rgba = rgba;
+        self.data.clear();
+        self.data.extend_from_slice(&data[..stride * height]);
+        true
+    }
+
+    /// The damage of the fed frame from the capturer, None if the whole frame changed.
+    /// Returns the frame removing the sent tiles that are damaged, it must be sent before
+    /// the encoded frame so that the client doesn't hide the change.
+    pub fn on_damage(&mut self, damage: Option<&[DamageRect]>) -> Option<VideoFrame> {
+        let now = Instant::now();
+        let whole = [DamageRect::new(0, 0, self.width as _, self.height as _)];
+        let mut dropped = vec![];
+        for r in damage.unwrap_or(&whole[..]) {
+            let Some(r) = r.intersect(&whole[0]) else {
+                continue;
+            };
+            let (x0, y0) = (r.x as usize / TILE_SIZE, r.y as usize / TILE_SIZE);
+            let x1 = (r.x as usize + r.w as usize + TILE_SIZE - 1) / TILE_SIZE;
+            let y1 = (r.y as usize + r.h as usize + TILE_SIZE - 1) / TILE_SIZE;
+            for ty in y0..y1 {
+                for tx in x0..x1.min(self.cols) {
+                    let (x, y, w, h) = self.tile_rect(tx, ty);
+                    let Some(tile) = self.tiles.get_mut(ty * self.cols + tx) else {
+                        continue;
+                    };
+                    if tile.sent {
+                        let mut t = RGBTile::new();
+                        t.x = x as _;
+                        t.y = y as _;
+                        t.width = w as _;
+                        t.height = h as _;
+                        dropped.push(t);
+                    }
+                    *tile = TileState::new(now);
+                }
+            }
+        }
+        if dropped.is_empty() {
+            None
+        } else {
+            Some(tiles_to_video_frame(dropped))
+        }
+    }
+
+    /// Returns the frame carrying the tiles that became static text since the last call.
+    /// Call it after the encoded frame is sent, also when nothing was captured.
+    pub fn promote(&mut self) -> Option<VideoFrame> {
+        let data = &self.data;
+        if self.tiles.is_empty() || data.len() < self.stride * self.height {
+            return None;
+        }
+        let mut promoted = vec![];
+        for i in 0..self.tiles.len() {
+            if promoted.len() >= MAX_TILES_PER_FRAME {
+                break;
+            }
+            let tile = self.tiles[i];
+            if tile.sent || tile.checked || tile.static_since.elapsed() < STATIC_DURATION {
+                continue;
+            }
+            self.tiles[i].checked = true;
+            let (x, y, w, h) = self.tile_rect(i % self.cols, i / self.cols);
+            let mut bgra = Vec::with_capacity(w * h * 4);
+            for row in y..y + h {
+                let offset = row * self.stride + x * 4;
+                bgra.extend_from_slice(&data[offset..offset + w * 4]);
+            }
+            if !is_text_like(&bgra) {
+                continue;
+            }
+            if self.rgba {
+                bgra.chunks_exact_mut(4).for_each(|p| p.swap(0, 2));
+            }
+            self.tiles[i].sent = true;
+            let mut t = RGBTile::new();
+            t.x = x as _;
+            t.y = y as _;
+            t.width = w as _;
+            t.height = h as _;
+            t.data = compress(&bgra).into();
+            promoted.push(t);
+        }
+        if promoted.is_empty() {
+            None
+        } else {
+            Some(tiles_to_video_frame(promoted))
+        }
+    }
+
+    /// The client drops all tiles on a key frame, send them again.
+    pub fn on_key_frame(&mut self) {
+        for tile in self.tiles.iter_mut() {
+            tile.sent = false;
+            tile.checked = false;
+        }
+    }
+
+    fn tile_rect(&self, tx: usize, ty: usize) -> (usize, usize, usize, usize) {
+        let (x, y) = (tx * TILE_SIZE, ty * TILE_SIZE);
+        (
+            x,
+            y,
+            TILE_SIZE.min(self.width - x),
+            TILE_SIZE.min(self.height - y),
+        )
+    }
+}
+
+fn tiles_to_video_frame(tiles: Vec<RGBTile>) -> VideoFrame {
+    let mut rgb = RGB::new();
+    rgb.compress = true;
+    rgb.tiles = tiles;
+    let mut vf = VideoFrame::new();
+    vf.set_rgb(rgb);
+    vf
+}
+
+fn is_text_like(bgra: &[u8]) -> bool {
+    let mut colors = HashSet::new();
+    for p in bgra.chunks_exact(4) {
+        colors.insert(u32::from_le_bytes([p[0], p[1], p[2], 0]));
+        if colors.len() > MAX_TEXT_COLORS {
+            return false;
+        }
+    }
+    colors.len() >= MIN_TEXT_COLORS
+}
+
+struct Tile {
+    width: usize,
+    height: usize,
+    bgra: Vec<u8>,
+}
+
+/// Client side: keep the received tiles and composite them over the decoded image.
+#[derive(Default)]
+pub struct TextTileDecoder {
+    tiles: HashMap<(usize, usize), Tile>,
+}
+
+impl TextTileDecoder {
+    /// Returns true if new tiles are added.
+    pub fn update(&mut self, rgb: &RGB) -> ResultType<bool> {
+        let mut added = false;
+        for t in rgb.tiles.iter() {
+            if t.x < 0 || t.y < 0 || t.width <= 0 || t.height <= 0 {
+                bail!("invalid tile");
+            }
+            let key = (t.x as usize, t.y as usize);
+            if t.data.is_empty() {
+                self.tiles.remove(&key);
+                continue;
+            }
+            let bgra = if rgb.compress {
+                decompress(&t.data)
+            } else {
+                t.data.to_vec()
+            };
+            let (width, height) = (t.width as usize, t.height as usize);
+            if bgra.len() != width * height * 4 {
+                bail!("invalid tile data length");
+            }
+            self.tiles.insert(
+                key,
+                Tile {
+                    width,
+                    height,
+                    bgra,
+                },
+            );
+            added = true;
+        }
+        Ok(added)
+    }
+
+    pub fn clear(&mut self) {
+        self.tiles.clear();
+    }
+
+    // rgb [in/out] fmt, stride and size must be set in ImageRgb
+    pub fn composite(&self, rgb: &mut ImageRgb) {
+        let bytes_per_pixel = match rgb.fmt {
+            ImageFormat::Raw => 3,
+            ImageFormat::ARGB | ImageFormat::ABGR => 4,
+        };
+        let align = rgb.align().max(1);
+        let bytes_per_row = (rgb.w * bytes_per_pixel + align - 1) & !(align - 1);
+        if rgb.raw.len() < bytes_per_row * rgb.h {
+            return;
+        }
+        for (&(x, y), tile) in self.tiles.iter() {
+            if x + tile.width > rgb.w || y + tile.height > rgb.h {
+                continue;
+            }
+            for row in 0..tile.height {
+                let src = &tile.bgra[row * tile.width * 4..(row + 1) * tile.width * 4];
+                let offset = (y + row) * bytes_per_row + x * bytes_per_pixel;
+                let dst = &mut rgb.raw[offset..offset + tile.width * bytes_per_pixel];
+                match rgb.fmt {
+                    ImageFormat::ARGB => dst.copy_from_slice(src),
+                    ImageFormat::ABGR => {
+                        for (d, s) in dst.chunks_exact_mut(4).zip(src.chunks_exact(4)) {
+                            d.copy_from_slice(&[s[2], s[1], s[0], s[3]]);
+                        }
+                    }
+                    ImageFormat::Raw => {
+                        for (d, s) in dst.chunks_exact_mut(3).zip(src.chunks_exact(4)) {
+                            d.copy_from_slice(&[s[2], s[1], s[0]]);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hbb_common::message_proto::video_frame;
+
+    fn text_frame(width: usize, height: usize) -> Vec<u8> {
+        let mut data = vec![0xFFu8; width * height * 4];
+        for (i, p) in data.chunks_exact_mut(4).enumerate() {
+            if i % 7 == 0 {
+                p.copy_from_slice(&[0, 0, 0, 0xFF]);
+            }
+        }
+        data
+    }
+
+    #[test]
+    fn test_text_tiles() {
+        let (w, h) = (100, 70);
+        let mut data = text_frame(w, h);
+        let mut encoder = TextTileEncoder::new();
+        assert!(encoder.on_frame(&data, w, h, w * 4, Pixfmt::BGRA));
+        assert!(encoder.on_damage(None).is_none());
+        assert!(encoder.promote().is_none());
+        for tile in encoder.tiles.iter_mut() {
+            tile.static_since -= STATIC_DURATION;
+        }
+        let vf = encoder.promote().unwrap();
+        let Some(video_frame::Union::Rgb(rgb)) = &vf.union else {
+            panic!("not rgb");
+        };
+        assert_eq!(rgb.tiles.len(), 4);
+        assert_eq!(rgb.tiles[3].width, 36);
+        assert_eq!(rgb.tiles[3].height, 6);
+        assert!(encoder.promote().is_none());
+
+        let mut decoder = TextTileDecoder::default();
+        assert!(decoder.update(rgb).unwrap());
+        let mut image = ImageRgb::new(ImageFormat::ABGR, 64);
+        image.w = w;
+        image.h = h;
+        image.raw = vec![0x80; 448 * h];
+        decoder.composite(&mut image);
+        assert_eq!(&image.raw[0..8], &[0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(&image.raw[400..448], &[0x80; 48]);
+
+        // a change the capturer doesn't report is not seen
+        data[0] = 0x10;
+        assert!(encoder.on_frame(&data, w, h, w * 4, Pixfmt::BGRA));
+        assert!(encoder.on_damage(Some(&[][..])).is_none());
+
+        // damage the last tile
+        data[(h - 1) * w * 4 + (w - 1) * 4] = 0x10;
+        assert!(encoder.on_frame(&data, w, h, w * 4, Pixfmt::BGRA));
+        let damage = [DamageRect::new(w as i32 - 1, h as i32 - 1, 1, 1)];
+        let vf = encoder.on_damage(Some(&damage[..])).unwrap();
+        let Some(video_frame::Union::Rgb(rgb)) = &vf.union else {
+            panic!("not rgb");
+        };
+        assert_eq!(rgb.tiles.len(), 1);
+        assert_eq!((rgb.tiles[0].x, rgb.tiles[0].y), (64, 64));
+        assert!(rgb.tiles[0].data.is_empty());
+        assert!(!decoder.update(rgb).unwrap());
+        assert_eq!(decoder.tiles.len(), 3);
+    }
+
+    #[test]
+    fn test_is_text_like() {
+        assert!(!is_text_like(&[0xFF; 64]));
+        assert!(is_text_like(&text_frame(8, 8)));
+        let gradient: Vec<u8> = (0..=255u8).flat_map(|i| [i, i, i, 0xFF]).collect();
+        assert!(!is_text_like(&gradient));
+    }
+}
@@ -9,7 +9,7 @@ use hbb_common::message_proto::{Chroma, EncodedVideoFrame, EncodedVideoFrames, V
 use hbb_common::ResultType;
 
 use crate::codec::{base_bitrate, codec_thread_num, EncoderApi};
-use crate::{EncodeInput, EncodeYuvFormat, GoogleImage, Pixfmt, STRIDE_ALIGN};
+use crate::{DamageRect, EncodeInput, EncodeYuvFormat, GoogleImage, Pixfmt, STRIDE_ALIGN};
 
 use super::vpx::{vp8e_enc_control_id::*, vpx_codec_err_t::*, *};
 use crate::{generate_call_macro, generate_call_ptr_macro, Error, Result};
@@ -20,6 +20,11 @@ use std::{ptr, slice};
 generate_call_macro!(call_vpx, false);
 generate_call_ptr_macro!(call_vpx_ptr);
 
+// VP9 ROI map works on 8x8 blocks, segment 1 is the damaged region.
+const ROI_BLOCK_SIZE: usize = 8;
+const ROI_SEGMENT_DAMAGED: u8 = 1;
+const ROI_DAMAGED_DELTA_Q: c_int = -10;
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum VpxVideoCodecId {
     VP8,
@@ -39,6 +44,9 @@ pub struct VpxEncoder {
     id: VpxVideoCodecId,
     i444: bool,
     yuvfmt: EncodeYuvFormat,
+    roi_map: Vec<u8>,
+    roi_enabled: bool,
+    roi_failed: bool,
 }
 
 pub struct VpxDecoder {
@@ -165,6 +173,9 @@ impl EncoderApi for VpxEncoder {
                     id: config.codec,
                     i444,
                     yuvfmt: Self::get_yuvfmt(config.width, config.height, i444),
+                    roi_map: Vec::new(),
+                    roi_enabled: false,
+                    roi_failed: false,
                 })
             }
             _ => Err(anyhow!("encoder type mismatch")),
@@ -228,6 +239,52 @@ impl EncoderApi for VpxEncoder {
     }
 
     fn disable(&self) {}
+
+    fn set_roi(&mut self, rects: &[DamageRect]) -> ResultType<()> {
+        if self.id != VpxVideoCodecId::VP9 || self.roi_failed {
+            return Ok(());
+        }
+        let enabled = !rects.is_empty();
+        if !enabled && !self.roi_enabled {
+            return Ok(());
+        }
+        let rows = (self.height + ROI_BLOCK_SIZE - 1) / ROI_BLOCK_SIZE;
+        let cols = (self.width + ROI_BLOCK_SIZE - 1) / ROI_BLOCK_SIZE;
+        self.roi_map.clear();
+        self.roi_map.resize(rows * cols, 0);
+        for r in rects {
+            let x0 = (r.x.max(0) as usize / ROI_BLOCK_SIZE).min(cols);
+            let y0 = (r.y.max(0) as usize / ROI_BLOCK_SIZE).min(rows);
+            let x1 = ((r.x.max(0) as usize + r.w as usize + ROI_BLOCK_SIZE - 1) / ROI_BLOCK_SIZE)
+                .min(cols);
+            let y1 = ((r.y.max(0) as usize + r.h as usize + ROI_BLOCK_SIZE - 1) / ROI_BLOCK_SIZE)
+                .min(rows);
+            for y in y0..y1 {
+                self.roi_map[y * cols + x0..y * cols + x1].fill(ROI_SEGMENT_DAMAGED);
+            }
+        }
+        let mut roi: vpx_roi_map_t = unsafe { std::mem::zeroed() };
+        roi.enabled = enabled as _;
+        roi.roi_map = self.roi_map.as_mut_ptr();
+        roi.rows = rows as _;
+        roi.cols = cols as _;
+        roi.delta_q[ROI_SEGMENT_DAMAGED as usize] = ROI_DAMAGED_DELTA_Q;
+        let res = unsafe {
+            vpx_codec_control_(
+                &mut self.ctx,
+                VP9E_SET_ROI_MAP as _,
+                &mut roi as *mut vpx_roi_map_t,
+            )
+        };
+        if res != VPX_CODEC_OK {
+            // Not supported with the current encoder settings, don't try again.
+            self.roi_failed = true;
+            log::warn!("Failed to set vp9 roi map: {:?}", res);
+            return Ok(());
+        }
+        self.roi_enabled = enabled;
+        Ok(())
+    }
 }
 
 impl VpxEncoder {
@@ -1,6 +1,6 @@
 use crate::{
     wayland::{capturable::*, *},
-    Frame, TraitCapturer,
+    DamageRect, Frame, TraitCapturer,
 };
 use std::{io, sync::RwLock, time::Duration};
 
@@ -59,6 +59,10 @@ impl TraitCapturer for Capturer {
             _ => Err(map_err("Invalid data")),
         }
     }
+
+    fn damage(&self) -> Option<Vec<DamageRect>> {
+        self.1.damage()
+    }
 }
 
 pub struct Display(pipewire::PipeWireCapturable);
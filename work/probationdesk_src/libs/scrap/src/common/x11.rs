@@ -1,4 +1,4 @@
-use crate::{common::TraitCapturer, x11, Frame, Pixfmt, TraitPixelBuffer};
+use crate::{common::TraitCapturer, x11, DamageRect, Frame, Pixfmt, TraitPixelBuffer};
 use std::{io, time::Duration};
 
 pub struct Capturer(x11::Capturer);
@@ -31,6 +31,10 @@ impl TraitCapturer for Capturer {
             height,
         )))
     }
+
+    fn damage(&self) -> Option<Vec<DamageRect>> {
+        Some(self.0.damage().to_vec())
+    }
 }
 
 pub struct PixelBuffer<'a> {
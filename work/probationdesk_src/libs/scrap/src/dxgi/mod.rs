@@ -22,7 +22,7 @@ use winapi::{
 
 use crate::RotationMode::*;
 
-use crate::{AdapterDevice, Frame, PixelBuffer};
+use crate::{AdapterDevice, DamageRect, DamageTracker, Frame, PixelBuffer};
 use std::ffi::c_void;
 
 pub struct ComPtr<T>(*mut T);
@@ -54,7 +54,8 @@ pub struct Capturer {
     rotated: Vec<u8>,
     gdi_capturer: Option<CapturerGDI>,
     gdi_buffer: Vec<u8>,
-    saved_raw_data: Vec<u8>, // for faster compare and copy
+    tracker: DamageTracker, // gdi has no dirty rects
+    damage: Option<Vec<DamageRect>>,
     output_texture: bool,
     adapter_desc1: DXGI_ADAPTER_DESC1,
     rotate: Rotate,
@@ -170,7 +171,8 @@ impl Capturer {
             rotated: Vec::new(),
             gdi_capturer,
             gdi_buffer: Vec::new(),
-            saved_raw_data: Vec::new(),
+            tracker: DamageTracker::new(),
+            damage: None,
             output_texture: false,
             adapter_desc1,
             rotate,
@@ -340,6 +342,10 @@ impl Capturer {
         if *info.LastPresentTime.QuadPart() == 0 {
             return Err(std::io::ErrorKind::WouldBlock.into());
         }
+        self.damage = self.load_damage(info.TotalMetadataBufferSize);
+        if self.damage.as_ref().map(|d| d.is_empty()) == Some(true) {
+            return Err(std::io::ErrorKind::WouldBlock.into());
+        }
 
         #[allow(invalid_value)]
         let mut rect = mem::MaybeUninit::uninit().assume_init();
@@ -352,6 +358,56 @@ impl Capturer {
         Ok((rect.pBits, rect.Pitch))
     }
 
+    // Dirty rects and the destinations of move rects of the acquired frame.
+    unsafe fn load_damage(&mut self, metadata_size: UINT) -> Option<Vec<DamageRect>> {
+        if metadata_size == 0 {
+            return None;
+        }
+        let mut rects = Vec::new();
+
+        let mut move_rects: Vec<DXGI_OUTDUPL_MOVE_RECT> =
+            vec![
+                mem::zeroed();
+                metadata_size as usize / mem::size_of::<DXGI_OUTDUPL_MOVE_RECT>() + 1
+            ];
+        let mut required = 0;
+        if S_OK
+            != (*self.duplication.0).GetFrameMoveRects(
+                (move_rects.len() * mem::size_of::<DXGI_OUTDUPL_MOVE_RECT>()) as _,
+                move_rects.as_mut_ptr(),
+                &mut required,
+            )
+        {
+            return None;
+        }
+        let n = required as usize / mem::size_of::<DXGI_OUTDUPL_MOVE_RECT>();
+        rects.extend(
+            move_rects[..n]
+                .iter()
+                .map(|r| rect_to_damage(&r.DestinationRect)),
+        );
+
+        let mut dirty_rects: Vec<RECT> =
+            vec![mem::zeroed(); metadata_size as usize / mem::size_of::<RECT>() + 1];
+        if S_OK
+            != (*self.duplication.0).GetFrameDirtyRects(
+                (dirty_rects.len() * mem::size_of::<RECT>()) as _,
+                dirty_rects.as_mut_ptr(),
+                &mut required,
+            )
+        {
+            return None;
+        }
+        let n = required as usize / mem::size_of::<RECT>();
+        rects.extend(dirty_rects[..n].iter().map(rect_to_damage));
+        crate::damage::merge_rects(&mut rects);
+        Some(rects)
+    }
+
+    pub fn damage(&self) -> Option<Vec<DamageRect>> {
+        self.damage.clone()
+    }
+
     // copy from GPU memory to system memory
     unsafe fn ohgodwhat(&mut self, frame: *mut IDXGIResource) -> io::Result<*mut IDXGISurface> {
         let mut texture: *mut ID3D11Texture2D = ptr::null_mut();
@@ -391,6 +447,7 @@ impl Capturer {
     }
 
     pub fn frame<'a>(&'a mut self, timeout: UINT) -> io::Result<Frame<'a>> {
+        self.damage = None;
         if self.output_texture {
             Ok(Frame::Texture(self.get_texture(timeout)?))
         } else {
@@ -413,10 +470,14 @@ impl Capturer {
                 if let Some(gdi_capturer) = &self.gdi_capturer {
                     match gdi_capturer.frame(&mut self.gdi_buffer) {
                         Ok(_) => {
-                            crate::would_block_if_equal(
-                                &mut self.saved_raw_data,
+                            self.tracker.update(
                                 &self.gdi_buffer,
+                                self.width,
+                                self.height,
+                                self.width * 4,
+                                4,
                             )?;
+                            self.damage = Some(self.tracker.rects().to_vec());
                             &self.gdi_buffer
                         }
                         Err(err) => {
@@ -441,6 +502,8 @@ impl Capturer {
                     if rotate == kRotate0 {
                         slice::from_raw_parts(r.0, r.1 as usize * self.height)
                     } else {
+                        // Dirty rects are in the coordinates of the unrotated surface.
+                        self.damage = None;
                         self.rotated.resize(self.width * self.height * 4, 0);
                         crate::common::ARGBRotate(
                             r.0,
@@ -855,6 +918,16 @@ impl Display {
     }
 }
 
+#[inline]
+fn rect_to_damage(r: &RECT) -> DamageRect {
+    DamageRect::new(
+        r.left,
+        r.top,
+        (r.right - r.left).max(0) as _,
+        (r.bottom - r.top).max(0) as _,
+    )
+}
+
 fn wrap_hresult(x: HRESULT) -> io::Result<()> {
     use std::io::ErrorKind::*;
     Err((match x {
@@ -1,3 +1,4 @@
+use crate::DamageRect;
 use std::boxed::Box;
 use std::error::Error;
 
@@ -25,6 +26,10 @@ impl<'a> PixelProvider<'a> {
 
 pub trait Recorder {
     fn capture(&mut self, timeout_ms: u64) -> Result<PixelProvider, Box<dyn Error>>;
+
+    fn damage(&self) -> Option<Vec<DamageRect>> {
+        None
+    }
 }
 
 pub trait BoxCloneCapturable {
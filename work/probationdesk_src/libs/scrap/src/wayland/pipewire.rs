@@ -24,6 +24,7 @@ use super::capturable::{Capturable, Recorder};
 use super::remote_desktop_portal::OrgFreedesktopPortalRemoteDesktop as remote_desktop_portal;
 use super::request_portal::OrgFreedesktopPortalRequestResponse;
 use super::screencast_portal::OrgFreedesktopPortalScreenCast as screencast_portal;
+use crate::{DamageRect, DamageTracker};
 use hbb_common::platform::linux::CMD_SH;
 use lazy_static::lazy_static;
 
@@ -210,7 +211,9 @@ pub struct PipeWireRecorder {
     appsink: AppSink,
     width: usize,
     height: usize,
-    saved_raw_data: Vec<u8>, // for faster compare and copy
+    // pipewiresrc does not forward the SPA damage meta, so compare the buffers here.
+    tracker: DamageTracker,
+    damage: Vec<DamageRect>,
 }
 
 impl PipeWireRecorder {
@@ -257,7 +260,8 @@ impl PipeWireRecorder {
             height: 0,
             buffer_cropped: vec![],
             is_cropped: false,
-            saved_raw_data: Vec::new(),
+            tracker: DamageTracker::new(),
+            damage: Vec::new(),
         })
     }
 }
@@ -295,7 +299,7 @@ impl Recorder for PipeWireRecorder {
             let buf = buf
                 .into_mapped_buffer_readable()
                 .map_err(|_| GStreamerError("Failed to map buffer.".into()))?;
-            if let Err(..) = crate::would_block_if_equal(&mut self.saved_raw_data, buf.as_slice()) {
+            if let Err(..) = self.tracker.update(buf.as_slice(), w, h, w * 4, 4) {
                 return Ok(PixelProvider::NONE);
             }
             let buf_size = buf.get_size();
@@ -334,6 +338,13 @@ impl Recorder for PipeWireRecorder {
                     self.height = h;
                 }
                 self.is_cropped = crop.is_some();
+                let (x_off, y_off) = crop.map(|c| (c.0 as i32, c.1 as i32)).unwrap_or((0, 0));
+                self.damage = self
+                    .tracker
+                    .rects()
+                    .iter()
+                    .filter_map(|r| r.to_display((x_off, y_off), self.width, self.height))
+                    .collect();
                 self.buffer = Some(buf);
             }
         } else {
@@ -359,6 +370,10 @@ impl Recorder for PipeWireRecorder {
             )))),
         }
     }
+
+    fn damage(&self) -> Option<Vec<DamageRect>> {
+        Some(self.damage.clone())
+    }
 }
 
 impl Drop for PipeWireRecorder {
@@ -1,6 +1,7 @@
 use super::ffi::*;
 use super::Display;
-use hbb_common::libc;
+use crate::{DamageRect, DamageTracker};
+use hbb_common::{libc, log};
 use std::{io, ptr, slice};
 
 pub struct Capturer {
@@ -10,7 +11,9 @@ pub struct Capturer {
     buffer: *const u8,
 
     size: usize,
-    saved_raw_data: Vec<u8>, // for faster compare and copy
+    xdamage: Option<XDamage>,
+    tracker: DamageTracker, // fallback if xdamage is not available
+    rects: Vec<DamageRect>,
 }
 
 impl Capturer {
@@ -57,13 +60,18 @@ impl Capturer {
             );
         }
 
+        let xdamage = XDamage::new(&display);
+        log::info!("x11 capturer, xdamage: {}", xdamage.is_some());
+
         let c = Capturer {
             display,
             shmid,
             xcbid,
             buffer,
             size,
-            saved_raw_data: Vec::new(),
+            xdamage,
+            tracker: DamageTracker::new(),
+            rects: Vec::new(),
         };
         Ok(c)
     }
@@ -94,15 +102,152 @@ impl Capturer {
     }
 
     pub fn frame<'b>(&'b mut self) -> std::io::Result<&'b [u8]> {
+        let rect = self.display.rect();
+        let (width, height) = (rect.w as usize, rect.h as usize);
+        if let Some(xdamage) = self.xdamage.as_mut() {
+            // Skip fetching the image if nothing on this display has changed.
+            let rects = xdamage.take_rects();
+            self.rects = rects
+                .iter()
+                .filter_map(|r| r.to_display((rect.x as _, rect.y as _), width, height))
+                .collect();
+            crate::damage::merge_rects(&mut self.rects);
+            if self.rects.is_empty() {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            self.get_image();
+            return Ok(unsafe { slice::from_raw_parts(self.buffer, self.size) });
+        }
         self.get_image();
         let result = unsafe { slice::from_raw_parts(self.buffer, self.size) };
-        crate::would_block_if_equal(&mut self.saved_raw_data, result)?;
+        let bytes_per_pixel = self.display.pixfmt().bytes_per_pixel();
+        self.tracker.update(
+            result,
+            width,
+            height,
+            width * bytes_per_pixel,
+            bytes_per_pixel,
+        )?;
+        self.rects = self.tracker.rects().to_vec();
         Ok(result)
     }
+
+    pub fn damage(&self) -> &[DamageRect] {
+        &self.rects
+    }
+}
+
+// Damage object on the root window, polled with DamageSubtract before every capture.
+struct XDamage {
+    server: *mut xcb_connection_t,
+    damage: xcb_damage_damage_t,
+    region: xcb_xfixes_region_t,
+    first: bool,
+}
+
+impl XDamage {
+    fn new(display: &Display) -> Option<Self> {
+        let server = display.server().raw();
+        unsafe {
+            // Both extensions must be initialized before their requests are used.
+            let reply = xcb_xfixes_query_version_reply(
+                server,
+                xcb_xfixes_query_version(server, 2, 0),
+                ptr::null_mut(),
+            );
+            if reply.is_null() {
+                return None;
+            }
+            libc::free(reply as *mut _);
+            let reply = xcb_damage_query_version_reply(
+                server,
+                xcb_damage_query_version(server, 1, 1),
+                ptr::null_mut(),
+            );
+            if reply.is_null() {
+                return None;
+            }
+            libc::free(reply as *mut _);
+
+            let damage = xcb_generate_id(server);
+            let cookie = xcb_damage_create_checked(
+                server,
+                damage,
+                display.root(),
+                XCB_DAMAGE_REPORT_LEVEL_NON_EMPTY,
+            );
+            let err = xcb_request_check(server, cookie);
+            if !err.is_null() {
+                libc::free(err as *mut _);
+                return None;
+            }
+            let region = xcb_generate_id(server);
+            xcb_xfixes_create_region(server, region, 0, ptr::null());
+            Some(Self {
+                server,
+                damage,
+                region,
+                first: true,
+            })
+        }
+    }
+
+    // Damaged rects in screen coordinates since the last call.
+    fn take_rects(&mut self) -> Vec<DamageRect> {
+        let mut rects = Vec::new();
+        unsafe {
+            // Damage notify events are not used, drop them so they don't pile up.
+            loop {
+                let event = xcb_poll_for_event(self.server);
+                if event.is_null() {
+                    break;
+                }
+                libc::free(event as *mut _);
+            }
+            xcb_damage_subtract(self.server, self.damage, XCB_NONE, self.region);
+            let reply = xcb_xfixes_fetch_region_reply(
+                self.server,
+                xcb_xfixes_fetch_region(self.server, self.region),
+                ptr::null_mut(),
+            );
+            if !reply.is_null() {
+                let ptr = xcb_xfixes_fetch_region_rectangles(reply);
+                let len = xcb_xfixes_fetch_region_rectangles_length(reply);
+                if !ptr.is_null() && len > 0 {
+                    for r in slice::from_raw_parts(ptr, len as usize) {
+                        rects.push(DamageRect::new(
+                            r.x as _,
+                            r.y as _,
+                            r.width as _,
+                            r.height as _,
+                        ));
+                    }
+                }
+                libc::free(reply as *mut _);
+            }
+        }
+        if self.first {
+            // The first frame is always full.
+            self.first = false;
+            rects.push(DamageRect::new(0, 0, u16::MAX as _, u16::MAX as _));
+        }
+        rects
+    }
+}
+
+impl Drop for XDamage {
+    fn drop(&mut self) {
+        unsafe {
+            xcb_xfixes_destroy_region(self.server, self.region);
+            xcb_damage_destroy(self.server, self.damage);
+        }
+    }
 }
 
 impl Drop for Capturer {
     fn drop(&mut self) {
+        // Release the damage object while the connection is still alive.
+        self.xdamage.take();
         unsafe {
             // Detach segment from XCB.
             xcb_shm_detach(self.display.server().raw(), self.xcbid);
@@ -100,9 +100,95 @@ extern "C" {
         e: *mut *mut xcb_generic_error_t,
     ) -> *mut xcb_get_geometry_reply_t;
 
+    pub fn xcb_poll_for_event(c: *mut xcb_connection_t) -> *mut c_void;
+
+    pub fn xcb_request_check(
+        c: *mut xcb_connection_t,
+        cookie: xcb_void_cookie_t,
+    ) -> *mut xcb_generic_error_t;
+}
+
+#[link(name = "xcb-damage")]
+#[link(name = "xcb-xfixes")]
+extern "C" {
+    pub fn xcb_damage_query_version(
+        c: *mut xcb_connection_t,
+        client_major_version: u32,
+        client_minor_version: u32,
+    ) -> xcb_damage_query_version_cookie_t;
+
+    pub fn xcb_damage_query_version_reply(
+        c: *mut xcb_connection_t,
+        cookie: xcb_damage_query_version_cookie_t,
+        e: *mut *mut xcb_generic_error_t,
+    ) -> *mut xcb_damage_query_version_reply_t;
+
+    pub fn xcb_damage_create_checked(
+        c: *mut xcb_connection_t,
+        damage: xcb_damage_damage_t,
+        drawable: xcb_drawable_t,
+        level: u8,
+    ) -> xcb_void_cookie_t;
+
+    pub fn xcb_damage_destroy(
+        c: *mut xcb_connection_t,
+        damage: xcb_damage_damage_t,
+    ) -> xcb_void_cookie_t;
+
+    pub fn xcb_damage_subtract(
+        c: *mut xcb_connection_t,
+        damage: xcb_damage_damage_t,
+        repair: xcb_xfixes_region_t,
+        parts: xcb_xfixes_region_t,
+    ) -> xcb_void_cookie_t;
+
+    pub fn xcb_xfixes_query_version(
+        c: *mut xcb_connection_t,
+        client_major_version: u32,
+        client_minor_version: u32,
+    ) -> xcb_xfixes_query_version_cookie_t;
+
+    pub fn xcb_xfixes_query_version_reply(
+        c: *mut xcb_connection_t,
+        cookie: xcb_xfixes_query_version_cookie_t,
+        e: *mut *mut xcb_generic_error_t,
+    ) -> *mut xcb_xfixes_query_version_reply_t;
+
+    pub fn xcb_xfixes_create_region(
+        c: *mut xcb_connection_t,
+        region: xcb_xfixes_region_t,
+        rectangles_len: u32,
+        rectangles: *const xcb_rectangle_t,
+    ) -> xcb_void_cookie_t;
+
+    pub fn xcb_xfixes_destroy_region(
+        c: *mut xcb_connection_t,
+        region: xcb_xfixes_region_t,
+    ) -> xcb_void_cookie_t;
+
+    pub fn xcb_xfixes_fetch_region(
+        c: *mut xcb_connection_t,
+        region: xcb_xfixes_region_t,
+    ) -> xcb_xfixes_fetch_region_cookie_t;
+
+    pub fn xcb_xfixes_fetch_region_reply(
+        c: *mut xcb_connection_t,
+        cookie: xcb_xfixes_fetch_region_cookie_t,
+        e: *mut *mut xcb_generic_error_t,
+    ) -> *mut xcb_xfixes_fetch_region_reply_t;
+
+    pub fn xcb_xfixes_fetch_region_rectangles(
+        r: *const xcb_xfixes_fetch_region_reply_t,
+    ) -> *mut xcb_rectangle_t;
+
+    pub fn xcb_xfixes_fetch_region_rectangles_length(
+        r: *const xcb_xfixes_fetch_region_reply_t,
+    ) -> i32;
 }
 
 pub const XCB_IMAGE_FORMAT_Z_PIXMAP: u8 = 2;
+pub const XCB_DAMAGE_REPORT_LEVEL_NON_EMPTY: u8 = 3;
+pub const XCB_NONE: u32 = 0;
 
 pub type xcb_atom_t = u32;
 pub type xcb_connection_t = c_void;
@@ -116,6 +202,8 @@ pub type xcb_drawable_t = u32;
 pub type xcb_get_atom_name_cookie_t = u32;
 pub type xcb_get_atom_name_reply_t = u32;
 pub type xcb_get_atom_name_request_t = xcb_get_atom_name_reply_t;
+pub type xcb_damage_damage_t = u32;
+pub type xcb_xfixes_region_t = u32;
 
 #[repr(C)]
 pub struct xcb_setup_t {
@@ -281,3 +369,62 @@ pub struct xcb_get_geometry_reply_t {
     pub border_width: u16,
     pub pad0: [u8; 2],
 }
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct xcb_rectangle_t {
+    pub x: i16,
+    pub y: i16,
+    pub width: u16,
+    pub height: u16,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct xcb_damage_query_version_cookie_t {
+    pub sequence: u32,
+}
+
+#[repr(C)]
+pub struct xcb_damage_query_version_reply_t {
+    pub response_type: u8,
+    pub pad0: u8,
+    pub sequence: u16,
+    pub length: u32,
+    pub major_version: u32,
+    pub minor_version: u32,
+    pub pad1: [u8; 16],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct xcb_xfixes_query_version_cookie_t {
+    pub sequence: u32,
+}
+
+#[repr(C)]
+pub struct xcb_xfixes_query_version_reply_t {
+    pub response_type: u8,
+    pub pad0: u8,
+    pub sequence: u16,
+    pub length: u32,
+    pub major_version: u32,
+    pub minor_version: u32,
+    pub pad1: [u8; 16],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct xcb_xfixes_fetch_region_cookie_t {
+    pub sequence: u32,
+}
+
+#[repr(C)]
+pub struct xcb_xfixes_fetch_region_reply_t {
+    pub response_type: u8,
+    pub pad0: u8,
+    pub sequence: u16,
+    pub length: u32,
+    pub extents: xcb_rectangle_t,
+    pub pad1: [u8; 16],
+}
@@ -0,0 +1,779 @@
+// The local address book, it works without an API server.
+//
+// Sync with the server is a three-way merge of the last synced state, the local and the remote
+// address book. A peer edited on both sides is reported as a conflict and left untouched on
+// both sides until it is resolved with `resolve_conflict()`.
+
+use hbb_common::{
+    bail,
+    config::{AbConflict, AbPeer, LocalAb, LocalConfig},
+    lazy_static, log, ResultType,
+};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::Mutex,
+};
+
+const CSV_COLUMNS: [&str; 6] = ["id", "alias", "username", "hostname", "platform", "tags"];
+// Tags are joined with this in one csv column.
+const CSV_TAG_SEPARATOR: char = ';';
+
+lazy_static::lazy_static! {
+    // Serializes the load-modify-store of the local address book.
+    static ref LOCK: Mutex<()> = Default::default();
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Csv,
+    Json,
+}
+
+impl std::str::FromStr for Format {
+    type Err = hbb_common::anyhow::Error;
+
+    fn from_str(s: &str) -> ResultType<Self> {
+        match s.to_lowercase().as_str() {
+            "csv" => Ok(Self::Csv),
+            "json" => Ok(Self::Json),
+            _ => bail!("Unsupported address book format: {}", s),
+        }
+    }
+}
+
+/// The address book data as the legacy `/api/ab` API stores it.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+struct AbData {
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    peers: Vec<AbPeer>,
+    #[serde(default)]
+    tag_colors: String,
+}
+
+#[derive(Debug, Default, Serialize, Clone)]
+pub struct SyncReport {
+    pub pulled: usize,
+    pub pushed: usize,
+    pub conflicts: Vec<AbConflict>,
+}
+
+fn modify<R>(f: impl FnOnce(&mut LocalAb) -> ResultType<R>) -> ResultType<R> {
+    let _lock = LOCK.lock().unwrap();
+    let mut ab = LocalAb::load();
+    let res = f(&mut ab)?;
+    ab.store()?;
+    Ok(res)
+}
+
+pub fn load() -> LocalAb {
+    let _lock = LOCK.lock().unwrap();
+    LocalAb::load()
+}
+
+fn parse_tag_colors(tag_colors: &str) -> BTreeMap<String, i64> {
+    serde_json::from_str(tag_colors).unwrap_or_default()
+}
+
+fn add_missing_tags(ab: &mut LocalAb, tags: &[String]) {
+    for tag in tags {
+        if !tag.is_empty() && !ab.tags.contains(tag) {
+            ab.tags.push(tag.clone());
+        }
+    }
+}
+
+pub fn add_peer(peer: AbPeer) -> ResultType<()> {
+    if peer.id.is_empty() {
+        bail!("Empty peer id");
+    }
+    modify(|ab| {
+        if ab.peers.iter().any(|p| p.id == peer.id) {
+            bail!("Peer {} already exists", peer.id);
+        }
+        add_missing_tags(ab, &peer.tags);
+        ab.peers.push(peer);
+        Ok(())
+    })
+}
+
+pub fn update_peer(peer: AbPeer) -> ResultType<()> {
+    modify(|ab| {
+        let Some(index) = ab.peers.iter().position(|p| p.id == peer.id) else {
+            bail!("Peer {} not found", peer.id);
+        };
+        add_missing_tags(ab, &peer.tags);
+        ab.peers[index] = peer;
+        Ok(())
+    })
+}
+
+pub fn remove_peers(ids: &[String]) -> ResultType<()> {
+    modify(|ab| {
+        ab.peers.retain(|p| !ids.contains(&p.id));
+        Ok(())
+    })
+}
+
+pub fn add_tag(name: &str) -> ResultType<()> {
+    if name.is_empty() {
+        bail!("Empty tag name");
+    }
+    modify(|ab| {
+        add_missing_tags(ab, &[name.to_owned()]);
+        Ok(())
+    })
+}
+
+pub fn rename_tag(old: &str, new: &str) -> ResultType<()> {
+    if new.is_empty() {
+        bail!("Empty tag name");
+    }
+    modify(|ab| {
+        if ab.tags.iter().any(|t| t == new) {
+            bail!("Tag {} already exists", new);
+        }
+        let Some(tag) = ab.tags.iter_mut().find(|t| t.as_str() == old) else {
+            bail!("Tag {} not found", old);
+        };
+        *tag = new.to_owned();
+        for peer in ab.peers.iter_mut() {
+            for tag in peer.tags.iter_mut().filter(|t| t.as_str() == old) {
+                *tag = new.to_owned();
+            }
+        }
+        let mut colors = parse_tag_colors(&ab.tag_colors);
+        if let Some(color) = colors.remove(old) {
+            colors.insert(new.to_owned(), color);
+        }
+        ab.tag_colors = serde_json::to_string(&colors)?;
+        Ok(())
+    })
+}
+
+pub fn remove_tags(names: &[String]) -> ResultType<()> {
+    modify(|ab| {
+        ab.tags.retain(|t| !names.contains(t));
+        for peer in ab.peers.iter_mut() {
+            peer.tags.retain(|t| !names.contains(t));
+        }
+        let mut colors = parse_tag_colors(&ab.tag_colors);
+        colors.retain(|t, _| !names.contains(t));
+        ab.tag_colors = serde_json::to_string(&colors)?;
+        Ok(())
+    })
+}
+
+pub fn set_tag_color(name: &str, color: i64) -> ResultType<()> {
+    modify(|ab| {
+        if !ab.tags.iter().any(|t| t == name) {
+            bail!("Tag {} not found", name);
+        }
+        let mut colors = parse_tag_colors(&ab.tag_colors);
+        colors.insert(name.to_owned(), color);
+        ab.tag_colors = serde_json::to_string(&colors)?;
+        Ok(())
+    })
+}
+
+// The password hashes are never exported.
+pub fn export(format: Format) -> ResultType<String> {
+    let ab = load();
+    let peers = ab
+        .peers
+        .into_iter()
+        .map(|p| AbPeer {
+            hash: "".to_owned(),
+            ..p
+        })
+        .collect::<Vec<_>>();
+    match format {
+        Format::Json => Ok(serde_json::to_string_pretty(&AbData {
+            tags: ab.tags,
+            peers,
+            tag_colors: ab.tag_colors,
+        })?),
+        Format::Csv => {
+            let mut rows = vec![CSV_COLUMNS.iter().map(|c| c.to_string()).collect()];
+            for p in peers {
+                rows.push(vec![
+                    p.id,
+                    p.alias,
+                    p.username,
+                    p.hostname,
+                    p.platform,
+                    p.tags.join(&CSV_TAG_SEPARATOR.to_string()),
+                ]);
+            }
+            Ok(write_csv(&rows))
+        }
+    }
+}
+
+/// Imports peers and tags, existing peers are updated. Returns the number of imported peers.
+pub fn import(data: &str, format: Format) -> ResultType<usize> {
+    let imported = match format {
+        Format::Json => serde_json::from_str::<AbData>(data)?,
+        Format::Csv => parse_csv_ab(data)?,
+    };
+    modify(|ab| {
+        add_missing_tags(ab, &imported.tags);
+        let mut colors = parse_tag_colors(&ab.tag_colors);
+        for (tag, color) in parse_tag_colors(&imported.tag_colors) {
+            colors.entry(tag).or_insert(color);
+        }
+        ab.tag_colors = serde_json::to_string(&colors)?;
+        let count = imported.peers.len();
+        for peer in imported.peers {
+            add_missing_tags(ab, &peer.tags);
+            if let Some(p) = ab.peers.iter_mut().find(|p| p.id == peer.id) {
+                // Keep what the import does not carry, e.g. the password hash.
+                let hash = std::mem::take(&mut p.hash);
+                let extra = std::mem::take(&mut p.extra);
+                *p = peer;
+                if p.hash.is_empty() {
+                    p.hash = hash;
+                }
+                for (k, v) in extra {
+                    p.extra.entry(k).or_insert(v);
+                }
+            } else {
+                ab.peers.push(peer);
+            }
+        }
+        Ok(count)
+    })
+}
+
+fn parse_csv_ab(data: &str) -> ResultType<AbData> {
+    let mut rows = parse_csv(data)?.into_iter();
+    let Some(header) = rows.next() else {
+        bail!("Empty csv");
+    };
+    let columns: HashMap<String, usize> = header
+        .iter()
+        .enumerate()
+        .map(|(i, c)| (c.trim().to_lowercase(), i))
+        .collect();
+    let Some(&id_column) = columns.get("id") else {
+        bail!("No id column in csv");
+    };
+    let mut ab = AbData::default();
+    for row in rows {
+        let get = |name: &str| {
+            columns
+                .get(name)
+                .and_then(|&i| row.get(i))
+                .map(|v| v.trim().to_owned())
+                .unwrap_or_default()
+        };
+        let id = row.get(id_column).map(|v| v.trim()).unwrap_or_default();
+        if id.is_empty() {
+            continue;
+        }
+        let tags: Vec<String> = get("tags")
+            .split(CSV_TAG_SEPARATOR)
+            .map(|t| t.trim().to_owned())
+            .filter(|t| !t.is_empty())
+            .collect();
+        for tag in tags.iter() {
+            if !ab.tags.contains(tag) {
+                ab.tags.push(tag.clone());
+            }
+        }
+        ab.peers.push(AbPeer {
+            id: id.to_owned(),
+            alias: get("alias"),
+            username: get("username"),
+            hostname: get("hostname"),
+            platform: get("platform"),
+            tags,
+            ..Default::default()
+        });
+    }
+    Ok(ab)
+}
+
+fn write_csv(rows: &[Vec<String>]) -> String {
+    let mut out = String::new();
+    for row in rows {
+        let fields: Vec<String> = row
+            .iter()
+            .map(|f| {
+                if f.contains(|c| c == ',' || c == '"' || c == '\n' || c == '\r') {
+                    format!("\"{}\"", f.replace('"', "\"\""))
+                } else {
+                    f.clone()
+                }
+            })
+            .collect();
+        out.push_str(&fields.join(","));
+        out.push_str("\r\n");
+    }
+    out
+}
+
+// RFC 4180, quoted fields may contain commas, quotes and line breaks.
+fn parse_csv(data: &str) -> ResultType<Vec<Vec<String>>> {
+    let mut rows = vec![];
+    let mut row = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = data.trim_start_matches('\u{feff}').chars().peekable();
+    while let Some(c) = chars.next() {
+        if quoted {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                '"' => quoted = false,
+                _ => field.push(c),
+            }
+            continue;
+        }
+        match c {
+            '"' if field.is_empty() => quoted = true,
+            ',' => row.push(std::mem::take(&mut field)),
+            '\r' | '\n' => {
+                if c == '\r' && chars.peek() == Some(&'\n') {
+                    chars.next();
+                }
+                row.push(std::mem::take(&mut field));
+                if row.iter().any(|f| !f.is_empty()) {
+                    rows.push(std::mem::take(&mut row));
+                }
+                row.clear();
+            }
+            _ => field.push(c),
+        }
+    }
+    if quoted {
+        bail!("Unterminated quoted field in csv");
+    }
+    row.push(field);
+    if row.iter().any(|f| !f.is_empty()) {
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+enum Merged<T> {
+    Agreed(Option<T>),
+    Conflict,
+}
+
+fn merge3<T: PartialEq + Clone>(
+    base: Option<&T>,
+    local: Option<&T>,
+    remote: Option<&T>,
+) -> Merged<T> {
+    if local == remote || remote == base {
+        Merged::Agreed(local.cloned())
+    } else if local == base {
+        Merged::Agreed(remote.cloned())
+    } else {
+        Merged::Conflict
+    }
+}
+
+struct MergeResult {
+    local: AbData,
+    remote: AbData,
+    synced: AbData,
+    conflicts: Vec<AbConflict>,
+    pulled: usize,
+    pushed: usize,
+}
+
+fn merge(base: &AbData, local: &AbData, remote: &AbData) -> ResultType<MergeResult> {
+    let by_id = |peers: &[AbPeer]| -> HashMap<String, AbPeer> {
+        peers.iter().map(|p| (p.id.clone(), p.clone())).collect()
+    };
+    let (base_peers, local_peers, remote_peers) = (
+        by_id(&base.peers),
+        by_id(&local.peers),
+        by_id(&remote.peers),
+    );
+    // Keep the remote order, then the peers added locally.
+    let mut ids: Vec<String> = vec![];
+    let mut seen = HashSet::new();
+    for p in remote
+        .peers
+        .iter()
+        .chain(local.peers.iter())
+        .chain(base.peers.iter())
+    {
+        if seen.insert(p.id.clone()) {
+            ids.push(p.id.clone());
+        }
+    }
+    let mut res = MergeResult {
+        local: AbData::default(),
+        remote: AbData::default(),
+        synced: AbData::default(),
+        conflicts: vec![],
+        pulled: 0,
+        pushed: 0,
+    };
+    for id in ids {
+        let (b, l, r) = (
+            base_peers.get(&id),
+            local_peers.get(&id),
+            remote_peers.get(&id),
+        );
+        match merge3(b, l, r) {
+            Merged::Agreed(peer) => {
+                if l != peer.as_ref() {
+                    res.pulled += 1;
+                }
+                if r != peer.as_ref() {
+                    res.pushed += 1;
+                }
+                if let Some(peer) = peer {
+                    res.local.peers.push(peer.clone());
+                    res.remote.peers.push(peer.clone());
+                    res.synced.peers.push(peer);
+                }
+            }
+            Merged::Conflict => {
+                res.local.peers.extend(l.cloned());
+                res.remote.peers.extend(r.cloned());
+                res.synced.peers.extend(b.cloned());
+                res.conflicts.push(AbConflict {
+                    id,
+                    local: l.cloned(),
+                    remote: r.cloned(),
+                });
+            }
+        }
+    }
+
+    let as_set = |tags: &[String]| -> BTreeMap<String, ()> {
+        tags.iter().map(|t| (t.clone(), ())).collect()
+    };
+    let (base_tags, local_tags, remote_tags) = (
+        as_set(&base.tags),
+        as_set(&local.tags),
+        as_set(&remote.tags),
+    );
+    for tag in remote.tags.iter().chain(local.tags.iter()) {
+        if res.synced.tags.contains(tag) {
+            continue;
+        }
+        let keep = match merge3(
+            base_tags.get(tag),
+            local_tags.get(tag),
+            remote_tags.get(tag),
+        ) {
+            Merged::Agreed(v) => v.is_some(),
+            Merged::Conflict => true,
+        };
+        if keep {
+            res.synced.tags.push(tag.clone());
+        }
+    }
+    res.local.tags = res.synced.tags.clone();
+    res.remote.tags = res.synced.tags.clone();
+
+    let (base_colors, local_colors, remote_colors) = (
+        parse_tag_colors(&base.tag_colors),
+        parse_tag_colors(&local.tag_colors),
+        parse_tag_colors(&remote.tag_colors),
+    );
+    let mut colors = BTreeMap::new();
+    for tag in res.synced.tags.iter() {
+        let color = match merge3(
+            base_colors.get(tag),
+            local_colors.get(tag),
+            remote_colors.get(tag),
+        ) {
+            Merged::Agreed(v) => v,
+            // Not worth a conflict, the local color wins.
+            Merged::Conflict => local_colors.get(tag).cloned(),
+        };
+        if let Some(color) = color {
+            colors.insert(tag.clone(), color);
+        }
+    }
+    let colors = serde_json::to_string(&colors)?;
+    res.local.tag_colors = colors.clone();
+    res.remote.tag_colors = colors.clone();
+    res.synced.tag_colors = colors;
+    Ok(res)
+}
+
+fn api_url() -> ResultType<(String, String)> {
+    let api_server = crate::ui_interface::get_api_server();
+    if api_server.is_empty() {
+        bail!("No API server");
+    }
+    let access_token = LocalConfig::get_option("access_token");
+    if access_token.is_empty() {
+        bail!("Not logged in");
+    }
+    Ok((format!("{}/api/ab", api_server), access_token))
+}
+
+fn pull(client: &reqwest::blocking::Client, url: &str, token: &str) -> ResultType<AbData> {
+    let resp = client.get(url).bearer_auth(token).send()?;
+    let status = resp.status();
+    let body = resp.text()?;
+    if body.trim().is_empty() || body.trim().eq_ignore_ascii_case("null") {
+        // empty address book
+        if status.is_success() {
+            return Ok(AbData::default());
+        }
+        bail!("HTTP {}", status);
+    }
+    let map = serde_json::from_str::<Map<String, Value>>(&body)?;
+    if let Some(err) = map.get("error") {
+        bail!("{}", err.as_str().unwrap_or_default());
+    }
+    match map.get("data").and_then(|d| d.as_str()) {
+        Some(data) => Ok(serde_json::from_str(data)?),
+        None => bail!("Invalid address book response"),
+    }
+}
+
+fn push(client: &reqwest::blocking::Client, url: &str, token: &str, ab: &AbData) -> ResultType<()> {
+    let body = serde_json::json!({ "data": serde_json::to_string(ab)? });
+    let resp = client.post(url).bearer_auth(token).json(&body).send()?;
+    let status = resp.status();
+    let body = resp.text()?;
+    if let Ok(map) = serde_json::from_str::<Map<String, Value>>(&body) {
+        if let Some(err) = map.get("error") {
+            bail!("{}", err.as_str().unwrap_or_default());
+        }
+    }
+    if !status.is_success() {
+        bail!("HTTP {}", status);
+    }
+    Ok(())
+}
+
+// The last synced and the local address book.
+fn sync_state(ab: &LocalAb) -> (AbData, AbData) {
+    let base = AbData {
+        tags: ab.synced_tags.clone(),
+        peers: ab.synced_peers.clone(),
+        tag_colors: ab.synced_tag_colors.clone(),
+    };
+    let local = AbData {
+        tags: ab.tags.clone(),
+        peers: ab.peers.clone(),
+        tag_colors: ab.tag_colors.clone(),
+    };
+    (base, local)
+}
+
+fn set_sync_state(ab: &mut LocalAb, local: AbData, synced: AbData, conflicts: Vec<AbConflict>) {
+    ab.peers = local.peers;
+    ab.tags = local.tags;
+    ab.tag_colors = local.tag_colors;
+    ab.synced_peers = synced.peers;
+    ab.synced_tags = synced.tags;
+    ab.synced_tag_colors = synced.tag_colors;
+    ab.conflicts = conflicts;
+}
+
+// Commits the merge pushed to the server though the local address book changed during the
+// sync, else the next sync would take the pushed changes for remote ones. The changes
+// meanwhile are kept on top of the merged local, to be pushed by the next sync.
+fn commit_pushed(ab: &mut LocalAb, state: &(AbData, AbData), res: MergeResult) -> ResultType<()> {
+    let current = sync_state(ab);
+    if current.0 != state.0 {
+        // Synced by another sync meanwhile.
+        return Ok(());
+    }
+    let rebased = merge(&state.1, &current.1, &res.local)?;
+    set_sync_state(ab, rebased.local, res.synced, res.conflicts);
+    Ok(())
+}
+
+/// Two-way sync with the legacy address book of the API server.
+///
+/// The lock is not held over the push, the merge is committed only if the local address book
+/// has not changed meanwhile, or if it was pushed.
+pub fn sync() -> ResultType<SyncReport> {
+    let (url, token) = api_url()?;
+    let client = crate::hbbs_http::create_http_client();
+    let remote = pull(&client, &url, &token)?;
+    let (state, res) = {
+        let _lock = LOCK.lock().unwrap();
+        let state = sync_state(&LocalAb::load());
+        let res = merge(&state.0, &state.1, &remote)?;
+        (state, res)
+    };
+    let pushed = res.pushed > 0
+        || res.remote.tags != remote.tags
+        || parse_tag_colors(&res.remote.tag_colors) != parse_tag_colors(&remote.tag_colors);
+    if pushed {
+        push(&client, &url, &token, &res.remote)?;
+    }
+    let report = modify(|ab| {
+        if sync_state(ab) != state {
+            if pushed {
+                commit_pushed(ab, &state, res)?;
+            }
+            return Ok(None);
+        }
+        let report = SyncReport {
+            pulled: res.pulled,
+            pushed: res.pushed,
+            conflicts: res.conflicts.clone(),
+        };
+        set_sync_state(ab, res.local, res.synced, res.conflicts);
+        log::info!(
+            "address book synced, pulled: {}, pushed: {}, conflicts: {}",
+            report.pulled,
+            report.pushed,
+            report.conflicts.len()
+        );
+        Ok(Some(report))
+    })?;
+    match report {
+        Some(report) => Ok(report),
+        None => bail!("The address book was changed during the sync, please sync again"),
+    }
+}
+
+/// Resolves a sync conflict, the kept side is applied on both sides on the next sync.
+pub fn resolve_conflict(id: &str, keep_local: bool) -> ResultType<()> {
+    modify(|ab| {
+        let Some(index) = ab.conflicts.iter().position(|c| c.id == id) else {
+            bail!("No conflict of peer {}", id);
+        };
+        let conflict = ab.conflicts.remove(index);
+        // Make the other side look unchanged since the last sync.
+        let base = if keep_local {
+            conflict.remote
+        } else {
+            ab.peers.retain(|p| p.id != id);
+            ab.peers.extend(conflict.remote.clone());
+            conflict.remote
+        };
+        ab.synced_peers.retain(|p| p.id != id);
+        ab.synced_peers.extend(base);
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer(id: &str, alias: &str) -> AbPeer {
+        AbPeer {
+            id: id.to_owned(),
+            alias: alias.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_csv() {
+        let rows = vec![
+            vec!["id".to_owned(), "alias".to_owned()],
+            vec!["123".to_owned(), "a, \"quoted\"\nalias".to_owned()],
+        ];
+        assert_eq!(parse_csv(&write_csv(&rows)).unwrap(), rows);
+        assert!(parse_csv("id\n\"123").is_err());
+
+        let ab = parse_csv_ab("\u{feff}ID,Tags,Alias\r\n123,a; b,x\n,c,y\n\n456,,\n").unwrap();
+        assert_eq!(ab.peers.len(), 2);
+        assert_eq!(ab.peers[0].id, "123");
+        assert_eq!(ab.peers[0].alias, "x");
+        assert_eq!(ab.peers[0].tags, vec!["a", "b"]);
+        assert_eq!(ab.tags, vec!["a", "b"]);
+        assert_eq!(ab.peers[1].id, "456");
+        assert!(parse_csv_ab("alias\nx\n").is_err());
+    }
+
+    #[test]
+    fn test_merge() {
+        let base = AbData {
+            peers: vec![peer("1", ""), peer("2", ""), peer("3", ""), peer("4", "")],
+            tags: vec!["a".to_owned(), "b".to_owned()],
+            ..Default::default()
+        };
+        // 1: edited locally, 2: edited remotely, 3: edited on both sides, 4: deleted locally
+        let local = AbData {
+            peers: vec![peer("1", "l"), peer("2", ""), peer("3", "l"), peer("5", "")],
+            tags: vec!["a".to_owned(), "c".to_owned()],
+            ..Default::default()
+        };
+        let remote = AbData {
+            peers: vec![peer("1", ""), peer("2", "r"), peer("3", "r"), peer("4", "")],
+            tags: vec!["a".to_owned(), "b".to_owned(), "d".to_owned()],
+            ..Default::default()
+        };
+        let res = merge(&base, &local, &remote).unwrap();
+        assert_eq!(
+            res.local.peers,
+            vec![
+                peer("1", "l"),
+                peer("2", "r"),
+                peer("3", "l"),
+                peer("5", "")
+            ]
+        );
+        assert_eq!(
+            res.remote.peers,
+            vec![
+                peer("1", "l"),
+                peer("2", "r"),
+                peer("3", "r"),
+                peer("5", "")
+            ]
+        );
+        assert_eq!(
+            res.synced.peers,
+            vec![peer("1", "l"), peer("2", "r"), peer("3", ""), peer("5", "")]
+        );
+        assert_eq!(
+            res.conflicts,
+            vec![AbConflict {
+                id: "3".to_owned(),
+                local: Some(peer("3", "l")),
+                remote: Some(peer("3", "r")),
+            }]
+        );
+        assert_eq!(res.pulled, 1);
+        assert_eq!(res.pushed, 3);
+        assert_eq!(res.synced.tags, vec!["a", "d", "c"]);
+    }
+
+    #[test]
+    fn test_commit_pushed() {
+        let base = AbData {
+            peers: vec![peer("1", ""), peer("2", "")],
+            ..Default::default()
+        };
+        let local = AbData {
+            peers: vec![peer("1", "l"), peer("2", "")],
+            ..Default::default()
+        };
+        let remote = AbData {
+            peers: vec![peer("1", ""), peer("2", "r")],
+            ..Default::default()
+        };
+        let state = (base, local);
+        let res = merge(&state.0, &state.1, &remote).unwrap();
+        // 3 added locally during the sync
+        let mut ab = LocalAb {
+            peers: vec![peer("1", "l"), peer("2", ""), peer("3", "")],
+            synced_peers: state.0.peers.clone(),
+            ..Default::default()
+        };
+        commit_pushed(&mut ab, &state, res).unwrap();
+        assert_eq!(ab.synced_peers, vec![peer("1", "l"), peer("2", "r")]);
+        assert_eq!(
+            ab.peers,
+            vec![peer("1", "l"), peer("2", "r"), peer("3", "")]
+        );
+    }
+}
@@ -0,0 +1,479 @@
+//! Run the same action on many peers, e.g. push a file, run a command or send a notice.
+//!
+//! Every peer gets its own short-lived connection of the matching type,
+//! at most `concurrency` of them are open at the same time.
+
+use crate::client::{self, Client, Interface, LoginConfigHandler};
+use async_trait::async_trait;
+use hbb_common::{
+    bail,
+    config::{self, LocalConfig, READ_TIMEOUT},
+    fs,
+    futures::{stream, StreamExt},
+    log,
+    message_proto::*,
+    protobuf::Message as _,
+    rendezvous_proto::ConnType,
+    timeout,
+    tokio::{
+        self,
+        time::{self, Duration, Instant},
+    },
+    ResultType, Stream,
+};
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex, RwLock},
+};
+
+const DEFAULT_CONCURRENCY: usize = 8;
+const DEFAULT_TIMEOUT_SECS: u64 = 60;
+const MAX_OUTPUT_LEN: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Action {
+    /// Upload a local file or directory into `to` on the peer.
+    SendFile { path: String, to: String },
+    /// Run a command in the shell of the peer and collect the output.
+    RunCommand { command: String },
+    /// Show a chat message in the connection manager of the peer.
+    Chat { text: String },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Job {
+    #[serde(default)]
+    pub peers: Vec<String>,
+    /// Address book tag, its peers are added to `peers`.
+    #[serde(default)]
+    pub tag: String,
+    /// Used if there is no remembered or address book password of the peer.
+    #[serde(default)]
+    pub password: String,
+    pub action: Action,
+    #[serde(default)]
+    pub concurrency: usize,
+    /// Seconds a command may run, or a chat connection is kept open for the notice to be read.
+    #[serde(default)]
+    pub timeout: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PeerResult {
+    pub id: String,
+    pub success: bool,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub output: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub error: String,
+}
+
+/// The peers of the job, the ones with `job.tag` in the local and the cached address books included.
+pub fn resolve_peers(job: &Job) -> Vec<String> {
+    let mut ids: Vec<String> = job
+        .peers
+        .iter()
+        .map(|id| id.trim().to_owned())
+        .filter(|id| !id.is_empty())
+        .collect();
+    if !job.tag.is_empty() {
+        #[cfg(feature = "flutter")]
+        let local = crate::address_book::load().peers;
+        #[cfg(not(feature = "flutter"))]
+        let local = Vec::new();
+        let cached = config::Ab::load()
+            .ab_entries
+            .into_iter()
+            .flat_map(|e| e.peers);
+        for peer in local.into_iter().chain(cached) {
+            if peer.tags.contains(&job.tag) {
+                ids.push(peer.id);
+            }
+        }
+    }
+    let mut seen = std::collections::HashSet::new();
+    ids.retain(|id| seen.insert(id.clone()));
+    ids
+}
+
+/// Run the job, `on_result` is called as soon as a peer is done.
+pub async fn run(job: Job, on_result: impl Fn(&PeerResult)) -> Vec<PeerResult> {
+    let ids = resolve_peers(&job);
+    let concurrency = if job.concurrency == 0 {
+        DEFAULT_CONCURRENCY
+    } else {
+        job.concurrency
+    };
+    let key = crate::get_key(false).await;
+    let token = LocalConfig::get_option("access_token");
+    let job = &job;
+    let (key, token) = (&key, &token);
+    stream::iter(ids)
+        .map(|id| async move {
+            let res = match run_one(&id, job, key, token).await {
+                Ok(output) => PeerResult {
+                    id,
+                    success: true,
+                    output,
+                    ..Default::default()
+                },
+                Err(err) => PeerResult {
+                    id,
+                    error: err.to_string(),
+                    ..Default::default()
+                },
+            };
+            log::info!(
+                "batch {} on {}: {}",
+                action_name(&job.action),
+                res.id,
+                if res.success { "ok" } else { &res.error }
+            );
+            res
+        })
+        .buffer_unordered(concurrency)
+        .inspect(&on_result)
+        .collect()
+        .await
+}
+
+#[tokio::main(flavor = "multi_thread")]
+pub async fn run_sync(job: Job, on_result: impl Fn(&PeerResult)) -> Vec<PeerResult> {
+    run(job, on_result).await
+}
+
+fn action_name(action: &Action) -> &'static str {
+    match action {
+        Action::SendFile { .. } => "send_file",
+        Action::RunCommand { .. } => "run_command",
+        Action::Chat { .. } => "chat",
+    }
+}
+
+async fn run_one(id: &str, job: &Job, key: &str, token: &str) -> ResultType<String> {
+    let conn_type = match job.action {
+        Action::RunCommand { .. } => ConnType::TERMINAL,
+        Action::SendFile { .. } | Action::Chat { .. } => ConnType::FILE_TRANSFER,
+    };
+    let session = BatchSession::new(id, conn_type);
+    let ((mut stream, direct, _pk, _kcp, _stream_type), (feedback, rendezvous_server)) =
+        Client::start(id, key, token, conn_type, session.clone()).await?;
+    session.update_direct(Some(direct));
+    let _keep_it = client::hc_connection(feedback, rendezvous_server, token).await;
+    let pi = login(&session, &job.password, &mut stream).await?;
+    let secs = if job.timeout == 0 {
+        DEFAULT_TIMEOUT_SECS
+    } else {
+        job.timeout
+    };
+    let deadline = Instant::now() + Duration::from_secs(secs);
+    match &job.action {
+        Action::SendFile { path, to } => send_file(&session, &pi, path, to, &mut stream).await,
+        Action::RunCommand { command } => run_command(command, secs, &mut stream).await,
+        Action::Chat { text } => chat(text, deadline, &mut stream).await,
+    }
+}
+
+async fn login(
+    session: &BatchSession,
+    password: &str,
+    stream: &mut Stream,
+) -> ResultType<PeerInfo> {
+    loop {
+        let bytes = match timeout(READ_TIMEOUT, stream.next()).await {
+            Err(_) => bail!("Timeout"),
+            Ok(Some(Ok(bytes))) => bytes,
+            Ok(Some(Err(err))) => bail!("Connection closed: {}", err),
+            Ok(None) => bail!("Reset by the peer"),
+        };
+        session.update_received(true);
+        let msg_in = Message::parse_from_bytes(&bytes)?;
+        match msg_in.union {
+            Some(message::Union::Hash(hash)) => {
+                session.handle_hash(password, hash, stream).await;
+            }
+            Some(message::Union::DirectAccess(da)) => {
+                client::handle_direct_access(da, &session.get_id(), stream).await;
+            }
+            Some(message::Union::LoginResponse(lr)) => match lr.union {
+                Some(login_response::Union::Error(err)) => {
+                    session.handle_login_error(&err);
+                    bail!("{}", err);
+                }
+                Some(login_response::Union::PeerInfo(pi)) => {
+                    session.handle_peer_info(pi.clone());
+                    return Ok(pi);
+                }
+                _ => {}
+            },
+            Some(message::Union::TestDelay(t)) => {
+                session.handle_test_delay(t, stream).await;
+            }
+            _ => {}
+        }
+        // Nobody can type a password or click accept for a batch.
+        if let Some(err) = session.error.lock().unwrap().take() {
+            bail!("{}", err);
+        }
+    }
+}
+
+async fn send_file(
+    session: &BatchSession,
+    pi: &PeerInfo,
+    path: &str,
+    to: &str,
+    stream: &mut Stream,
+) -> ResultType<String> {
+    const JOB_ID: i32 = 1;
+    let od = fs::can_enable_overwrite_detection(session.lc.read().unwrap().version);
+    let mut job = fs::TransferJob::new_read(
+        JOB_ID,
+        fs::JobType::Generic,
+        to.to_owned(),
+        fs::DataSource::FilePath(PathBuf::from(path)),
+        0,
+        true,
+        false,
+        od,
+    )?;
+    job.set_overwrite_strategy(Some(true));
+    let mut files = job.files().clone();
+    if cfg!(windows) && pi.platform != "Windows" {
+        fs::transform_windows_path(&mut files);
+    }
+    let total_size = job.total_size();
+    let num_files = files.len();
+    stream
+        .send(&fs::new_receive(
+            JOB_ID,
+            to.to_owned(),
+            0,
+            files,
+            total_size,
+        ))
+        .await?;
+    let mut jobs = vec![job];
+    let mut timer = crate::rustdesk_interval(time::interval(Duration::from_millis(1)));
+    while !jobs.is_empty() {
+        tokio::select! {
+            res = timeout(READ_TIMEOUT, stream.next()) => {
+                let bytes = match res {
+                    Err(_) => bail!("Timeout"),
+                    Ok(Some(Ok(bytes))) => bytes,
+                    Ok(Some(Err(err))) => bail!("Connection closed: {}", err),
+                    Ok(None) => bail!("Reset by the peer"),
+                };
+                let Some(message::Union::FileResponse(fr)) = Message::parse_from_bytes(&bytes)?.union else {
+                    continue;
+                };
+                match fr.union {
+                    Some(file_response::Union::Digest(digest)) if digest.is_upload => {
+                        if let Some(job) = fs::get_job(digest.id, &mut jobs) {
+                            let req = FileTransferSendConfirmRequest {
+                                id: digest.id,
+                                file_num: digest.file_num,
+                                union: Some(file_transfer_send_confirm_request::Union::OffsetBlk(0)),
+                                ..Default::default()
+                            };
+                            job.confirm(&req).await;
+                            stream.send(&fs::new_send_confirm(req)).await?;
+                        }
+                    }
+                    Some(file_response::Union::Error(e)) => {
+                        bail!("{}", e.error);
+                    }
+                    _ => {}
+                }
+            }
+            _ = timer.tick() => {
+                fs::handle_read_jobs(&mut jobs, stream).await?;
+            }
+        }
+    }
+    Ok(format!("{} file(s), {} bytes", num_files, total_size))
+}
+
+async fn run_command(command: &str, timeout_secs: u64, stream: &mut Stream) -> ResultType<String> {
+    const EXEC_ID: i32 = 1;
+    let mut action = TerminalAction::new();
+    action.set_exec(ExecCommand {
+        exec_id: EXEC_ID,
+        command: command.to_owned(),
+        timeout_secs: timeout_secs as _,
+        ..Default::default()
+    });
+    let mut msg_out = Message::new();
+    msg_out.set_terminal_action(action);
+    stream.send(&msg_out).await?;
+    // The peer kills the command on timeout, give it some time to report that.
+    let deadline = Instant::now() + Duration::from_secs(timeout_secs + 10);
+    let (mut stdout, mut stderr) = (Vec::new(), Vec::new());
+    loop {
+        let Ok(res) = time::timeout_at(deadline, stream.next()).await else {
+            bail!("Command timed out");
+        };
+        let bytes = match res {
+            Some(Ok(bytes)) => bytes,
+            Some(Err(err)) => bail!("Connection closed: {}", err),
+            None => bail!("Reset by the peer"),
+        };
+        let Some(message::Union::TerminalResponse(tr)) = Message::parse_from_bytes(&bytes)?.union
+        else {
+            continue;
+        };
+        match tr.union {
+            Some(terminal_response::Union::ExecOutput(output)) => {
+                let buf = if output.stream.enum_value() == Ok(exec_output::Stream::Stderr) {
+                    &mut stderr
+                } else {
+                    &mut stdout
+                };
+                buf.extend_from_slice(&output.data);
+                if buf.len() > MAX_OUTPUT_LEN {
+                    buf.drain(..buf.len() - MAX_OUTPUT_LEN);
+                }
+            }
+            Some(terminal_response::Union::ExecResult(result)) => {
+                let stdout = String::from_utf8_lossy(&stdout).to_string();
+                let stderr = String::from_utf8_lossy(&stderr).to_string();
+                if !result.error.is_empty() {
+                    bail!("{}", result.error);
+                }
+                if result.timed_out {
+                    bail!("Command timed out\n{}", stderr);
+                }
+                if result.exit_code != 0 {
+                    bail!("Exit code {}\n{}", result.exit_code, stderr);
+                }
+                return Ok(stdout);
+            }
+            Some(terminal_response::Union::Error(e)) => {
+                bail!("{}", e.message);
+            }
+            _ => {}
+        }
+    }
+}
+
+async fn chat(text: &str, deadline: Instant, stream: &mut Stream) -> ResultType<String> {
+    let mut misc = Misc::new();
+    misc.set_chat_message(ChatMessage {
+        text: text.to_owned(),
+        ..Default::default()
+    });
+    let mut msg_out = Message::new();
+    msg_out.set_misc(misc);
+    stream.send(&msg_out).await?;
+    // Keep the connection, otherwise the connection manager closes the chat at once.
+    let mut replies = Vec::new();
+    while let Ok(Some(Ok(bytes))) = time::timeout_at(deadline, stream.next()).await {
+        if let Ok(msg_in) = Message::parse_from_bytes(&bytes) {
+            if let Some(message::Union::Misc(Misc {
+                union: Some(misc::Union::ChatMessage(c)),
+                ..
+            })) = msg_in.union
+            {
+                replies.push(c.text);
+            }
+        }
+    }
+    Ok(replies.join("\n"))
+}
+
+#[derive(Clone)]
+struct BatchSession {
+    lc: Arc<RwLock<LoginConfigHandler>>,
+    error: Arc<Mutex<Option<String>>>,
+}
+
+impl BatchSession {
+    fn new(id: &str, conn_type: ConnType) -> Self {
+        let mut lc = LoginConfigHandler::default();
+        lc.initialize(id.to_owned(), conn_type, None, false, None, None, None);
+        Self {
+            lc: Arc::new(RwLock::new(lc)),
+            error: Default::default(),
+        }
+    }
+}
+
+#[async_trait]
+impl Interface for BatchSession {
+    fn send(&self, _data: client::Data) {}
+
+    fn msgbox(&self, msgtype: &str, title: &str, text: &str, _link: &str) {
+        let err = match msgtype {
+            "input-password" | "re-input-password" => "Password Required".to_owned(),
+            "input-2fa" => "2FA required".to_owned(),
+            _ if msgtype.contains("error") => format!("{}: {}", title, text),
+            _ => return,
+        };
+        *self.error.lock().unwrap() = Some(err);
+    }
+
+    fn handle_login_error(&self, err: &str) -> bool {
+        client::handle_login_error(self.lc.clone(), err, self);
+        false
+    }
+
+    fn handle_peer_info(&self, pi: PeerInfo) {
+        self.lc.write().unwrap().handle_peer_info(&pi);
+    }
+
+    fn set_multiple_windows_session(&self, _sessions: Vec<WindowsSession>) {}
+
+    async fn handle_hash(&self, pass: &str, hash: Hash, peer: &mut Stream) {
+        client::handle_hash(self.lc.clone(), pass, hash, self, peer).await;
+    }
+
+    async fn handle_login_from_ui(
+        &self,
+        os_username: String,
+        os_password: String,
+        password: String,
+        remember: bool,
+        peer: &mut Stream,
+    ) {
+        client::handle_login_from_ui(
+            self.lc.clone(),
+            os_username,
+            os_password,
+            password,
+            remember,
+            peer,
+        )
+        .await;
+    }
+
+    async fn handle_test_delay(&self, t: TestDelay, peer: &mut Stream) {
+        client::handle_test_delay(t, peer).await;
+    }
+
+    fn get_lch(&self) -> Arc<RwLock<LoginConfigHandler>> {
+        self.lc.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_job() {
+        let job: Job = serde_json::from_str(
+            r#"{"peers": ["123", " 456 ", "", "123"], "action": {"type": "run_command", "command": "uptime"}}"#,
+        )
+        .unwrap();
+        assert_eq!(resolve_peers(&job), vec!["123", "456"]);
+        assert!(matches!(job.action, Action::RunCommand { ref command } if command == "uptime"));
+        assert_eq!(job.concurrency, 0);
+        let job: Job = serde_json::from_str(
+            r#"{"tag": "", "action": {"type": "send_file", "path": "/tmp/a", "to": "C:\\a"}}"#,
+        )
+        .unwrap();
+        assert!(resolve_peers(&job).is_empty());
+    }
+}
@@ -0,0 +1,478 @@
+// The chat history with the peers, persisted next to their `PeerConfig`.
+//
+// The history is an append-only log of json lines, the messages and the receipts of them,
+// it is folded into the records on load. The received attachments are saved beside it.
+//
+// The message only names its attachments, the files follow as file transfer jobs, so the
+// controlled side writes and sends them only with the file permission.
+
+use hbb_common::{
+    bail,
+    compress::decompress,
+    config::PeerConfig,
+    fs::{self, DataSource, JobType, TransferJob},
+    get_time, lazy_static, log,
+    message_proto::{
+        ChatAttachment, ChatMessage, FileAction, FileTransferBlock, FileTransferReceiveRequest,
+        Message,
+    },
+    ResultType,
+};
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+const HISTORY_FILE: &str = "history.jsonl";
+/// The attachments of a message are limited in total, the file transfer is for the larger ones.
+pub const MAX_ATTACHMENTS_SIZE: u64 = 1024 * 1024;
+
+lazy_static::lazy_static! {
+    static ref LOCK: Mutex<()> = Default::default();
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Json,
+    Text,
+}
+
+impl std::str::FromStr for Format {
+    type Err = hbb_common::anyhow::Error;
+
+    fn from_str(s: &str) -> ResultType<Self> {
+        match s.to_lowercase().as_str() {
+            "json" => Ok(Self::Json),
+            "txt" | "text" => Ok(Self::Text),
+            _ => bail!("Unsupported chat history format: {}", s),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Receipt {
+    Delivered,
+    Read,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ChatRecord {
+    pub msg_id: String,
+    // Milliseconds since the epoch, by the sender.
+    pub timestamp: i64,
+    // Sent to the peer, or received from it.
+    pub outgoing: bool,
+    pub text: String,
+    // The local paths of the attachments.
+    #[serde(default)]
+    pub attachments: Vec<String>,
+    // The times of the receipts, 0 if not yet.
+    #[serde(default)]
+    pub delivered: i64,
+    #[serde(default)]
+    pub read: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Entry {
+    Message(ChatRecord),
+    Receipt {
+        msg_ids: Vec<String>,
+        receipt: Receipt,
+        timestamp: i64,
+    },
+}
+
+fn append(id: &str, entry: &Entry) -> ResultType<()> {
+    let dir = PeerConfig::chat_dir(id);
+    let _lock = LOCK.lock().unwrap();
+    std::fs::create_dir_all(&dir)?;
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(dir.join(HISTORY_FILE))?;
+    writeln!(file, "{}", serde_json::to_string(entry)?)?;
+    Ok(())
+}
+
+pub fn new_msg_id() -> String {
+    hbb_common::uuid::Uuid::new_v4().to_string()
+}
+
+/// The files to be sent with a message, by their names and sizes.
+pub fn check_attachments(paths: &[String]) -> ResultType<Vec<ChatAttachment>> {
+    let mut attachments = vec![];
+    for path in paths {
+        let path = Path::new(path);
+        let Some(name) = path.file_name().map(|n| n.to_string_lossy().to_string()) else {
+            bail!("Invalid attachment: {}", path.display());
+        };
+        let metadata = std::fs::metadata(path)?;
+        if !metadata.is_file() {
+            bail!("Invalid attachment: {}", path.display());
+        }
+        attachments.push(ChatAttachment {
+            name,
+            size: metadata.len(),
+            ..Default::default()
+        });
+    }
+    if total_size(&attachments) > MAX_ATTACHMENTS_SIZE {
+        bail!(
+            "The attachments exceed {} KB, please use file transfer",
+            MAX_ATTACHMENTS_SIZE / 1024
+        );
+    }
+    Ok(attachments)
+}
+
+fn total_size(attachments: &[ChatAttachment]) -> u64 {
+    attachments.iter().map(|a| a.size).sum()
+}
+
+/// The jobs sending the attachments of the message `msg_id`, each with the request announcing it.
+pub fn new_read_jobs(msg_id: &str, paths: &[String]) -> Vec<(TransferJob, Message)> {
+    let mut jobs = vec![];
+    for path in paths {
+        let Some(name) = Path::new(path)
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+        else {
+            continue;
+        };
+        let job = match TransferJob::new_read(
+            fs::get_next_job_id(),
+            JobType::Generic,
+            name.clone(),
+            DataSource::FilePath(PathBuf::from(path)),
+            0,
+            false,
+            false,
+            false,
+        ) {
+            Ok(job) => job,
+            Err(e) => {
+                log::error!("Failed to read chat attachment {}: {}", path, e);
+                continue;
+            }
+        };
+        let mut action = FileAction::new();
+        action.set_receive(FileTransferReceiveRequest {
+            id: job.id(),
+            path: name,
+            files: job.files().clone(),
+            total_size: job.total_size(),
+            chat_msg_id: msg_id.to_owned(),
+            ..Default::default()
+        });
+        let mut msg = Message::new();
+        msg.set_file_action(action);
+        jobs.push((job, msg));
+    }
+    jobs
+}
+
+/// Where the attachment `name` of the message `msg_id` from the peer `id` is saved.
+/// The names are from the peer, only the file names are kept.
+pub fn attachment_path(id: &str, msg_id: &str, name: &str) -> Option<PathBuf> {
+    let msg_id = Path::new(msg_id).file_name()?;
+    let name = Path::new(name).file_name()?;
+    Some(PeerConfig::chat_dir(id).join(msg_id).join(name))
+}
+
+/// The attachments being received on a connection, so the peer writes no more than it announced
+/// with the messages, within `MAX_ATTACHMENTS_SIZE`.
+#[derive(Default)]
+pub struct Receiving {
+    // The bytes left to the messages.
+    left: HashMap<String, u64>,
+    // The messages of the jobs.
+    jobs: HashMap<i32, String>,
+}
+
+impl Receiving {
+    /// The names of the attachments of `msg`, none if they exceed the limit.
+    pub fn expect(&mut self, msg: &ChatMessage) -> Vec<String> {
+        if msg.attachments.is_empty() || msg.msg_id.is_empty() {
+            return vec![];
+        }
+        let total = total_size(&msg.attachments);
+        if total > MAX_ATTACHMENTS_SIZE {
+            log::warn!("Rejected chat attachments of {} bytes", total);
+            return vec![];
+        }
+        self.left.insert(msg.msg_id.clone(), total);
+        msg.attachments.iter().map(|a| a.name.clone()).collect()
+    }
+
+    /// Whether the job `id` of an attachment announced by the message `msg_id` is accepted.
+    pub fn add_job(&mut self, id: i32, msg_id: &str, size: u64) -> bool {
+        match self.left.get(msg_id) {
+            Some(left) if size <= *left => {
+                self.jobs.insert(id, msg_id.to_owned());
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// False if the block exceeds what is left to its message, the job is removed then.
+    /// The blocks of the other jobs are let through.
+    pub fn receive(&mut self, block: &FileTransferBlock) -> bool {
+        let Some(msg_id) = self.jobs.get(&block.id).cloned() else {
+            return true;
+        };
+        let size = if block.compressed {
+            decompress(&block.data).len()
+        } else {
+            block.data.len()
+        } as u64;
+        match self.left.get_mut(&msg_id) {
+            Some(left) if size <= *left => {
+                *left -= size;
+                true
+            }
+            _ => {
+                log::warn!("Chat attachment job {} exceeds its size", block.id);
+                self.remove_job(block.id);
+                false
+            }
+        }
+    }
+
+    pub fn remove_job(&mut self, id: i32) {
+        self.jobs.remove(&id);
+    }
+}
+
+/// Records a message sent to the peer `id`, returns the record.
+pub fn add_outgoing(id: &str, text: String, attachments: Vec<String>) -> ChatRecord {
+    let record = ChatRecord {
+        msg_id: new_msg_id(),
+        timestamp: get_time(),
+        outgoing: true,
+        text,
+        attachments,
+        ..Default::default()
+    };
+    if let Err(e) = append(id, &Entry::Message(record.clone())) {
+        log::error!("Failed to record chat message: {}", e);
+    }
+    record
+}
+
+/// Records a message received from the peer `id`, returns the record.
+/// The attachments are by their names, saved to `attachment_path` as their jobs go.
+/// Messages from old versions have neither an id nor a timestamp.
+pub fn add_incoming(
+    id: &str,
+    msg_id: String,
+    timestamp: i64,
+    text: String,
+    attachments: Vec<String>,
+) -> ChatRecord {
+    let msg_id = if msg_id.is_empty() {
+        new_msg_id()
+    } else {
+        msg_id
+    };
+    let record = ChatRecord {
+        timestamp: if timestamp > 0 { timestamp } else { get_time() },
+        outgoing: false,
+        text,
+        attachments: attachments
+            .iter()
+            .filter_map(|name| attachment_path(id, &msg_id, name))
+            .map(|path| path.to_string_lossy().to_string())
+            .collect(),
+        msg_id,
+        ..Default::default()
+    };
+    if let Err(e) = append(id, &Entry::Message(record.clone())) {
+        log::error!("Failed to record chat message: {}", e);
+    }
+    record
+}
+
+pub fn add_receipt(id: &str, msg_ids: Vec<String>, receipt: Receipt) {
+    let entry = Entry::Receipt {
+        msg_ids,
+        receipt,
+        timestamp: get_time(),
+    };
+    if let Err(e) = append(id, &entry) {
+        log::error!("Failed to record chat receipt: {}", e);
+    }
+}
+
+/// The messages with the peer `id`, the oldest first.
+pub fn load(id: &str) -> Vec<ChatRecord> {
+    let path = PeerConfig::chat_dir(id).join(HISTORY_FILE);
+    let data = {
+        let _lock = LOCK.lock().unwrap();
+        std::fs::read_to_string(path).unwrap_or_default()
+    };
+    fold(&data)
+}
+
+fn fold(data: &str) -> Vec<ChatRecord> {
+    let mut records: Vec<ChatRecord> = vec![];
+    let mut index = HashMap::new();
+    for line in data.lines().filter(|l| !l.trim().is_empty()) {
+        match serde_json::from_str::<Entry>(line) {
+            Ok(Entry::Message(record)) => {
+                index.insert(record.msg_id.clone(), records.len());
+                records.push(record);
+            }
+            Ok(Entry::Receipt {
+                msg_ids,
+                receipt,
+                timestamp,
+            }) => {
+                for msg_id in msg_ids {
+                    let Some(record) = index.get(&msg_id).map(|i| &mut records[*i]) else {
+                        continue;
+                    };
+                    if record.delivered == 0 {
+                        record.delivered = timestamp;
+                    }
+                    if receipt == Receipt::Read && record.read == 0 {
+                        record.read = timestamp;
+                    }
+                }
+            }
+            Err(e) => log::warn!("Invalid chat history line: {}", e),
+        }
+    }
+    records
+}
+
+pub fn export(id: &str, format: Format) -> ResultType<String> {
+    let records = load(id);
+    match format {
+        Format::Json => Ok(serde_json::to_string_pretty(&records)?),
+        Format::Text => Ok(records
+            .iter()
+            .map(|r| format_record(id, r))
+            .collect::<Vec<_>>()
+            .join("\n")),
+    }
+}
+
+fn format_time(ms: i64) -> String {
+    use chrono::{Local, TimeZone};
+    match Local.timestamp_millis_opt(ms) {
+        chrono::LocalResult::Single(t) => t.format("%Y-%m-%d %H:%M:%S").to_string(),
+        _ => "-".to_owned(),
+    }
+}
+
+fn format_record(id: &str, record: &ChatRecord) -> String {
+    let mut line = format!(
+        "[{}] {}: {}",
+        format_time(record.timestamp),
+        if record.outgoing { "Me" } else { id },
+        record.text
+    );
+    for attachment in record.attachments.iter() {
+        line += &format!("\n    attachment: {}", attachment);
+    }
+    if record.read > 0 {
+        line += &format!("\n    read: {}", format_time(record.read));
+    } else if record.delivered > 0 {
+        line += &format!("\n    delivered: {}", format_time(record.delivered));
+    }
+    line
+}
+
+pub fn to_message(record: &ChatRecord, attachments: Vec<ChatAttachment>) -> ChatMessage {
+    ChatMessage {
+        text: record.text.clone(),
+        msg_id: record.msg_id.clone(),
+        timestamp: record.timestamp,
+        attachments,
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fold() {
+        let lines = [
+            Entry::Message(ChatRecord {
+                msg_id: "a".to_owned(),
+                outgoing: true,
+                text: "hello".to_owned(),
+                ..Default::default()
+            }),
+            Entry::Message(ChatRecord {
+                msg_id: "b".to_owned(),
+                text: "hi".to_owned(),
+                ..Default::default()
+            }),
+            Entry::Receipt {
+                msg_ids: vec!["a".to_owned(), "unknown".to_owned()],
+                receipt: Receipt::Delivered,
+                timestamp: 1,
+            },
+            Entry::Receipt {
+                msg_ids: vec!["a".to_owned(), "b".to_owned()],
+                receipt: Receipt::Read,
+                timestamp: 2,
+            },
+        ];
+        let data = lines
+            .iter()
+            .map(|l| serde_json::to_string(l).unwrap())
+            .chain(std::iter::once("broken".to_owned()))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let records = fold(&data);
+        assert_eq!(records.len(), 2);
+        assert_eq!((records[0].delivered, records[0].read), (1, 2));
+        // Read implies delivered.
+        assert_eq!((records[1].delivered, records[1].read), (2, 2));
+    }
+
+    #[test]
+    fn test_receiving() {
+        let attachment = |size| ChatAttachment {
+            name: "a.txt".to_owned(),
+            size,
+            ..Default::default()
+        };
+        let block = |id, len| FileTransferBlock {
+            id,
+            data: vec![0; len].into(),
+            ..Default::default()
+        };
+        let mut receiving = Receiving::default();
+        let too_large = ChatMessage {
+            msg_id: "a".to_owned(),
+            attachments: vec![attachment(MAX_ATTACHMENTS_SIZE), attachment(1)],
+            ..Default::default()
+        };
+        assert!(receiving.expect(&too_large).is_empty());
+        assert!(!receiving.add_job(1, "a", 1));
+        let msg = ChatMessage {
+            msg_id: "b".to_owned(),
+            attachments: vec![attachment(10)],
+            ..Default::default()
+        };
+        assert_eq!(receiving.expect(&msg), vec!["a.txt".to_owned()]);
+        assert!(!receiving.add_job(2, "b", 11));
+        assert!(receiving.add_job(2, "b", 10));
+        assert!(receiving.receive(&block(2, 6)));
+        assert!(!receiving.receive(&block(2, 6)));
+        // Not an attachment any more.
+        assert!(receiving.receive(&block(2, 6)));
+    }
+}
@@ -30,7 +30,8 @@ use uuid::Uuid;
 use crate::{
     check_port,
     common::input::{MOUSE_BUTTON_LEFT, MOUSE_BUTTON_RIGHT, MOUSE_TYPE_DOWN, MOUSE_TYPE_UP},
-    create_symmetric_key_msg, decode_id_pk, get_rs_pk, is_keyboard_mode_supported,
+    create_forward_secret_key_msg, create_symmetric_key_msg, decode_id_pk, diagnostics, get_rs_pk,
+    is_keyboard_mode_supported,
     kcp_stream::KcpStream,
     secure_tcp,
     ui_interface::{get_builtin_option, use_texture_render},
@@ -50,17 +51,20 @@ use hbb_common::{
         self, keys, use_ws, Config, LocalConfig, PeerConfig, PeerInfoSerde, Resolution,
         CONNECT_TIMEOUT, READ_TIMEOUT, RELAY_PORT, RENDEZVOUS_PORT, RENDEZVOUS_SERVERS,
     },
+    direct_access,
     fs::JobType,
     futures::future::{select_ok, FutureExt},
-    get_version_number, log,
+    get_time, get_version_number, log,
     message_proto::{option_message::BoolOption, *},
     protobuf::{Message as _, MessageField},
+    proxy_resolver::proxy_for_async,
+    quic::QuicFramedStream,
     rand,
     rendezvous_proto::*,
     sha2::{Digest, Sha256},
-    socket_client::{connect_tcp, connect_tcp_local, ipv4_to_ipv6, new_direct_udp_for},
+    socket_client::{connect_tcp, connect_tcp_local, new_direct_udp_for},
     sodiumoxide::{base64, crypto::sign},
-    timeout,
+    tcp, timeout,
     tokio::{
         self,
         net::UdpSocket,
@@ -73,6 +77,7 @@ use hbb_common::{
     AddrMangle, ResultType, Stream,
 };
 pub use helper::*;
+use jitter_buffer::{AvSync, JitterBuffer};
 use scrap::{
     codec::Decoder,
     record::{Recorder, RecorderContext},
@@ -91,7 +96,9 @@ pub use super::lang::*;
 
 pub mod file_trait;
 pub mod helper;
+pub mod input_macro;
 pub mod io_loop;
+pub mod jitter_buffer;
 pub mod screenshot;
 
 pub const MILLI1: Duration = Duration::from_millis(1);
@@ -117,6 +124,8 @@ pub const LOGIN_MSG_2FA_WRONG: &str = "Wrong 2FA Code";
 pub const REQUIRE_2FA: &'static str = "2FA Required";
 pub const LOGIN_MSG_NO_PASSWORD_ACCESS: &str = "No Password Access";
 pub const LOGIN_MSG_OFFLINE: &str = "Offline";
+pub const PEER_KEY_CHANGED: &str = "Peer key changed";
+pub const ENCRYPTION_REQUIRED: &str = "Encrypted connection required";
 pub const LOGIN_SCREEN_WAYLAND: &str = "Wayland login screen is not supported";
 #[cfg(target_os = "linux")]
 pub const SCRAP_UBUNTU_HIGHER_REQUIRED: &str = "Wayland requires Ubuntu 21.04 or higher version.";
@@ -250,12 +259,18 @@ impl Client {
         if config::is_incoming_only() {
             bail!("Incoming only mode");
         }
+        // The direct ip access is not encrypted, unless over TLS.
+        if crate::common::is_strict_encryption()
+            && (hbb_common::is_ip_str(peer) || hbb_common::is_domain_port_str(peer))
+            && !direct_access::ClientSettings::load(peer).tls
+        {
+            bail!(ENCRYPTION_REQUIRED);
+        }
         // to-do: remember the port for each peer, so that we can retry easier
         if hbb_common::is_ip_str(peer) {
             return Ok((
                 (
-                    connect_tcp_local(check_port(peer, RELAY_PORT + 1), None, CONNECT_TIMEOUT)
-                        .await?,
+                    Self::connect_direct(&check_port(peer, RELAY_PORT + 1), &interface).await?,
                     true,
                     None,
                     None,
@@ -269,7 +284,7 @@ impl Client {
         if hbb_common::is_domain_port_str(peer) {
             return Ok((
                 (
-                    connect_tcp_local(peer, None, CONNECT_TIMEOUT).await?,
+                    Self::connect_direct(peer, &interface).await?,
                     true,
                     None,
                     None,
@@ -303,30 +318,40 @@ impl Client {
             }
         };
 
-        if crate::get_ipv6_punch_enabled() {
-            crate::test_ipv6().await;
+        // Behind a proxy to the rendezvous server, the peer is reached by the relay only.
+        if proxy_for_async(rendezvous_server.clone()).await.is_some() {
+            interface.get_lch().write().unwrap().force_relay = true;
         }
 
+        // The public IPv6 address is found while the UDP NAT test is set up.
+        let ipv6_test = async {
+            if crate::get_ipv6_punch_enabled().await {
+                crate::test_ipv6().await;
+            }
+        };
+
         let (stop_udp_tx, stop_udp_rx) = oneshot::channel::<()>();
-        let udp =
-        // no need to care about multiple rendezvous servers case, since it is acutally not used any more.
-        // Shared state for UDP NAT test result
-        if crate::get_udp_punch_enabled() && !interface.is_force_relay() {
-            if let Ok((socket, addr)) = new_direct_udp_for(&rendezvous_server).await {
-                let udp_port = Arc::new(Mutex::new(0));
-                let up_cloned = udp_port.clone();
-                let socket_cloned = socket.clone();
-                let func = async move {
-                    allow_err!(test_udp_uat(socket_cloned, addr, up_cloned, stop_udp_rx).await);
-                };
-                tokio::spawn(func);
-                (Some(socket), Some(udp_port))
+        let udp = async {
+            // no need to care about multiple rendezvous servers case, since it is acutally not used any more.
+            // Shared state for UDP NAT test result
+            if crate::get_udp_punch_enabled() && !interface.is_force_relay() {
+                if let Ok((socket, addr)) = new_direct_udp_for(&rendezvous_server).await {
+                    let udp_port = Arc::new(Mutex::new(0));
+                    let up_cloned = udp_port.clone();
+                    let socket_cloned = socket.clone();
+                    let func = async move {
+                        allow_err!(test_udp_uat(socket_cloned, addr, up_cloned, stop_udp_rx).await);
+                    };
+                    tokio::spawn(func);
+                    (Some(socket), Some(udp_port))
+                } else {
+                    (None, None)
+                }
             } else {
                 (None, None)
             }
-        } else {
-            (None, None)
         };
+        let (_, udp) = tokio::join!(ipv6_test, udp);
         let fut = Self::_start_inner(
             peer.to_owned(),
             key.to_owned(),
@@ -363,6 +388,28 @@ impl Client {
         }
     }
 
+    // Over TLS if set for the peer, see `hbb_common::direct_access`.
+    async fn connect_direct(addr: &str, interface: &impl Interface) -> ResultType<Stream> {
+        let lc = interface.get_lch();
+        let settings =
+            direct_access::ClientSettings::from_options(&lc.read().unwrap().config.options);
+        if !settings.tls {
+            return connect_tcp_local(addr, None, CONNECT_TIMEOUT).await;
+        }
+        let (stream, pin) = direct_access::connect(addr, CONNECT_TIMEOUT, &settings).await?;
+        // Trust on first use, a changed certificate is refused after.
+        if settings.pins.is_empty() {
+            log::info!("Pinned the direct access certificate {}", pin);
+            let mut lc = lc.write().unwrap();
+            let mut config = lc.load_config();
+            config
+                .options
+                .insert(keys::OPTION_DIRECT_ACCESS_PINS.to_owned(), pin);
+            lc.save_config(config);
+        }
+        Ok(stream)
+    }
+
     async fn _start_inner(
         peer: String,
         key: String,
@@ -405,6 +452,19 @@ impl Client {
             crate::refresh_rendezvous_server();
         }
         log::info!("rendezvous server: {}", rendezvous_server);
+        diagnostics::record(&interface, |r| {
+            r.rendezvous.server = rendezvous_server.clone();
+            r.rendezvous.reachable = socket.is_ok();
+            r.rendezvous.connect_ms = rtt.as_millis() as _;
+            match &socket {
+                Ok(s) => {
+                    r.rendezvous.family =
+                        hbb_common::happy_eyeballs::family(&s.local_addr()).to_owned();
+                    r.reach(diagnostics::Stage::Rendezvous);
+                }
+                Err(err) => r.rendezvous.error = err.to_string(),
+            }
+        });
         let mut socket = socket?;
         let my_addr = socket.local_addr();
         let mut signed_id_pk = Vec::new();
@@ -414,12 +474,19 @@ impl Client {
         let my_nat_type = crate::get_nat_type(100).await;
         let mut is_local = false;
         let mut feedback = 0;
+        let mut quic = false;
         use hbb_common::protobuf::Enum;
         let nat_type = if interface.is_force_relay() {
             NatType::SYMMETRIC
         } else {
             NatType::from_i32(my_nat_type).unwrap_or(NatType::UNKNOWN_NAT)
         };
+        diagnostics::record(&interface, |r| {
+            r.local_nat_type = format!(
+                "{:?}",
+                NatType::from_i32(my_nat_type).unwrap_or(NatType::UNKNOWN_NAT)
+            );
+        });
 
         if !key.is_empty() && !token.is_empty() {
             // mainly for the security of token
@@ -443,7 +510,7 @@ impl Client {
         // Stop UDP NAT test task if still running
         stop_udp_tx.map(|tx| tx.send(()));
         let mut msg_out = RendezvousMessage::new();
-        let mut ipv6 = if crate::get_ipv6_punch_enabled() {
+        let mut ipv6 = if crate::get_ipv6_punch_enabled().await {
             if let Some((socket, addr)) = crate::get_ipv6_socket().await {
                 (Some(socket), Some(addr))
             } else {
@@ -464,6 +531,7 @@ impl Client {
             udp_port: udp_nat_port as _,
             force_relay: interface.is_force_relay(),
             socket_addr_v6: ipv6.1.unwrap_or_default(),
+            quic: true,
             ..Default::default()
         });
         for i in 1..=3 {
@@ -475,10 +543,29 @@ impl Client {
                 peer
             );
             socket.send(&msg_out).await?;
+            let sent = Instant::now();
             // below timeout should not bigger than hbbs's connection timeout.
-            if let Some(msg_in) =
-                crate::get_next_nonkeyexchange_msg(&mut socket, Some(i * 3000)).await
-            {
+            let msg_in = crate::get_next_nonkeyexchange_msg(&mut socket, Some(i * 3000)).await;
+            diagnostics::record(&interface, |r| {
+                let response = match msg_in.as_ref().and_then(|m| m.union.as_ref()) {
+                    None => "timeout",
+                    Some(rendezvous_message::Union::PunchHoleResponse(ph))
+                        if ph.socket_addr.is_empty() =>
+                    {
+                        "failure"
+                    }
+                    Some(rendezvous_message::Union::PunchHoleResponse(_)) => "punch_hole",
+                    Some(rendezvous_message::Union::RelayResponse(_)) => "relay",
+                    Some(_) => "unexpected",
+                };
+                r.punch_attempts.push(diagnostics::PunchAttempt {
+                    punch_type: punch_type.to_owned(),
+                    attempt: i,
+                    response: response.to_owned(),
+                    ms: sent.elapsed().as_millis() as _,
+                });
+            });
+            if let Some(msg_in) = msg_in {
                 match msg_in.union {
                     Some(rendezvous_message::Union::PunchHoleResponse(ph)) => {
                         if ph.socket_addr.is_empty() {
@@ -501,12 +588,19 @@ impl Client {
                                 _ => bail!("other punch hole failure"),
                             }
                         } else {
+                            diagnostics::record(&interface, |r| {
+                                r.reach(diagnostics::Stage::PunchHole);
+                                r.peer_nat_type = format!("{:?}", ph.nat_type());
+                                r.is_local = ph.is_local();
+                                r.relay_server = ph.relay_server.clone();
+                            });
                             peer_nat_type = ph.nat_type();
                             is_local = ph.is_local();
                             signed_id_pk = ph.pk.into();
                             relay_server = ph.relay_server;
                             peer_addr = AddrMangle::decode(&ph.socket_addr);
                             feedback = ph.feedback;
+                            quic = ph.quic;
                             let s = udp.0.take();
                             if ph.is_udp && s.is_some() {
                                 if let Some(s) = s {
@@ -535,13 +629,19 @@ impl Client {
                             rr.relay_server
                         );
                         start = Instant::now();
+                        diagnostics::record(&interface, |r| {
+                            r.reach(diagnostics::Stage::PunchHole);
+                            r.relay_server = rr.relay_server.clone();
+                        });
                         let mut connect_futures = Vec::new();
                         if let Some(s) = ipv6.0 {
                             let addr = AddrMangle::decode(&rr.socket_addr_v6);
                             if addr.port() > 0 {
                                 if s.connect(addr).await.is_ok() {
-                                    connect_futures
-                                        .push(udp_nat_connect(s, "IPv6", CONNECT_TIMEOUT).boxed());
+                                    connect_futures.push(
+                                        udp_nat_connect(s, "IPv6", &peer, rr.quic, CONNECT_TIMEOUT)
+                                            .boxed(),
+                                    );
                                 }
                             }
                         }
@@ -552,7 +652,6 @@ impl Client {
                             rr.relay_server,
                             &key,
                             conn_type,
-                            my_addr.is_ipv4(),
                         );
                         connect_futures.push(
                             async move {
@@ -570,8 +669,14 @@ impl Client {
                         let mut conn = conn?;
                         feedback = rr.feedback;
                         log::info!("{:?} used to establish {typ} connection", start.elapsed());
-                        let pk =
-                            Self::secure_connection(&peer, signed_id_pk, &key, &mut conn).await?;
+                        let pk = Self::secure_connection(
+                            &peer,
+                            signed_id_pk,
+                            &key,
+                            &mut conn,
+                            &interface,
+                        )
+                        .await?;
                         return Ok((
                             (conn, typ == "IPv6", pk, kcp, typ),
                             (feedback, rendezvous_server),
@@ -618,6 +723,7 @@ impl Client {
                 interface,
                 udp.0,
                 ipv6.0,
+                quic,
                 punch_type,
             )
             .await?,
@@ -644,6 +750,7 @@ impl Client {
         interface: impl Interface,
         udp_socket_nat: Option<Arc<UdpSocket>>,
         udp_socket_v6: Option<Arc<UdpSocket>>,
+        quic: bool,
         punch_type: &str,
     ) -> ResultType<(
         Stream,
@@ -697,10 +804,14 @@ impl Client {
             .boxed(),
         );
         if let Some(udp_socket_nat) = udp_socket_nat {
-            connect_futures.push(udp_nat_connect(udp_socket_nat, "UDP", connect_timeout).boxed());
+            connect_futures.push(
+                udp_nat_connect(udp_socket_nat, "UDP", peer_id, quic, connect_timeout).boxed(),
+            );
         }
         if let Some(udp_socket_v6) = udp_socket_v6 {
-            connect_futures.push(udp_nat_connect(udp_socket_v6, "IPv6", connect_timeout).boxed());
+            connect_futures.push(
+                udp_nat_connect(udp_socket_v6, "IPv6", peer_id, quic, connect_timeout).boxed(),
+            );
         }
         // Run all connection attempts concurrently, return the first successful one
         let (mut conn, kcp, mut typ) = match select_ok(connect_futures).await {
@@ -709,6 +820,9 @@ impl Client {
         };
 
         let mut direct = !conn.is_err();
+        if let Err(err) = &conn {
+            diagnostics::record(&interface, |r| r.direct_error = err.to_string());
+        }
         if interface.is_force_relay() || conn.is_err() {
             if !relay_server.is_empty() {
                 conn = Self::request_relay(
@@ -738,7 +852,7 @@ impl Client {
             start.elapsed(),
             punch_type
         );
-        let res = Self::secure_connection(peer_id, signed_id_pk, key, &mut conn).await;
+        let res = Self::secure_connection(peer_id, signed_id_pk, key, &mut conn, &interface).await;
         let pk: Option<Vec<u8>> = match res {
             Ok(pk) => pk,
             Err(e) => {
@@ -757,7 +871,9 @@ impl Client {
         signed_id_pk: Vec<u8>,
         key: &str,
         conn: &mut Stream,
+        interface: &impl Interface,
     ) -> ResultType<Option<Vec<u8>>> {
+        let strict = crate::common::is_strict_encryption();
         let rs_pk = get_rs_pk(if key.is_empty() {
             config::RS_PUB_KEY
         } else {
@@ -767,7 +883,7 @@ impl Client {
         let mut option_pk = None;
         if !signed_id_pk.is_empty() {
             if let Some(rs_pk) = rs_pk {
-                if let Ok((id, pk)) = decode_id_pk(&signed_id_pk, &rs_pk) {
+                if let Ok((id, pk, _)) = decode_id_pk(&signed_id_pk, &rs_pk) {
                     if id == peer_id {
                         sign_pk = Some(sign::PublicKey(pk));
                         option_pk = Some(pk.to_vec());
@@ -781,45 +897,86 @@ impl Client {
         let sign_pk = match sign_pk {
             Some(v) => v,
             None => {
+                // The pinned key must be proven, no falling back to the unsigned connection.
+                if !interface.get_lch().read().unwrap().config.trusted_pk.is_empty() {
+                    bail!(PEER_KEY_CHANGED);
+                }
+                if strict {
+                    bail!(ENCRYPTION_REQUIRED);
+                }
                 // send an empty message out in case server is setting up secure and waiting for first message
                 conn.send(&Message::new()).await?;
                 return Ok(option_pk);
             }
         };
+        let lc = interface.get_lch();
+        let trusted_pk = lc.read().unwrap().config.trusted_pk.clone();
+        let new_pk = check_trusted_pk(&trusted_pk, &sign_pk.0)?;
+        let fallback = |reason: &str| -> ResultType<()> {
+            log::error!("Handshake failed: {}", reason);
+            // Neither with a pinned key, else a bad SignedId would downgrade the connection.
+            if !trusted_pk.is_empty() {
+                bail!(PEER_KEY_CHANGED);
+            }
+            if strict {
+                bail!(ENCRYPTION_REQUIRED);
+            }
+            Ok(())
+        };
         match timeout(READ_TIMEOUT, conn.next()).await? {
             Some(res) => {
                 let bytes = res?;
                 if let Ok(msg_in) = Message::parse_from_bytes(&bytes) {
                     if let Some(message::Union::SignedId(si)) = msg_in.union {
-                        if let Ok((id, their_pk_b)) = decode_id_pk(&si.id, &sign_pk) {
+                        if let Ok((id, their_pk_b, key_exchange)) = decode_id_pk(&si.id, &sign_pk) {
                             if id == peer_id {
-                                let (asymmetric_value, symmetric_value, key) =
-                                    create_symmetric_key_msg(their_pk_b);
                                 let mut msg_out = Message::new();
-                                msg_out.set_public_key(PublicKey {
-                                    asymmetric_value,
-                                    symmetric_value,
-                                    ..Default::default()
-                                });
-                                timeout(CONNECT_TIMEOUT, conn.send(&msg_out)).await??;
-                                conn.set_key(key);
+                                if key_exchange >= tcp::KEY_EXCHANGE_FORWARD_SECRET {
+                                    let (asymmetric_value, encrypt) =
+                                        create_forward_secret_key_msg(their_pk_b)?;
+                                    msg_out.set_public_key(PublicKey {
+                                        asymmetric_value,
+                                        key_exchange: tcp::KEY_EXCHANGE_FORWARD_SECRET,
+                                        ..Default::default()
+                                    });
+                                    timeout(CONNECT_TIMEOUT, conn.send(&msg_out)).await??;
+                                    conn.set_encrypt(encrypt);
+                                } else {
+                                    let (asymmetric_value, symmetric_value, key) =
+                                        create_symmetric_key_msg(their_pk_b);
+                                    msg_out.set_public_key(PublicKey {
+                                        asymmetric_value,
+                                        symmetric_value,
+                                        ..Default::default()
+                                    });
+                                    timeout(CONNECT_TIMEOUT, conn.send(&msg_out)).await??;
+                                    conn.set_key(key);
+                                }
+                                // The peer has proven it holds the key.
+                                if new_pk {
+                                    let mut lc = lc.write().unwrap();
+                                    let mut config = lc.load_config();
+                                    config.trusted_pk =
+                                        base64::encode(sign_pk.0, base64::Variant::Original);
+                                    lc.save_config(config);
+                                }
                             } else {
-                                log::error!("Handshake failed: sign failure");
+                                fallback("sign failure")?;
                                 conn.send(&Message::new()).await?;
                             }
                         } else {
                             // fall back to non-secure connection in case pk mismatch
-                            log::info!("pk mismatch, fall back to non-secure");
+                            fallback("pk mismatch")?;
                             let mut msg_out = Message::new();
                             msg_out.set_public_key(PublicKey::new());
                             conn.send(&msg_out).await?;
                         }
                     } else {
-                        log::error!("Handshake failed: invalid message type");
+                        fallback("invalid message type")?;
                         conn.send(&Message::new()).await?;
                     }
                 } else {
-                    log::error!("Handshake failed: invalid message format");
+                    fallback("invalid message format")?;
                     conn.send(&Message::new()).await?;
                 }
             }
@@ -842,7 +999,6 @@ impl Client {
     ) -> ResultType<Stream> {
         let mut succeed = false;
         let mut uuid = "".to_owned();
-        let mut ipv4 = true;
 
         for i in 1..=3 {
             // use different socket due to current hbbs implementation requiring different nat address for each attempt
@@ -855,7 +1011,6 @@ impl Client {
                 secure_tcp(&mut socket, key).await?;
             }
 
-            ipv4 = socket.local_addr().is_ipv4();
             let mut msg_out = RendezvousMessage::new();
             uuid = Uuid::new_v4().to_string();
             log::info!(
@@ -891,7 +1046,7 @@ impl Client {
         if !succeed {
             bail!("Timeout");
         }
-        Self::create_relay(peer, uuid, relay_server, key, conn_type, ipv4).await
+        Self::create_relay(peer, uuid, relay_server, key, conn_type).await
     }
 
     /// Create a relay connection to the server.
@@ -901,14 +1056,11 @@ impl Client {
         relay_server: String,
         key: &str,
         conn_type: ConnType,
-        ipv4: bool,
     ) -> ResultType<Stream> {
-        let mut conn = connect_tcp(
-            ipv4_to_ipv6(check_port(relay_server, RELAY_PORT), ipv4),
-            CONNECT_TIMEOUT,
-        )
-        .await
-        .with_context(|| "Failed to connect to relay server")?;
+        // Either family of the relay server, raced.
+        let mut conn = connect_tcp(check_port(relay_server, RELAY_PORT), CONNECT_TIMEOUT)
+            .await
+            .with_context(|| "Failed to connect to relay server")?;
         let mut msg_out = RendezvousMessage::new();
         msg_out.set_request_relay(RequestRelay {
             licence_key: key.to_owned(),
@@ -1175,6 +1327,9 @@ impl ClientClipboardHandler {
 #[derive(Default)]
 pub struct AudioHandler {
     audio_decoder: Option<(AudioDecoder, Vec<f32>)>,
+    jitter_buffer: JitterBuffer,
+    // The samples per channel of the last frame.
+    frame_samples: usize,
     #[cfg(target_os = "linux")]
     simple: Option<psimple::Simple>,
     #[cfg(not(target_os = "linux"))]
@@ -1190,11 +1345,7 @@ pub struct AudioHandler {
 }
 
 #[cfg(not(target_os = "linux"))]
-struct AudioBuffer(
-    pub Arc<std::sync::Mutex<ringbuf::HeapRb<f32>>>,
-    usize,
-    [usize; 30],
-);
+struct AudioBuffer(pub Arc<std::sync::Mutex<ringbuf::HeapRb<f32>>>, usize);
 
 #[cfg(not(target_os = "linux"))]
 impl Default for AudioBuffer {
@@ -1204,7 +1355,6 @@ impl Default for AudioBuffer {
                 ringbuf::HeapRb::<f32>::new(48000 * 2 * AUDIO_BUFFER_MS / 1000), // 48000hz, 2 channel
             )),
             48000 * 2,
-            [0; 30],
         )
     }
 }
@@ -1221,72 +1371,10 @@ impl AudioBuffer {
         }
     }
 
-    fn try_shrink(&mut self, having: usize) {
-        extern crate chrono;
-        use chrono::prelude::*;
-
-        let mut i = (having * 10) / self.1;
-        if i > 29 {
-            i = 29;
-        }
-        self.2[i] += 1;
-
-        #[allow(non_upper_case_globals)]
-        static mut tms: i64 = 0;
-        let dt = Local::now().timestamp_millis();
-        unsafe {
-            if tms == 0 {
-                tms = dt;
-                return;
-            } else if dt < tms + 12000 {
-                return;
-            }
-            tms = dt;
-        }
-
-        // the safer water mark to drop
-        let mut zero = 0;
-        // the water mark taking most of time
-        let mut max = 0;
-        for i in 0..30 {
-            if self.2[i] == 0 && zero == i {
-                zero += 1;
-            }
-
-            if self.2[i] > self.2[max] {
-                self.2[max] = 0;
-                max = i;
-            } else {
-                self.2[i] = 0;
-            }
-        }
-        zero = zero * 2 / 3;
-
-        // how many data can be dropped:
-        // 1. will not drop if buffered data is less than 600ms
-        // 2. choose based on min(zero, max)
-        const N: usize = 4;
-        self.2[max] = 0;
-        if max < 6 {
-            return;
-        } else if max > zero * N {
-            max = zero * N;
-        }
-
-        let mut lock = self.0.lock().unwrap();
-        let cap = lock.capacity();
-        let having = lock.occupied_len();
-        let skip = (cap * max / (30 * N) + 1) & (!1);
-        if (having > skip * 3) && (skip > 0) {
-            lock.skip(skip);
-            log::info!("skip {skip}, based {max} {zero}");
-        }
-    }
-
     /// append pcm to audio buffer, if buffered data
     /// exceeds AUDIO_BUFFER_MS,  only AUDIO_BUFFER_MS
     /// will be kept.
-    fn append_pcm2(&self, buffer: &[f32]) -> usize {
+    pub fn append_pcm(&self, buffer: &[f32]) -> usize {
         let mut lock = self.0.lock().unwrap();
         let cap = lock.capacity();
         if buffer.len() > cap {
@@ -1302,16 +1390,20 @@ impl AudioBuffer {
         lock.occupied_len()
     }
 
-    /// append pcm to audio buffer, trying to drop data
-    /// when data is too much (per 12 seconds) based
-    /// statistics.
-    pub fn append_pcm(&mut self, buffer: &[f32]) {
-        let having = self.append_pcm2(buffer);
-        self.try_shrink(having);
+    /// The duration of the buffered samples.
+    fn level_ms(&self) -> f64 {
+        self.0.lock().unwrap().occupied_len() as f64 * 1000. / self.1.max(1) as f64
     }
 }
 
 impl AudioHandler {
+    pub fn new(av_sync: Arc<AvSync>) -> Self {
+        Self {
+            jitter_buffer: JitterBuffer::new(av_sync),
+            ..Default::default()
+        }
+    }
+
     #[cfg(target_os = "linux")]
     fn start_audio(&mut self, format0: AudioFormat) -> ResultType<()> {
         use psimple::Simple;
@@ -1399,6 +1491,8 @@ impl AudioHandler {
                 let buffer = vec![0.; f.sample_rate as usize * f.channels as usize];
                 self.audio_decoder = Some((d, buffer));
                 self.channels = f.channels as _;
+                self.frame_samples = f.sample_rate as usize / 100;
+                self.jitter_buffer.reset();
                 allow_err!(self.start_audio(f));
             }
             Err(err) => {
@@ -1419,42 +1513,68 @@ impl AudioHandler {
             log::debug!("PulseAudio simple binding does not exists");
             return;
         }
-        self.audio_decoder.as_mut().map(|(d, buffer)| {
-            if let Ok(n) = d.decode_float(&frame.data, buffer, false) {
-                let channels = self.channels;
-                let n = n * (channels as usize);
-                #[cfg(not(target_os = "linux"))]
-                {
-                    let sample_rate0 = self.sample_rate.0;
-                    let sample_rate = self.sample_rate.1;
-                    let mut buffer = buffer[0..n].to_owned();
-                    if sample_rate != sample_rate0 {
-                        buffer = crate::audio_resample(
-                            &buffer[0..n],
-                            sample_rate0,
-                            sample_rate,
-                            channels,
-                        );
-                    }
-                    if self.channels != self.device_channel {
-                        buffer = crate::audio_rechannel(
-                            buffer,
-                            sample_rate,
-                            sample_rate,
-                            self.channels,
-                            self.device_channel,
-                        );
-                    }
-                    self.audio_buffer.append_pcm(&buffer);
-                }
-                #[cfg(target_os = "linux")]
-                {
-                    let data_u8 =
-                        unsafe { std::slice::from_raw_parts::<u8>(buffer.as_ptr() as _, n * 4) };
-                    self.simple.as_mut().map(|x| x.write(data_u8));
-                }
+        let Some((d, buffer)) = self.audio_decoder.as_mut() else {
+            return;
+        };
+        let channels = self.channels as usize;
+        let sample_rate0 = self.sample_rate.0;
+        let frame_ms = self.frame_samples as f64 * 1000. / sample_rate0.max(1) as f64;
+        let arrival = self
+            .jitter_buffer
+            .on_frame(frame.seq, frame.timestamp, get_time(), frame_ms);
+        let jitter_buffer::Arrival::Next { lost } = arrival else {
+            return;
+        };
+        let lost = lost.min(jitter_buffer::MAX_CONCEALED_FRAMES);
+        let mut pcm = Vec::new();
+        let frame_len = (self.frame_samples * channels).min(buffer.len());
+        for i in 0..lost {
+            // The last lost frame is recovered from the FEC data of this one if any, the others are PLC.
+            let fec = i + 1 == lost;
+            let data: &[u8] = if fec { &frame.data[..] } else { &[] };
+            if let Ok(n) = d.decode_float(data, &mut buffer[..frame_len], fec) {
+                pcm.extend_from_slice(&buffer[..n * channels]);
             }
-        });
+        }
+        if let Ok(n) = d.decode_float(&frame.data, buffer, false) {
+            self.frame_samples = n;
+            pcm.extend_from_slice(&buffer[..n * channels]);
+        }
+        if pcm.is_empty() {
+            return;
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let sample_rate = self.sample_rate.1;
+            if sample_rate != sample_rate0 {
+                pcm = crate::audio_resample(&pcm, sample_rate0, sample_rate, self.channels);
+            }
+            if self.channels != self.device_channel {
+                pcm = crate::audio_rechannel(
+                    pcm,
+                    sample_rate,
+                    sample_rate,
+                    self.channels,
+                    self.device_channel,
+                );
+            }
+            let level_ms = self.audio_buffer.level_ms();
+            let pcm = self
+                .jitter_buffer
+                .adjust(pcm, self.device_channel as _, level_ms);
+            self.audio_buffer.append_pcm(&pcm);
+        }
+        #[cfg(target_os = "linux")]
+        if let Some(simple) = self.simple.as_mut() {
+            let level_ms = simple
+                .get_latency()
+                .map(|l| l.0 as f64 / 1000.)
+                .unwrap_or_default();
+            let pcm = self.jitter_buffer.adjust(pcm, channels, level_ms);
+            let data_u8 =
+                unsafe { std::slice::from_raw_parts::<u8>(pcm.as_ptr() as _, pcm.len() * 4) };
+            allow_err!(simple.write(data_u8));
+        }
     }
 
     /// Build audio output stream for current device.
@@ -1472,6 +1592,7 @@ impl AudioHandler {
         self.audio_buffer
             .resize(config.sample_rate.0 as _, config.channels as _);
         let audio_buffer = self.audio_buffer.0.clone();
+        let av_sync = self.jitter_buffer.sync();
         let ready = self.ready.clone();
         let timeout = None;
         let stream = device.build_output_stream(
@@ -1510,6 +1631,9 @@ impl AudioHandler {
                 if having < n {
                     n = having;
                 }
+                if n > 0 && n < data.len() {
+                    av_sync.add_underrun();
+                }
                 let mut elems = vec![0.0f32; n];
                 if n > 0 {
                     lock.pop_slice(&mut elems);
@@ -1587,7 +1711,8 @@ impl VideoHandler {
         chroma: &mut Option<Chroma>,
     ) -> ResultType<bool> {
         let format = CodecFormat::from(&vf);
-        if format != self.decoder.format() {
+        // Lossless text tiles have no codec format, they don't change the decoder.
+        if format != CodecFormat::Unknown && format != self.decoder.format() {
             self.reset(Some(format));
         }
         match &vf.union {
@@ -1599,6 +1724,9 @@ impl VideoHandler {
                     pixelbuffer,
                     chroma,
                 );
+                if format == CodecFormat::Unknown {
+                    return res;
+                }
                 if res.as_ref().is_ok_and(|x| *x) {
                     self.fail_counter = 0;
                 } else {
@@ -1847,8 +1975,7 @@ impl LoginConfigHandler {
         self.force_relay =
             config::option2bool("force-always-relay", &self.get_option("force-always-relay"))
                 || force_relay
-                || use_ws()
-                || Config::is_proxy();
+                || use_ws();
         if let Some((real_id, server, key)) = &self.other_server {
             let other_server_key = self.get_option("other-server-key");
             if !other_server_key.is_empty() && key.is_empty() {
@@ -2145,6 +2272,14 @@ impl LoginConfigHandler {
                 BoolOption::No
             }
             .into();
+        } else if name == keys::OPTION_TEXT_LOSSLESS {
+            let v = !self.get_toggle_option(&name);
+            if v {
+                config.options.insert(name.clone(), "Y".to_owned());
+            } else {
+                config.options.remove(&name);
+            }
+            option.text_lossless = (if v { BoolOption::Yes } else { BoolOption::No }).into();
         } else {
             let is_set = self
                 .options
@@ -2258,6 +2393,9 @@ impl LoginConfigHandler {
         if view_only || self.get_toggle_option("disable-clipboard") {
             msg.disable_clipboard = BoolOption::Yes.into();
         }
+        if self.get_toggle_option(keys::OPTION_TEXT_LOSSLESS) {
+            msg.text_lossless = BoolOption::Yes.into();
+        }
         msg.supported_decoding = MessageField::some(self.get_supported_decoding());
         Some(msg)
     }
@@ -2343,6 +2481,14 @@ impl LoginConfigHandler {
         }
     }
 
+    pub fn is_pen_supported(&self) -> bool {
+        if let Some(features) = &self.features {
+            features.pen
+        } else {
+            false
+        }
+    }
+
     /// Create a [`Message`] for refreshing video.
     pub fn refresh() -> Message {
         let mut misc = Misc::new();
@@ -2814,6 +2960,7 @@ pub fn start_video_thread<F, T>(
                             }
                         };
                         let display = vf.display as usize;
+                        let capture_time = vf.timestamp;
                         let start = std::time::Instant::now();
                         let format = CodecFormat::from(&vf);
                         if video_handler.is_none() {
@@ -2829,7 +2976,8 @@ pub fn start_video_thread<F, T>(
                         if let Some(handler) = video_handler.as_mut() {
                             let mut pixelbuffer = true;
                             let mut tmp_chroma = None;
-                            let format_changed = handler.decoder.format() != format;
+                            let format_changed = format != CodecFormat::Unknown
+                                && handler.decoder.format() != format;
                             match handler.handle_frame(vf, &mut pixelbuffer, &mut tmp_chroma) {
                                 Ok(true) => {
                                     video_callback(
@@ -2838,6 +2986,12 @@ pub fn start_video_thread<F, T>(
                                         handler.texture.texture,
                                         pixelbuffer,
                                     );
+                                    session.av_sync.update_video_delay(start.elapsed());
+                                    if capture_time > 0 {
+                                        session
+                                            .av_sync
+                                            .update_video_latency(get_time() - capture_time);
+                                    }
 
                                     // chroma
                                     if tmp_chroma.is_some() && last_chroma != tmp_chroma {
@@ -2919,9 +3073,14 @@ pub fn start_video_thread<F, T>(
 /// Start an audio thread
 /// Return a audio [`MediaSender`]
 pub fn start_audio_thread() -> MediaSender {
+    start_audio_thread_with_sync(Default::default())
+}
+
+/// Start an audio thread, which keeps the audio in sync with the video of `av_sync`.
+pub fn start_audio_thread_with_sync(av_sync: Arc<AvSync>) -> MediaSender {
     let (audio_sender, audio_receiver) = mpsc::channel::<MediaData>();
     std::thread::spawn(move || {
-        let mut audio_handler = AudioHandler::default();
+        let mut audio_handler = AudioHandler::new(av_sync);
         loop {
             if let Ok(data) = audio_receiver.recv() {
                 match data {
@@ -3043,6 +3202,20 @@ pub async fn handle_test_delay(t: TestDelay, peer: &mut Stream) {
     }
 }
 
+/// Answer the access token challenge of the direct ip access, before the login.
+///
+/// # Arguments
+///
+/// * `da` - The challenge with the nonce.
+/// * `id` - The id of the peer, the token is of its `PeerConfig`.
+/// * `peer` - The peer.
+pub async fn handle_direct_access(da: DirectAccess, id: &str, peer: &mut Stream) {
+    if !da.nonce.is_empty() {
+        let token = direct_access::ClientSettings::load(id).token;
+        allow_err!(peer.send(&direct_access::answer(&da, &token)).await);
+    }
+}
+
 /// Whether is track pad scrolling.
 #[inline]
 #[cfg(all(target_os = "macos", not(feature = "flutter")))]
@@ -3322,6 +3495,9 @@ pub fn handle_login_error(
         }
         interface.msgbox("input-2fa", err, "", "");
         true
+    } else if err == direct_access::WRONG_TOKEN {
+        interface.msgbox("input-access-token", err, "", "");
+        true
     } else if LOGIN_ERROR_MAP.contains_key(err) {
         if let Some(msgbox_info) = LOGIN_ERROR_MAP.get(err) {
             interface.msgbox(
@@ -3618,9 +3794,23 @@ pub trait Interface: Send + Clone + 'static + Sized {
         self.get_lch().write().unwrap().received = received;
     }
 
+    /// The report the connect sequence is recorded into, if it is diagnosed.
+    fn diagnostics(&self) -> Option<Arc<Mutex<crate::diagnostics::Report>>> {
+        None
+    }
+
     fn on_establish_connection_error(&self, err: String) {
         let title = "Connection Error";
         let text = err.to_string();
+        if err == PEER_KEY_CHANGED {
+            self.msgbox(
+                "peer-key-changed",
+                PEER_KEY_CHANGED,
+                "peer_key_changed_tip",
+                "",
+            );
+            return;
+        }
         let lc = self.get_lch();
         let direct = lc.read().unwrap().direct;
         let received = lc.read().unwrap().received;
@@ -3680,7 +3870,9 @@ pub enum Data {
     CloseVoiceCall,
     ResetDecoder(Option<usize>),
     RenameFile((i32, String, String, bool)),
-    TakeScreenshot((i32, String)),
+    TakeScreenshot(ScreenshotRequest),
+    // The message id and the local paths of the attachments.
+    SendChatAttachments((String, Vec<String>)),
 }
 
 /// Keycode for key events.
@@ -4144,18 +4336,27 @@ async fn test_udp_uat(
     Ok(())
 }
 
+// QUIC if the peer listens with it, kcp otherwise or if QUIC fails.
 #[inline]
 async fn udp_nat_connect(
     socket: Arc<UdpSocket>,
     typ: &'static str,
+    peer_id: &str,
+    quic: bool,
     ms_timeout: u64,
 ) -> ResultType<(Stream, Option<KcpStream>, &'static str)> {
-    crate::punch_udp(socket.clone(), false)
+    crate::punch_udp(socket.clone(), None, false)
         .await
         .map_err(|err| {
             log::debug!("{err}");
             anyhow!(err)
         })?;
+    if quic {
+        match QuicFramedStream::connect(socket.clone(), peer_id, ms_timeout).await {
+            Ok(stream) => return Ok((Stream::Quic(stream), None, typ)),
+            Err(err) => log::debug!("Failed to connect QUIC stream, fall back to kcp: {}", err),
+        }
+    }
     let res = KcpStream::connect(socket, Duration::from_millis(ms_timeout))
         .await
         .map_err(|err| {
@@ -4164,3 +4365,111 @@ async fn udp_nat_connect(
         })?;
     Ok((res.1, Some(res.0), typ))
 }
+
+// Trust on first use of the signing key of the peer, `trusted` is the pinned one in base64.
+// Returns whether the key is new, to be pinned once the peer has proven it holds it.
+fn check_trusted_pk(trusted: &str, pk: &[u8]) -> ResultType<bool> {
+    if trusted.is_empty() {
+        return Ok(true);
+    }
+    match base64::decode(trusted, base64::Variant::Original) {
+        Ok(trusted) if trusted == pk => Ok(false),
+        _ => bail!(PEER_KEY_CHANGED),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_trusted_pk() {
+        let (pk, _) = sign::gen_keypair();
+        let (other, _) = sign::gen_keypair();
+        assert!(check_trusted_pk("", &pk.0).unwrap());
+        let trusted = base64::encode(pk.0, base64::Variant::Original);
+        assert!(!check_trusted_pk(&trusted, &pk.0).unwrap());
+        let err = check_trusted_pk(&trusted, &other.0).unwrap_err();
+        assert_eq!(err.to_string(), PEER_KEY_CHANGED);
+        assert!(check_trusted_pk("broken", &pk.0).is_err());
+    }
+
+    struct PinnedSession(Arc<RwLock<LoginConfigHandler>>);
+
+    #[async_trait]
+    impl Interface for PinnedSession {
+        fn send(&self, _data: Data) {}
+
+        fn msgbox(&self, _msgtype: &str, _title: &str, _text: &str, _link: &str) {}
+
+        fn handle_login_error(&self, _err: &str) -> bool {
+            false
+        }
+
+        fn handle_peer_info(&self, _pi: PeerInfo) {}
+
+        fn set_multiple_windows_session(&self, _sessions: Vec<WindowsSession>) {}
+
+        async fn handle_hash(&self, _pass: &str, _hash: Hash, _peer: &mut Stream) {}
+
+        async fn handle_login_from_ui(
+            &self,
+            _os_username: String,
+            _os_password: String,
+            _password: String,
+            _remember: bool,
+            _peer: &mut Stream,
+        ) {
+        }
+
+        async fn handle_test_delay(&self, _t: TestDelay, _peer: &mut Stream) {}
+
+        fn get_lch(&self) -> Arc<RwLock<LoginConfigHandler>> {
+            self.0.clone()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pinned_pk_mismatch() {
+        let id = "123456789";
+        let (rs_pk, rs_sk) = sign::gen_keypair();
+        let (pk, _) = sign::gen_keypair();
+        let (_, other_sk) = sign::gen_keypair();
+        let signed_id_pk = sign::sign(
+            &IdPk {
+                id: id.to_owned(),
+                pk: pk.0.to_vec().into(),
+                ..Default::default()
+            }
+            .write_to_bytes()
+            .unwrap(),
+            &rs_sk,
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        // A man in the middle, its SignedId is not signed by the pinned key.
+        let mitm = tokio::spawn(async move {
+            let (socket, addr) = listener.accept().await.unwrap();
+            let mut stream = tcp::FramedStream::from(socket, addr);
+            let mut msg = Message::new();
+            msg.set_signed_id(SignedId {
+                id: sign::sign(b"123456789", &other_sk).into(),
+                ..Default::default()
+            });
+            stream.send(&msg).await.unwrap();
+            stream.next().await
+        });
+        let mut conn = Stream::Tcp(tcp::FramedStream::new(addr, None, 3000).await.unwrap());
+        let session = PinnedSession(Default::default());
+        session.0.write().unwrap().config.trusted_pk =
+            base64::encode(pk.0, base64::Variant::Original);
+        let key = crate::encode64(rs_pk.0);
+        let err = Client::secure_connection(id, signed_id_pk, &key, &mut conn, &session)
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), PEER_KEY_CHANGED);
+        // Nothing was sent to go on in plaintext.
+        drop(conn);
+        assert!(mitm.await.unwrap().is_none());
+    }
+}
@@ -1,6 +1,7 @@
+use super::jitter_buffer::AudioStats;
 use hbb_common::{
     get_time,
-    message_proto::{Message, VoiceCallRequest, VoiceCallResponse},
+    message_proto::{CaptureStats, Message, VoiceCallRequest, VoiceCallResponse},
 };
 use scrap::CodecFormat;
 use std::collections::HashMap;
@@ -13,6 +14,9 @@ pub struct QualityStatus {
     pub target_bitrate: Option<i32>,
     pub codec_format: Option<CodecFormat>,
     pub chroma: Option<String>,
+    pub audio: Option<AudioStats>,
+    // Of the peer displays, by their indexes
+    pub capture: HashMap<i32, CaptureStats>,
 }
 
 #[inline]
@@ -0,0 +1,467 @@
+// Input macros: record the key and mouse events sent to the peer and replay them later,
+// on the same or on other peers.
+//
+// Mouse positions are saved relative to the display they fall in, so that a macro still
+// hits the same spot on a peer with another resolution or display layout.
+// Typed text may contain `${name}` variables, which are filled in on replay.
+
+use crate::input::{MOUSE_TYPE_DOWN, MOUSE_TYPE_MOVE, MOUSE_TYPE_UP};
+use hbb_common::{
+    config::{MacroAction, MacroStep},
+    log,
+    message_proto::*,
+    protobuf::{EnumOrUnknown, Message as _},
+    sodiumoxide::base64,
+};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+pub const MIN_SPEED: f64 = 0.1;
+pub const MAX_SPEED: f64 = 10.;
+// Check for abort at least this often while waiting for the next step.
+const ABORT_CHECK_INTERVAL: Duration = Duration::from_millis(10);
+
+struct Recorder {
+    last: Instant,
+    steps: Vec<MacroStep>,
+}
+
+#[derive(Default)]
+pub struct InputMacroState {
+    recording: Option<Recorder>,
+    replaying: Option<Arc<AtomicBool>>,
+}
+
+impl InputMacroState {
+    pub fn start_recording(&mut self) {
+        self.recording = Some(Recorder {
+            last: Instant::now(),
+            steps: vec![],
+        });
+    }
+
+    pub fn stop_recording(&mut self) -> Option<Vec<MacroStep>> {
+        self.recording.take().map(|r| r.steps)
+    }
+
+    #[inline]
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    #[inline]
+    pub fn is_replaying(&self) -> bool {
+        self.replaying.is_some()
+    }
+
+    /// Add typed text to the recording, which may contain `${name}` variables.
+    pub fn add_text(&mut self, text: String) {
+        self.push(MacroAction::Text { text });
+    }
+
+    /// Called with every input message the user sends to the peer.
+    pub fn record(&mut self, msg: &Message, displays: &[DisplayInfo]) {
+        let action = match &msg.union {
+            Some(message::Union::KeyEvent(evt)) => match &evt.union {
+                Some(key_event::Union::Seq(seq)) => MacroAction::Text { text: seq.clone() },
+                _ => match evt.write_to_bytes() {
+                    Ok(bytes) => MacroAction::Key {
+                        event: base64::encode(bytes, base64::Variant::Original),
+                    },
+                    Err(e) => {
+                        log::error!("Failed to record key event: {}", e);
+                        return;
+                    }
+                },
+            },
+            Some(message::Union::MouseEvent(evt)) => mouse_to_action(evt, displays),
+            _ => return,
+        };
+        self.push(action);
+    }
+
+    fn push(&mut self, action: MacroAction) {
+        let Some(recorder) = self.recording.as_mut() else {
+            return;
+        };
+        let now = Instant::now();
+        // Don't replay the time between starting the recording and the first input.
+        let delay = if recorder.steps.is_empty() {
+            0
+        } else {
+            now.duration_since(recorder.last).as_millis() as _
+        };
+        recorder.steps.push(MacroStep { delay, action });
+        recorder.last = now;
+    }
+
+    /// Returns the abort flag of the new replay, or None if a replay is running.
+    pub fn start_replay(&mut self) -> Option<Arc<AtomicBool>> {
+        if self.replaying.is_some() {
+            return None;
+        }
+        let abort = Arc::new(AtomicBool::new(false));
+        self.replaying = Some(abort.clone());
+        Some(abort)
+    }
+
+    pub fn abort_replay(&mut self) {
+        if let Some(abort) = self.replaying.take() {
+            abort.store(true, Ordering::SeqCst);
+        }
+    }
+
+    pub fn end_replay(&mut self, abort: &Arc<AtomicBool>) {
+        if self
+            .replaying
+            .as_ref()
+            .map_or(false, |a| Arc::ptr_eq(a, abort))
+        {
+            self.replaying = None;
+        }
+    }
+}
+
+/// Whether the user input stops a running replay, a plain mouse move doesn't, the mouse
+/// may well be nudged while watching the replay.
+pub fn aborts_replay(msg: &Message) -> bool {
+    match &msg.union {
+        Some(message::Union::KeyEvent(_)) => true,
+        Some(message::Union::MouseEvent(evt)) => evt.mask & 0x7 != MOUSE_TYPE_MOVE,
+        _ => false,
+    }
+}
+
+#[inline]
+fn is_positional(mask: i32) -> bool {
+    matches!(
+        mask & 0x7,
+        MOUSE_TYPE_MOVE | MOUSE_TYPE_DOWN | MOUSE_TYPE_UP
+    )
+}
+
+fn mouse_to_action(evt: &MouseEvent, displays: &[DisplayInfo]) -> MacroAction {
+    let modifiers = evt.modifiers.iter().map(|m| m.value()).collect();
+    let display = if is_positional(evt.mask) {
+        displays
+            .iter()
+            .position(|d| {
+                evt.x >= d.x && evt.x < d.x + d.width && evt.y >= d.y && evt.y < d.y + d.height
+            })
+            .or((!displays.is_empty()).then_some(0))
+            .filter(|&i| displays[i].width > 0 && displays[i].height > 0)
+    } else {
+        None
+    };
+    match display {
+        Some(i) => {
+            let d = &displays[i];
+            MacroAction::Mouse {
+                mask: evt.mask,
+                display: i as _,
+                x: (evt.x - d.x) as f64 / d.width as f64,
+                y: (evt.y - d.y) as f64 / d.height as f64,
+                modifiers,
+            }
+        }
+        None => MacroAction::Mouse {
+            mask: evt.mask,
+            display: -1,
+            x: evt.x as _,
+            y: evt.y as _,
+            modifiers,
+        },
+    }
+}
+
+fn action_to_message(
+    action: &MacroAction,
+    displays: &[DisplayInfo],
+    variables: &HashMap<String, String>,
+) -> Option<Message> {
+    let mut msg = Message::new();
+    match action {
+        MacroAction::Key { event } => {
+            let bytes = base64::decode(event, base64::Variant::Original).ok()?;
+            msg.set_key_event(KeyEvent::parse_from_bytes(&bytes).ok()?);
+        }
+        MacroAction::Mouse {
+            mask,
+            display,
+            x,
+            y,
+            modifiers,
+        } => {
+            let (x, y) = if *display >= 0 && is_positional(*mask) {
+                // Fall back to the primary display if the peer has fewer displays.
+                let d = displays.get(*display as usize).or(displays.first())?;
+                let x = (x.clamp(0., 1.) * d.width as f64).round() as i32;
+                let y = (y.clamp(0., 1.) * d.height as f64).round() as i32;
+                (
+                    d.x + x.min(d.width - 1).max(0),
+                    d.y + y.min(d.height - 1).max(0),
+                )
+            } else {
+                (*x as i32, *y as i32)
+            };
+            msg.set_mouse_event(MouseEvent {
+                mask: *mask,
+                x,
+                y,
+                modifiers: modifiers
+                    .iter()
+                    .map(|m| EnumOrUnknown::from_i32(*m))
+                    .collect(),
+                ..Default::default()
+            });
+        }
+        MacroAction::Text { text } => {
+            let mut evt = KeyEvent::new();
+            evt.set_seq(substitute_variables(text, variables));
+            msg.set_key_event(evt);
+        }
+    }
+    Some(msg)
+}
+
+pub fn substitute_variables(text: &str, variables: &HashMap<String, String>) -> String {
+    let mut text = text.to_owned();
+    for (name, value) in variables.iter() {
+        text = text.replace(&format!("${{{}}}", name), value);
+    }
+    text
+}
+
+/// The names of the `${name}` variables used by the steps, in order of appearance.
+pub fn get_variables(steps: &[MacroStep]) -> Vec<String> {
+    let mut names: Vec<String> = vec![];
+    for step in steps.iter() {
+        let MacroAction::Text { text } = &step.action else {
+            continue;
+        };
+        let mut rest = text.as_str();
+        while let Some(start) = rest.find("${") {
+            rest = &rest[start + 2..];
+            let Some(end) = rest.find('}') else {
+                break;
+            };
+            let name = &rest[..end];
+            if !name.is_empty() && !names.iter().any(|n| n == name) {
+                names.push(name.to_owned());
+            }
+            rest = &rest[end + 1..];
+        }
+    }
+    names
+}
+
+// Keep the release events of the keys and buttons held down by the replay,
+// so that an aborted replay doesn't leave them pressed on the peer.
+fn track_pressed(pressed: &mut Vec<Message>, msg: &Message) {
+    match &msg.union {
+        Some(message::Union::KeyEvent(evt)) if !evt.press => {
+            pressed.retain(|m| m.key_event().union != evt.union);
+            if evt.down {
+                let mut release = msg.clone();
+                release.mut_key_event().down = false;
+                pressed.push(release);
+            }
+        }
+        Some(message::Union::MouseEvent(evt)) => {
+            let buttons = evt.mask >> 3;
+            if buttons == 0 {
+                return;
+            }
+            match evt.mask & 0x7 {
+                MOUSE_TYPE_DOWN => {
+                    let mut release = msg.clone();
+                    release.mut_mouse_event().mask = buttons << 3 | MOUSE_TYPE_UP;
+                    pressed.push(release);
+                }
+                MOUSE_TYPE_UP => {
+                    pressed.retain(|m| !m.has_mouse_event() || m.mouse_event().mask >> 3 != buttons)
+                }
+                _ => {}
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Replay `steps` at `speed` times the recorded pace.
+/// `displays` returns the current displays of the peer, `send` sends a message to it.
+/// Returns false if aborted.
+pub fn replay(
+    steps: &[MacroStep],
+    speed: f64,
+    variables: &HashMap<String, String>,
+    abort: &AtomicBool,
+    displays: impl Fn() -> Vec<DisplayInfo>,
+    send: impl Fn(Message),
+) -> bool {
+    let speed = speed.clamp(MIN_SPEED, MAX_SPEED);
+    let mut pressed = vec![];
+    let mut completed = true;
+    for step in steps.iter() {
+        let deadline = Instant::now() + Duration::from_millis((step.delay as f64 / speed) as _);
+        loop {
+            if abort.load(Ordering::SeqCst) {
+                completed = false;
+                break;
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            std::thread::sleep((deadline - now).min(ABORT_CHECK_INTERVAL));
+        }
+        if !completed {
+            break;
+        }
+        if let Some(msg) = action_to_message(&step.action, &displays(), variables) {
+            track_pressed(&mut pressed, &msg);
+            send(msg);
+        }
+    }
+    for msg in pressed.into_iter().rev() {
+        send(msg);
+    }
+    completed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn displays() -> Vec<DisplayInfo> {
+        vec![
+            DisplayInfo {
+                x: 0,
+                y: 0,
+                width: 1920,
+                height: 1080,
+                ..Default::default()
+            },
+            DisplayInfo {
+                x: 1920,
+                y: 0,
+                width: 1280,
+                height: 1024,
+                ..Default::default()
+            },
+        ]
+    }
+
+    #[test]
+    fn test_mouse_relative() {
+        let evt = MouseEvent {
+            mask: MOUSE_TYPE_DOWN | 1 << 3,
+            x: 1920 + 640,
+            y: 256,
+            ..Default::default()
+        };
+        let action = mouse_to_action(&evt, &displays());
+        let MacroAction::Mouse { display, x, y, .. } = action.clone() else {
+            panic!("not mouse");
+        };
+        assert_eq!((display, x, y), (1, 0.5, 0.25));
+
+        // replay on a peer with a single smaller display
+        let single = vec![DisplayInfo {
+            width: 800,
+            height: 600,
+            ..Default::default()
+        }];
+        let msg = action_to_message(&action, &single, &HashMap::new()).unwrap();
+        assert_eq!((msg.mouse_event().x, msg.mouse_event().y), (400, 150));
+
+        let wheel = MouseEvent {
+            mask: crate::input::MOUSE_TYPE_WHEEL,
+            x: 0,
+            y: -120,
+            ..Default::default()
+        };
+        let action = mouse_to_action(&wheel, &displays());
+        let msg = action_to_message(&action, &displays(), &HashMap::new()).unwrap();
+        assert_eq!((msg.mouse_event().x, msg.mouse_event().y), (0, -120));
+    }
+
+    #[test]
+    fn test_variables() {
+        let steps = vec![
+            MacroStep {
+                delay: 0,
+                action: MacroAction::Text {
+                    text: "${user}@${domain} ${}".to_owned(),
+                },
+            },
+            MacroStep {
+                delay: 10,
+                action: MacroAction::Text {
+                    text: "${user".to_owned(),
+                },
+            },
+        ];
+        assert_eq!(get_variables(&steps), vec!["user", "domain"]);
+        let variables = HashMap::from([
+            ("user".to_owned(), "admin".to_owned()),
+            ("domain".to_owned(), "corp".to_owned()),
+        ]);
+        assert_eq!(
+            substitute_variables("${user}@${domain} ${other}", &variables),
+            "admin@corp ${other}"
+        );
+    }
+
+    #[test]
+    fn test_release_on_abort() {
+        let mut state = InputMacroState::default();
+        state.start_recording();
+        let mut key = KeyEvent::new();
+        key.set_control_key(ControlKey::Shift);
+        key.down = true;
+        let mut msg = Message::new();
+        msg.set_key_event(key);
+        assert!(aborts_replay(&msg));
+        let mut mouse = Message::new();
+        mouse.set_mouse_event(MouseEvent {
+            mask: MOUSE_TYPE_MOVE,
+            ..Default::default()
+        });
+        assert!(!aborts_replay(&mouse));
+        state.record(&msg, &[]);
+        state.add_text("${name}".to_owned());
+        let steps = state.stop_recording().unwrap();
+        assert_eq!(steps.len(), 2);
+
+        let abort = state.start_replay().unwrap();
+        assert!(state.start_replay().is_none());
+        let sent = std::sync::Mutex::new(vec![]);
+        let completed = replay(&steps[..1], 1., &HashMap::new(), &abort, Vec::new, |m| {
+            sent.lock().unwrap().push(m)
+        });
+        assert!(completed);
+        let sent = sent.into_inner().unwrap();
+        assert_eq!(sent.len(), 2);
+        assert!(sent[0].key_event().down);
+        assert!(!sent[1].key_event().down);
+
+        state.abort_replay();
+        assert!(abort.load(Ordering::SeqCst));
+        assert!(!state.is_replaying());
+        assert!(!replay(
+            &steps,
+            1.,
+            &HashMap::new(),
+            &abort,
+            Vec::new,
+            |_| {}
+        ));
+    }
+}
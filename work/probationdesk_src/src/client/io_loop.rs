@@ -3,6 +3,7 @@ use crate::clipboard::{update_clipboard, ClipboardSide};
 #[cfg(not(any(target_os = "ios")))]
 use crate::{audio_service, clipboard::CLIPBOARD_INTERVAL, ConnInner, CLIENT_SERVER};
 use crate::{
+    chat_history,
     client::{
         self, new_voice_call_request, Client, Data, Interface, MediaData, MediaSender,
         QualityStatus, MILLI1, SEC30,
@@ -63,6 +64,7 @@ pub struct Remote<T: InvokeUiSession> {
     voice_call_request_timestamp: Option<NonZeroI64>,
     read_jobs: Vec<fs::TransferJob>,
     write_jobs: Vec<fs::TransferJob>,
+    chat_attachments: chat_history::Receiving,
     remove_jobs: HashMap<i32, RemoveJob>,
     timer: crate::RustDeskInterval,
     last_update_jobs_status: (Instant, HashMap<i32, u64>),
@@ -103,13 +105,15 @@ impl<T: InvokeUiSession> Remote<T> {
         receiver: mpsc::UnboundedReceiver<Data>,
         sender: mpsc::UnboundedSender<Data>,
     ) -> Self {
+        let av_sync = handler.av_sync.clone();
         Self {
             handler,
-            audio_sender: crate::client::start_audio_thread(),
+            audio_sender: crate::client::start_audio_thread_with_sync(av_sync),
             receiver,
             sender,
             read_jobs: Vec::new(),
             write_jobs: Vec::new(),
+            chat_attachments: Default::default(),
             remove_jobs: Default::default(),
             timer: crate::rustdesk_interval(time::interval(SEC30)),
             last_update_jobs_status: (Instant::now(), Default::default()),
@@ -180,8 +184,15 @@ impl<T: InvokeUiSession> Remote<T> {
                     .lock()
                     .unwrap()
                     .set_connected();
+                // The address family won is reported with the stream type.
+                let family = hbb_common::happy_eyeballs::family(&peer.local_addr());
+                log::info!("Connected over {}", family);
+                let stream_type = match stream_type {
+                    "IPv6" => format!("UDP, {family}"),
+                    _ => format!("{stream_type}, {family}"),
+                };
                 self.handler
-                    .set_connection_type(peer.is_secured(), direct, stream_type); // flutter -> connection_ready
+                    .set_connection_type(peer.is_secured(), direct, &stream_type); // flutter -> connection_ready
                 self.handler.update_direct(Some(direct));
                 if conn_type == ConnType::DEFAULT_CONN || conn_type == ConnType::VIEW_CAMERA {
                     self.handler
@@ -308,11 +319,13 @@ impl<T: InvokeUiSession> Remote<T> {
                             } else {
                                 Some(self.video_format.clone())
                             };
+                            let audio = self.handler.av_sync.stats();
                             self.handler.update_quality_status(QualityStatus {
                                 speed: Some(speed),
                                 fps,
                                 chroma,
                                 codec_format,
+                                audio: (audio.target_ms > 0).then_some(audio),
                                 ..Default::default()
                             });
                         }
@@ -992,15 +1005,18 @@ impl<T: InvokeUiSession> Remote<T> {
                     }
                 }
             },
-            Data::TakeScreenshot((display, sid)) => {
+            Data::TakeScreenshot(request) => {
                 let mut msg = Message::new();
-                msg.set_screenshot_request(ScreenshotRequest {
-                    display,
-                    sid,
-                    ..Default::default()
-                });
+                msg.set_screenshot_request(request);
                 allow_err!(peer.send(&msg).await);
             }
+            Data::SendChatAttachments((msg_id, paths)) => {
+                for (job, msg) in chat_history::new_read_jobs(&msg_id, &paths) {
+                    allow_err!(peer.send(&msg).await);
+                    self.read_jobs.push(job);
+                    self.timer = crate::rustdesk_interval(time::interval(MILLI1));
+                }
+            }
             _ => {}
         }
         true
@@ -1289,7 +1305,10 @@ impl<T: InvokeUiSession> Remote<T> {
                         self.send_toggle_virtual_display_msg(peer).await;
                         self.send_toggle_privacy_mode_msg(peer).await;
                     }
-                    self.video_format = CodecFormat::from(&vf);
+                    let video_format = CodecFormat::from(&vf);
+                    if video_format != CodecFormat::Unknown {
+                        self.video_format = video_format;
+                    }
 
                     let display = vf.display as usize;
                     if !self.video_threads.contains_key(&display) {
@@ -1318,6 +1337,9 @@ impl<T: InvokeUiSession> Remote<T> {
                         .handle_hash(&self.handler.password.clone(), hash, peer)
                         .await;
                 }
+                Some(message::Union::DirectAccess(da)) => {
+                    client::handle_direct_access(da, &self.handler.get_id(), peer).await;
+                }
                 Some(message::Union::LoginResponse(lr)) => match lr.union {
                     Some(login_response::Union::Error(err)) => {
                         if err == client::REQUIRE_2FA {
@@ -1610,7 +1632,19 @@ impl<T: InvokeUiSession> Remote<T> {
                             }
                         }
                         Some(file_response::Union::Block(block)) => {
-                            if let Some(job) = fs::get_job(block.id, &mut self.write_jobs) {
+                            if !self.chat_attachments.receive(&block) {
+                                if let Some(job) = fs::remove_job(block.id, &mut self.write_jobs) {
+                                    job.remove_download_file();
+                                }
+                                allow_err!(
+                                    peer.send(&fs::new_error(
+                                        block.id,
+                                        "The attachment exceeds its size",
+                                        block.file_num,
+                                    ))
+                                    .await
+                                );
+                            } else if let Some(job) = fs::get_job(block.id, &mut self.write_jobs) {
                                 if let Err(_err) = job.write(block).await {
                                     // to-do: add "skip" for writing job
                                 }
@@ -1620,6 +1654,7 @@ impl<T: InvokeUiSession> Remote<T> {
                             }
                         }
                         Some(file_response::Union::Done(d)) => {
+                            self.chat_attachments.remove_job(d.id);
                             let mut err: Option<String> = None;
                             let mut job_type = fs::JobType::Generic;
                             let mut printer_data = None;
@@ -1673,6 +1708,7 @@ impl<T: InvokeUiSession> Remote<T> {
                             }
                         }
                         Some(file_response::Union::Error(e)) => {
+                            self.chat_attachments.remove_job(e.id);
                             let job_type = fs::remove_job(e.id, &mut self.write_jobs)
                                 .map(|j| j.r#type)
                                 .unwrap_or(fs::JobType::Generic);
@@ -1692,8 +1728,46 @@ impl<T: InvokeUiSession> Remote<T> {
                     Some(misc::Union::AudioFormat(f)) => {
                         self.audio_sender.send(MediaData::AudioFormat(f)).ok();
                     }
+                    Some(misc::Union::AudioSources(s)) => {
+                        self.handler.set_audio_sources(s.sources);
+                    }
+                    Some(misc::Union::Inventory(i)) => {
+                        self.handler.handle_inventory(i);
+                    }
+                    Some(misc::Union::ControlTokenState(state)) => {
+                        self.handler.update_control_token(state);
+                    }
                     Some(misc::Union::ChatMessage(c)) => {
-                        self.handler.new_message(c.text);
+                        let receipt = !c.msg_id.is_empty();
+                        let attachments = self.chat_attachments.expect(&c);
+                        let record = chat_history::add_incoming(
+                            &self.handler.get_id(),
+                            c.msg_id,
+                            c.timestamp,
+                            c.text,
+                            attachments,
+                        );
+                        if receipt {
+                            self.handler.send_chat_receipt(
+                                vec![record.msg_id.clone()],
+                                chat_history::Receipt::Delivered,
+                            );
+                        }
+                        self.handler.new_chat_message(record);
+                    }
+                    Some(misc::Union::ChatReceipt(r)) => {
+                        let read = r.status.enum_value() == Ok(chat_receipt::Status::Read);
+                        let receipt = if read {
+                            chat_history::Receipt::Read
+                        } else {
+                            chat_history::Receipt::Delivered
+                        };
+                        chat_history::add_receipt(
+                            &self.handler.get_id(),
+                            r.msg_ids.clone(),
+                            receipt,
+                        );
+                        self.handler.chat_receipt(r.msg_ids, read);
                     }
                     Some(misc::Union::PermissionInfo(p)) => {
                         log::info!("Change permission {:?} -> {}", p.permission, p.enabled);
@@ -1746,6 +1820,9 @@ impl<T: InvokeUiSession> Remote<T> {
                             Ok(Permission::BlockInput) => {
                                 self.handler.set_permission("block_input", p.enabled);
                             }
+                            Ok(Permission::Inventory) => {
+                                self.handler.set_permission("inventory", p.enabled);
+                            }
                             _ => {}
                         }
                     }
@@ -1945,6 +2022,41 @@ impl<T: InvokeUiSession> Remote<T> {
                             job.confirm(&c).await;
                         }
                     }
+                    Some(file_action::Union::Receive(r)) if !r.chat_msg_id.is_empty() => {
+                        let accepted =
+                            self.chat_attachments
+                                .add_job(r.id, &r.chat_msg_id, r.total_size);
+                        let path = chat_history::attachment_path(
+                            &self.handler.get_id(),
+                            &r.chat_msg_id,
+                            &r.path,
+                        );
+                        match path {
+                            Some(path) if accepted => {
+                                self.write_jobs.push(fs::TransferJob::new_write(
+                                    r.id,
+                                    fs::JobType::Generic,
+                                    r.path,
+                                    fs::DataSource::FilePath(path),
+                                    0,
+                                    false,
+                                    true,
+                                    r.files,
+                                    false,
+                                ));
+                            }
+                            _ => {
+                                allow_err!(
+                                    peer.send(&fs::new_error(
+                                        r.id,
+                                        "The attachment is rejected",
+                                        0
+                                    ))
+                                    .await
+                                );
+                            }
+                        }
+                    }
                     _ => {}
                 },
                 Some(message::Union::MessageBox(msgbox)) => {
@@ -1991,9 +2103,16 @@ impl<T: InvokeUiSession> Remote<T> {
                     self.handler.set_platform_additions(&pi.platform_additions);
                 }
                 Some(message::Union::ScreenshotResponse(response)) => {
-                    crate::client::screenshot::set_screenshot(response.data);
-                    self.handler
-                        .handle_screenshot_resp(response.sid, response.msg);
+                    if response.sid == crate::client::screenshot::INTERVAL_SID {
+                        self.handler.save_interval_screenshot(response);
+                    } else {
+                        crate::client::screenshot::set_screenshot(
+                            response.data,
+                            response.format.enum_value_or_default(),
+                        );
+                        self.handler
+                            .handle_screenshot_resp(response.sid, response.msg);
+                    }
                 }
                 Some(message::Union::TerminalResponse(response)) => {
                     use hbb_common::message_proto::terminal_response::Union;
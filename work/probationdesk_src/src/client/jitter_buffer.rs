@@ -0,0 +1,314 @@
+// Adaptive playout of the received audio.
+//
+// The target delay follows the inter-arrival jitter of the frames (RFC 3550) and the latency of the
+// video, so that the audio neither crackles on lossy links nor runs ahead of the picture. Both are
+// stamped with the capture time by the peer, the audio is played as long after its capture as the
+// video is shown after its capture, the clock offset of the peer cancels out.
+// The lost frames are concealed by the decoder, see `AudioHandler::handle_frame`, and the playout
+// is stretched or compressed by a few percent to move to the target instead of cutting the audio.
+
+use serde_derive::Serialize;
+use std::{
+    sync::{
+        atomic::{AtomicU32, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+const MIN_TARGET_MS: f64 = 40.;
+const MAX_TARGET_MS: f64 = 1000.;
+// The frames are 10 ms, a longer loss is not concealed, it would be noise rather than speech then.
+pub const MAX_CONCEALED_FRAMES: u32 = 6;
+// Such a gap without the timestamps, e.g. the silence gate of the peer, is not jitter.
+const RESET_GAP_MS: i64 = 1000;
+// The pitch shift of resampling by a few percent is hardly audible.
+const STRETCH_RATIO: f64 = 0.04;
+// Far behind, e.g. after a burst.
+const FAST_COMPRESS_RATIO: f64 = 0.15;
+const UNDERRUN_BOOST_MS: f64 = 20.;
+// Per frame, the boost halves in about 7 seconds.
+const BOOST_DECAY: f64 = 0.999;
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct AudioStats {
+    pub buffer_ms: u32,
+    pub target_ms: u32,
+    pub jitter_ms: u32,
+    // The counters since the audio started.
+    pub concealed: u64,
+    pub stretched: u64,
+    pub compressed: u64,
+    pub underruns: u64,
+}
+
+/// Shared by the video threads, the audio thread and the io loop of a session.
+#[derive(Debug, Default)]
+pub struct AvSync {
+    // The smoothed time to decode and render a video frame.
+    video_delay_ms: AtomicU32,
+    // The smoothed time from the capture to the render of a video frame, with the clock offset of
+    // the peer.
+    video_latency_ms: Mutex<Option<f64>>,
+    underruns: AtomicU64,
+    // The frames received and lost since the last `take_loss_perc`.
+    received: AtomicU64,
+    lost: AtomicU64,
+    stats: Mutex<AudioStats>,
+}
+
+impl AvSync {
+    pub fn update_video_delay(&self, delay: Duration) {
+        let ms = delay.as_millis().min(MAX_TARGET_MS as _) as u32;
+        let old = self.video_delay_ms.load(Ordering::Relaxed);
+        let new = if old == 0 { ms } else { (old * 7 + ms) / 8 };
+        self.video_delay_ms.store(new, Ordering::Relaxed);
+    }
+
+    /// `latency` is the time since the capture on the peer, see `VideoFrame.timestamp`.
+    pub fn update_video_latency(&self, latency: i64) {
+        let mut video_latency = self.video_latency_ms.lock().unwrap();
+        *video_latency = Some(smooth(*video_latency, latency as f64));
+    }
+
+    /// The percentage of the frames lost since the last call, reported to the peer for the FEC.
+    pub fn take_loss_perc(&self) -> u32 {
+        let received = self.received.swap(0, Ordering::Relaxed);
+        let lost = self.lost.swap(0, Ordering::Relaxed);
+        if received + lost == 0 {
+            return 0;
+        }
+        (lost * 100 / (received + lost)) as u32
+    }
+
+    /// By the audio output, when it has not got enough samples.
+    pub fn add_underrun(&self) {
+        self.underruns.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self) -> AudioStats {
+        self.stats.lock().unwrap().clone()
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Arrival {
+    /// The next frame, after `lost` missing ones.
+    Next { lost: u32 },
+    /// Older than the last frame, it has been concealed already.
+    Late,
+}
+
+#[derive(Default)]
+pub struct JitterBuffer {
+    sync: Arc<AvSync>,
+    // (seq, timestamp, arrival) of the last frame.
+    last: Option<(u32, i64, i64)>,
+    jitter_ms: f64,
+    // The smoothed time from the capture to the arrival of a frame, with the clock offset of the peer.
+    latency_ms: Option<f64>,
+    boost_ms: f64,
+    stats: AudioStats,
+}
+
+impl JitterBuffer {
+    pub fn new(sync: Arc<AvSync>) -> Self {
+        Self {
+            sync,
+            ..Default::default()
+        }
+    }
+
+    pub fn sync(&self) -> Arc<AvSync> {
+        self.sync.clone()
+    }
+
+    /// The sequence of the frames starts over, e.g. the audio format changed.
+    pub fn reset(&mut self) {
+        self.last = None;
+    }
+
+    /// `seq` and `timestamp` of the peer are 0 for old versions, `frame_ms` is the duration of the last frame.
+    pub fn on_frame(&mut self, seq: u32, timestamp: i64, arrival: i64, frame_ms: f64) -> Arrival {
+        let Some((last_seq, last_timestamp, last_arrival)) = self.last else {
+            self.last = Some((seq, timestamp, arrival));
+            self.on_next(timestamp, arrival, 0);
+            return Arrival::Next { lost: 0 };
+        };
+        let mut lost = 0;
+        if seq != 0 && last_seq != 0 {
+            let diff = seq.wrapping_sub(last_seq);
+            if diff == 0 || diff > u32::MAX / 2 {
+                return Arrival::Late;
+            }
+            lost = diff - 1;
+        }
+        let arrival_diff = arrival - last_arrival;
+        let sent_diff = if timestamp > 0 && last_timestamp > 0 {
+            Some(timestamp - last_timestamp)
+        } else if arrival_diff < RESET_GAP_MS {
+            Some((frame_ms * (lost + 1) as f64) as i64)
+        } else {
+            None
+        };
+        if let Some(sent_diff) = sent_diff {
+            let d = (arrival_diff - sent_diff).abs() as f64;
+            self.jitter_ms += (d - self.jitter_ms) / 16.;
+        }
+        self.last = Some((seq, timestamp, arrival));
+        self.stats.concealed += lost.min(MAX_CONCEALED_FRAMES) as u64;
+        self.on_next(timestamp, arrival, lost);
+        Arrival::Next { lost }
+    }
+
+    fn on_next(&mut self, timestamp: i64, arrival: i64, lost: u32) {
+        if timestamp > 0 {
+            self.latency_ms = Some(smooth(self.latency_ms, (arrival - timestamp) as f64));
+        }
+        self.sync.received.fetch_add(1, Ordering::Relaxed);
+        self.sync.lost.fetch_add(lost as _, Ordering::Relaxed);
+    }
+
+    pub fn target_ms(&self) -> f64 {
+        // Not ahead of the video, the frames wait in the buffer for the video captured with them,
+        // or without the capture times of old versions, as long as the video is decoded.
+        let video_latency = *self.sync.video_latency_ms.lock().unwrap();
+        let video_delay = match (video_latency, self.latency_ms) {
+            (Some(video), Some(audio)) => video - audio,
+            _ => self.sync.video_delay_ms.load(Ordering::Relaxed) as f64,
+        };
+        (self.jitter_ms * 3. + self.boost_ms)
+            .max(video_delay)
+            .clamp(MIN_TARGET_MS, MAX_TARGET_MS)
+    }
+
+    /// Stretches or compresses the decoded `samples`, to move the buffered `level_ms` to the target.
+    pub fn adjust(&mut self, samples: Vec<f32>, channels: usize, level_ms: f64) -> Vec<f32> {
+        let underruns = self.sync.underruns.load(Ordering::Relaxed);
+        if underruns > self.stats.underruns {
+            let boost = UNDERRUN_BOOST_MS * (underruns - self.stats.underruns) as f64;
+            self.boost_ms = (self.boost_ms + boost).min(MAX_TARGET_MS);
+            self.stats.underruns = underruns;
+        } else {
+            self.boost_ms *= BOOST_DECAY;
+        }
+        let target = self.target_ms();
+        let hysteresis = (target / 4.).max(10.);
+        let ratio = if level_ms > target * 3. + hysteresis {
+            1. - FAST_COMPRESS_RATIO
+        } else if level_ms > target + hysteresis {
+            1. - STRETCH_RATIO
+        } else if level_ms < target - hysteresis {
+            1. + STRETCH_RATIO
+        } else {
+            1.
+        };
+        if ratio > 1. {
+            self.stats.stretched += 1;
+        } else if ratio < 1. {
+            self.stats.compressed += 1;
+        }
+        self.stats.buffer_ms = level_ms as _;
+        self.stats.target_ms = target as _;
+        self.stats.jitter_ms = self.jitter_ms as _;
+        *self.sync.stats.lock().unwrap() = self.stats.clone();
+
+        if channels == 0 || ratio == 1. {
+            return samples;
+        }
+        let frames = samples.len() / channels;
+        resample(&samples, channels, (frames as f64 * ratio).round() as usize)
+    }
+}
+
+fn smooth(old: Option<f64>, new: f64) -> f64 {
+    match old {
+        Some(old) => old + (new - old) / 8.,
+        None => new,
+    }
+}
+
+// Linear interpolation of the interleaved samples to `out_frames` frames.
+fn resample(samples: &[f32], channels: usize, out_frames: usize) -> Vec<f32> {
+    let frames = samples.len() / channels;
+    if frames < 2 || out_frames < 2 || out_frames == frames {
+        return samples.to_vec();
+    }
+    let step = (frames - 1) as f64 / (out_frames - 1) as f64;
+    let mut out = Vec::with_capacity(out_frames * channels);
+    for i in 0..out_frames {
+        let pos = i as f64 * step;
+        let i0 = (pos as usize).min(frames - 1);
+        let i1 = (i0 + 1).min(frames - 1);
+        let t = (pos - i0 as f64) as f32;
+        for c in 0..channels {
+            out.push(samples[i0 * channels + c] * (1. - t) + samples[i1 * channels + c] * t);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_on_frame() {
+        let mut jb = JitterBuffer::default();
+        assert_eq!(jb.on_frame(1, 1000, 5000, 10.), Arrival::Next { lost: 0 });
+        assert_eq!(jb.on_frame(2, 1010, 5010, 10.), Arrival::Next { lost: 0 });
+        assert_eq!(jb.jitter_ms, 0.);
+        assert_eq!(jb.on_frame(5, 1040, 5040, 10.), Arrival::Next { lost: 2 });
+        assert_eq!(jb.on_frame(4, 1030, 5045, 10.), Arrival::Late);
+        assert_eq!(jb.on_frame(5, 1040, 5045, 10.), Arrival::Late);
+        assert_eq!(jb.stats.concealed, 2);
+        // Bursty arrivals.
+        for i in 0..50u32 {
+            let arrival = 5040 + (i as i64 / 5 + 1) * 50;
+            jb.on_frame(6 + i, 1050 + i as i64 * 10, arrival, 10.);
+        }
+        assert!(jb.jitter_ms > 5.);
+        // Old versions.
+        let mut jb = JitterBuffer::default();
+        assert_eq!(jb.on_frame(0, 0, 5000, 10.), Arrival::Next { lost: 0 });
+        assert_eq!(jb.on_frame(0, 0, 5010, 10.), Arrival::Next { lost: 0 });
+        assert_eq!(jb.on_frame(0, 0, 9000, 10.), Arrival::Next { lost: 0 });
+        assert_eq!(jb.jitter_ms, 0.);
+    }
+
+    #[test]
+    fn test_adjust() {
+        let mut jb = JitterBuffer::default();
+        let samples: Vec<f32> = (0..200).map(|i| i as f32).collect();
+        let target = jb.target_ms();
+        assert_eq!(target, MIN_TARGET_MS);
+        assert_eq!(jb.adjust(samples.clone(), 2, target).len(), 200);
+        assert!(jb.adjust(samples.clone(), 2, 0.).len() > 200);
+        assert!(jb.adjust(samples.clone(), 2, target * 2.).len() < 200);
+        let fast = jb.adjust(samples.clone(), 2, target * 10.);
+        assert_eq!(fast.len(), 170);
+        // The channels are kept apart.
+        assert_eq!(&fast[..2], &[0., 1.]);
+        assert_eq!(&fast[fast.len() - 2..], &[198., 199.]);
+
+        jb.sync.add_underrun();
+        jb.adjust(samples, 2, target);
+        assert_eq!(jb.target_ms(), MIN_TARGET_MS);
+        jb.sync.update_video_delay(Duration::from_millis(120));
+        assert_eq!(jb.target_ms(), 120.);
+    }
+
+    #[test]
+    fn test_lip_sync() {
+        let mut jb = JitterBuffer::default();
+        // The clock of the peer is 10 seconds ahead, the audio arrives 30 ms after the capture.
+        jb.on_frame(1, 15000, 5030, 10.);
+        jb.on_frame(3, 15020, 5050, 10.);
+        jb.sync.update_video_delay(Duration::from_millis(20));
+        // The video is shown 230 ms after the capture.
+        jb.sync.update_video_latency(5230 - 15000);
+        assert_eq!(jb.target_ms(), 200.);
+        assert_eq!(jb.sync.take_loss_perc(), 33);
+        assert_eq!(jb.sync.take_loss_perc(), 0);
+    }
+}
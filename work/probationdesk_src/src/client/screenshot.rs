@@ -1,7 +1,14 @@
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 use crate::clipboard::{update_clipboard, ClipboardSide};
-use hbb_common::{message_proto::*, ResultType};
-use std::sync::Mutex;
+use hbb_common::{bail, chrono, message_proto::*, ResultType};
+use serde_derive::Deserialize;
+use std::{
+    path::PathBuf,
+    sync::{mpsc, Mutex},
+};
+
+/// The sid of the screenshots taken by the interval mode, they are saved to a folder.
+pub const INTERVAL_SID: &str = "interval";
 
 lazy_static::lazy_static! {
     static ref SCREENSHOT: Mutex<Screenshot> = Default::default();
@@ -50,24 +57,30 @@ impl Into<String> for ScreenshotAction {
 #[derive(Default)]
 pub struct Screenshot {
     data: Option<bytes::Bytes>,
+    format: ScreenshotFormat,
 }
 
 impl Screenshot {
-    fn set_screenshot(&mut self, data: bytes::Bytes) {
+    fn set_screenshot(&mut self, data: bytes::Bytes, format: ScreenshotFormat) {
         self.data.replace(data);
+        self.format = format;
     }
 
     fn handle_screenshot(&mut self, action: String) -> String {
         let Some(data) = self.data.take() else {
             return "No cached screenshot".to_owned();
         };
-        match Self::handle_screenshot_(data, action) {
+        match Self::handle_screenshot_(data, self.format, action) {
             Ok(()) => "".to_owned(),
             Err(e) => e.to_string(),
         }
     }
 
-    fn handle_screenshot_(data: bytes::Bytes, action: String) -> ResultType<()> {
+    fn handle_screenshot_(
+        data: bytes::Bytes,
+        _format: ScreenshotFormat,
+        action: String,
+    ) -> ResultType<()> {
         match ScreenshotAction::from(&action as &str) {
             ScreenshotAction::SaveAs(p) => {
                 std::fs::write(p, data)?;
@@ -75,9 +88,17 @@ impl Screenshot {
             ScreenshotAction::CopyToClipboard => {
                 #[cfg(not(any(target_os = "android", target_os = "ios")))]
                 {
+                    let content = if _format == ScreenshotFormat::Png {
+                        data
+                    } else {
+                        let mut png = std::io::Cursor::new(Vec::new());
+                        image::load_from_memory(&data)?
+                            .write_to(&mut png, image::ImageOutputFormat::Png)?;
+                        png.into_inner().into()
+                    };
                     let clips = vec![Clipboard {
                         compress: false,
-                        content: data,
+                        content,
                         format: ClipboardFormat::ImagePng.into(),
                         ..Default::default()
                     }];
@@ -90,10 +111,134 @@ impl Screenshot {
     }
 }
 
-pub fn set_screenshot(data: bytes::Bytes) {
-    SCREENSHOT.lock().unwrap().set_screenshot(data);
+pub fn set_screenshot(data: bytes::Bytes, format: ScreenshotFormat) {
+    SCREENSHOT.lock().unwrap().set_screenshot(data, format);
 }
 
 pub fn handle_screenshot(action: String) -> String {
     SCREENSHOT.lock().unwrap().handle_screenshot(action)
 }
+
+/// The file extension of the cached screenshot.
+pub fn cached_extension() -> &'static str {
+    extension(SCREENSHOT.lock().unwrap().format)
+}
+
+pub fn extension(format: ScreenshotFormat) -> &'static str {
+    match format {
+        ScreenshotFormat::Png => "png",
+        ScreenshotFormat::Jpeg => "jpg",
+        ScreenshotFormat::WebP => "webp",
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Options {
+    all_displays: bool,
+    // x, y, width, height
+    region: Option<(i32, i32, i32, i32)>,
+    format: String,
+    quality: u32,
+}
+
+/// Parse the json options of a screenshot, e.g.
+/// `{"all_displays": true, "region": [0, 0, 800, 600], "format": "jpeg", "quality": 80}`.
+/// All fields are optional, the default is a png of the whole display.
+pub fn parse_request(display: i32, sid: String, options: &str) -> ResultType<ScreenshotRequest> {
+    let options: Options = if options.trim().is_empty() {
+        Default::default()
+    } else {
+        serde_json::from_str(options)?
+    };
+    let format = match options.format.to_lowercase().as_str() {
+        "" | "png" => ScreenshotFormat::Png,
+        "jpg" | "jpeg" => ScreenshotFormat::Jpeg,
+        "webp" => ScreenshotFormat::WebP,
+        f => bail!("Unsupported format: {}", f),
+    };
+    if options.quality > 100 {
+        bail!("Invalid quality: {}", options.quality);
+    }
+    let region = match options.region {
+        Some((_, _, width, height)) if width <= 0 || height <= 0 => {
+            bail!("Invalid region");
+        }
+        Some((x, y, width, height)) => Some(ScreenshotRegion {
+            x,
+            y,
+            width,
+            height,
+            ..Default::default()
+        }),
+        None => None,
+    };
+    Ok(ScreenshotRequest {
+        display,
+        sid,
+        all_displays: options.all_displays,
+        region: region.into(),
+        format: format.into(),
+        quality: options.quality,
+        ..Default::default()
+    })
+}
+
+/// The interval mode of a session, it is stopped when this is dropped.
+pub struct ScreenshotInterval {
+    pub folder: String,
+    _stop: mpsc::Sender<()>,
+}
+
+impl ScreenshotInterval {
+    /// The receiver is disconnected when the interval mode is stopped.
+    pub fn new(folder: String) -> (Self, mpsc::Receiver<()>) {
+        let (tx, rx) = mpsc::channel();
+        (Self { folder, _stop: tx }, rx)
+    }
+}
+
+/// Save the screenshot of the interval mode as `<folder>/<peer id>_<time>.<ext>`.
+pub fn save_to_folder(
+    folder: &str,
+    peer_id: &str,
+    data: &[u8],
+    format: ScreenshotFormat,
+) -> ResultType<PathBuf> {
+    let name = format!(
+        "{}_{}.{}",
+        peer_id,
+        chrono::Local::now().format("%Y%m%d_%H%M%S"),
+        extension(format)
+    );
+    let path = PathBuf::from(folder).join(name);
+    std::fs::write(&path, data)?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_request() {
+        let request = parse_request(1, "sid".to_owned(), "").unwrap();
+        assert_eq!(request.display, 1);
+        assert_eq!(request.format.enum_value(), Ok(ScreenshotFormat::Png));
+        assert!(request.region.is_none());
+        let request = parse_request(
+            0,
+            "sid".to_owned(),
+            r#"{"all_displays": true, "region": [10, 20, 300, 200], "format": "JPEG", "quality": 70}"#,
+        )
+        .unwrap();
+        assert!(request.all_displays);
+        assert_eq!(request.region.width, 300);
+        assert_eq!(request.region.y, 20);
+        assert_eq!(request.format.enum_value(), Ok(ScreenshotFormat::Jpeg));
+        assert_eq!(request.quality, 70);
+        assert!(parse_request(0, "".to_owned(), r#"{"format": "bmp"}"#).is_err());
+        assert!(parse_request(0, "".to_owned(), r#"{"region": [0, 0, 0, 10]}"#).is_err());
+        assert!(parse_request(0, "".to_owned(), r#"{"quality": 101}"#).is_err());
+    }
+}
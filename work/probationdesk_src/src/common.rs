@@ -26,6 +26,7 @@ use hbb_common::{
     rendezvous_proto::*,
     socket_client,
     sodiumoxide::crypto::{box_, secretbox, sign},
+    tcp::Encrypt,
     timeout,
     tokio::{
         self,
@@ -64,6 +65,8 @@ pub const DEFAULT_KEEP_ALIVE: i32 = 60_000;
 const MIN_VER_MULTI_UI_SESSION: &str = "1.2.4";
 
 pub mod input {
+    use hbb_common::message_proto::{MouseEvent, PenEvent};
+
     pub const MOUSE_TYPE_MOVE: i32 = 0;
     pub const MOUSE_TYPE_DOWN: i32 = 1;
     pub const MOUSE_TYPE_UP: i32 = 2;
@@ -75,6 +78,42 @@ pub mod input {
     pub const MOUSE_BUTTON_WHEEL: i32 = 0x04;
     pub const MOUSE_BUTTON_BACK: i32 = 0x08;
     pub const MOUSE_BUTTON_FORWARD: i32 = 0x10;
+
+    pub const PEN_PRESSURE_MAX: u32 = 1024;
+    pub const PEN_TILT_MAX: i32 = 90;
+    pub const PEN_BUTTON_BARREL: u32 = 0x01;
+    pub const PEN_BUTTON_BARREL2: u32 = 0x02;
+
+    /// Convert a pen event to mouse events, for peers or platforms without pen support.
+    /// `pressed` is the mouse button held down by the pen, 0 if none.
+    pub fn pen_to_mouse(pen: &PenEvent, pressed: &mut i32) -> Vec<MouseEvent> {
+        let mouse = |mask| MouseEvent {
+            mask,
+            x: pen.x,
+            y: pen.y,
+            ..Default::default()
+        };
+        let button = if pen.in_range && pen.pressure > 0 {
+            if pen.buttons & PEN_BUTTON_BARREL != 0 {
+                MOUSE_BUTTON_RIGHT
+            } else {
+                MOUSE_BUTTON_LEFT
+            }
+        } else {
+            0
+        };
+        let mut events = vec![mouse(MOUSE_TYPE_MOVE)];
+        if button != *pressed {
+            if *pressed != 0 {
+                events.push(mouse(*pressed << 3 | MOUSE_TYPE_UP));
+            }
+            if button != 0 {
+                events.push(mouse(button << 3 | MOUSE_TYPE_DOWN));
+            }
+            *pressed = button;
+        }
+        events
+    }
 }
 
 lazy_static::lazy_static! {
@@ -912,7 +951,8 @@ pub fn check_software_update() {
 pub async fn do_check_software_update() -> hbb_common::ResultType<()> {
     let (request, url) =
         hbb_common::version_check_request(hbb_common::VER_TYPE_RUSTDESK_CLIENT.to_string());
-    let latest_release_response = create_http_client_async()
+    let latest_release_response = create_http_client_async(&url)
+        .await
         .post(url)
         .json(&request)
         .send()
@@ -1039,13 +1079,25 @@ pub fn get_udp_punch_enabled() -> bool {
     )
 }
 
-pub fn get_ipv6_punch_enabled() -> bool {
+pub async fn get_ipv6_punch_enabled() -> bool {
+    // An IPv6-only host punches over IPv6 unless it is disabled explicitly.
+    if LocalConfig::get_option(keys::OPTION_ENABLE_IPV6_PUNCH).is_empty()
+        && !hbb_common::happy_eyeballs::has_route(false).await
+        && hbb_common::happy_eyeballs::has_route(true).await
+    {
+        return true;
+    }
     config::option2bool(
         keys::OPTION_ENABLE_IPV6_PUNCH,
         &get_local_option(keys::OPTION_ENABLE_IPV6_PUNCH),
     )
 }
 
+/// Whether the connections without end-to-end encryption are refused, set by policy.
+pub fn is_strict_encryption() -> bool {
+    Config::get_bool_option(keys::OPTION_STRICT_ENCRYPTION)
+}
+
 pub fn get_local_option(key: &str) -> String {
     let v = LocalConfig::get_option(key);
     if key == keys::OPTION_ENABLE_UDP_PUNCH || key == keys::OPTION_ENABLE_IPV6_PUNCH {
@@ -1067,7 +1119,7 @@ pub fn get_audit_server(api: String, custom: String, typ: String) -> String {
 }
 
 pub async fn post_request(url: String, body: String, header: &str) -> ResultType<String> {
-    let mut req = create_http_client_async().post(url);
+    let mut req = create_http_client_async(&url).await.post(url);
     if !header.is_empty() {
         let tmp: Vec<&str> = header.split(": ").collect();
         if tmp.len() == 2 {
@@ -1091,7 +1143,7 @@ pub async fn http_request_sync(
     body: Option<String>,
     header: String,
 ) -> ResultType<String> {
-    let http_client = create_http_client_async();
+    let http_client = create_http_client_async(&url).await;
     let mut http_client = match method.as_str() {
         "get" => http_client.get(url),
         "post" => http_client.post(url),
@@ -1245,6 +1297,97 @@ pub fn make_empty_dirs_response_to_json(res: &ReadEmptyDirsResponse) -> String {
     serde_json::to_string(&map).unwrap_or("".into())
 }
 
+pub fn make_inventory_to_json(inventory: &Inventory) -> String {
+    let value = json!({
+        "hostname": inventory.hostname,
+        "os_name": inventory.os_name,
+        "os_version": inventory.os_version,
+        "kernel_version": inventory.kernel_version,
+        "cpu_brand": inventory.cpu_brand,
+        "cpu_cores": inventory.cpu_cores,
+        "cpu_physical_cores": inventory.cpu_physical_cores,
+        "cpu_usage": inventory.cpu_usage,
+        "total_memory": inventory.total_memory,
+        "used_memory": inventory.used_memory,
+        "uptime": inventory.uptime,
+        "disks": inventory.disks.iter().map(|d| json!({
+            "name": d.name,
+            "mount_point": d.mount_point,
+            "file_system": d.file_system,
+            "total_space": d.total_space,
+            "available_space": d.available_space,
+        })).collect::<Vec<_>>(),
+        "network_interfaces": inventory.network_interfaces.iter().map(|i| json!({
+            "name": i.name,
+            "mac": i.mac,
+            "ips": i.ips,
+        })).collect::<Vec<_>>(),
+        "packages": inventory.packages.iter().map(|p| json!({
+            "name": p.name,
+            "version": p.version,
+        })).collect::<Vec<_>>(),
+        "users": inventory.users.iter().map(|u| json!({
+            "name": u.name,
+            "session": u.session,
+        })).collect::<Vec<_>>(),
+        "error": inventory.error,
+    });
+    serde_json::to_string(&value).unwrap_or("".into())
+}
+
+/// Flatten the inventory json into `section,name,value` csv rows.
+pub fn inventory_json_to_csv(inventory: &str) -> ResultType<String> {
+    fn field(s: &str) -> String {
+        if s.contains(&[',', '"', '\n', '\r'][..]) {
+            format!("\"{}\"", s.replace('"', "\"\""))
+        } else {
+            s.to_owned()
+        }
+    }
+    fn value_to_string(v: &Value) -> String {
+        match v {
+            Value::String(s) => s.clone(),
+            Value::Array(a) => a.iter().map(value_to_string).collect::<Vec<_>>().join(" "),
+            Value::Null => "".to_owned(),
+            v => v.to_string(),
+        }
+    }
+    let map: Map<String, Value> = serde_json::from_str(inventory)?;
+    let mut out = "section,name,value\r\n".to_owned();
+    let mut push = |section: &str, name: &str, value: &str| {
+        out.push_str(&format!(
+            "{},{},{}\r\n",
+            field(section),
+            field(name),
+            field(value)
+        ));
+    };
+    for (k, v) in map.iter() {
+        match v {
+            Value::Array(items) => {
+                for item in items {
+                    let Value::Object(obj) = item else {
+                        continue;
+                    };
+                    let name = obj.get("name").map(value_to_string).unwrap_or_default();
+                    for (field_name, field_value) in obj.iter() {
+                        if field_name == "name" {
+                            continue;
+                        }
+                        push(
+                            k,
+                            &format!("{}.{}", name, field_name),
+                            &value_to_string(field_value),
+                        );
+                    }
+                }
+            }
+            v => push("system", k, &value_to_string(v)),
+        }
+    }
+    Ok(out)
+}
+
 /// The function to handle the url scheme sent by the system.
 ///
 /// 1. Try to send the url scheme from ipc.
@@ -1283,14 +1426,39 @@ pub async fn get_key(sync: bool) -> String {
         Config::get_option("key")
     } else {
         let mut options = crate::ipc::get_options_async().await;
-        options.remove("key").unwrap_or_default()
+        let key = options.remove("key").unwrap_or_default();
+        // The peer hosting the embedded server is its peer too.
+        #[cfg(not(target_os = "android"))]
+        if key.is_empty() && embedded_server_allowed(&options) {
+            return options
+                .remove(config::keys::OPTION_EMBEDDED_SERVER_KEY)
+                .unwrap_or_default();
+        }
+        key
     };
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    if key.is_empty() && sync && embedded_server_allowed(&Config::get_options()) {
+        key = Config::get_option(config::keys::OPTION_EMBEDDED_SERVER_KEY);
+    }
     if key.is_empty() {
         key = config::RS_PUB_KEY.to_owned();
     }
     key
 }
 
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+fn embedded_server_allowed(options: &HashMap<String, String>) -> bool {
+    let k = config::keys::OPTION_ALLOW_EMBEDDED_SERVER;
+    config::option2bool(k, options.get(k).map(|v| v.as_str()).unwrap_or_default())
+        && options
+            .get(config::keys::OPTION_EMBEDDED_SERVER_KEY)
+            .is_some_and(|v| !v.is_empty())
+        // Not the key of the other servers.
+        && options
+            .get("custom-rendezvous-server")
+            .is_some_and(|v| crate::embedded_server::is_this_host(v))
+}
+
 pub fn pk_to_fingerprint(pk: Vec<u8>) -> String {
     let s: String = pk.iter().map(|u| format!("{:02x}", u)).collect();
     s.chars()
@@ -1467,12 +1635,13 @@ pub fn get_rs_pk(str_base64: &str) -> Option<sign::PublicKey> {
     }
 }
 
-pub fn decode_id_pk(signed: &[u8], key: &sign::PublicKey) -> ResultType<(String, [u8; 32])> {
+/// Returns the id, the public key and the supported key exchange.
+pub fn decode_id_pk(signed: &[u8], key: &sign::PublicKey) -> ResultType<(String, [u8; 32], u32)> {
     let res = IdPk::parse_from_bytes(
         &sign::verify(signed, key).map_err(|_| anyhow!("Signature mismatch"))?,
     )?;
     if let Some(pk) = get_pk(&res.pk) {
-        Ok((res.id, pk))
+        Ok((res.id, pk, res.key_exchange))
     } else {
         bail!("Wrong their public length");
     }
@@ -1487,6 +1656,13 @@ pub fn create_symmetric_key_msg(their_pk_b: [u8; 32]) -> (Bytes, Bytes, secretbo
     (Vec::from(our_pk_b.0).into(), sealed_key.into(), key)
 }
 
+/// The forward secret key exchange, see `tcp::KEY_EXCHANGE_FORWARD_SECRET`.
+pub fn create_forward_secret_key_msg(their_pk_b: [u8; 32]) -> ResultType<(Bytes, Encrypt)> {
+    let (our_pk_b, our_sk_b) = box_::gen_keypair();
+    let encrypt = Encrypt::forward_secret(&our_sk_b, &our_pk_b, &their_pk_b, true)?;
+    Ok((Vec::from(our_pk_b.0).into(), encrypt))
+}
+
 #[inline]
 pub fn using_public_server() -> bool {
     option_env!("RENDEZVOUS_SERVER").unwrap_or("").is_empty()
@@ -1958,15 +2134,24 @@ pub async fn test_ipv6() -> Option<tokio::task::JoinHandle<()>> {
     }))
 }
 
+// `peer` for an unconnected socket, the packets of other addresses are ignored.
 pub async fn punch_udp(
     socket: Arc<UdpSocket>,
+    peer: Option<SocketAddr>,
     listen: bool,
 ) -> ResultType<Option<bytes::BytesMut>> {
+    let socket_ref = &socket;
+    let send = move || async move {
+        match peer {
+            Some(peer) => socket_ref.send_to(&[], peer).await,
+            None => socket_ref.send(&[]).await,
+        }
+    };
     let mut retry_interval = Duration::from_millis(20);
     const MAX_INTERVAL: Duration = Duration::from_millis(200);
     const MAX_TIME: Duration = Duration::from_secs(20);
     let mut packets_sent = 0;
-    socket.send(&[]).await.ok();
+    send().await.ok();
     packets_sent += 1;
     let mut last_send_time = Instant::now();
     let tm = Instant::now();
@@ -1981,7 +2166,7 @@ pub async fn punch_udp(
                 let elapsed = last_send_time.elapsed();
 
                 if elapsed >= retry_interval {
-                    socket.send(&[]).await.ok();
+                    send().await.ok();
                     packets_sent += 1;
 
                     // Exponentially increase interval to reduce network pressure
@@ -1992,9 +2177,10 @@ pub async fn punch_udp(
                     last_send_time = Instant::now();
                 }
             }
-            res = socket.recv(&mut data) => match res {
+            res = socket.recv_from(&mut data) => match res {
                 Err(e) => bail!("UDP punch failed, {packets_sent} packets sent: {e}"),
-                Ok(n) => {
+                Ok((_, from)) if peer.is_some_and(|peer| peer != from) => continue,
+                Ok((n, _)) => {
                     // log::debug!("UDP punch succeeded after sending {} packets after {:?}", packets_sent, tm.elapsed());
                     if listen {
                         if n == 0 {
@@ -2196,4 +2382,42 @@ mod tests {
             Duration::from_nanos(0)
         );
     }
+
+    #[test]
+    fn test_pen_to_mouse() {
+        use input::*;
+        let mut pressed = 0;
+        let mut pen = PenEvent {
+            x: 10,
+            y: 20,
+            in_range: true,
+            ..Default::default()
+        };
+        let masks = |events: Vec<MouseEvent>| events.iter().map(|e| e.mask).collect::<Vec<_>>();
+        assert_eq!(
+            masks(pen_to_mouse(&pen, &mut pressed)),
+            vec![MOUSE_TYPE_MOVE]
+        );
+        pen.pressure = 300;
+        assert_eq!(
+            masks(pen_to_mouse(&pen, &mut pressed)),
+            vec![MOUSE_TYPE_MOVE, MOUSE_BUTTON_LEFT << 3 | MOUSE_TYPE_DOWN]
+        );
+        assert_eq!(pressed, MOUSE_BUTTON_LEFT);
+        pen.buttons = PEN_BUTTON_BARREL;
+        assert_eq!(
+            masks(pen_to_mouse(&pen, &mut pressed)),
+            vec![
+                MOUSE_TYPE_MOVE,
+                MOUSE_BUTTON_LEFT << 3 | MOUSE_TYPE_UP,
+                MOUSE_BUTTON_RIGHT << 3 | MOUSE_TYPE_DOWN
+            ]
+        );
+        pen.in_range = false;
+        assert_eq!(
+            masks(pen_to_mouse(&pen, &mut pressed)),
+            vec![MOUSE_TYPE_MOVE, MOUSE_BUTTON_RIGHT << 3 | MOUSE_TYPE_UP]
+        );
+        assert_eq!(pressed, 0);
+    }
 }
@@ -557,6 +557,36 @@ pub fn core_main() -> Option<Vec<String>> {
                 println!("Installation and administrative privileges required!");
             }
             return None;
+        } else if args[0] == "--batch" {
+            // --batch job.json, prints one json line per peer when the peer is done.
+            if args.len() == 2 {
+                let job = std::fs::read_to_string(&args[1])
+                    .map_err(|e| e.to_string())
+                    .and_then(|s| {
+                        serde_json::from_str::<crate::batch::Job>(&s).map_err(|e| e.to_string())
+                    });
+                match job {
+                    Ok(job) => {
+                        let results = crate::batch::run_sync(job, |res| {
+                            println!("{}", serde_json::to_string(res).unwrap_or_default());
+                        });
+                        let failed = results.iter().filter(|res| !res.success).count();
+                        println!("{} succeeded, {} failed", results.len() - failed, failed);
+                    }
+                    Err(err) => println!("{err}"),
+                }
+            }
+            return None;
+        } else if args[0] == "--diagnose" {
+            // --diagnose <id>, prints the report of the connect sequence as json.
+            if args.len() == 2 {
+                let report = crate::diagnostics::run_sync(&args[1]);
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&report).unwrap_or_default()
+                );
+            }
+            return None;
         } else if args[0] == "--check-hwcodec-config" {
             #[cfg(feature = "hwcodec")]
             crate::ipc::hwcodec_process();
@@ -0,0 +1,277 @@
+//! Diagnose the connection to a peer: the full connect sequence is run and reported as json,
+//! to be attached to a ticket.
+//!
+//! The client records the rendezvous, NAT and punch hole steps into the report of the session,
+//! the rest is measured here on the connection, which is closed before the login.
+
+use crate::client::{self, Client, Interface, LoginConfigHandler};
+use async_trait::async_trait;
+use hbb_common::{
+    config::{LocalConfig, READ_TIMEOUT},
+    get_time, log,
+    message_proto::*,
+    protobuf::Message as _,
+    rendezvous_proto::ConnType,
+    timeout,
+    tokio::{self, time::Instant},
+    ResultType, Stream,
+};
+use serde_derive::Serialize;
+use std::sync::{Arc, Mutex, RwLock};
+
+/// The furthest step of the connect sequence reached.
+#[derive(Debug, Clone, Copy, Default, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    #[default]
+    Start,
+    Rendezvous,
+    PunchHole,
+    Connected,
+    Secured,
+    LoginRequested,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Rendezvous {
+    pub server: String,
+    pub reachable: bool,
+    pub connect_ms: u64,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub family: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub error: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PunchAttempt {
+    /// "TCP" or "UDP", how the rendezvous server is asked.
+    pub punch_type: String,
+    pub attempt: u64,
+    /// "punch_hole", "relay", "failure" or "timeout".
+    pub response: String,
+    pub ms: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Report {
+    pub id: String,
+    pub version: String,
+    pub success: bool,
+    pub stage: Stage,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub error: String,
+    pub rendezvous: Rendezvous,
+    pub local_nat_type: String,
+    pub peer_nat_type: String,
+    pub is_local: bool,
+    pub punch_attempts: Vec<PunchAttempt>,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub direct_error: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub relay_server: String,
+    /// "TCP", "UDP", "IPv6", "Relay" or "WebSocket".
+    pub stream_type: String,
+    pub direct: bool,
+    pub family: String,
+    pub connect_ms: u64,
+    pub encrypted: bool,
+    /// The round trip of a test delay to the peer, before the login.
+    pub rtt_ms: Option<u64>,
+    pub elapsed_ms: u64,
+}
+
+impl Report {
+    pub(crate) fn reach(&mut self, stage: Stage) {
+        if stage > self.stage {
+            self.stage = stage;
+        }
+    }
+}
+
+/// Records into the report of the session, if it is diagnosed.
+pub(crate) fn record(interface: &impl Interface, f: impl FnOnce(&mut Report)) {
+    if let Some(report) = interface.diagnostics() {
+        f(&mut report.lock().unwrap());
+    }
+}
+
+/// Run the connect sequence to `id` and report it.
+pub async fn run(id: &str) -> Report {
+    let session = DiagnosticsSession::new(id);
+    let key = crate::get_key(false).await;
+    let token = LocalConfig::get_option("access_token");
+    let start = Instant::now();
+    let res = diagnose(&session, &key, &token, start).await;
+    let mut report = session.report.lock().unwrap().clone();
+    report.id = id.to_owned();
+    report.version = crate::VERSION.to_owned();
+    report.elapsed_ms = start.elapsed().as_millis() as _;
+    match res {
+        Ok(()) => report.success = true,
+        Err(err) => report.error = err.to_string(),
+    }
+    log::info!(
+        "diagnostics of {}: {:?}, {}",
+        id,
+        report.stage,
+        if report.success { "ok" } else { &report.error }
+    );
+    report
+}
+
+#[tokio::main(flavor = "multi_thread")]
+pub async fn run_sync(id: &str) -> Report {
+    run(id).await
+}
+
+async fn diagnose(
+    session: &DiagnosticsSession,
+    key: &str,
+    token: &str,
+    start: Instant,
+) -> ResultType<()> {
+    let id = session.get_id();
+    let ((mut stream, direct, _pk, _kcp, stream_type), _) =
+        Client::start(&id, key, token, ConnType::DEFAULT_CONN, session.clone()).await?;
+    let encrypted = stream.is_secured();
+    record(session, |r| {
+        r.reach(if encrypted {
+            Stage::Secured
+        } else {
+            Stage::Connected
+        });
+        r.stream_type = stream_type.to_owned();
+        r.direct = direct;
+        r.family = hbb_common::happy_eyeballs::family(&stream.local_addr()).to_owned();
+        r.connect_ms = start.elapsed().as_millis() as _;
+        r.encrypted = encrypted;
+    });
+    let rtt = test_delay(session, &mut stream).await?;
+    record(session, |r| r.rtt_ms = rtt);
+    Ok(())
+}
+
+// Waits for the login to be requested, and measures the round trip meanwhile.
+async fn test_delay(session: &DiagnosticsSession, stream: &mut Stream) -> ResultType<Option<u64>> {
+    let mut msg_out = Message::new();
+    msg_out.set_test_delay(TestDelay {
+        time: get_time(),
+        from_client: true,
+        ..Default::default()
+    });
+    stream.send(&msg_out).await?;
+    let sent = Instant::now();
+    let mut rtt = None;
+    let mut login_requested = false;
+    while !(login_requested && rtt.is_some()) {
+        let Ok(Some(Ok(bytes))) = timeout(READ_TIMEOUT, stream.next()).await else {
+            break;
+        };
+        match Message::parse_from_bytes(&bytes)?.union {
+            Some(message::Union::Hash(_)) => {
+                login_requested = true;
+                record(session, |r| r.reach(Stage::LoginRequested));
+            }
+            Some(message::Union::TestDelay(t)) if t.from_client => {
+                rtt = Some(sent.elapsed().as_millis() as u64);
+            }
+            Some(message::Union::DirectAccess(da)) => {
+                client::handle_direct_access(da, &session.get_id(), stream).await;
+            }
+            Some(message::Union::LoginResponse(LoginResponse {
+                union: Some(login_response::Union::Error(err)),
+                ..
+            })) => {
+                hbb_common::bail!("{}", err);
+            }
+            _ => {}
+        }
+    }
+    Ok(rtt)
+}
+
+#[derive(Clone)]
+struct DiagnosticsSession {
+    lc: Arc<RwLock<LoginConfigHandler>>,
+    report: Arc<Mutex<Report>>,
+}
+
+impl DiagnosticsSession {
+    fn new(id: &str) -> Self {
+        let mut lc = LoginConfigHandler::default();
+        lc.initialize(
+            id.to_owned(),
+            ConnType::DEFAULT_CONN,
+            None,
+            false,
+            None,
+            None,
+            None,
+        );
+        Self {
+            lc: Arc::new(RwLock::new(lc)),
+            report: Default::default(),
+        }
+    }
+}
+
+#[async_trait]
+impl Interface for DiagnosticsSession {
+    fn send(&self, _data: client::Data) {}
+
+    fn msgbox(&self, _msgtype: &str, _title: &str, _text: &str, _link: &str) {}
+
+    fn handle_login_error(&self, _err: &str) -> bool {
+        false
+    }
+
+    fn handle_peer_info(&self, _pi: PeerInfo) {}
+
+    fn set_multiple_windows_session(&self, _sessions: Vec<WindowsSession>) {}
+
+    async fn handle_hash(&self, _pass: &str, _hash: Hash, _peer: &mut Stream) {}
+
+    async fn handle_login_from_ui(
+        &self,
+        _os_username: String,
+        _os_password: String,
+        _password: String,
+        _remember: bool,
+        _peer: &mut Stream,
+    ) {
+    }
+
+    async fn handle_test_delay(&self, t: TestDelay, peer: &mut Stream) {
+        client::handle_test_delay(t, peer).await;
+    }
+
+    fn get_lch(&self) -> Arc<RwLock<LoginConfigHandler>> {
+        self.lc.clone()
+    }
+
+    fn diagnostics(&self) -> Option<Arc<Mutex<Report>>> {
+        Some(self.report.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_json() {
+        let mut report = Report {
+            id: "123456789".to_owned(),
+            ..Default::default()
+        };
+        report.reach(Stage::PunchHole);
+        report.reach(Stage::Rendezvous);
+        assert_eq!(report.stage, Stage::PunchHole);
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["stage"], "punch_hole");
+        assert_eq!(json["rtt_ms"], serde_json::Value::Null);
+        assert!(json.get("error").is_none());
+        assert!(json["rendezvous"].get("family").is_none());
+    }
+}
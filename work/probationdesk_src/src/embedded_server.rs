@@ -0,0 +1,799 @@
+// The rendezvous and relay server inside a peer, for a small site without hbbs and hbbr.
+//
+// It serves the usual ports of them: the NAT test and the online states on `port` - 1, the
+// registration, punch hole and relay requests on `port` over tcp and udp, the relay on
+// `port` + 1, and the registration over http on `port` + 2, see `http`. The ids and the keys of the peers are kept in a sled database, the registrations
+// and the requests waiting for the answers of the peers are in memory.
+// Each side is told the relay, and the address of the other side if it is loopback, by the address
+// of this host on the route to it, so the peer hosting the server may register on loopback.
+// It listens on the loopback and the private addresses only, it is for the LAN, and is restarted
+// when they change.
+// It is also a local stand-in of the real servers for the tests.
+
+mod http;
+mod relay;
+mod store;
+
+use hbb_common::{
+    config::{keys, option2bool, Config, RENDEZVOUS_PORT},
+    log,
+    message_proto::IdPk,
+    protobuf::Message as _,
+    rendezvous_proto::*,
+    sleep,
+    sodiumoxide::crypto::{box_, secretbox, sign},
+    tcp::new_listener,
+    timeout, tokio,
+    tokio::{
+        net::{TcpListener, TcpStream, UdpSocket},
+        select,
+        sync::mpsc,
+        task::JoinHandle,
+    },
+    try_into_v4, AddrMangle, ResultType, Stream,
+};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use store::Store;
+
+// Not registered within this, a peer is offline.
+const REG_TIMEOUT: Duration = Duration::from_secs(30);
+// The registered peers send every 15 seconds, the requests are answered within seconds.
+const TCP_TIMEOUT: u64 = 30_000;
+// Seconds, for the tcp registrations.
+const KEEP_ALIVE: i32 = 60;
+// The addresses of the LAN are checked this often.
+const LAN_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+type Sender = mpsc::UnboundedSender<RendezvousMessage>;
+
+struct Peer {
+    addr: SocketAddr,
+    last_reg: Instant,
+    // The tcp connection it registers on, udp if none.
+    tcp: Option<Sender>,
+}
+
+struct State {
+    store: Store,
+    sk: sign::SecretKey,
+    key: String,
+    relay_port: u16,
+    // One per ipv4 address listened on.
+    udp: Vec<Arc<UdpSocket>>,
+    peers: Mutex<HashMap<String, Peer>>,
+    // The tcp connections of the requests waiting for the answers of the peers.
+    waiting: Mutex<HashMap<SocketAddr, Sender>>,
+}
+
+/// The running server, it stops on drop.
+pub struct EmbeddedServer {
+    key: String,
+    ports: (u16, u16, u16),
+    http_port: u16,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl Drop for EmbeddedServer {
+    fn drop(&mut self) {
+        for task in self.tasks.iter() {
+            task.abort();
+        }
+    }
+}
+
+impl EmbeddedServer {
+    /// Listens on `port` - 1 to `port` + 2 of `ips`, with the database at `path`.
+    /// With `port` 0, on any free ports.
+    pub async fn start(ips: &[IpAddr], port: u16, path: &Path) -> ResultType<Self> {
+        let store = Store::open(path)?;
+        let (pk, sk) = store.key_pair()?;
+        let key = crate::encode64(pk);
+        let any = |p: u16| if port == 0 { 0 } else { p };
+        let (nat, nat_port) = listen(ips, any(port.saturating_sub(1))).await?;
+        let (rendezvous, port) = listen(ips, port).await?;
+        let (relay, relay_port) = listen(ips, any(port + 1)).await?;
+        let (http, http_port) = listen(ips, any(port + 2)).await?;
+        let mut udp = vec![];
+        for ip in ips.iter().filter(|ip| ip.is_ipv4()) {
+            udp.push(Arc::new(UdpSocket::bind(SocketAddr::new(*ip, port)).await?));
+        }
+        let state = Arc::new(State {
+            store,
+            sk,
+            key: key.clone(),
+            relay_port,
+            udp: udp.clone(),
+            peers: Default::default(),
+            waiting: Default::default(),
+        });
+        log::info!("Embedded server listening on {:?}:{}", ips, port);
+        let mut tasks = vec![tokio::spawn(relay::serve(relay, key.clone()))];
+        for listener in nat {
+            tasks.push(tokio::spawn(serve_tcp(listener, state.clone(), false)));
+        }
+        for listener in rendezvous {
+            tasks.push(tokio::spawn(serve_tcp(listener, state.clone(), true)));
+        }
+        for socket in udp {
+            tasks.push(tokio::spawn(serve_udp(socket, state.clone())));
+        }
+        for listener in http {
+            tasks.push(tokio::spawn(http::serve(listener, state.clone())));
+        }
+        Ok(Self {
+            key,
+            ports: (nat_port, port, relay_port),
+            http_port,
+            tasks,
+        })
+    }
+
+    /// The public key of the server in base64, the `key` option of its peers.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// The ports of the NAT test, the rendezvous and the relay.
+    pub fn ports(&self) -> (u16, u16, u16) {
+        self.ports
+    }
+
+    /// The port of the registration over http.
+    pub fn http_port(&self) -> u16 {
+        self.http_port
+    }
+}
+
+// On `port` of each of `ips`, the port of the first one if 0.
+async fn listen(ips: &[IpAddr], mut port: u16) -> ResultType<(Vec<TcpListener>, u16)> {
+    let mut listeners = vec![];
+    for ip in ips {
+        let listener = new_listener(SocketAddr::new(*ip, port), true).await?;
+        port = listener.local_addr()?.port();
+        listeners.push(listener);
+    }
+    Ok((listeners, port))
+}
+
+/// The loopback and the private addresses of this host.
+pub fn lan_ips() -> Vec<IpAddr> {
+    let mut ips: Vec<IpAddr> = vec![Ipv4Addr::LOCALHOST.into()];
+    for interface in default_net::get_interfaces() {
+        for v4 in interface.ipv4 {
+            if v4.addr.is_private() || v4.addr.is_link_local() {
+                ips.push(v4.addr.into());
+            }
+        }
+        for v6 in interface.ipv6 {
+            // The unique local addresses, fc00::/7.
+            if v6.addr.segments()[0] & 0xfe00 == 0xfc00 {
+                ips.push(v6.addr.into());
+            }
+        }
+    }
+    ips.sort();
+    ips.dedup();
+    ips
+}
+
+/// Whether `server`, as `custom-rendezvous-server`, is the embedded server of this host.
+pub fn is_this_host(server: &str) -> bool {
+    let server = server.trim();
+    let (host, port) = match server.parse::<SocketAddr>() {
+        Ok(addr) => (addr.ip().to_string(), addr.port()),
+        Err(_) => match server.rsplit_once(':') {
+            Some((host, port)) if !host.contains(':') => {
+                (host.to_owned(), port.parse().unwrap_or_default())
+            }
+            _ => (
+                server.trim_matches(|c| c == '[' || c == ']').to_owned(),
+                RENDEZVOUS_PORT as u16,
+            ),
+        },
+    };
+    if port != RENDEZVOUS_PORT as u16 {
+        return false;
+    }
+    if host.eq_ignore_ascii_case("localhost") {
+        return true;
+    }
+    match host.parse::<IpAddr>() {
+        Ok(ip) => ip.is_loopback() || lan_ips().contains(&ip),
+        Err(_) => false,
+    }
+}
+
+/// Runs the embedded server while it is allowed, and publishes its key in the options.
+pub async fn watch() {
+    let mut server: Option<EmbeddedServer> = None;
+    let mut ips = vec![];
+    let mut last_lan_check = Instant::now();
+    loop {
+        let allowed = option2bool(
+            keys::OPTION_ALLOW_EMBEDDED_SERVER,
+            &Config::get_option(keys::OPTION_ALLOW_EMBEDDED_SERVER),
+        ) && !option2bool("stop-service", &Config::get_option("stop-service"));
+        if server.is_some() && last_lan_check.elapsed() >= LAN_CHECK_INTERVAL {
+            last_lan_check = Instant::now();
+            if lan_ips() != ips {
+                log::info!("Embedded server restarted on the changed LAN addresses");
+                server = None;
+            }
+        }
+        if allowed && server.is_none() {
+            let path = Config::path("embedded_server");
+            ips = lan_ips();
+            last_lan_check = Instant::now();
+            match EmbeddedServer::start(&ips, RENDEZVOUS_PORT as _, &path).await {
+                Ok(s) => {
+                    if Config::get_option(keys::OPTION_EMBEDDED_SERVER_KEY) != s.key() {
+                        Config::set_option(
+                            keys::OPTION_EMBEDDED_SERVER_KEY.to_owned(),
+                            s.key().to_owned(),
+                        );
+                    }
+                    server = Some(s);
+                }
+                Err(err) => {
+                    log::error!("Failed to start the embedded server: {}", err);
+                    sleep(30.).await;
+                }
+            }
+        } else if !allowed && server.take().is_some() {
+            log::info!("Embedded server stopped");
+        }
+        sleep(1.).await;
+    }
+}
+
+async fn serve_udp(udp: Arc<UdpSocket>, state: Arc<State>) {
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let (n, addr) = match udp.recv_from(&mut buf).await {
+            Ok(res) => res,
+            // e.g. ICMP port unreachable of a peer gone on Windows
+            Err(_) => continue,
+        };
+        let Ok(msg) = RendezvousMessage::parse_from_bytes(&buf[..n]) else {
+            continue;
+        };
+        if let Some(res) = state.handle(msg, try_into_v4(addr), None).await {
+            if let Ok(bytes) = res.write_to_bytes() {
+                udp.send_to(&bytes, addr).await.ok();
+            }
+        }
+    }
+}
+
+async fn serve_tcp(listener: TcpListener, state: Arc<State>, secure: bool) {
+    while let Ok((stream, addr)) = listener.accept().await {
+        let state = state.clone();
+        tokio::spawn(async move {
+            if let Err(err) = handle_tcp(stream, try_into_v4(addr), state, secure).await {
+                log::debug!("Embedded server connection from {} closed: {}", addr, err);
+            }
+        });
+    }
+}
+
+async fn handle_tcp(
+    stream: TcpStream,
+    addr: SocketAddr,
+    state: Arc<State>,
+    secure: bool,
+) -> ResultType<()> {
+    stream.set_nodelay(true).ok();
+    let local_addr = stream.local_addr()?;
+    let mut stream = Stream::from(stream, local_addr);
+    // The key exchange is offered, the peers answer it or go on in plain text, see `secure_tcp`.
+    let (our_pk_b, our_sk_b) = box_::gen_keypair();
+    if secure {
+        let mut msg_out = RendezvousMessage::new();
+        msg_out.set_key_exchange(KeyExchange {
+            keys: vec![sign::sign(&our_pk_b.0, &state.sk).into()],
+            ..Default::default()
+        });
+        stream.send(&msg_out).await?;
+    }
+    let (tx, mut rx) = mpsc::unbounded_channel::<RendezvousMessage>();
+    let mut registered: Option<String> = None;
+    let res = loop {
+        select! {
+            res = timeout(TCP_TIMEOUT, stream.next()) => {
+                let bytes = match res {
+                    Ok(Some(Ok(bytes))) => bytes,
+                    _ => break Ok(()),
+                };
+                let Ok(msg) = RendezvousMessage::parse_from_bytes(&bytes) else {
+                    continue;
+                };
+                if let Some(rendezvous_message::Union::KeyExchange(ex)) = &msg.union {
+                    if ex.keys.len() == 2 {
+                        let their_pk_b = box_::PublicKey::from_slice(&ex.keys[0]);
+                        let nonce = box_::Nonce([0u8; box_::NONCEBYTES]);
+                        let key = their_pk_b
+                            .and_then(|pk| box_::open(&ex.keys[1], &nonce, &pk, &our_sk_b).ok())
+                            .and_then(|key| secretbox::Key::from_slice(&key));
+                        match key {
+                            Some(key) => stream.set_key(key),
+                            None => break Err(hbb_common::anyhow::anyhow!("Invalid key exchange")),
+                        }
+                    }
+                    continue;
+                }
+                if let Some(rendezvous_message::Union::RegisterPeer(rp)) = &msg.union {
+                    registered = Some(rp.id.clone());
+                }
+                if let Some(res) = state.handle(msg, addr, Some(&tx)).await {
+                    if let Err(err) = stream.send(&res).await {
+                        break Err(err);
+                    }
+                }
+            }
+            Some(msg) = rx.recv() => {
+                if let Err(err) = stream.send(&msg).await {
+                    break Err(err);
+                }
+            }
+        }
+    };
+    state.waiting.lock().unwrap().remove(&addr);
+    if let Some(id) = registered {
+        if let Some(peer) = state.peers.lock().unwrap().get_mut(&id) {
+            if peer.addr == addr {
+                peer.tcp = None;
+            }
+        }
+    }
+    res
+}
+
+// The ip of this host on the route to `to`.
+fn local_ip_for(to: SocketAddr) -> IpAddr {
+    let any: SocketAddr = if to.is_ipv4() {
+        (Ipv4Addr::UNSPECIFIED, 0).into()
+    } else {
+        (std::net::Ipv6Addr::UNSPECIFIED, 0).into()
+    };
+    std::net::UdpSocket::bind(any)
+        .and_then(|s| s.connect(to).and_then(|_| s.local_addr()))
+        .map(|a| a.ip())
+        .unwrap_or(Ipv4Addr::LOCALHOST.into())
+}
+
+// `addr` as reachable from `to`.
+fn visible_addr(addr: SocketAddr, to: SocketAddr) -> SocketAddr {
+    if addr.ip().is_loopback() && !to.ip().is_loopback() {
+        SocketAddr::new(local_ip_for(to), addr.port())
+    } else {
+        addr
+    }
+}
+
+impl State {
+    fn relay_server(&self, to: SocketAddr) -> String {
+        SocketAddr::new(local_ip_for(to), self.relay_port).to_string()
+    }
+
+    fn signed_id_pk(&self, id: &str) -> Vec<u8> {
+        let Some(record) = self.store.get(id) else {
+            return Vec::new();
+        };
+        let id_pk = IdPk {
+            id: id.to_owned(),
+            pk: record.pk.into(),
+            ..Default::default()
+        };
+        match id_pk.write_to_bytes() {
+            Ok(bytes) => sign::sign(&bytes, &self.sk),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    // The online peer of `id`, its address and tcp connection.
+    fn peer(&self, id: &str) -> Option<(SocketAddr, Option<Sender>)> {
+        let peers = self.peers.lock().unwrap();
+        let peer = peers.get(id)?;
+        if peer.last_reg.elapsed() > REG_TIMEOUT {
+            return None;
+        }
+        Some((peer.addr, peer.tcp.clone()))
+    }
+
+    async fn send_to_peer(&self, addr: SocketAddr, tcp: Option<Sender>, msg: RendezvousMessage) {
+        match tcp {
+            Some(tx) => {
+                tx.send(msg).ok();
+            }
+            None => {
+                // On the address of the route to the peer.
+                let ip = local_ip_for(addr);
+                let udp = self
+                    .udp
+                    .iter()
+                    .find(|s| s.local_addr().map(|a| a.ip() == ip).unwrap_or_default())
+                    .or(self.udp.first());
+                if let (Some(udp), Ok(bytes)) = (udp, msg.write_to_bytes()) {
+                    udp.send_to(&bytes, addr).await.ok();
+                }
+            }
+        }
+    }
+
+    // The requester of `addr`, also by the port if it has been given as seen by the peer.
+    fn take_waiting(&self, addr: SocketAddr) -> Option<(SocketAddr, Sender)> {
+        let waiting = self.waiting.lock().unwrap();
+        waiting
+            .get_key_value(&addr)
+            .or_else(|| {
+                waiting
+                    .iter()
+                    .find(|(a, _)| a.ip().is_loopback() && a.port() == addr.port())
+            })
+            .map(|(a, tx)| (*a, tx.clone()))
+    }
+
+    /// The answer to `msg` from `addr`, `tcp` is the connection of it.
+    async fn handle(
+        &self,
+        msg: RendezvousMessage,
+        addr: SocketAddr,
+        tcp: Option<&Sender>,
+    ) -> Option<RendezvousMessage> {
+        let mut msg_out = RendezvousMessage::new();
+        match msg.union? {
+            rendezvous_message::Union::RegisterPeer(rp) => {
+                if rp.id.is_empty() {
+                    return None;
+                }
+                self.peers.lock().unwrap().insert(
+                    rp.id.clone(),
+                    Peer {
+                        addr,
+                        last_reg: Instant::now(),
+                        tcp: tcp.cloned(),
+                    },
+                );
+                msg_out.set_register_peer_response(RegisterPeerResponse {
+                    request_pk: self.store.get(&rp.id).map_or(true, |r| r.pk.is_empty()),
+                    ..Default::default()
+                });
+            }
+            rendezvous_message::Union::RegisterPk(rk) => {
+                let result = if rk.id.is_empty() || rk.uuid.is_empty() || rk.pk.is_empty() {
+                    register_pk_response::Result::NOT_SUPPORT
+                } else {
+                    self.store.register(&rk.id, &rk.uuid, &rk.pk, &rk.old_id)
+                };
+                msg_out.set_register_pk_response(RegisterPkResponse {
+                    result: result.into(),
+                    keep_alive: KEEP_ALIVE,
+                    ..Default::default()
+                });
+            }
+            rendezvous_message::Union::TestNatRequest(_) => {
+                msg_out.set_test_nat_response(TestNatResponse {
+                    port: addr.port() as _,
+                    ..Default::default()
+                });
+            }
+            rendezvous_message::Union::OnlineRequest(or) => {
+                let mut states = vec![0u8; or.peers.len().div_ceil(8)];
+                for (i, id) in or.peers.iter().enumerate() {
+                    if self.peer(id).is_some() {
+                        // bytes index from left to right
+                        states[i / 8] |= 0x01 << (7 - i % 8);
+                    }
+                }
+                msg_out.set_online_response(OnlineResponse {
+                    states: states.into(),
+                    ..Default::default()
+                });
+            }
+            rendezvous_message::Union::PunchHoleRequest(ph) => {
+                let tx = tcp?;
+                let failure = |failure| {
+                    let mut msg_out = RendezvousMessage::new();
+                    msg_out.set_punch_hole_response(PunchHoleResponse {
+                        failure: failure.into(),
+                        ..Default::default()
+                    });
+                    Some(msg_out)
+                };
+                if ph.licence_key != self.key {
+                    return failure(punch_hole_response::Failure::LICENSE_MISMATCH);
+                }
+                let Some((peer_addr, peer_tcp)) = self.peer(&ph.id) else {
+                    return failure(if self.store.get(&ph.id).is_some() {
+                        punch_hole_response::Failure::OFFLINE
+                    } else {
+                        punch_hole_response::Failure::ID_NOT_EXIST
+                    });
+                };
+                self.waiting.lock().unwrap().insert(addr, tx.clone());
+                let socket_addr = AddrMangle::encode(visible_addr(addr, peer_addr)).into();
+                let relay_server = self.relay_server(peer_addr);
+                let mut msg_peer = RendezvousMessage::new();
+                // In the same intranet, the local address is used.
+                if addr.ip() == peer_addr.ip() {
+                    msg_peer.set_fetch_local_addr(FetchLocalAddr {
+                        socket_addr,
+                        relay_server,
+                        socket_addr_v6: ph.socket_addr_v6,
+                        quic: ph.quic,
+                        ..Default::default()
+                    });
+                } else {
+                    msg_peer.set_punch_hole(PunchHole {
+                        socket_addr,
+                        relay_server,
+                        nat_type: ph.nat_type,
+                        udp_port: ph.udp_port,
+                        force_relay: ph.force_relay,
+                        upnp_port: ph.upnp_port,
+                        socket_addr_v6: ph.socket_addr_v6,
+                        quic: ph.quic,
+                        ..Default::default()
+                    });
+                }
+                self.send_to_peer(peer_addr, peer_tcp, msg_peer).await;
+                return None;
+            }
+            rendezvous_message::Union::RequestRelay(mut rr) => {
+                let tx = tcp?;
+                let Some((peer_addr, peer_tcp)) = self.peer(&rr.id) else {
+                    msg_out.set_relay_response(RelayResponse {
+                        refuse_reason: "Remote desktop is offline".to_owned(),
+                        ..Default::default()
+                    });
+                    return Some(msg_out);
+                };
+                self.waiting.lock().unwrap().insert(addr, tx.clone());
+                rr.socket_addr = AddrMangle::encode(visible_addr(addr, peer_addr)).into();
+                rr.relay_server = self.relay_server(peer_addr);
+                let mut msg_peer = RendezvousMessage::new();
+                msg_peer.set_request_relay(rr);
+                self.send_to_peer(peer_addr, peer_tcp, msg_peer).await;
+                return None;
+            }
+            rendezvous_message::Union::PunchHoleSent(phs) => {
+                let (to, tx) = self.take_waiting(AddrMangle::decode(&phs.socket_addr))?;
+                let mut ph = PunchHoleResponse {
+                    socket_addr: AddrMangle::encode(visible_addr(addr, to)).into(),
+                    pk: self.signed_id_pk(&phs.id).into(),
+                    relay_server: self.relay_server(to),
+                    is_udp: tcp.is_none(),
+                    upnp_port: phs.upnp_port,
+                    socket_addr_v6: phs.socket_addr_v6,
+                    quic: phs.quic,
+                    ..Default::default()
+                };
+                ph.set_nat_type(phs.nat_type.enum_value_or_default());
+                msg_out.set_punch_hole_response(ph);
+                tx.send(msg_out).ok();
+                return None;
+            }
+            rendezvous_message::Union::LocalAddr(la) => {
+                let (to, tx) = self.take_waiting(AddrMangle::decode(&la.socket_addr))?;
+                let mut ph = PunchHoleResponse {
+                    socket_addr: AddrMangle::encode(visible_addr(
+                        AddrMangle::decode(&la.local_addr),
+                        to,
+                    ))
+                    .into(),
+                    pk: self.signed_id_pk(&la.id).into(),
+                    relay_server: self.relay_server(to),
+                    socket_addr_v6: la.socket_addr_v6,
+                    quic: la.quic,
+                    ..Default::default()
+                };
+                ph.set_is_local(true);
+                msg_out.set_punch_hole_response(ph);
+                tx.send(msg_out).ok();
+                return None;
+            }
+            rendezvous_message::Union::RelayResponse(mut rr) => {
+                let (to, tx) = self.take_waiting(AddrMangle::decode(&rr.socket_addr))?;
+                let id = rr.id().to_owned();
+                rr.set_pk(self.signed_id_pk(&id).into());
+                rr.relay_server = self.relay_server(to);
+                msg_out.set_relay_response(rr);
+                tx.send(msg_out).ok();
+                return None;
+            }
+            _ => return None,
+        }
+        Some(msg_out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn connect(port: u16) -> Stream {
+        hbb_common::socket_client::connect_tcp_local(format!("127.0.0.1:{}", port), None, 3000)
+            .await
+            .unwrap()
+    }
+
+    async fn next(stream: &mut Stream) -> rendezvous_message::Union {
+        loop {
+            let bytes = stream.next_timeout(3000).await.unwrap().unwrap();
+            let msg = RendezvousMessage::parse_from_bytes(&bytes).unwrap();
+            match msg.union.unwrap() {
+                rendezvous_message::Union::KeyExchange(_) => continue,
+                union => return union,
+            }
+        }
+    }
+
+    async fn udp_request(
+        socket: &UdpSocket,
+        port: u16,
+        msg: RendezvousMessage,
+    ) -> rendezvous_message::Union {
+        socket
+            .send_to(&msg.write_to_bytes().unwrap(), ("127.0.0.1", port))
+            .await
+            .unwrap();
+        udp_next(socket).await
+    }
+
+    async fn udp_next(socket: &UdpSocket) -> rendezvous_message::Union {
+        let mut buf = vec![0u8; 4096];
+        let (n, _) = timeout(3000, socket.recv_from(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        RendezvousMessage::parse_from_bytes(&buf[..n])
+            .unwrap()
+            .union
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_embedded_server() {
+        let dir = std::env::temp_dir().join(format!("embedded_server_{}", std::process::id()));
+        let server = EmbeddedServer::start(&[Ipv4Addr::LOCALHOST.into()], 0, &dir)
+            .await
+            .unwrap();
+        let (nat_port, port, relay_port) = server.ports();
+        let rs_pk = crate::get_rs_pk(server.key()).unwrap();
+
+        // The controlled side registers over udp.
+        let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let (peer_pk, _) = sign::gen_keypair();
+        let mut msg = RendezvousMessage::new();
+        msg.set_register_peer(RegisterPeer {
+            id: "embedded1".to_owned(),
+            ..Default::default()
+        });
+        let rendezvous_message::Union::RegisterPeerResponse(rpr) =
+            udp_request(&peer, port, msg.clone()).await
+        else {
+            panic!("no register peer response");
+        };
+        assert!(rpr.request_pk);
+        let mut msg_pk = RendezvousMessage::new();
+        msg_pk.set_register_pk(RegisterPk {
+            id: "embedded1".to_owned(),
+            uuid: b"uuid".to_vec().into(),
+            pk: peer_pk.0.to_vec().into(),
+            ..Default::default()
+        });
+        let rendezvous_message::Union::RegisterPkResponse(rpr) =
+            udp_request(&peer, port, msg_pk).await
+        else {
+            panic!("no register pk response");
+        };
+        assert_eq!(
+            rpr.result.enum_value(),
+            Ok(register_pk_response::Result::OK)
+        );
+        let rendezvous_message::Union::RegisterPeerResponse(rpr) =
+            udp_request(&peer, port, msg).await
+        else {
+            panic!("no register peer response");
+        };
+        assert!(!rpr.request_pk);
+
+        // The wrong key is refused, the right one reaches the peer.
+        let mut client = connect(port).await;
+        let mut msg = RendezvousMessage::new();
+        msg.set_punch_hole_request(PunchHoleRequest {
+            id: "embedded1".to_owned(),
+            licence_key: "wrong".to_owned(),
+            ..Default::default()
+        });
+        client.send(&msg).await.unwrap();
+        let rendezvous_message::Union::PunchHoleResponse(ph) = next(&mut client).await else {
+            panic!("no punch hole response");
+        };
+        assert_eq!(
+            ph.failure.enum_value(),
+            Ok(punch_hole_response::Failure::LICENSE_MISMATCH)
+        );
+        msg.mut_punch_hole_request().licence_key = server.key().to_owned();
+        client.send(&msg).await.unwrap();
+        // Both on loopback, the same intranet.
+        let rendezvous_message::Union::FetchLocalAddr(fla) = udp_next(&peer).await else {
+            panic!("no fetch local addr");
+        };
+        assert_eq!(AddrMangle::decode(&fla.socket_addr), client.local_addr());
+
+        // The controlled side answers on a new connection.
+        let mut answer = connect(port).await;
+        let mut msg = RendezvousMessage::new();
+        msg.set_local_addr(LocalAddr {
+            id: "embedded1".to_owned(),
+            socket_addr: fla.socket_addr,
+            local_addr: AddrMangle::encode(answer.local_addr()).into(),
+            ..Default::default()
+        });
+        answer.send(&msg).await.unwrap();
+        let rendezvous_message::Union::PunchHoleResponse(ph) = next(&mut client).await else {
+            panic!("no punch hole response");
+        };
+        assert!(ph.is_local());
+        assert_eq!(AddrMangle::decode(&ph.socket_addr), answer.local_addr());
+        let (id, pk, _) = crate::decode_id_pk(&ph.pk, &rs_pk).unwrap();
+        assert_eq!((id.as_str(), pk), ("embedded1", peer_pk.0));
+        assert!(ph.relay_server.ends_with(&format!(":{}", relay_port)));
+
+        // The relay pairs the two sides by the uuid.
+        let mut a = connect(relay_port).await;
+        let mut b = connect(relay_port).await;
+        let mut msg = RendezvousMessage::new();
+        msg.set_request_relay(RequestRelay {
+            uuid: "relay-uuid".to_owned(),
+            licence_key: server.key().to_owned(),
+            ..Default::default()
+        });
+        a.send(&msg).await.unwrap();
+        sleep(0.1).await;
+        b.send(&msg).await.unwrap();
+        a.send_bytes(b"hello".to_vec().into()).await.unwrap();
+        assert_eq!(&b.next_timeout(3000).await.unwrap().unwrap()[..], b"hello");
+
+        // Online states and the nat test.
+        let mut online = connect(nat_port).await;
+        let mut msg = RendezvousMessage::new();
+        msg.set_online_request(OnlineRequest {
+            peers: vec!["nobody".to_owned(), "embedded1".to_owned()],
+            ..Default::default()
+        });
+        online.send(&msg).await.unwrap();
+        let rendezvous_message::Union::OnlineResponse(or) = next(&mut online).await else {
+            panic!("no online response");
+        };
+        assert_eq!(&or.states[..], &[0x40]);
+        let mut msg = RendezvousMessage::new();
+        msg.set_test_nat_request(Default::default());
+        online.send(&msg).await.unwrap();
+        let rendezvous_message::Union::TestNatResponse(tnr) = next(&mut online).await else {
+            panic!("no test nat response");
+        };
+        assert_eq!(tnr.port, online.local_addr().port() as i32);
+
+        drop(server);
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_is_this_host() {
+        assert!(is_this_host("127.0.0.1"));
+        assert!(is_this_host("localhost:21116"));
+        assert!(is_this_host("[::1]:21116"));
+        assert!(!is_this_host("127.0.0.1:21200"));
+        assert!(!is_this_host("8.8.8.8"));
+        assert!(!is_this_host("rs-ny.rustdesk.com"));
+        assert!(!is_this_host(""));
+    }
+}
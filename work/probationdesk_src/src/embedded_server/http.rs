@@ -0,0 +1,240 @@
+// The registration over http of the embedded server, the counterpart of the https fallback of
+// the peers, see `rendezvous_mediator::http`. It is on http, for a TLS proxy in front of it on
+// 443, or for `rendezvous-http-url` on http.
+//
+// `POST /rendezvous/message` takes the messages in base64, one per line, and answers them in
+// the body the same way. `GET /rendezvous/events` sends the messages for the session as
+// server-sent events, a comment as heartbeat meanwhile. The session is `X-Rendezvous-Session`,
+// it is on the address of its first request, as a tcp connection is.
+
+use super::{State, TCP_TIMEOUT};
+use hbb_common::{
+    bail,
+    base64::{engine::general_purpose::STANDARD, Engine as _},
+    log,
+    protobuf::Message as _,
+    rendezvous_proto::RendezvousMessage,
+    timeout,
+    tokio::{
+        self,
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+        select,
+        sync::mpsc,
+    },
+    try_into_v4, ResultType,
+};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+const PATH: &str = "/rendezvous";
+const SESSION_HEADER: &str = "X-Rendezvous-Session";
+// Within the idle timeout of the peers on the events.
+const HEARTBEAT: Duration = Duration::from_secs(30);
+// Without any request, the events included.
+const SESSION_TIMEOUT: Duration = Duration::from_secs(120);
+const MAX_HEAD: usize = 16 * 1024;
+const MAX_BODY: usize = 64 * 1024;
+
+struct Session {
+    addr: SocketAddr,
+    tx: super::Sender,
+    // Taken while the events are sent.
+    rx: Option<mpsc::UnboundedReceiver<RendezvousMessage>>,
+    last_seen: Instant,
+}
+
+type Sessions = Arc<Mutex<HashMap<String, Session>>>;
+
+struct Request {
+    method: String,
+    path: String,
+    session: String,
+    body: Vec<u8>,
+}
+
+pub async fn serve(listener: TcpListener, state: Arc<State>) {
+    let sessions: Sessions = Default::default();
+    while let Ok((stream, addr)) = listener.accept().await {
+        let (state, sessions) = (state.clone(), sessions.clone());
+        tokio::spawn(async move {
+            if let Err(err) = handle(stream, try_into_v4(addr), state, sessions).await {
+                log::debug!("Embedded server http request from {} failed: {}", addr, err);
+            }
+        });
+    }
+}
+
+async fn handle(
+    mut stream: TcpStream,
+    addr: SocketAddr,
+    state: Arc<State>,
+    sessions: Sessions,
+) -> ResultType<()> {
+    let req = timeout(TCP_TIMEOUT, read_request(&mut stream)).await??;
+    let route = req.path.strip_prefix(PATH).unwrap_or_default();
+    if req.session.is_empty() {
+        return respond(&mut stream, "400 Bad Request", "").await;
+    }
+    let (session_addr, tx) = session(&sessions, &state, &req.session, addr);
+    match (req.method.as_str(), route) {
+        ("POST", "/message") => {
+            let mut answers = String::new();
+            for line in String::from_utf8_lossy(&req.body).lines() {
+                let Some(msg) = STANDARD
+                    .decode(line.trim())
+                    .ok()
+                    .and_then(|bytes| RendezvousMessage::parse_from_bytes(&bytes).ok())
+                else {
+                    continue;
+                };
+                if let Some(res) = state.handle(msg, session_addr, Some(&tx)).await {
+                    answers.push_str(&STANDARD.encode(res.write_to_bytes()?));
+                    answers.push('\n');
+                }
+            }
+            respond(&mut stream, "200 OK", &answers).await
+        }
+        ("GET", "/events") => {
+            let rx = sessions
+                .lock()
+                .unwrap()
+                .get_mut(&req.session)
+                .and_then(|s| s.rx.take());
+            let Some(mut rx) = rx else {
+                return respond(&mut stream, "409 Conflict", "").await;
+            };
+            let res = send_events(&mut stream, &mut rx, &sessions, &req.session).await;
+            if let Some(session) = sessions.lock().unwrap().get_mut(&req.session) {
+                session.rx = Some(rx);
+            }
+            res
+        }
+        _ => respond(&mut stream, "404 Not Found", "").await,
+    }
+}
+
+// The address and the sender of `id`, the sessions gone quiet are dropped.
+fn session(
+    sessions: &Sessions,
+    state: &State,
+    id: &str,
+    addr: SocketAddr,
+) -> (SocketAddr, super::Sender) {
+    let mut sessions = sessions.lock().unwrap();
+    sessions.retain(|_, s| {
+        let alive = s.rx.is_none() || s.last_seen.elapsed() < SESSION_TIMEOUT;
+        if !alive {
+            state.waiting.lock().unwrap().remove(&s.addr);
+        }
+        alive
+    });
+    let session = sessions.entry(id.to_owned()).or_insert_with(|| {
+        let (tx, rx) = mpsc::unbounded_channel();
+        Session {
+            addr,
+            tx,
+            rx: Some(rx),
+            last_seen: Instant::now(),
+        }
+    });
+    session.last_seen = Instant::now();
+    (session.addr, session.tx.clone())
+}
+
+// Until the peer closes the connection or it fails.
+async fn send_events(
+    stream: &mut TcpStream,
+    rx: &mut mpsc::UnboundedReceiver<RendezvousMessage>,
+    sessions: &Sessions,
+    id: &str,
+) -> ResultType<()> {
+    let (mut reader, mut writer) = stream.split();
+    writer
+        .write_all(
+            b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\n\
+            Cache-Control: no-cache\r\nConnection: close\r\n\r\n",
+        )
+        .await?;
+    let mut heartbeat = tokio::time::interval(HEARTBEAT);
+    let mut buf = [0u8; 256];
+    loop {
+        let event = select! {
+            Some(msg) = rx.recv() => format!("data: {}\n\n", STANDARD.encode(msg.write_to_bytes()?)),
+            _ = heartbeat.tick() => {
+                if let Some(session) = sessions.lock().unwrap().get_mut(id) {
+                    session.last_seen = Instant::now();
+                }
+                ": heartbeat\n\n".to_owned()
+            }
+            res = reader.read(&mut buf) => match res {
+                Ok(0) | Err(_) => return Ok(()),
+                Ok(_) => continue,
+            },
+        };
+        writer.write_all(event.as_bytes()).await?;
+    }
+}
+
+async fn read_request(stream: &mut TcpStream) -> ResultType<Request> {
+    let mut data = Vec::new();
+    let mut buf = [0u8; 4096];
+    loop {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            bail!("The connection is closed before the request");
+        }
+        data.extend_from_slice(&buf[..n]);
+        let mut headers = [httparse::EMPTY_HEADER; 32];
+        let mut parsed = httparse::Request::new(&mut headers);
+        let httparse::Status::Complete(head) = parsed.parse(&data)? else {
+            if data.len() > MAX_HEAD {
+                bail!("The request head is too large");
+            }
+            continue;
+        };
+        let header = |name: &str| {
+            parsed
+                .headers
+                .iter()
+                .find(|h| h.name.eq_ignore_ascii_case(name))
+                .map(|h| String::from_utf8_lossy(h.value).trim().to_owned())
+                .unwrap_or_default()
+        };
+        let len: usize = header("Content-Length").parse().unwrap_or(0);
+        if len > MAX_BODY {
+            bail!("The request body is too large");
+        }
+        let mut req = Request {
+            method: parsed.method.unwrap_or_default().to_owned(),
+            path: parsed.path.unwrap_or_default().to_owned(),
+            session: header(SESSION_HEADER),
+            body: data[head..].to_vec(),
+        };
+        while req.body.len() < len {
+            let n = stream.read(&mut buf).await?;
+            if n == 0 {
+                bail!("The connection is closed in the request body");
+            }
+            req.body.extend_from_slice(&buf[..n]);
+        }
+        req.body.truncate(len);
+        return Ok(req);
+    }
+}
+
+async fn respond(stream: &mut TcpStream, status: &str, body: &str) -> ResultType<()> {
+    let head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(body.as_bytes()).await?;
+    stream.shutdown().await.ok();
+    Ok(())
+}
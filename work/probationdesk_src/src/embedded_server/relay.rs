@@ -0,0 +1,105 @@
+// The relay of the embedded server, as hbbr: the two sides connect with the same uuid in
+// `RequestRelay`, and the frames are forwarded between them as they are, they are encrypted
+// end to end by the peers.
+
+use hbb_common::{
+    futures::future::join_all,
+    log,
+    protobuf::Message as _,
+    rendezvous_proto::*,
+    tokio::{self, net::TcpListener, select},
+    ResultType, Stream,
+};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+// The first side waits this long for the other one.
+const PAIR_TIMEOUT: Duration = Duration::from_secs(30);
+const FIRST_MSG_TIMEOUT: u64 = 10_000;
+
+type Pending = Arc<Mutex<HashMap<String, (Stream, Instant)>>>;
+
+// The two sides may come on different addresses of this host.
+pub async fn serve(listeners: Vec<TcpListener>, key: String) {
+    let pending: Pending = Default::default();
+    join_all(
+        listeners
+            .into_iter()
+            .map(|listener| accept(listener, key.clone(), pending.clone())),
+    )
+    .await;
+}
+
+async fn accept(listener: TcpListener, key: String, pending: Pending) {
+    while let Ok((stream, addr)) = listener.accept().await {
+        stream.set_nodelay(true).ok();
+        let local_addr = match stream.local_addr() {
+            Ok(addr) => addr,
+            Err(_) => continue,
+        };
+        let stream = Stream::from(stream, local_addr);
+        let (pending, key) = (pending.clone(), key.clone());
+        tokio::spawn(async move {
+            if let Err(err) = handle(stream, addr, &key, pending).await {
+                log::debug!("Relay connection from {} closed: {}", addr, err);
+            }
+        });
+    }
+}
+
+async fn handle(
+    mut stream: Stream,
+    addr: SocketAddr,
+    key: &str,
+    pending: Pending,
+) -> ResultType<()> {
+    let Some(Ok(bytes)) = stream.next_timeout(FIRST_MSG_TIMEOUT).await else {
+        return Ok(());
+    };
+    let Some(rendezvous_message::Union::RequestRelay(rr)) =
+        RendezvousMessage::parse_from_bytes(&bytes)?.union
+    else {
+        return Ok(());
+    };
+    if rr.licence_key != key {
+        log::warn!("Relay request with a wrong key from {}", addr);
+        return Ok(());
+    }
+    let other = {
+        let mut pending = pending.lock().unwrap();
+        pending.retain(|_, (_, at)| at.elapsed() < PAIR_TIMEOUT);
+        match pending.remove(&rr.uuid) {
+            Some((other, _)) => other,
+            None => {
+                pending.insert(rr.uuid, (stream, Instant::now()));
+                return Ok(());
+            }
+        }
+    };
+    log::info!("Relay {} paired from {}", rr.uuid, addr);
+    pipe(stream, other).await;
+    log::info!("Relay {} closed", rr.uuid);
+    Ok(())
+}
+
+async fn pipe(mut a: Stream, mut b: Stream) {
+    loop {
+        let ok = select! {
+            res = a.next() => match res {
+                Some(Ok(bytes)) => b.send_bytes(bytes.freeze()).await.is_ok(),
+                _ => false,
+            },
+            res = b.next() => match res {
+                Some(Ok(bytes)) => a.send_bytes(bytes.freeze()).await.is_ok(),
+                _ => false,
+            },
+        };
+        if !ok {
+            break;
+        }
+    }
+}
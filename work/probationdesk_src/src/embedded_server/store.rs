@@ -0,0 +1,99 @@
+// The ids, uuids and public keys of the peers registered to the embedded server, and the key
+// pair of the server, in a sled database.
+
+use hbb_common::{
+    anyhow::anyhow, rendezvous_proto::register_pk_response::Result as RegisterResult, serde_json,
+    sodiumoxide::crypto::sign, ResultType,
+};
+use serde_derive::{Deserialize, Serialize};
+use std::path::Path;
+
+const SERVER_KEY: &[u8] = b"server-key";
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Record {
+    pub uuid: Vec<u8>,
+    pub pk: Vec<u8>,
+}
+
+pub struct Store {
+    db: sled::Db,
+    peers: sled::Tree,
+}
+
+impl Store {
+    pub fn open(path: &Path) -> ResultType<Self> {
+        let db = sled::open(path)?;
+        let peers = db.open_tree("peers")?;
+        Ok(Self { db, peers })
+    }
+
+    /// The signing key pair of the server, generated on the first start.
+    pub fn key_pair(&self) -> ResultType<(sign::PublicKey, sign::SecretKey)> {
+        if let Some(v) = self.db.get(SERVER_KEY)? {
+            let sk =
+                sign::SecretKey::from_slice(&v).ok_or_else(|| anyhow!("Invalid server key"))?;
+            return Ok((sk.public_key(), sk));
+        }
+        let (pk, sk) = sign::gen_keypair();
+        self.db.insert(SERVER_KEY, &sk.0[..])?;
+        self.db.flush()?;
+        Ok((pk, sk))
+    }
+
+    pub fn get(&self, id: &str) -> Option<Record> {
+        let v = self.peers.get(id).ok()??;
+        serde_json::from_slice(&v).ok()
+    }
+
+    fn put(&self, id: &str, record: &Record) -> ResultType<()> {
+        self.peers.insert(id, serde_json::to_vec(record)?)?;
+        self.peers.flush()?;
+        Ok(())
+    }
+
+    /// Registers the key of `id`, or changes the id of the peer from `old_id`.
+    pub fn register(&self, id: &str, uuid: &[u8], pk: &[u8], old_id: &str) -> RegisterResult {
+        let record = Record {
+            uuid: uuid.to_vec(),
+            pk: pk.to_vec(),
+        };
+        let res = if !old_id.is_empty() && old_id != id {
+            if !is_valid_id(id) {
+                return RegisterResult::INVALID_ID_FORMAT;
+            }
+            if self.get(id).is_some() {
+                return RegisterResult::ID_EXISTS;
+            }
+            if self.get(old_id).is_some_and(|r| r.uuid != uuid) {
+                return RegisterResult::UUID_MISMATCH;
+            }
+            self.peers
+                .remove(old_id)
+                .map_err(hbb_common::anyhow::Error::from)
+                .and_then(|_| self.put(id, &record))
+        } else {
+            match self.get(id) {
+                Some(r) if r.uuid != uuid => return RegisterResult::UUID_MISMATCH,
+                Some(r) if r == record => Ok(()),
+                _ => self.put(id, &record),
+            }
+        };
+        match res {
+            Ok(()) => RegisterResult::OK,
+            Err(err) => {
+                hbb_common::log::error!("Failed to register {}: {}", id, err);
+                RegisterResult::SERVER_ERROR
+            }
+        }
+    }
+}
+
+// As hbbs, 6 to 16 letters, digits, `-` or `_`, beginning with a letter.
+fn is_valid_id(id: &str) -> bool {
+    (6..=16).contains(&id.len())
+        && id.starts_with(|c: char| c.is_ascii_alphabetic())
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
@@ -712,6 +712,35 @@ impl InvokeUiSession for FlutterHandler {
                     &status.codec_format.map_or(NULL, |it| it.to_string()),
                 ),
                 ("chroma", &status.chroma.map_or(NULL, |it| it.to_string())),
+                (
+                    "audio",
+                    &status.audio.map_or(NULL, |it| {
+                        serde_json::to_string(&it).unwrap_or(NULL.to_owned())
+                    }),
+                ),
+                (
+                    "capture",
+                    &if status.capture.is_empty() {
+                        NULL
+                    } else {
+                        let capture = status
+                            .capture
+                            .iter()
+                            .map(|(idx, s)| {
+                                (
+                                    idx.to_string(),
+                                    serde_json::json!({
+                                        "captured": s.captured,
+                                        "idle": s.idle,
+                                        "damage_ratio": s.damage_ratio,
+                                        "encode_ms": s.encode_ms,
+                                    }),
+                                )
+                            })
+                            .collect::<serde_json::Map<_, _>>();
+                        serde_json::to_string(&capture).unwrap_or(NULL.to_owned())
+                    },
+                ),
             ],
             &[],
         );
@@ -801,6 +830,67 @@ impl InvokeUiSession for FlutterHandler {
         );
     }
 
+    fn set_audio_sources(&self, sources: Vec<AudioSource>) {
+        let sources: Vec<HashMap<&str, String>> = sources
+            .into_iter()
+            .map(|s| {
+                let kind = match s.kind.enum_value() {
+                    Ok(audio_source::Kind::Input) => "input",
+                    Ok(audio_source::Kind::Application) => "application",
+                    _ => "output",
+                };
+                HashMap::from([("id", s.id), ("name", s.name), ("kind", kind.to_owned())])
+            })
+            .collect();
+        self.push_event(
+            "audio_sources",
+            &[(
+                "sources",
+                &serde_json::ser::to_string(&sources).unwrap_or("".to_owned()),
+            )],
+            &[],
+        );
+    }
+
+    fn update_control_token(&self, state: ControlTokenState) {
+        let requests: Vec<_> = state
+            .requests
+            .iter()
+            .map(|r| json!({"conn_id": r.conn_id, "name": r.name}))
+            .collect();
+        self.push_event(
+            "control_token",
+            &[
+                ("has_control", json!(state.has_control)),
+                ("holder_name", json!(state.holder_name)),
+                ("controllers", json!(state.controllers)),
+                ("requested", json!(state.requested)),
+                ("requests", json!(requests)),
+            ],
+            &[],
+        );
+    }
+
+    fn handle_inventory(&self, inventory: Inventory) {
+        self.push_event(
+            "inventory",
+            &[(
+                "inventory",
+                &crate::common::make_inventory_to_json(&inventory),
+            )],
+            &[],
+        );
+    }
+
+    fn input_macro_replayed(&self, name: &str, completed: bool) {
+        let completed = if completed { "true" } else { "" };
+        self.push_event(
+            "input_macro_replayed",
+            &[("name", name), ("completed", completed)],
+            &[],
+        );
+    }
+
     // unused in flutter
     fn update_transfer_list(&self) {}
 
@@ -993,6 +1083,27 @@ impl InvokeUiSession for FlutterHandler {
         self.push_event("chat_client_mode", &[("text", &msg)], &[]);
     }
 
+    fn new_chat_message(&self, record: crate::chat_history::ChatRecord) {
+        self.push_event(
+            "chat_client_mode",
+            &[
+                ("text", json!(record.text)),
+                ("msg_id", json!(record.msg_id)),
+                ("timestamp", json!(record.timestamp)),
+                ("attachments", json!(record.attachments)),
+            ],
+            &[],
+        );
+    }
+
+    fn chat_receipt(&self, msg_ids: Vec<String>, read: bool) {
+        self.push_event(
+            "chat_receipt",
+            &[("msg_ids", json!(msg_ids)), ("read", json!(read))],
+            &[],
+        );
+    }
+
     fn switch_display(&self, display: &SwitchDisplay) {
         let resolutions = serialize_resolutions(&display.resolutions.resolutions);
         self.push_event(
@@ -1100,7 +1211,14 @@ impl InvokeUiSession for FlutterHandler {
 
     fn handle_screenshot_resp(&self, sid: String, msg: String) {
         match SessionID::from_str(&sid) {
-            Ok(sid) => self.push_event_to("screenshot", &[("msg", json!(msg))], &[&sid]),
+            Ok(sid) => self.push_event_to(
+                "screenshot",
+                &[
+                    ("msg", json!(msg)),
+                    ("ext", json!(crate::client::screenshot::cached_extension())),
+                ],
+                &[&sid],
+            ),
             Err(e) => {
                 // Unreachable!
                 log::error!("Failed to parse sid \"{}\", {}", sid, e);
@@ -1158,6 +1276,30 @@ impl InvokeUiSession for FlutterHandler {
                 ];
                 self.push_event_("terminal_response", &event_data, &[], &[]);
             }
+            Some(Union::ExecOutput(output)) => {
+                let stream = match output.stream.enum_value() {
+                    Ok(exec_output::Stream::Stderr) => "stderr",
+                    _ => "stdout",
+                };
+                let event_data: Vec<(&str, serde_json::Value)> = vec![
+                    ("type", json!("exec_output")),
+                    ("exec_id", json!(output.exec_id)),
+                    ("stream", json!(stream)),
+                    ("data", json!(crate::encode64(&output.data))),
+                ];
+                self.push_event_("terminal_response", &event_data, &[], &[]);
+            }
+            Some(Union::ExecResult(result)) => {
+                let event_data: Vec<(&str, serde_json::Value)> = vec![
+                    ("type", json!("exec_result")),
+                    ("exec_id", json!(result.exec_id)),
+                    ("exit_code", json!(result.exit_code)),
+                    ("timed_out", json!(result.timed_out)),
+                    ("canceled", json!(result.canceled)),
+                    ("error", json!(&result.error)),
+                ];
+                self.push_event_("terminal_response", &event_data, &[], &[]);
+            }
             None => {}
             Some(_) => {
                 log::warn!("Unhandled terminal response type");
@@ -1502,10 +1644,33 @@ pub mod connection_manager {
             );
         }
 
-        fn new_message(&self, id: i32, text: String) {
+        fn new_message(&self, id: i32, record: &crate::chat_history::ChatRecord) {
             self.push_event(
                 "chat_server_mode",
-                &[("id", &id.to_string()), ("text", &text)],
+                &[
+                    ("id", &id.to_string()),
+                    ("text", &record.text),
+                    ("msg_id", &record.msg_id),
+                    ("timestamp", &record.timestamp.to_string()),
+                    (
+                        "attachments",
+                        &serde_json::to_string(&record.attachments).unwrap_or_default(),
+                    ),
+                ],
+            );
+        }
+
+        fn chat_receipt(&self, id: i32, msg_ids: Vec<String>, read: bool) {
+            self.push_event(
+                "chat_receipt",
+                &[
+                    ("id", &id.to_string()),
+                    (
+                        "msg_ids",
+                        &serde_json::to_string(&msg_ids).unwrap_or_default(),
+                    ),
+                    ("read", &read.to_string()),
+                ],
             );
         }
 
@@ -1536,6 +1701,16 @@ pub mod connection_manager {
         fn file_transfer_log(&self, action: &str, log: &str) {
             self.push_event("cm_file_transfer_log", &[(action, log)]);
         }
+
+        fn update_control_token(&self, client: &crate::ui_cm_interface::Client) {
+            let client_json = serde_json::to_string(&client).unwrap_or("".into());
+            self.push_event("update_control_token", &[("client", &client_json)]);
+        }
+
+        fn update_traffic(&self, client: &crate::ui_cm_interface::Client) {
+            let client_json = serde_json::to_string(&client).unwrap_or("".into());
+            self.push_event("update_traffic", &[("client", &client_json)]);
+        }
     }
 
     impl FlutterHandler {
@@ -1909,6 +2084,36 @@ fn session_send_touch_event(
     }
 }
 
+fn session_send_pen_event(
+    session_id: SessionID,
+    v: &serde_json::Value,
+    alt: bool,
+    ctrl: bool,
+    shift: bool,
+    command: bool,
+) {
+    let get = |k: &str| v.get(k).and_then(|x| x.as_i64()).unwrap_or(0);
+    let tool = if v.get("eraser").and_then(|x| x.as_bool()) == Some(true) {
+        pen_event::Tool::Eraser
+    } else {
+        pen_event::Tool::Pen
+    };
+    let pen = PenEvent {
+        x: get("x") as _,
+        y: get("y") as _,
+        pressure: get("pressure").clamp(0, crate::input::PEN_PRESSURE_MAX as _) as _,
+        tilt_x: get("tilt_x") as _,
+        tilt_y: get("tilt_y") as _,
+        tool: tool.into(),
+        buttons: get("buttons") as _,
+        in_range: v.get("in_range").and_then(|x| x.as_bool()).unwrap_or(true),
+        ..Default::default()
+    };
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.send_pen_event(pen, alt, ctrl, shift, command);
+    }
+}
+
 pub fn session_send_pointer(session_id: SessionID, msg: String) {
     if let Ok(m) = serde_json::from_str::<HashMap<String, serde_json::Value>>(&msg) {
         let alt = m.get("alt").is_some();
@@ -1918,6 +2123,7 @@ pub fn session_send_pointer(session_id: SessionID, msg: String) {
         match (m.get("k"), m.get("v")) {
             (Some(k), Some(v)) => match k.as_str() {
                 Some("touch") => session_send_touch_event(session_id, v, alt, ctrl, shift, command),
+                Some("pen") => session_send_pen_event(session_id, v, alt, ctrl, shift, command),
                 _ => {}
             },
             _ => {}
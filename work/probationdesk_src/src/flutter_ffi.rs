@@ -273,6 +273,45 @@ pub fn session_take_screenshot(session_id: SessionID, display: usize) {
     }
 }
 
+/// `options` is a json object, see `screenshot::parse_request`. Returns the error or empty.
+pub fn session_take_screenshot_with_options(
+    session_id: SessionID,
+    display: usize,
+    options: String,
+) -> String {
+    if let Some(s) = sessions::get_session_by_session_id(&session_id) {
+        if let Err(e) =
+            s.take_screenshot_with_options(display as _, session_id.to_string(), &options)
+        {
+            return e.to_string();
+        }
+    }
+    "".to_owned()
+}
+
+pub fn session_start_screenshot_interval(
+    session_id: SessionID,
+    display: usize,
+    options: String,
+    interval_secs: u32,
+    folder: String,
+) -> String {
+    if let Some(s) = sessions::get_session_by_session_id(&session_id) {
+        if let Err(e) =
+            s.start_screenshot_interval(display as _, &options, interval_secs as _, folder)
+        {
+            return e.to_string();
+        }
+    }
+    "".to_owned()
+}
+
+pub fn session_stop_screenshot_interval(session_id: SessionID) {
+    if let Some(s) = sessions::get_session_by_session_id(&session_id) {
+        s.stop_screenshot_interval();
+    }
+}
+
 pub fn session_handle_screenshot(#[allow(unused_variables)] session_id: SessionID, action: String) -> String {
     crate::client::screenshot::handle_screenshot(action)
 }
@@ -628,6 +667,46 @@ pub fn session_send_chat(session_id: SessionID, text: String) {
     }
 }
 
+// The record or {"error": ".."}
+pub fn session_send_chat_with_attachments(
+    session_id: SessionID,
+    text: String,
+    paths: Vec<String>,
+) -> String {
+    let Some(session) = sessions::get_session_by_session_id(&session_id) else {
+        return serde_json::json!({ "error": "Session not found" }).to_string();
+    };
+    chat_record_result(session.send_chat_with_attachments(text, &paths))
+}
+
+pub fn session_send_chat_read(session_id: SessionID, msg_ids: Vec<String>) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.send_chat_receipt(msg_ids, crate::chat_history::Receipt::Read);
+    }
+}
+
+fn chat_record_result(res: ResultType<crate::chat_history::ChatRecord>) -> String {
+    match res {
+        Ok(record) => serde_json::to_string(&record).unwrap_or_default(),
+        Err(e) => serde_json::json!({ "error": e.to_string() }).to_string(),
+    }
+}
+
+pub fn main_load_chat_history(id: String) -> String {
+    serde_json::to_string(&crate::chat_history::load(&id)).unwrap_or_default()
+}
+
+// {"data": ".."} or {"error": ".."}
+pub fn main_export_chat_history(id: String, format: String) -> String {
+    let res = format
+        .parse()
+        .and_then(|format| crate::chat_history::export(&id, format));
+    match res {
+        Ok(data) => serde_json::json!({ "data": data }).to_string(),
+        Err(e) => serde_json::json!({ "error": e.to_string() }).to_string(),
+    }
+}
+
 // Terminal functions
 pub fn session_open_terminal(session_id: SessionID, terminal_id: i32, rows: u32, cols: u32) {
     if let Some(session) = sessions::get_session_by_session_id(&session_id) {
@@ -652,6 +731,36 @@ pub fn session_resize_terminal(session_id: SessionID, terminal_id: i32, rows: u3
     }
 }
 
+// args: json array, empty to run `command` through the shell. env: json object.
+pub fn session_exec_command(
+    session_id: SessionID,
+    exec_id: i32,
+    command: String,
+    args: String,
+    env: String,
+    working_dir: String,
+    timeout_secs: u32,
+    run_as: String,
+) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.exec_command(
+            exec_id,
+            command,
+            serde_json::from_str(&args).unwrap_or_default(),
+            serde_json::from_str(&env).unwrap_or_default(),
+            working_dir,
+            timeout_secs,
+            run_as,
+        );
+    }
+}
+
+pub fn session_cancel_exec(session_id: SessionID, exec_id: i32) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.cancel_exec(exec_id);
+    }
+}
+
 pub fn session_close_terminal(session_id: SessionID, terminal_id: i32) {
     if let Some(session) = sessions::get_session_by_session_id(&session_id) {
         session.close_terminal(terminal_id);
@@ -1232,6 +1341,15 @@ pub fn main_forget_password(id: String) {
     forget_password(id)
 }
 
+pub fn main_retrust_peer_key(id: String) {
+    retrust_peer_key(id)
+}
+
+/// The diagnostics of connecting to the peer, in json.
+pub fn main_diagnose_connection(id: String) -> String {
+    serde_json::to_string(&crate::diagnostics::run_sync(&id)).unwrap_or_default()
+}
+
 pub fn main_peer_has_password(id: String) -> bool {
     peer_has_password(id)
 }
@@ -1537,6 +1655,14 @@ pub fn cm_close_voice_call(id: i32) {
     crate::ui_cm_interface::close_voice_call(id);
 }
 
+pub fn cm_set_control_token(id: i32, has_control: bool) {
+    crate::ui_cm_interface::set_control_token(id, has_control);
+}
+
+pub fn cm_set_bandwidth_limit(id: i32, upload: u32, download: u32) {
+    crate::ui_cm_interface::set_bandwidth_limit(id, upload, download);
+}
+
 pub fn set_voice_call_input_device(_is_cm: bool, _device: String) {
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
     if _is_cm {
@@ -1677,6 +1803,124 @@ pub fn main_load_ab() -> String {
     serde_json::to_string(&config::Ab::load()).unwrap_or_default()
 }
 
+fn local_ab_result(res: ResultType<()>) -> String {
+    match res {
+        Ok(()) => "".to_owned(),
+        Err(e) => e.to_string(),
+    }
+}
+
+// Runs in the background, pushes `batch_result` for each peer and `batch_done` with all results.
+pub fn main_run_batch(job: String) -> String {
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    {
+        let job: crate::batch::Job = match serde_json::from_str(&job) {
+            Ok(job) => job,
+            Err(e) => return e.to_string(),
+        };
+        std::thread::spawn(move || {
+            let results = crate::batch::run_sync(job, |res| {
+                let data = HashMap::from([
+                    ("name", "batch_result".to_owned()),
+                    ("result", serde_json::to_string(res).unwrap_or_default()),
+                ]);
+                let _res = flutter::push_global_event(
+                    flutter::APP_TYPE_MAIN,
+                    serde_json::ser::to_string(&data).unwrap_or("".to_owned()),
+                );
+            });
+            let data = HashMap::from([
+                ("name", "batch_done".to_owned()),
+                ("results", serde_json::to_string(&results).unwrap_or_default()),
+            ]);
+            let _res = flutter::push_global_event(
+                flutter::APP_TYPE_MAIN,
+                serde_json::ser::to_string(&data).unwrap_or("".to_owned()),
+            );
+        });
+        "".to_owned()
+    }
+    #[cfg(any(target_os = "android", target_os = "ios"))]
+    {
+        let _ = job;
+        "Not supported".to_owned()
+    }
+}
+
+pub fn main_load_local_ab() -> String {
+    serde_json::to_string(&crate::address_book::load()).unwrap_or_default()
+}
+
+pub fn main_local_ab_add_peer(peer: String) -> String {
+    local_ab_result(
+        serde_json::from_str(&peer)
+            .map_err(|e| e.into())
+            .and_then(crate::address_book::add_peer),
+    )
+}
+
+pub fn main_local_ab_update_peer(peer: String) -> String {
+    local_ab_result(
+        serde_json::from_str(&peer)
+            .map_err(|e| e.into())
+            .and_then(crate::address_book::update_peer),
+    )
+}
+
+pub fn main_local_ab_remove_peers(ids: String) -> String {
+    let ids: Vec<String> = serde_json::from_str(&ids).unwrap_or_default();
+    local_ab_result(crate::address_book::remove_peers(&ids))
+}
+
+pub fn main_local_ab_add_tag(name: String) -> String {
+    local_ab_result(crate::address_book::add_tag(&name))
+}
+
+pub fn main_local_ab_rename_tag(old: String, new: String) -> String {
+    local_ab_result(crate::address_book::rename_tag(&old, &new))
+}
+
+pub fn main_local_ab_remove_tags(names: String) -> String {
+    let names: Vec<String> = serde_json::from_str(&names).unwrap_or_default();
+    local_ab_result(crate::address_book::remove_tags(&names))
+}
+
+pub fn main_local_ab_set_tag_color(name: String, color: i64) -> String {
+    local_ab_result(crate::address_book::set_tag_color(&name, color))
+}
+
+// {"count": n} or {"error": ".."}
+pub fn main_local_ab_import(data: String, format: String) -> String {
+    let res = format
+        .parse()
+        .and_then(|format| crate::address_book::import(&data, format));
+    match res {
+        Ok(count) => serde_json::json!({ "count": count }).to_string(),
+        Err(e) => serde_json::json!({ "error": e.to_string() }).to_string(),
+    }
+}
+
+// {"data": ".."} or {"error": ".."}
+pub fn main_local_ab_export(format: String) -> String {
+    let res = format.parse().and_then(crate::address_book::export);
+    match res {
+        Ok(data) => serde_json::json!({ "data": data }).to_string(),
+        Err(e) => serde_json::json!({ "error": e.to_string() }).to_string(),
+    }
+}
+
+// The sync report or {"error": ".."}
+pub fn main_local_ab_sync() -> String {
+    match crate::address_book::sync() {
+        Ok(report) => serde_json::to_string(&report).unwrap_or_default(),
+        Err(e) => serde_json::json!({ "error": e.to_string() }).to_string(),
+    }
+}
+
+pub fn main_local_ab_resolve_conflict(id: String, keep_local: bool) -> String {
+    local_ab_result(crate::address_book::resolve_conflict(&id, keep_local))
+}
+
 pub fn main_save_group(json: String) {
     if json.len() > 1024 {
         std::thread::spawn(|| {
@@ -1770,6 +2014,130 @@ pub fn session_alternative_codecs(session_id: SessionID) -> String {
     }
 }
 
+// sources: [{"id": "...", "gain": 1.0}], empty to capture the default audio source
+pub fn session_select_audio_sources(session_id: SessionID, sources: String) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        let sources: Vec<HashMap<String, serde_json::Value>> =
+            serde_json::from_str(&sources).unwrap_or_default();
+        session.select_audio_sources(
+            sources
+                .iter()
+                .filter_map(|s| {
+                    let id = s.get("id")?.as_str()?.to_owned();
+                    let gain = s.get("gain").and_then(|g| g.as_f64()).unwrap_or(1.);
+                    Some((id, gain as f32))
+                })
+                .collect(),
+        );
+    }
+}
+
+// action: "request", "release", "grant" or "deny", conn_id is the requester for "grant" and "deny".
+pub fn session_control_token_action(session_id: SessionID, action: String, conn_id: i32) {
+    use hbb_common::message_proto::control_token_action::Type;
+    let action = match action.as_str() {
+        "request" => Type::Request,
+        "release" => Type::Release,
+        "grant" => Type::Grant,
+        "deny" => Type::Deny,
+        _ => return,
+    };
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.control_token_action(action, conn_id);
+    }
+}
+
+pub fn session_request_inventory(session_id: SessionID, include_packages: bool) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.request_inventory(include_packages);
+    }
+}
+
+// format: "json" or "csv", returns the error or an empty string.
+pub fn main_export_inventory(inventory: String, path: String, format: String) -> String {
+    let data = if format == "csv" {
+        match crate::common::inventory_json_to_csv(&inventory) {
+            Ok(data) => data,
+            Err(e) => return e.to_string(),
+        }
+    } else {
+        inventory
+    };
+    match std::fs::write(&path, data) {
+        Ok(_) => "".to_owned(),
+        Err(e) => e.to_string(),
+    }
+}
+
+pub fn session_start_input_macro_recording(session_id: SessionID) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.start_input_macro_recording();
+    }
+}
+
+// Returns the error, empty if the macro is saved.
+pub fn session_stop_input_macro_recording(session_id: SessionID, name: String) -> String {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        if let Err(e) = session.stop_input_macro_recording(name) {
+            return e.to_string();
+        }
+    }
+    "".to_owned()
+}
+
+pub fn session_insert_input_macro_text(session_id: SessionID, text: String) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.insert_input_macro_text(text);
+    }
+}
+
+// Replay on the session, or on all remote control sessions if `all_sessions` is true.
+pub fn session_replay_input_macro(
+    session_id: SessionID,
+    name: String,
+    speed: f64,
+    variables: String,
+    all_sessions: bool,
+) {
+    let variables: HashMap<String, String> = serde_json::from_str(&variables).unwrap_or_default();
+    if all_sessions {
+        for session in sessions::get_sessions() {
+            if session.is_default() {
+                session.replay_input_macro(name.clone(), speed, variables.clone());
+            }
+        }
+    } else if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.replay_input_macro(name, speed, variables);
+    }
+}
+
+pub fn session_abort_input_macro(session_id: SessionID) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.abort_input_macro();
+    }
+}
+
+pub fn main_get_input_macros() -> String {
+    let macros: Vec<HashMap<&str, serde_json::Value>> = config::InputMacro::list()
+        .into_iter()
+        .map(|m| {
+            HashMap::from([
+                (
+                    "variables",
+                    crate::client::input_macro::get_variables(&m.steps).into(),
+                ),
+                ("steps", m.steps.len().into()),
+                ("name", m.name.into()),
+            ])
+        })
+        .collect();
+    serde_json::ser::to_string(&macros).unwrap_or("".to_owned())
+}
+
+pub fn main_remove_input_macro(name: String) {
+    config::InputMacro::remove(&name);
+}
+
 pub fn session_change_prefer_codec(session_id: SessionID) {
     if let Some(session) = sessions::get_session_by_session_id(&session_id) {
         session.update_supported_decodings();
@@ -1884,6 +2252,26 @@ pub fn cm_send_chat(conn_id: i32, msg: String) {
     crate::ui_cm_interface::send_chat(conn_id, msg);
 }
 
+// The record or {"error": ".."}
+pub fn cm_send_chat_with_attachments(conn_id: i32, msg: String, paths: Vec<String>) -> String {
+    #[cfg(not(any(target_os = "ios")))]
+    {
+        chat_record_result(crate::ui_cm_interface::send_chat_with_attachments(
+            conn_id, msg, &paths,
+        ))
+    }
+    #[cfg(any(target_os = "ios"))]
+    {
+        let _ = (conn_id, msg, paths);
+        "".to_owned()
+    }
+}
+
+pub fn cm_send_chat_read(conn_id: i32, msg_ids: Vec<String>) {
+    #[cfg(not(any(target_os = "ios")))]
+    crate::ui_cm_interface::send_chat_receipt(conn_id, msg_ids, true);
+}
+
 pub fn cm_login_res(conn_id: i32, res: bool) {
     #[cfg(not(any(target_os = "ios")))]
     if res {
@@ -75,6 +75,15 @@ pub enum FS {
         total_size: u64,
         conn_id: i32,
     },
+    // An attachment of the chat message `msg_id`, written to the chat directory.
+    NewChatWrite {
+        id: i32,
+        msg_id: String,
+        name: String,
+        files: Vec<(String, u64)>,
+        total_size: u64,
+        conn_id: i32,
+    },
     CancelWrite {
         id: i32,
     },
@@ -160,6 +169,19 @@ pub enum DataMouse {
     Refresh,
 }
 
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct DataPen {
+    pub x: i32,
+    pub y: i32,
+    pub pressure: u32,
+    pub tilt_x: i32,
+    pub tilt_y: i32,
+    pub buttons: u32,
+    pub eraser: bool,
+    pub in_range: bool,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(tag = "t", content = "c")]
 pub enum DataControl {
@@ -205,10 +227,22 @@ pub enum Data {
         restart: bool,
         recording: bool,
         block_input: bool,
+        inventory: bool,
         from_switch: bool,
     },
     ChatMessage {
         text: String,
+        #[serde(default)]
+        msg_id: String,
+        #[serde(default)]
+        timestamp: i64,
+        // The names of the ones of the peer, the local paths of the ones to the peer.
+        #[serde(default)]
+        attachments: Vec<String>,
+    },
+    ChatReceipt {
+        msg_ids: Vec<String>,
+        read: bool,
     },
     SwitchPermission {
         name: String,
@@ -246,6 +280,8 @@ pub enum Data {
     KeyboardResponse(DataKeyboardResponse),
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
     Mouse(DataMouse),
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    Pen(DataPen),
     Control(DataControl),
     Theme(String),
     Language(String),
@@ -259,6 +295,18 @@ pub enum Data {
     StartVoiceCall,
     VoiceCallResponse(bool),
     CloseVoiceCall(String),
+    // Connection -> cm: the state of the connection, cm -> connection: grant or revoke.
+    ControlToken {
+        has_control: bool,
+        requested: bool,
+    },
+    // Connection -> cm, the bytes sent and received.
+    Traffic(crate::server::bandwidth::TrafficStats),
+    // Cm -> connection, the caps of the connection in KB/s, 0 for the global ones only.
+    BandwidthLimit {
+        upload: u32,
+        download: u32,
+    },
     #[cfg(all(feature = "flutter", feature = "plugin_framework"))]
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
     Plugin(Plugin),
@@ -768,6 +816,7 @@ pub async fn connect(ms_timeout: u64, postfix: &str) -> ResultType<ConnectionTmp
 pub async fn start_pa() {
     use crate::audio_service::AUDIO_DATA_SIZE_U8;
 
+    std::thread::spawn(start_pa_sources);
     match new_listener("_pa").await {
         Ok(mut incoming) => {
             loop {
@@ -776,10 +825,22 @@ pub async fn start_pa() {
                         Ok(stream) => {
                             let mut stream = Connection::new(stream);
                             let mut device: String = "".to_owned();
-                            if let Some(Ok(Some(Data::Config((_, Some(x)))))) =
-                                stream.next_timeout2(1000).await
-                            {
-                                device = x;
+                            let mut sources: Vec<(String, f32)> = vec![];
+                            match stream.next_timeout2(1000).await {
+                                Some(Ok(Some(Data::Config((name, Some(x))))))
+                                    if name == crate::audio_service::CONFIG_AUDIO_SOURCES =>
+                                {
+                                    sources = serde_json::from_str(&x).unwrap_or_default();
+                                }
+                                Some(Ok(Some(Data::Config((_, Some(x)))))) => {
+                                    device = x;
+                                }
+                                _ => {}
+                            }
+                            if !sources.is_empty() {
+                                if pa_send_pipewire_sources(&mut stream, &sources).await {
+                                    continue;
+                                }
                             }
                             if !device.is_empty() {
                                 device = crate::platform::linux::get_pa_source_name(&device);
@@ -840,6 +901,72 @@ pub async fn start_pa() {
     }
 }
 
+// Returns false if the sources can't be captured, then the default device is used.
+#[cfg(target_os = "linux")]
+async fn pa_send_pipewire_sources(stream: &mut Connection, sources: &[(String, f32)]) -> bool {
+    if !crate::platform::linux_pipewire::is_available() {
+        log::warn!("pipewire tools are not available, fall back to pulseaudio");
+        return false;
+    }
+    let mut mixer = match crate::platform::linux_pipewire::Mixer::new(sources) {
+        Ok(mixer) => mixer,
+        Err(err) => {
+            log::error!("Failed to capture pipewire sources: {}", err);
+            return false;
+        }
+    };
+    while let Ok(frame) = mixer.read() {
+        let out: Vec<u8> = if frame.iter().all(|x| *x == 0.) {
+            vec![]
+        } else {
+            frame.iter().flat_map(|x| x.to_le_bytes()).collect()
+        };
+        if let Err(err) = stream.send_raw(out.into()).await {
+            log::error!("Failed to send audio data:{}", err);
+            break;
+        }
+    }
+    true
+}
+
+// Answer the audio sources query of the server, separated from `_pa` which is busy sending audio data.
+#[cfg(target_os = "linux")]
+#[tokio::main(flavor = "current_thread")]
+async fn start_pa_sources() {
+    use hbb_common::{message_proto::AudioSources, protobuf::Message as _};
+
+    match new_listener("_pa_sources").await {
+        Ok(mut incoming) => loop {
+            if let Some(result) = incoming.next().await {
+                match result {
+                    Ok(stream) => {
+                        let mut stream = Connection::new(stream);
+                        if let Some(Ok(Some(Data::Config((name, None))))) =
+                            stream.next_timeout2(1000).await
+                        {
+                            if name == crate::audio_service::CONFIG_AUDIO_SOURCES {
+                                let sources = AudioSources {
+                                    sources: crate::platform::linux_pipewire::get_sources(),
+                                    ..Default::default()
+                                };
+                                if let Ok(bytes) = sources.write_to_bytes() {
+                                    allow_err!(stream.send_raw(bytes.into()).await);
+                                }
+                            }
+                        }
+                    }
+                    Err(err) => {
+                        log::error!("Couldn't get pa sources client: {:?}", err);
+                    }
+                }
+            }
+        },
+        Err(err) => {
+            log::error!("Failed to start pa sources ipc server: {}", err);
+        }
+    }
+}
+
 #[inline]
 #[cfg(not(windows))]
 fn get_pid_file(postfix: &str) -> String {
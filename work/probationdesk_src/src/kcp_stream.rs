@@ -149,3 +149,33 @@ impl Drop for KcpStream {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hbb_common::netsim::{udp_pair, NetConditions};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_lossy_link() {
+        let (a, b, link) = udp_pair(NetConditions::mobile()).await.unwrap();
+        let timeout = Duration::from_secs(5);
+        let (accepted, connected) = tokio::join!(
+            KcpStream::accept(Arc::new(a), timeout, None),
+            KcpStream::connect(Arc::new(b), timeout)
+        );
+        let (_a, mut a) = accepted.unwrap();
+        let (_b, mut b) = connected.unwrap();
+        for i in 0..100u8 {
+            if i == 50 {
+                link.outage(Duration::from_millis(500));
+            }
+            b.send_bytes(Bytes::from(vec![i; 1000])).await.unwrap();
+        }
+        // Nothing is lost or reordered by kcp, and the short outage is survived.
+        for i in 0..100u8 {
+            let msg = a.next_timeout(10_000).await.unwrap().unwrap();
+            assert_eq!(msg[0], i);
+        }
+    }
+}
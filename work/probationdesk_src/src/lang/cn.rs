@@ -710,5 +710,56 @@ pub static ref T: std::collections::HashMap<&'static str, &'static str> =
         ("elevation_username_tip", "输入用户名或域名\\用户名"),
         ("Preparing for installation ...", "准备安装..."),
         ("Show my cursor", "显示我的光标"),
+        ("Lossless text", "无损文字"),
+        ("Audio source", "音频源"),
+        ("Start macro recording", "开始录制宏"),
+        ("Stop macro recording", "停止录制宏"),
+        ("Insert macro text", "插入宏文本"),
+        ("Replay macro", "回放宏"),
+        ("Save macro", "保存宏"),
+        ("Save", "保存"),
+        ("Discard", "放弃"),
+        ("Text", "文本"),
+        ("No macros", "没有宏"),
+        ("Empty macro", "空宏"),
+        ("Aborted", "已中止"),
+        ("Replay on all sessions", "在所有会话中回放"),
+        ("input_macro_variable_tip", "回放时 ${名称} 会被替换为变量的值。"),
+        ("input_macro_abort_tip", "任何输入都会中止回放。"),
+        ("Enable inventory query", "允许查询系统清单"),
+        ("System inventory", "系统清单"),
+        ("Hostname", "主机名"),
+        ("Memory", "内存"),
+        ("User", "用户"),
+        ("Packages", "软件包"),
+        ("Export", "导出"),
+        ("Request control", "请求控制权"),
+        ("Release control", "释放控制权"),
+        ("Cancel control request", "取消控制权请求"),
+        ("Grant control", "授予控制权"),
+        ("Grant requested control", "授予请求的控制权"),
+        ("Revoke control", "收回控制权"),
+        ("You have the control", "您已获得控制权"),
+        ("View only", "仅查看"),
+        ("Grant", "授予"),
+        ("Deny", "拒绝"),
+        ("control-request-tip", "{} 请求控制权"),
+        ("Attach files", "添加附件"),
+        ("Export chat history", "导出聊天记录"),
+        ("Bandwidth limit", "带宽限制"),
+        ("Upload", "上传"),
+        ("Unlimited", "不限"),
+        ("bandwidth_limit_tip", "限制传入连接的带宽，视频、文件传输、剪贴板和端口转发公平共享。留空表示不限。"),
+        ("Peer key changed", "对方密钥已变更"),
+        ("peer_key_changed_tip", "对方的签名密钥与首次连接时记录的不同，可能是对方重新安装了软件，也可能是连接被劫持。请通过其他途径与对方确认后，再信任新密钥。"),
+        ("Trust new key", "信任新密钥"),
+        ("Require encryption", "要求加密连接"),
+        ("Encrypted connection required", "需要加密连接"),
+        ("Use system proxy", "使用系统代理"),
+        ("Embedded ID/relay server", "内置 ID/中继服务器"),
+        ("embedded_server_tip", "本设备同时作为所在网络的 ID 和中继服务器，使用默认端口。其他设备将其地址设为 ID 服务器，并使用点击此处复制的 Key。"),
+        ("Direct IP access over TLS", "通过 TLS 直接 IP 访问"),
+        ("Wrong access token", "访问令牌错误"),
+        ("Access token", "访问令牌"),
     ].iter().cloned().collect();
 }
@@ -710,5 +710,56 @@ pub static ref T: std::collections::HashMap<&'static str, &'static str> =
         ("elevation_username_tip", "Geben Sie Benutzername oder Domäne\\Benutzername ein"),
         ("Preparing for installation ...", "Installation wird vorbereitet …"),
         ("Show my cursor", "Meinen Cursor anzeigen"),
+        ("Lossless text", ""),
+        ("Audio source", ""),
+        ("Start macro recording", ""),
+        ("Stop macro recording", ""),
+        ("Insert macro text", ""),
+        ("Replay macro", ""),
+        ("Save macro", ""),
+        ("Save", ""),
+        ("Discard", ""),
+        ("Text", ""),
+        ("No macros", ""),
+        ("Empty macro", ""),
+        ("Aborted", ""),
+        ("Replay on all sessions", ""),
+        ("input_macro_variable_tip", ""),
+        ("input_macro_abort_tip", ""),
+        ("Enable inventory query", ""),
+        ("System inventory", ""),
+        ("Hostname", ""),
+        ("Memory", ""),
+        ("User", ""),
+        ("Packages", ""),
+        ("Export", ""),
+        ("Request control", ""),
+        ("Release control", ""),
+        ("Cancel control request", ""),
+        ("Grant control", ""),
+        ("Grant requested control", ""),
+        ("Revoke control", ""),
+        ("You have the control", ""),
+        ("View only", ""),
+        ("Grant", ""),
+        ("Deny", ""),
+        ("control-request-tip", ""),
+        ("Attach files", ""),
+        ("Export chat history", ""),
+        ("Bandwidth limit", ""),
+        ("Upload", ""),
+        ("Unlimited", ""),
+        ("bandwidth_limit_tip", ""),
+        ("Peer key changed", ""),
+        ("peer_key_changed_tip", ""),
+        ("Trust new key", ""),
+        ("Require encryption", ""),
+        ("Encrypted connection required", ""),
+        ("Use system proxy", "Systemproxy verwenden"),
+        ("Embedded ID/relay server", "Integrierter ID/Relay-Server"),
+        ("embedded_server_tip", "Dieses Gerät dient auch als ID- und Relay-Server seines Netzwerks auf den Standardports. Die anderen Geräte tragen seine Adresse als ID-Server ein und den Schlüssel, der durch Klicken hier kopiert wird."),
+        ("Direct IP access over TLS", "Direkter IP-Zugang über TLS"),
+        ("Wrong access token", "Falsches Zugangs-Token"),
+        ("Access token", ""),
     ].iter().cloned().collect();
 }
@@ -258,5 +258,11 @@ pub static ref T: std::collections::HashMap<&'static str, &'static str> =
         ("websocket_tip", "When using WebSocket, only relay connections are supported."),
         ("terminal-admin-login-tip", "Please input the administrator username and password of the controlled side."),
         ("elevation_username_tip", "Input username or domain\\username"),
+        ("input_macro_variable_tip", "${name} is replaced by the value of the variable name on replay."),
+        ("input_macro_abort_tip", "Any input aborts the replay."),
+        ("control-request-tip", "{} requests the control."),
+        ("bandwidth_limit_tip", "Caps the bandwidth of the incoming connections, shared fairly by video, file transfer, clipboard and port forwarding. Empty for unlimited."),
+        ("peer_key_changed_tip", "The signing key of the remote device differs from the one trusted on the first connection. The software may have been reinstalled, or the connection may be intercepted. Confirm it with the remote side by other means before trusting the new key."),
+        ("embedded_server_tip", "This device also serves as the ID and relay server of its network, on the default ports. The other devices set its address as the ID server, and the key copied by clicking here."),
     ].iter().cloned().collect();
 }
@@ -710,5 +710,56 @@ pub static ref T: std::collections::HashMap<&'static str, &'static str> =
         ("elevation_username_tip", ""),
         ("Preparing for installation ...", ""),
         ("Show my cursor", ""),
+        ("Lossless text", ""),
+        ("Audio source", ""),
+        ("Start macro recording", ""),
+        ("Stop macro recording", ""),
+        ("Insert macro text", ""),
+        ("Replay macro", ""),
+        ("Save macro", ""),
+        ("Save", ""),
+        ("Discard", ""),
+        ("Text", ""),
+        ("No macros", ""),
+        ("Empty macro", ""),
+        ("Aborted", ""),
+        ("Replay on all sessions", ""),
+        ("input_macro_variable_tip", ""),
+        ("input_macro_abort_tip", ""),
+        ("Enable inventory query", ""),
+        ("System inventory", ""),
+        ("Hostname", ""),
+        ("Memory", ""),
+        ("User", ""),
+        ("Packages", ""),
+        ("Export", ""),
+        ("Request control", ""),
+        ("Release control", ""),
+        ("Cancel control request", ""),
+        ("Grant control", ""),
+        ("Grant requested control", ""),
+        ("Revoke control", ""),
+        ("You have the control", ""),
+        ("View only", ""),
+        ("Grant", ""),
+        ("Deny", ""),
+        ("control-request-tip", ""),
+        ("Attach files", ""),
+        ("Export chat history", ""),
+        ("Bandwidth limit", ""),
+        ("Upload", ""),
+        ("Unlimited", ""),
+        ("bandwidth_limit_tip", ""),
+        ("Peer key changed", ""),
+        ("peer_key_changed_tip", ""),
+        ("Trust new key", ""),
+        ("Require encryption", ""),
+        ("Encrypted connection required", ""),
+        ("Use system proxy", ""),
+        ("Embedded ID/relay server", ""),
+        ("embedded_server_tip", ""),
+        ("Direct IP access over TLS", ""),
+        ("Wrong access token", ""),
+        ("Access token", ""),
     ].iter().cloned().collect();
 }
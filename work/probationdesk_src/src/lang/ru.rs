@@ -710,5 +710,56 @@ pub static ref T: std::collections::HashMap<&'static str, &'static str> =
         ("elevation_username_tip", "Введите пользователя или домен\\пользователя"),
         ("Preparing for installation ...", "Подготовка к установке..."),
         ("Show my cursor", "Показывать мой курсор"),
+        ("Lossless text", "Текст без потерь"),
+        ("Audio source", "Источник звука"),
+        ("Start macro recording", "Начать запись макроса"),
+        ("Stop macro recording", "Остановить запись макроса"),
+        ("Insert macro text", "Вставить текст в макрос"),
+        ("Replay macro", "Воспроизвести макрос"),
+        ("Save macro", "Сохранить макрос"),
+        ("Save", "Сохранить"),
+        ("Discard", "Отменить"),
+        ("Text", "Текст"),
+        ("No macros", "Нет макросов"),
+        ("Empty macro", "Пустой макрос"),
+        ("Aborted", "Прервано"),
+        ("Replay on all sessions", "Воспроизвести во всех сеансах"),
+        ("input_macro_variable_tip", "${имя} заменяется значением переменной при воспроизведении."),
+        ("input_macro_abort_tip", "Любой ввод прерывает воспроизведение."),
+        ("Enable inventory query", "Разрешить запрос сведений о системе"),
+        ("System inventory", "Сведения о системе"),
+        ("Hostname", "Имя хоста"),
+        ("Memory", "Память"),
+        ("User", "Пользователь"),
+        ("Packages", "Пакеты"),
+        ("Export", "Экспорт"),
+        ("Request control", "Запросить управление"),
+        ("Release control", "Отдать управление"),
+        ("Cancel control request", "Отменить запрос управления"),
+        ("Grant control", "Передать управление"),
+        ("Grant requested control", "Передать запрошенное управление"),
+        ("Revoke control", "Забрать управление"),
+        ("You have the control", "Управление у вас"),
+        ("View only", "Только просмотр"),
+        ("Grant", "Передать"),
+        ("Deny", "Отклонить"),
+        ("control-request-tip", "{} запрашивает управление"),
+        ("Attach files", "Прикрепить файлы"),
+        ("Export chat history", "Экспорт истории чата"),
+        ("Bandwidth limit", "Ограничение полосы пропускания"),
+        ("Upload", "Отдача"),
+        ("Unlimited", "Без ограничений"),
+        ("bandwidth_limit_tip", "Ограничивает полосу пропускания входящих подключений, её поровну делят видео, передача файлов, буфер обмена и проброс портов. Пусто — без ограничений."),
+        ("Peer key changed", "Ключ удалённого устройства изменился"),
+        ("peer_key_changed_tip", "Ключ подписи удалённого устройства отличается от сохранённого при первом подключении. Возможно, программа была переустановлена, или соединение перехвачено. Подтвердите это другим способом, прежде чем доверять новому ключу."),
+        ("Trust new key", "Доверять новому ключу"),
+        ("Require encryption", "Требовать шифрование"),
+        ("Encrypted connection required", "Требуется зашифрованное соединение"),
+        ("Use system proxy", "Использовать системный прокси"),
+        ("Embedded ID/relay server", "Встроенный ID/ретранслятор-сервер"),
+        ("embedded_server_tip", "Это устройство также служит ID-сервером и ретранслятором своей сети на стандартных портах. Другие устройства указывают его адрес как ID-сервер и ключ, скопированный нажатием здесь."),
+        ("Direct IP access over TLS", "Прямой IP-доступ через TLS"),
+        ("Wrong access token", "Неверный токен доступа"),
+        ("Access token", ""),
     ].iter().cloned().collect();
 }
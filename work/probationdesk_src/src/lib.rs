@@ -36,7 +36,13 @@ pub mod flutter;
 #[cfg(any(target_os = "android", target_os = "ios", feature = "flutter"))]
 pub mod flutter_ffi;
 use common::*;
+#[cfg(feature = "flutter")]
+mod address_book;
 mod auth_2fa;
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+mod batch;
+mod chat_history;
+mod diagnostics;
 #[cfg(feature = "cli")]
 pub mod cli;
 #[cfg(not(target_os = "ios"))]
@@ -61,6 +67,9 @@ mod whiteboard;
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 mod updater;
 
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub mod embedded_server;
+
 mod ui_cm_interface;
 mod ui_interface;
 mod ui_session_interface;
@@ -266,6 +266,9 @@ fn start_uinput_service() {
     std::thread::spawn(|| {
         service::start_service_mouse();
     });
+    std::thread::spawn(|| {
+        service::start_service_pen();
+    });
 }
 
 /// Suggests the best terminal type based on the environment.
@@ -1432,7 +1435,7 @@ impl WakeLock {
     }
 }
 
-fn has_cmd(cmd: &str) -> bool {
+pub fn has_cmd(cmd: &str) -> bool {
     std::process::Command::new("which")
         .arg(cmd)
         .status()
@@ -0,0 +1,266 @@
+// Audio capture of PipeWire nodes with the command line tools shipped with PipeWire.
+//
+// `pw-dump` lists the nodes, `pw-record` records one node, which can be the playback
+// stream of an application, an input device, or an output device (its monitor).
+// Several nodes are mixed with per-source gain into the same 10 ms f32 stereo frames
+// as the PulseAudio capture, so `start_pa` can send them the same way.
+
+use super::linux::has_cmd;
+use crate::audio_service::AUDIO_DATA_SIZE_U8;
+use hbb_common::{
+    bail, log,
+    message_proto::{audio_source::Kind, AudioSource},
+    serde_json::{self, Value},
+    ResultType,
+};
+use std::{
+    collections::VecDeque,
+    io::Read,
+    process::{Child, Command, Stdio},
+    sync::{
+        mpsc::{sync_channel, Receiver},
+        Arc, Mutex,
+    },
+};
+
+const FRAME_LEN: usize = AUDIO_DATA_SIZE_U8 / 4;
+// Bound the latency of the sources which are not the clock of the mixer.
+const MAX_BUFFERED_SAMPLES: usize = FRAME_LEN * 5;
+
+#[inline]
+pub fn is_available() -> bool {
+    has_cmd("pw-dump") && has_cmd("pw-record")
+}
+
+pub fn get_sources() -> Vec<AudioSource> {
+    match Command::new("pw-dump").output() {
+        Ok(output) if output.status.success() => {
+            parse_sources(&String::from_utf8_lossy(&output.stdout))
+        }
+        Ok(output) => {
+            log::error!(
+                "pw-dump failed: {}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+            vec![]
+        }
+        Err(err) => {
+            log::error!("Failed to run pw-dump: {}", err);
+            vec![]
+        }
+    }
+}
+
+fn parse_sources(dump: &str) -> Vec<AudioSource> {
+    let Ok(Value::Array(objects)) = serde_json::from_str::<Value>(dump) else {
+        return vec![];
+    };
+    let mut sources = vec![];
+    for o in objects.iter() {
+        if o["type"] != "PipeWire:Interface:Node" {
+            continue;
+        }
+        let props = &o["info"]["props"];
+        let prop = |k: &str| props[k].as_str().unwrap_or_default().to_owned();
+        let kind = match props["media.class"].as_str() {
+            Some("Audio/Sink") => Kind::Output,
+            Some("Audio/Source") => Kind::Input,
+            Some("Stream/Output/Audio") => Kind::Application,
+            _ => continue,
+        };
+        // `target.object` prefers the serial, the id may be reused by a new node.
+        let id = match props["object.serial"].as_u64().or(o["id"].as_u64()) {
+            Some(id) => id.to_string(),
+            None => continue,
+        };
+        let name = if kind == Kind::Application {
+            let app = prop("application.name");
+            let media = prop("media.name");
+            if media.is_empty() || app == media {
+                app
+            } else {
+                format!("{app}: {media}")
+            }
+        } else {
+            prop("node.description")
+        };
+        let name = if name.is_empty() {
+            prop("node.name")
+        } else {
+            name
+        };
+        sources.push(AudioSource {
+            id,
+            name,
+            kind: kind.into(),
+            ..Default::default()
+        });
+    }
+    sources
+}
+
+struct Source {
+    child: Child,
+    gain: f32,
+    buffer: Arc<Mutex<VecDeque<f32>>>,
+}
+
+/// Records the selected nodes and mixes them.
+/// The first source is the clock, `read()` blocks until it has a full frame.
+pub struct Mixer {
+    sources: Vec<Source>,
+    clock: Receiver<Vec<f32>>,
+}
+
+impl Mixer {
+    pub fn new(selected: &[(String, f32)]) -> ResultType<Self> {
+        let nodes = get_sources();
+        let mut sources = vec![];
+        let (tx, clock) = sync_channel(MAX_BUFFERED_SAMPLES / FRAME_LEN);
+        for (i, (id, gain)) in selected.iter().enumerate() {
+            let Some(node) = nodes.iter().find(|n| &n.id == id) else {
+                log::warn!("pipewire node {} not found", id);
+                continue;
+            };
+            let mut cmd = Command::new("pw-record");
+            cmd.args([
+                "--target",
+                id,
+                "--rate",
+                &crate::platform::PA_SAMPLE_RATE.to_string(),
+                "--channels",
+                "2",
+                "--format",
+                "f32",
+            ]);
+            if node.kind.enum_value() == Ok(Kind::Output) {
+                cmd.args(["-P", "{ stream.capture.sink = true }"]);
+            }
+            let mut child = cmd
+                .arg("-")
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()?;
+            let Some(mut stdout) = child.stdout.take() else {
+                child.kill().ok();
+                continue;
+            };
+            log::info!("pipewire record {} ({}), gain: {}", id, node.name, gain);
+            let buffer: Arc<Mutex<VecDeque<f32>>> = Default::default();
+            let buffer_cloned = buffer.clone();
+            let tx = if sources.is_empty() {
+                Some(tx.clone())
+            } else {
+                None
+            };
+            let id = id.clone();
+            std::thread::spawn(move || {
+                let mut buf = vec![0u8; AUDIO_DATA_SIZE_U8];
+                while stdout.read_exact(&mut buf).is_ok() {
+                    let frame: Vec<f32> = buf
+                        .chunks_exact(4)
+                        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                        .collect();
+                    if let Some(tx) = &tx {
+                        if tx.send(frame).is_err() {
+                            break;
+                        }
+                    } else {
+                        let mut lock = buffer_cloned.lock().unwrap();
+                        lock.extend(frame);
+                        let len = lock.len();
+                        if len > MAX_BUFFERED_SAMPLES {
+                            lock.drain(..len - MAX_BUFFERED_SAMPLES);
+                        }
+                    }
+                }
+                log::info!("pipewire record {} (#{}) exit", id, i);
+            });
+            sources.push(Source {
+                child,
+                gain: *gain,
+                buffer,
+            });
+        }
+        if sources.is_empty() {
+            bail!("no pipewire audio source");
+        }
+        Ok(Self { sources, clock })
+    }
+
+    /// Read the next mixed frame of `AUDIO_DATA_SIZE_U8 / 4` samples.
+    pub fn read(&mut self) -> ResultType<Vec<f32>> {
+        let mut out = self.clock.recv()?;
+        mix(&mut out, None, self.sources[0].gain);
+        for source in self.sources.iter().skip(1) {
+            let mut lock = source.buffer.lock().unwrap();
+            let n = lock.len().min(FRAME_LEN);
+            let samples: Vec<f32> = lock.drain(..n).collect();
+            mix(&mut out, Some(&samples), source.gain);
+        }
+        Ok(out)
+    }
+}
+
+impl Drop for Mixer {
+    fn drop(&mut self) {
+        for source in self.sources.iter_mut() {
+            source.child.kill().ok();
+            source.child.wait().ok();
+        }
+    }
+}
+
+// Add `input` scaled by `gain` to `out`, or scale `out` itself if `input` is None.
+fn mix(out: &mut [f32], input: Option<&[f32]>, gain: f32) {
+    match input {
+        Some(input) => {
+            for (o, i) in out.iter_mut().zip(input.iter()) {
+                *o = (*o + i * gain).clamp(-1., 1.);
+            }
+        }
+        None => {
+            for o in out.iter_mut() {
+                *o = (*o * gain).clamp(-1., 1.);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sources() {
+        let dump = r#"[
+            {"id": 30, "type": "PipeWire:Interface:Node", "info": {"props": {
+                "media.class": "Audio/Sink", "node.name": "alsa_output.pci",
+                "node.description": "Built-in Audio", "object.serial": 41}}},
+            {"id": 31, "type": "PipeWire:Interface:Node", "info": {"props": {
+                "media.class": "Stream/Output/Audio", "application.name": "Firefox",
+                "media.name": "YouTube"}}},
+            {"id": 32, "type": "PipeWire:Interface:Node", "info": {"props": {
+                "media.class": "Video/Source", "node.name": "v4l2"}}},
+            {"id": 33, "type": "PipeWire:Interface:Port", "info": {"props": {}}}
+        ]"#;
+        let sources = parse_sources(dump);
+        assert_eq!(sources.len(), 2);
+        assert_eq!(sources[0].id, "41");
+        assert_eq!(sources[0].name, "Built-in Audio");
+        assert_eq!(sources[0].kind.enum_value(), Ok(Kind::Output));
+        assert_eq!(sources[1].id, "31");
+        assert_eq!(sources[1].name, "Firefox: YouTube");
+        assert_eq!(sources[1].kind.enum_value(), Ok(Kind::Application));
+        assert!(parse_sources("not json").is_empty());
+    }
+
+    #[test]
+    fn test_mix() {
+        let mut out = vec![0.5, -0.5, 0.8];
+        mix(&mut out, None, 0.5);
+        assert_eq!(out, vec![0.25, -0.25, 0.4]);
+        mix(&mut out, Some(&[1.0, -1.0]), 1.0);
+        assert_eq!(out, vec![1.0, -1.0, 0.4]);
+    }
+}
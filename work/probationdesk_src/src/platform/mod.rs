@@ -11,6 +11,9 @@ pub mod windows;
 #[cfg(windows)]
 pub mod win_device;
 
+#[cfg(windows)]
+pub mod win_pen;
+
 #[cfg(target_os = "macos")]
 pub mod macos;
 
@@ -23,6 +26,9 @@ pub mod linux;
 #[cfg(target_os = "linux")]
 pub mod linux_desktop_manager;
 
+#[cfg(target_os = "linux")]
+pub mod linux_pipewire;
+
 #[cfg(target_os = "linux")]
 pub mod gtk_sudo;
 
@@ -209,6 +215,93 @@ pub fn get_pids_of_process_with_first_arg<S1: AsRef<str>, S2: AsRef<str>>(
     }
 }
 
+// (user, session) of the logged in users from `who`.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+pub fn get_logged_in_users() -> Vec<(String, String)> {
+    match std::process::Command::new("who").output() {
+        Ok(output) => parse_who(&String::from_utf8_lossy(&output.stdout)),
+        Err(e) => {
+            hbb_common::log::debug!("Failed to run who: {}", e);
+            vec![]
+        }
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn parse_who(out: &str) -> Vec<(String, String)> {
+    out.lines()
+        .filter_map(|line| {
+            let mut it = line.split_whitespace();
+            Some((it.next()?.to_owned(), it.next()?.to_owned()))
+        })
+        .collect()
+}
+
+// (name, version) of the packages installed by the package manager.
+#[cfg(target_os = "linux")]
+pub fn get_installed_packages() -> Vec<(String, String)> {
+    let queries: [(&str, &[&str]); 3] = [
+        ("dpkg-query", &["-W", "-f=${Package}\t${Version}\n"]),
+        ("rpm", &["-qa", "--qf", "%{NAME}\t%{VERSION}-%{RELEASE}\n"]),
+        ("pacman", &["-Q"]),
+    ];
+    for (cmd, args) in queries {
+        if let Ok(output) = std::process::Command::new(cmd).args(args).output() {
+            if output.status.success() {
+                return parse_packages(&String::from_utf8_lossy(&output.stdout));
+            }
+        }
+    }
+    vec![]
+}
+
+// The applications in /Applications, there is no package manager.
+#[cfg(target_os = "macos")]
+pub fn get_installed_packages() -> Vec<(String, String)> {
+    let Ok(dir) = std::fs::read_dir("/Applications") else {
+        return vec![];
+    };
+    let mut packages: Vec<(String, String)> = dir
+        .filter_map(|e| e.ok())
+        .filter_map(|e| {
+            let name = e.file_name().to_string_lossy().to_string();
+            let name = name.strip_suffix(".app")?.to_owned();
+            let version = std::process::Command::new("defaults")
+                .arg("read")
+                .arg(e.path().join("Contents/Info"))
+                .arg("CFBundleShortVersionString")
+                .output()
+                .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_owned())
+                .unwrap_or_default();
+            Some((name, version))
+        })
+        .collect();
+    packages.sort();
+    packages
+}
+
+// "name<tab>version" or "name version" per line
+#[cfg(target_os = "linux")]
+fn parse_packages(out: &str) -> Vec<(String, String)> {
+    let mut packages: Vec<(String, String)> = out
+        .lines()
+        .filter_map(|line| {
+            let line = line.trim();
+            let (name, version) = line
+                .split_once('\t')
+                .or_else(|| line.split_once(' '))
+                .unwrap_or((line, ""));
+            if name.is_empty() {
+                None
+            } else {
+                Some((name.to_owned(), version.trim().to_owned()))
+            }
+        })
+        .collect();
+    packages.sort();
+    packages
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -244,4 +337,24 @@ mod tests {
         println!("change:{:?}", change_resolution(name, 2880, 1800));
         println!("resolutions:{:?}", resolutions(name));
     }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_parse_inventory_output() {
+        assert_eq!(
+            parse_who("alice    seat0        2024-01-02 09:00 (login screen)\nbob      pts/0        2024-01-02 09:10 (10.0.0.2)\n\n"),
+            vec![
+                ("alice".to_owned(), "seat0".to_owned()),
+                ("bob".to_owned(), "pts/0".to_owned())
+            ]
+        );
+        assert_eq!(
+            parse_packages("zlib1g\t1:1.2.13\nbash 5.2.15-1\nnoversion\n\n"),
+            vec![
+                ("bash".to_owned(), "5.2.15-1".to_owned()),
+                ("noversion".to_owned(), "".to_owned()),
+                ("zlib1g".to_owned(), "1:1.2.13".to_owned())
+            ]
+        );
+    }
 }
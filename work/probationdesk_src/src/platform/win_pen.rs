@@ -0,0 +1,235 @@
+// Pen injection with the synthetic pointer API (Windows 10 1809+).
+//
+// The functions are loaded at runtime, so that the binary still starts on older systems,
+// where `is_supported()` returns false and pen events are simulated with the mouse.
+
+use crate::input::{PEN_BUTTON_BARREL, PEN_PRESSURE_MAX, PEN_TILT_MAX};
+use hbb_common::{
+    bail,
+    message_proto::{pen_event, PenEvent},
+    ResultType,
+};
+use std::{ffi::CString, mem, sync::Mutex};
+use winapi::{
+    shared::{
+        minwindef::{BOOL, DWORD, FALSE, FARPROC, UINT32},
+        ntdef::HANDLE,
+        windef::{HWND, POINT},
+    },
+    um::libloaderapi::{GetProcAddress, LoadLibraryA},
+};
+
+const PT_PEN: DWORD = 3;
+const POINTER_FEEDBACK_DEFAULT: DWORD = 1;
+
+const POINTER_FLAG_INRANGE: UINT32 = 0x00000002;
+const POINTER_FLAG_INCONTACT: UINT32 = 0x00000004;
+const POINTER_FLAG_FIRSTBUTTON: UINT32 = 0x00000010;
+const POINTER_FLAG_PRIMARY: UINT32 = 0x00002000;
+const POINTER_FLAG_DOWN: UINT32 = 0x00010000;
+const POINTER_FLAG_UPDATE: UINT32 = 0x00020000;
+const POINTER_FLAG_UP: UINT32 = 0x00040000;
+
+const POINTER_CHANGE_NONE: i32 = 0;
+const POINTER_CHANGE_FIRSTBUTTON_DOWN: i32 = 1;
+const POINTER_CHANGE_FIRSTBUTTON_UP: i32 = 2;
+
+const PEN_FLAG_BARREL: UINT32 = 0x00000001;
+const PEN_FLAG_INVERTED: UINT32 = 0x00000002;
+const PEN_FLAG_ERASER: UINT32 = 0x00000004;
+
+const PEN_MASK_PRESSURE: UINT32 = 0x00000001;
+const PEN_MASK_TILT_X: UINT32 = 0x00000004;
+const PEN_MASK_TILT_Y: UINT32 = 0x00000008;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct PointerInfo {
+    pointer_type: DWORD,
+    pointer_id: UINT32,
+    frame_id: UINT32,
+    pointer_flags: UINT32,
+    source_device: HANDLE,
+    hwnd_target: HWND,
+    pt_pixel_location: POINT,
+    pt_himetric_location: POINT,
+    pt_pixel_location_raw: POINT,
+    pt_himetric_location_raw: POINT,
+    dw_time: DWORD,
+    history_count: UINT32,
+    input_data: i32,
+    dw_key_states: DWORD,
+    performance_count: u64,
+    button_change_type: i32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct PointerPenInfo {
+    pointer_info: PointerInfo,
+    pen_flags: UINT32,
+    pen_mask: UINT32,
+    pressure: UINT32,
+    rotation: UINT32,
+    tilt_x: i32,
+    tilt_y: i32,
+}
+
+// POINTER_TOUCH_INFO is the larger member of the union in POINTER_TYPE_INFO.
+const POINTER_TOUCH_INFO_SIZE: usize = mem::size_of::<PointerInfo>() + 48;
+
+#[repr(C)]
+union PointerTypeInfoUnion {
+    pen_info: PointerPenInfo,
+    _touch_info: [u8; POINTER_TOUCH_INFO_SIZE],
+}
+
+#[repr(C)]
+struct PointerTypeInfo {
+    r#type: DWORD,
+    u: PointerTypeInfoUnion,
+}
+
+type CreateSyntheticPointerDevice = unsafe extern "system" fn(DWORD, u32, DWORD) -> HANDLE;
+type InjectSyntheticPointerInput =
+    unsafe extern "system" fn(HANDLE, *const PointerTypeInfo, u32) -> BOOL;
+
+struct Injector {
+    device: HANDLE,
+    inject: InjectSyntheticPointerInput,
+    in_contact: bool,
+}
+
+unsafe impl Send for Injector {}
+
+lazy_static::lazy_static! {
+    static ref INJECTOR: Mutex<Option<Injector>> = Mutex::new(None);
+}
+
+unsafe fn get_proc(name: &str) -> Option<FARPROC> {
+    let user32 = LoadLibraryA(CString::new("user32.dll").ok()?.as_ptr());
+    if user32.is_null() {
+        return None;
+    }
+    let func = GetProcAddress(user32, CString::new(name).ok()?.as_ptr());
+    if func.is_null() {
+        None
+    } else {
+        Some(func)
+    }
+}
+
+pub fn is_supported() -> bool {
+    unsafe {
+        get_proc("CreateSyntheticPointerDevice").is_some()
+            && get_proc("InjectSyntheticPointerInput").is_some()
+    }
+}
+
+fn new_injector() -> ResultType<Injector> {
+    unsafe {
+        let (Some(create), Some(inject)) = (
+            get_proc("CreateSyntheticPointerDevice"),
+            get_proc("InjectSyntheticPointerInput"),
+        ) else {
+            bail!("Synthetic pointer API is not supported");
+        };
+        let create: CreateSyntheticPointerDevice = mem::transmute(create);
+        let device = create(PT_PEN, 1, POINTER_FEEDBACK_DEFAULT);
+        if device.is_null() {
+            bail!(
+                "Failed to create synthetic pen device: {}",
+                std::io::Error::last_os_error()
+            );
+        }
+        Ok(Injector {
+            device,
+            inject: mem::transmute(inject),
+            in_contact: false,
+        })
+    }
+}
+
+pub fn inject(pen: &PenEvent) -> ResultType<()> {
+    let mut lock = INJECTOR.lock().unwrap();
+    if lock.is_none() {
+        *lock = Some(new_injector()?);
+    }
+    let Some(injector) = lock.as_mut() else {
+        bail!("No synthetic pen device");
+    };
+    let in_contact = pen.in_range && pen.pressure > 0;
+    let mut pointer_flags = POINTER_FLAG_PRIMARY;
+    if pen.in_range {
+        pointer_flags |= POINTER_FLAG_INRANGE;
+    }
+    let button_change_type = match (injector.in_contact, in_contact) {
+        (false, true) => {
+            pointer_flags |= POINTER_FLAG_INCONTACT | POINTER_FLAG_FIRSTBUTTON | POINTER_FLAG_DOWN;
+            POINTER_CHANGE_FIRSTBUTTON_DOWN
+        }
+        (true, true) => {
+            pointer_flags |=
+                POINTER_FLAG_INCONTACT | POINTER_FLAG_FIRSTBUTTON | POINTER_FLAG_UPDATE;
+            POINTER_CHANGE_NONE
+        }
+        (true, false) => {
+            pointer_flags |= POINTER_FLAG_UP;
+            POINTER_CHANGE_FIRSTBUTTON_UP
+        }
+        (false, false) => {
+            pointer_flags |= POINTER_FLAG_UPDATE;
+            POINTER_CHANGE_NONE
+        }
+    };
+    let mut pen_flags = 0;
+    if pen.buttons & PEN_BUTTON_BARREL != 0 {
+        pen_flags |= PEN_FLAG_BARREL;
+    }
+    if pen.tool.enum_value() == Ok(pen_event::Tool::Eraser) {
+        pen_flags |= PEN_FLAG_INVERTED;
+        if in_contact {
+            pen_flags |= PEN_FLAG_ERASER;
+        }
+    }
+    let location = POINT { x: pen.x, y: pen.y };
+    let info = PointerTypeInfo {
+        r#type: PT_PEN,
+        u: PointerTypeInfoUnion {
+            pen_info: PointerPenInfo {
+                pointer_info: PointerInfo {
+                    pointer_type: PT_PEN,
+                    pointer_id: 0,
+                    frame_id: 0,
+                    pointer_flags,
+                    source_device: std::ptr::null_mut(),
+                    hwnd_target: std::ptr::null_mut(),
+                    pt_pixel_location: location,
+                    pt_himetric_location: POINT { x: 0, y: 0 },
+                    pt_pixel_location_raw: location,
+                    pt_himetric_location_raw: POINT { x: 0, y: 0 },
+                    dw_time: 0,
+                    history_count: 0,
+                    input_data: 0,
+                    dw_key_states: 0,
+                    performance_count: 0,
+                    button_change_type,
+                },
+                pen_flags,
+                pen_mask: PEN_MASK_PRESSURE | PEN_MASK_TILT_X | PEN_MASK_TILT_Y,
+                pressure: pen.pressure.min(PEN_PRESSURE_MAX),
+                rotation: 0,
+                tilt_x: pen.tilt_x.clamp(-PEN_TILT_MAX, PEN_TILT_MAX),
+                tilt_y: pen.tilt_y.clamp(-PEN_TILT_MAX, PEN_TILT_MAX),
+            },
+        },
+    };
+    if unsafe { (injector.inject)(injector.device, &info, 1) } == FALSE {
+        bail!(
+            "Failed to inject pen input: {}",
+            std::io::Error::last_os_error()
+        );
+    }
+    injector.in_contact = in_contact;
+    Ok(())
+}
@@ -1071,6 +1071,56 @@ pub fn get_available_sessions(name: bool) -> Vec<WindowsSession> {
     v
 }
 
+// (user, session) of the sessions with a logged in user.
+pub fn get_logged_in_users() -> Vec<(String, String)> {
+    get_available_sessions(false)
+        .into_iter()
+        .filter_map(|s| {
+            let name = get_session_username(s.sid);
+            if name.is_empty() {
+                None
+            } else {
+                Some((name, s.sid.to_string()))
+            }
+        })
+        .collect()
+}
+
+// (name, version) of the programs in "Apps & features".
+pub fn get_installed_packages() -> Vec<(String, String)> {
+    let uninstall = "SOFTWARE\\Microsoft\\Windows\\CurrentVersion\\Uninstall";
+    let roots = [
+        (HKEY_LOCAL_MACHINE, KEY_READ | KEY_WOW64_64KEY),
+        (HKEY_LOCAL_MACHINE, KEY_READ | KEY_WOW64_32KEY),
+        (HKEY_CURRENT_USER, KEY_READ),
+    ];
+    let mut packages = vec![];
+    for (root, flags) in roots {
+        let Ok(key) = RegKey::predef(root as isize).open_subkey_with_flags(uninstall, flags) else {
+            continue;
+        };
+        for name in key.enum_keys().filter_map(|k| k.ok()) {
+            let Ok(app) = key.open_subkey_with_flags(&name, flags) else {
+                continue;
+            };
+            // Updates and components are hidden in "Apps & features".
+            if app.get_value::<u32, _>("SystemComponent").unwrap_or_default() == 1 {
+                continue;
+            }
+            let Ok(display_name) = app.get_value::<String, _>("DisplayName") else {
+                continue;
+            };
+            let version = app
+                .get_value::<String, _>("DisplayVersion")
+                .unwrap_or_default();
+            packages.push((display_name, version));
+        }
+    }
+    packages.sort();
+    packages.dedup();
+    packages
+}
+
 pub fn get_active_user_home() -> Option<PathBuf> {
     let username = get_active_username();
     if !username.is_empty() {
@@ -2203,6 +2253,243 @@ pub fn get_logon_user_token(user: &str, pwd: &str) -> ResultType<HANDLE> {
 // Р•СЃР»Рё РїСЂРµРґРѕСЃС‚Р°РІР»РµРЅРЅС‹Р№ С‚РѕРєРµРЅ СЏРІР»СЏРµС‚СЃСЏ С‚РѕРєРµРЅРѕРј РёРјРїРµСЂСЃРѕРЅР°С†РёРё, РѕРЅ РґСѓР±Р»РёСЂСѓРµС‚ РµРіРѕ РІ РїРµСЂРІРёС‡РЅС‹Р№ С‚РѕРєРµРЅ.
 // Р•СЃР»Рё РїСЂРµРґРѕСЃС‚Р°РІР»РµРЅРЅС‹Р№ С‚РѕРєРµРЅ СѓР¶Рµ СЏРІР»СЏРµС‚СЃСЏ РїРµСЂРІРёС‡РЅС‹Рј С‚РѕРєРµРЅРѕРј, РѕРЅ РІРѕР·РІСЂР°С‰Р°РµС‚ РµРіРѕ РєР°Рє РµСЃС‚СЊ.
 // Р’С‹Р·С‹РІР°СЋС‰РёР№ РѕС‚РІРµС‡Р°РµС‚ Р·Р° Р·Р°РєСЂС‹С‚РёРµ РІРѕР·РІСЂР°С‰РµРЅРЅРѕРіРѕ РґРµСЃРєСЂРёРїС‚РѕСЂР° С‚РѕРєРµРЅР°.
+/// A process started with a user token, its stdio redirected to pipes.
+pub struct PipedUserProcess {
+    process: HANDLE,
+    job: Job,
+    pub stdin: Option<fs::File>,
+    pub stdout: Option<fs::File>,
+    pub stderr: Option<fs::File>,
+}
+
+unsafe impl Send for PipedUserProcess {}
+
+impl PipedUserProcess {
+    pub fn try_wait(&mut self) -> ResultType<Option<i32>> {
+        let mut exit_code: DWORD = 0;
+        if unsafe { GetExitCodeProcess(self.process, &mut exit_code) } == FALSE {
+            bail!(
+                "Failed to get the exit code: {}",
+                io::Error::last_os_error()
+            );
+        }
+        if exit_code == STILL_ACTIVE {
+            Ok(None)
+        } else {
+            Ok(Some(exit_code as i32))
+        }
+    }
+
+    /// Kill the process with the processes it started.
+    pub fn kill(&mut self) {
+        self.job.terminate();
+    }
+}
+
+impl Drop for PipedUserProcess {
+    fn drop(&mut self) {
+        unsafe {
+            CloseHandle(self.process);
+        }
+    }
+}
+
+/// A job object, to kill a process with the processes it started. They are left running when
+/// the job is closed.
+pub struct Job(HANDLE);
+
+unsafe impl Send for Job {}
+
+impl Job {
+    pub fn new() -> ResultType<Self> {
+        let job = unsafe { winapi::um::jobapi2::CreateJobObjectW(null_mut(), null_mut()) };
+        if job.is_null() {
+            bail!(
+                "Failed to create job object: {}",
+                io::Error::last_os_error()
+            );
+        }
+        Ok(Self(job))
+    }
+
+    /// The processes the process starts later are in the job too.
+    pub fn assign(&self, process: HANDLE) -> ResultType<()> {
+        if unsafe { winapi::um::jobapi2::AssignProcessToJobObject(self.0, process) } == FALSE {
+            bail!(
+                "Failed to assign the process to the job: {}",
+                io::Error::last_os_error()
+            );
+        }
+        Ok(())
+    }
+
+    pub fn terminate(&self) {
+        unsafe {
+            winapi::um::jobapi2::TerminateJobObject(self.0, 1);
+        }
+    }
+}
+
+impl Drop for Job {
+    fn drop(&mut self) {
+        unsafe {
+            CloseHandle(self.0);
+        }
+    }
+}
+
+fn to_wide(s: &str) -> Vec<u16> {
+    use std::os::windows::ffi::OsStrExt;
+    std::ffi::OsStr::new(s)
+        .encode_wide()
+        .chain(Some(0).into_iter())
+        .collect()
+}
+
+// The environment of the user with `env` applied, as a block for `CreateProcessAsUserW`.
+unsafe fn user_environment_block(token: HANDLE, env: &HashMap<String, String>) -> Vec<u16> {
+    use winapi::um::userenv::{CreateEnvironmentBlock, DestroyEnvironmentBlock};
+    let mut vars: Vec<(String, String)> = vec![];
+    let mut block: LPVOID = null_mut();
+    if CreateEnvironmentBlock(&mut block, token, FALSE) == TRUE && !block.is_null() {
+        let mut p = block as *const u16;
+        loop {
+            let mut len = 0;
+            while *p.add(len) != 0 {
+                len += 1;
+            }
+            if len == 0 {
+                break;
+            }
+            let var = String::from_utf16_lossy(std::slice::from_raw_parts(p, len));
+            // Skip the hidden variables of the drives, e.g. "=C:=C:\".
+            if !var.starts_with('=') {
+                if let Some((k, v)) = var.split_once('=') {
+                    vars.push((k.to_owned(), v.to_owned()));
+                }
+            }
+            p = p.add(len + 1);
+        }
+        DestroyEnvironmentBlock(block);
+    }
+    for (k, v) in env {
+        vars.retain(|(k2, _)| !k2.eq_ignore_ascii_case(k));
+        vars.push((k.clone(), v.clone()));
+    }
+    let mut wide: Vec<u16> = vars
+        .iter()
+        .flat_map(|(k, v)| to_wide(&format!("{}={}", k, v)))
+        .collect();
+    wide.push(0);
+    wide
+}
+
+/// Start `cmdline` as the user of `token` without a window, stdin, stdout and stderr are pipes.
+pub fn spawn_piped_as_user(
+    token: u64,
+    cmdline: &str,
+    env: &HashMap<String, String>,
+    working_dir: &str,
+) -> ResultType<PipedUserProcess> {
+    use std::os::windows::io::FromRawHandle;
+    use winapi::um::{
+        handleapi::SetHandleInformation,
+        minwinbase::SECURITY_ATTRIBUTES,
+        namedpipeapi::CreatePipe,
+        processthreadsapi::{CreateProcessAsUserW, ResumeThread, TerminateProcess},
+    };
+    let token = token as HANDLE;
+    let job = Job::new()?;
+    unsafe {
+        let mut sa = SECURITY_ATTRIBUTES {
+            nLength: mem::size_of::<SECURITY_ATTRIBUTES>() as _,
+            lpSecurityDescriptor: null_mut(),
+            bInheritHandle: TRUE,
+        };
+        // (read, write) of stdin, stdout and stderr.
+        let mut pipes: [(HANDLE, HANDLE); 3] = [(null_mut(), null_mut()); 3];
+        let close_pipes = |pipes: &[(HANDLE, HANDLE)]| {
+            for (r, w) in pipes {
+                if !r.is_null() {
+                    CloseHandle(*r);
+                }
+                if !w.is_null() {
+                    CloseHandle(*w);
+                }
+            }
+        };
+        for i in 0..pipes.len() {
+            if CreatePipe(&mut pipes[i].0, &mut pipes[i].1, &mut sa, 0) == FALSE {
+                let err = io::Error::last_os_error();
+                close_pipes(&pipes);
+                bail!("Failed to create pipe: {}", err);
+            }
+        }
+        // The ends of this process must not be inherited.
+        SetHandleInformation(pipes[0].1, HANDLE_FLAG_INHERIT, 0);
+        SetHandleInformation(pipes[1].0, HANDLE_FLAG_INHERIT, 0);
+        SetHandleInformation(pipes[2].0, HANDLE_FLAG_INHERIT, 0);
+
+        let mut si: STARTUPINFOW = mem::zeroed();
+        si.cb = mem::size_of::<STARTUPINFOW>() as _;
+        si.dwFlags = STARTF_USESTDHANDLES;
+        si.hStdInput = pipes[0].0;
+        si.hStdOutput = pipes[1].1;
+        si.hStdError = pipes[2].1;
+        let mut pi: PROCESS_INFORMATION = mem::zeroed();
+        let mut cmdline = to_wide(cmdline);
+        let mut env_block = user_environment_block(token, env);
+        let working_dir = to_wide(working_dir);
+        let res = CreateProcessAsUserW(
+            token,
+            null_mut(),
+            cmdline.as_mut_ptr(),
+            null_mut(),
+            null_mut(),
+            TRUE,
+            // Resumed in the job, before it starts any process.
+            CREATE_UNICODE_ENVIRONMENT | CREATE_NO_WINDOW | CREATE_SUSPENDED,
+            env_block.as_mut_ptr() as _,
+            if working_dir.len() > 1 {
+                working_dir.as_ptr()
+            } else {
+                std::ptr::null()
+            },
+            &mut si,
+            &mut pi,
+        );
+        let err = io::Error::last_os_error();
+        // The ends of the child process are duplicated into it.
+        CloseHandle(pipes[0].0);
+        CloseHandle(pipes[1].1);
+        CloseHandle(pipes[2].1);
+        if res == FALSE {
+            close_pipes(&[
+                (null_mut(), pipes[0].1),
+                (pipes[1].0, null_mut()),
+                (pipes[2].0, null_mut()),
+            ]);
+            bail!("Failed to create process: {}", err);
+        }
+        let assigned = job.assign(pi.hProcess);
+        if assigned.is_ok() {
+            ResumeThread(pi.hThread);
+        } else {
+            TerminateProcess(pi.hProcess, 1);
+        }
+        CloseHandle(pi.hThread);
+        let process = PipedUserProcess {
+            process: pi.hProcess,
+            job,
+            stdin: Some(fs::File::from_raw_handle(pipes[0].1 as _)),
+            stdout: Some(fs::File::from_raw_handle(pipes[1].0 as _)),
+            stderr: Some(fs::File::from_raw_handle(pipes[2].0 as _)),
+        };
+        assigned?;
+        Ok(process)
+    }
+}
+
 pub fn ensure_primary_token(user_token: HANDLE) -> ResultType<HANDLE> {
     if user_token.is_null() || user_token == INVALID_HANDLE_VALUE {
         bail!("РџСЂРµРґРѕСЃС‚Р°РІР»РµРЅ РЅРµРґРѕРїСѓСЃС‚РёРјС‹Р№ С‚РѕРєРµРЅ РїРѕР»СЊР·РѕРІР°С‚РµР»СЏ");
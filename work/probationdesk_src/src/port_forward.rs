@@ -142,6 +142,9 @@ async fn connect_and_login(
                         Some(message::Union::Hash(hash)) => {
                             interface.handle_hash(password, hash, &mut stream).await;
                         }
+                        Some(message::Union::DirectAccess(da)) => {
+                            handle_direct_access(da, id, &mut stream).await;
+                        }
                         Some(message::Union::LoginResponse(lr)) => match lr.union {
                             Some(login_response::Union::Error(err)) => {
                                 if !interface.handle_login_error(&err) {
@@ -1,3 +1,5 @@
+mod http;
+
 use std::{
     net::SocketAddr,
     sync::{
@@ -15,13 +17,22 @@ use hbb_common::{
     config::{
         self, keys::*, option2bool, use_ws, Config, CONNECT_TIMEOUT, REG_INTERVAL, RENDEZVOUS_PORT,
     },
-    futures::future::join_all,
+    direct_access::{self, TlsAcceptor},
+    futures::future::{join_all, select_all},
     log,
     protobuf::Message as _,
+    proxy_resolver::proxy_for_async,
+    quic::QuicFramedStream,
     rendezvous_proto::*,
     sleep,
-    socket_client::{self, connect_tcp, is_ipv4, new_direct_udp_for, new_udp_for},
-    tokio::{self, select, sync::Mutex, time::interval},
+    socket_client::{self, connect_tcp, is_ipv4, new_direct_udp_for, new_udp_for, race_udp_for},
+    tokio::{
+        self,
+        net::{TcpListener, TcpStream},
+        select,
+        sync::Mutex,
+        time::interval,
+    },
     udp::FramedSocket,
     AddrMangle, IntoTargetAddr, ResultType, Stream, TargetAddr,
 };
@@ -47,6 +58,8 @@ pub struct RendezvousMediator {
     host: String,
     host_prefix: String,
     keep_alive: i32,
+    // Registered over https, see `http`.
+    http: Option<http::Channel>,
 }
 
 impl RendezvousMediator {
@@ -77,6 +90,8 @@ impl RendezvousMediator {
         tokio::spawn(async move {
             direct_server(server_cloned).await;
         });
+        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+        tokio::spawn(crate::embedded_server::watch());
         #[cfg(target_os = "android")]
         let start_lan_listening = true;
         #[cfg(not(any(target_os = "android", target_os = "ios")))]
@@ -156,16 +171,30 @@ impl RendezvousMediator {
     pub async fn start_udp(server: ServerPtr, host: String) -> ResultType<()> {
         let host = check_port(&host, RENDEZVOUS_PORT);
         log::info!("start udp: {host}");
-        let (mut socket, mut addr) = new_udp_for(&host, CONNECT_TIMEOUT).await?;
+        const MIN_REG_TIMEOUT: i64 = 3_000;
+        let host_prefix = Self::get_host_prefix(&host);
+        // The registration goes over both families, the socket of the first answered is kept.
+        let sent = Instant::now();
+        let (mut socket, mut addr, mut answer) =
+            match Self::registration(&host, &host_prefix).await {
+                Some(msg_out) => {
+                    let request = msg_out.write_to_bytes()?;
+                    race_udp_for(&host, &request, MIN_REG_TIMEOUT as _).await?
+                }
+                None => {
+                    let (socket, addr) = new_udp_for(&host, CONNECT_TIMEOUT).await?;
+                    (socket, addr, None)
+                }
+            };
         let mut rz = Self {
             addr: addr.clone(),
             host: host.clone(),
-            host_prefix: Self::get_host_prefix(&host),
+            host_prefix,
             keep_alive: crate::DEFAULT_KEEP_ALIVE,
+            http: None,
         };
 
         let mut timer = crate::rustdesk_interval(interval(crate::TIMER_OUT));
-        const MIN_REG_TIMEOUT: i64 = 3_000;
         const MAX_REG_TIMEOUT: i64 = 30_000;
         let mut reg_timeout = MIN_REG_TIMEOUT;
         const MAX_FAILS1: i64 = 2;
@@ -173,12 +202,15 @@ impl RendezvousMediator {
         const DNS_INTERVAL: i64 = 60_000;
         let mut fails = 0;
         let mut last_register_resp: Option<Instant> = None;
-        let mut last_register_sent: Option<Instant> = None;
+        let mut last_register_sent: Option<Instant> = answer.as_ref().map(|_| sent);
         let mut last_dns_check = Instant::now();
         let mut old_latency = 0;
         let mut ema_latency = 0;
         loop {
             let mut update_latency = || {
+                if last_register_resp.is_none() {
+                    http::set_failed(&host, false, false);
+                }
                 last_register_resp = Some(Instant::now());
                 fails = 0;
                 reg_timeout = MIN_REG_TIMEOUT;
@@ -205,6 +237,12 @@ impl RendezvousMediator {
                     old_latency = latency;
                 }
             };
+            if let Some(bytes) = answer.take() {
+                if let Ok(msg) = Message::parse_from_bytes(&bytes) {
+                    rz.handle_resp(msg.union, Sink::Framed(&mut socket, &addr), &server, &mut update_latency).await?;
+                }
+                continue;
+            }
             select! {
                 n = socket.next() => {
                     match n {
@@ -238,6 +276,11 @@ impl RendezvousMediator {
                     if timeout || (last_register_sent.is_none() && expired) {
                         if timeout {
                             fails += 1;
+                            if fails >= MAX_FAILS2 && last_register_resp.is_none() {
+                                // Never answered, UDP may be blocked.
+                                http::set_failed(&host, false, true);
+                                bail!("No response of the rendezvous server over UDP");
+                            }
                             if fails >= MAX_FAILS2 {
                                 Config::update_latency(&host, -1);
                                 old_latency = 0;
@@ -341,14 +384,22 @@ impl RendezvousMediator {
     pub async fn start_tcp(server: ServerPtr, host: String) -> ResultType<()> {
         let host = check_port(&host, RENDEZVOUS_PORT);
         log::info!("start tcp: {}", hbb_common::websocket::check_ws(&host));
-        let mut conn = connect_tcp(host.clone(), CONNECT_TIMEOUT).await?;
+        let mut conn = match connect_tcp(host.clone(), CONNECT_TIMEOUT).await {
+            Ok(conn) => conn,
+            Err(err) => {
+                http::set_failed(&host, true, true);
+                return Err(err);
+            }
+        };
         let key = crate::get_key(true).await;
         crate::secure_tcp(&mut conn, &key).await?;
+        http::set_failed(&host, true, false);
         let mut rz = Self {
             addr: conn.local_addr().into_target_addr()?,
             host: host.clone(),
             host_prefix: Self::get_host_prefix(&host),
             keep_alive: crate::DEFAULT_KEEP_ALIVE,
+            http: None,
         };
         let mut timer = crate::rustdesk_interval(interval(crate::TIMER_OUT));
         let mut last_register_sent: Option<Instant> = None;
@@ -396,13 +447,75 @@ impl RendezvousMediator {
         Ok(())
     }
 
+    pub async fn start_http(server: ServerPtr, host: String) -> ResultType<()> {
+        let host = check_port(&host, RENDEZVOUS_PORT);
+        let (channel, mut rx) = http::Channel::new(&host).await;
+        log::info!("start http: {}", channel.url());
+        let mut rz = Self {
+            addr: Config::get_any_listen_addr(true).into_target_addr()?,
+            host: host.clone(),
+            host_prefix: Self::get_host_prefix(&host),
+            keep_alive: crate::DEFAULT_KEEP_ALIVE,
+            http: Some(channel.clone()),
+        };
+        let poll = channel.clone();
+        let mut events = tokio::spawn(async move { poll.poll().await });
+        let mut timer = crate::rustdesk_interval(interval(crate::TIMER_OUT));
+        let mut last_register_sent: Option<Instant> = None;
+        let mut answered = false;
+        Config::set_host_key_confirmed(&rz.host_prefix, false);
+        let res: ResultType<()> = async {
+            loop {
+                let mut update_latency = || {
+                    let latency = last_register_sent
+                        .map(|x| x.elapsed().as_micros() as i64)
+                        .unwrap_or(0);
+                    Config::update_latency(&host, latency);
+                };
+                select! {
+                    msg = rx.recv() => {
+                        let Some(msg) = msg else {
+                            bail!("Rendezvous channel over https is closed");
+                        };
+                        answered = true;
+                        rz.handle_resp(msg.union, Sink::Http(&channel), &server, &mut update_latency).await?
+                    }
+                    res = &mut events => {
+                        res??;
+                    }
+                    _ = timer.tick() => {
+                        // UDP and TCP are tried again after a while.
+                        if SHOULD_EXIT.load(Ordering::SeqCst) || !http::is_fallback(&host) {
+                            break;
+                        }
+                        if last_register_sent.map(|x| x.elapsed().as_millis() as i64).unwrap_or(REG_INTERVAL) >= REG_INTERVAL {
+                            rz.register_peer(Sink::Http(&channel)).await?;
+                            last_register_sent = Some(Instant::now());
+                        }
+                    }
+                }
+            }
+            Ok(())
+        }
+        .await;
+        events.abort();
+        if res.is_err() && !answered {
+            http::reset(&host);
+        }
+        res
+    }
+
     pub async fn start(server: ServerPtr, host: String) -> ResultType<()> {
         log::info!("start rendezvous mediator of {}", host);
+        if http::is_fallback(&host) {
+            return Self::start_http(server, host).await;
+        }
         //If the investment agent type is http or https, then tcp forwarding is enabled.
         if (cfg!(debug_assertions) && option_env!("TEST_TCP").is_some())
-            || Config::is_proxy()
             || use_ws()
             || crate::is_udp_disabled()
+            || http::udp_failed(&host)
+            || proxy_for_async(host.clone()).await.is_some()
         {
             Self::start_tcp(server, host).await
         } else {
@@ -427,6 +540,7 @@ impl RendezvousMediator {
             rr.secure,
             false,
             Default::default(),
+            false,
         )
         .await
     }
@@ -440,6 +554,7 @@ impl RendezvousMediator {
         secure: bool,
         initiate: bool,
         socket_addr_v6: bytes::Bytes,
+        quic: bool,
     ) -> ResultType<()> {
         let peer_addr = AddrMangle::decode(&socket_addr);
         log::info!(
@@ -450,13 +565,12 @@ impl RendezvousMediator {
             secure,
         );
 
-        let mut socket = connect_tcp(&*self.host, CONNECT_TIMEOUT).await?;
-
         let mut msg_out = Message::new();
         let mut rr = RelayResponse {
             socket_addr: socket_addr.into(),
             version: crate::VERSION.to_owned(),
             socket_addr_v6,
+            quic,
             ..Default::default()
         };
         if initiate {
@@ -465,14 +579,30 @@ impl RendezvousMediator {
             rr.set_id(Config::get_id());
         }
         msg_out.set_relay_response(rr);
-        socket.send(&msg_out).await?;
+        // Kept until the relay connection is created.
+        let _socket = match &self.http {
+            Some(http) => {
+                http.send(&msg_out).await?;
+                None
+            }
+            None => {
+                let mut socket = connect_tcp(&*self.host, CONNECT_TIMEOUT).await?;
+                socket.send(&msg_out).await?;
+                Some(socket)
+            }
+        };
+        // Only https gets out, the relay goes over websocket on 443 too.
+        let relay_server = if self.http.is_some() {
+            http::relay_url(&relay_server)
+        } else {
+            relay_server
+        };
         crate::create_relay_connection(
             server,
             relay_server,
             uuid,
             peer_addr,
             secure,
-            is_ipv4(&self.addr),
         )
         .await;
         Ok(())
@@ -488,10 +618,10 @@ impl RendezvousMediator {
         }
         let peer_addr_v6 = hbb_common::AddrMangle::decode(&fla.socket_addr_v6);
         let relay_server = self.get_relay_server(fla.relay_server.clone());
-        let relay = use_ws() || Config::is_proxy();
+        let relay = use_ws() || self.is_proxied().await || self.http.is_some();
         let mut socket_addr_v6 = Default::default();
         if peer_addr_v6.port() > 0 && !relay {
-            socket_addr_v6 = start_ipv6(peer_addr_v6, addr, server.clone()).await;
+            socket_addr_v6 = start_ipv6(peer_addr_v6, addr, server.clone(), fla.quic).await;
         }
         if is_ipv4(&self.addr) && !relay && !config::is_disable_tcp_listen() {
             if let Err(err) = self
@@ -517,6 +647,7 @@ impl RendezvousMediator {
             true,
             true,
             socket_addr_v6,
+            fla.quic,
         )
         .await
     }
@@ -543,6 +674,7 @@ impl RendezvousMediator {
             relay_server,
             version: crate::VERSION.to_owned(),
             socket_addr_v6,
+            quic: fla.quic,
             ..Default::default()
         });
         let bytes = msg_out.write_to_bytes()?;
@@ -551,6 +683,11 @@ impl RendezvousMediator {
         Ok(())
     }
 
+    // Behind a proxy to the rendezvous server, the peers are reached by the relay only.
+    async fn is_proxied(&self) -> bool {
+        proxy_for_async(self.host.clone()).await.is_some()
+    }
+
     async fn handle_punch_hole(&self, ph: PunchHole, server: ServerPtr) -> ResultType<()> {
         let mut peer_addr = AddrMangle::decode(&ph.socket_addr);
         let last = *LAST_MSG.lock().await;
@@ -560,10 +697,11 @@ impl RendezvousMediator {
             return Ok(());
         }
         let peer_addr_v6 = hbb_common::AddrMangle::decode(&ph.socket_addr_v6);
-        let relay = use_ws() || Config::is_proxy() || ph.force_relay;
+        let relay =
+            use_ws() || self.is_proxied().await || ph.force_relay || self.http.is_some();
         let mut socket_addr_v6 = Default::default();
         if peer_addr_v6.port() > 0 && !relay {
-            socket_addr_v6 = start_ipv6(peer_addr_v6, peer_addr, server.clone()).await;
+            socket_addr_v6 = start_ipv6(peer_addr_v6, peer_addr, server.clone(), ph.quic).await;
         }
         let relay_server = self.get_relay_server(ph.relay_server);
         // for ensure, websocket go relay directly
@@ -582,6 +720,7 @@ impl RendezvousMediator {
                     true,
                     true,
                     socket_addr_v6.clone(),
+                    ph.quic,
                 )
                 .await;
         }
@@ -594,6 +733,7 @@ impl RendezvousMediator {
             nat_type: nat_type.into(),
             version: crate::VERSION.to_owned(),
             socket_addr_v6,
+            quic: ph.quic,
             ..Default::default()
         };
         if ph.udp_port > 0 {
@@ -624,6 +764,7 @@ impl RendezvousMediator {
         server: ServerPtr,
         msg_punch: PunchHoleSent,
     ) -> ResultType<()> {
+        let quic = msg_punch.quic;
         let mut msg_out = Message::new();
         msg_out.set_punch_hole_sent(msg_punch);
         let (socket, addr) = new_direct_udp_for(&self.host).await?;
@@ -637,11 +778,16 @@ impl RendezvousMediator {
                 socket.send_to(&data, addr).await.ok();
             }
         });
-        udp_nat_listen(socket_cloned.clone(), peer_addr, peer_addr, server).await?;
+        udp_nat_listen(socket_cloned.clone(), peer_addr, peer_addr, server, quic).await?;
         Ok(())
     }
 
     async fn register_pk(&mut self, socket: Sink<'_>) -> ResultType<()> {
+        socket.send(&Self::register_pk_msg()).await?;
+        Ok(())
+    }
+
+    fn register_pk_msg() -> Message {
         let mut msg_out = Message::new();
         let pk = Config::get_key_pair().1;
         let uuid = hbb_common::get_uuid();
@@ -653,8 +799,7 @@ impl RendezvousMediator {
             no_register_device: Config::no_register_device(),
             ..Default::default()
         });
-        socket.send(&msg_out).await?;
-        Ok(())
+        msg_out
     }
 
     async fn handle_uuid_mismatch(&mut self, socket: Sink<'_>) -> ResultType<()> {
@@ -673,24 +818,26 @@ impl RendezvousMediator {
     }
 
     async fn register_peer(&mut self, socket: Sink<'_>) -> ResultType<()> {
+        if let Some(msg_out) = Self::registration(&self.host, &self.host_prefix).await {
+            socket.send(&msg_out).await?;
+        }
+        Ok(())
+    }
+
+    // The RegisterPeer, or the RegisterPk with the key not confirmed, none while another
+    // host is solving the pk mismatch.
+    async fn registration(host: &str, host_prefix: &str) -> Option<Message> {
         let solving = SOLVING_PK_MISMATCH.lock().await;
-        if !(solving.is_empty() || *solving == self.host) {
-            return Ok(());
+        if !(solving.is_empty() || *solving == host) {
+            return None;
         }
         drop(solving);
-        if !Config::get_key_confirmed() || !Config::get_host_key_confirmed(&self.host_prefix) {
-            log::info!(
-                "register_pk of {} due to key not confirmed",
-                self.host_prefix
-            );
-            return self.register_pk(socket).await;
+        if !Config::get_key_confirmed() || !Config::get_host_key_confirmed(host_prefix) {
+            log::info!("register_pk of {} due to key not confirmed", host_prefix);
+            return Some(Self::register_pk_msg());
         }
         let id = Config::get_id();
-        log::trace!(
-            "Register my id {:?} to rendezvous server {:?}",
-            id,
-            self.addr,
-        );
+        log::trace!("Register my id {:?} to rendezvous server {:?}", id, host);
         let mut msg_out = Message::new();
         let serial = Config::get_serial();
         msg_out.set_register_peer(RegisterPeer {
@@ -698,8 +845,7 @@ impl RendezvousMediator {
             serial,
             ..Default::default()
         });
-        socket.send(&msg_out).await?;
-        Ok(())
+        Some(msg_out)
     }
 
     fn get_relay_server(&self, provided_by_rendezvous_server: String) -> String {
@@ -714,6 +860,9 @@ impl RendezvousMediator {
     }
 }
 
+// The bind ips may not be up yet, e.g. of a network configured after the start.
+const DIRECT_LISTEN_RETRY: Duration = Duration::from_secs(10);
+
 fn get_direct_port() -> i32 {
     let mut port = Config::get_option("direct-access-port")
         .parse::<i32>()
@@ -724,22 +873,61 @@ fn get_direct_port() -> i32 {
     port
 }
 
+// The options the direct server is restarted on.
+fn direct_server_options() -> (i32, String, bool, String, String) {
+    (
+        get_direct_port(),
+        Config::get_option(OPTION_DIRECT_ACCESS_BIND),
+        direct_access::is_tls(),
+        Config::get_option(OPTION_DIRECT_ACCESS_CERT),
+        Config::get_option(OPTION_DIRECT_ACCESS_CLIENT_CA),
+    )
+}
+
+async fn direct_listen(
+    port: i32,
+    bind: &str,
+    tls: bool,
+) -> ResultType<(Vec<TcpListener>, Option<TlsAcceptor>)> {
+    let addrs = direct_access::bind_addrs(bind, port as _)?;
+    let listeners = if addrs.is_empty() {
+        vec![hbb_common::tcp::listen_any(port as _).await?]
+    } else {
+        let mut listeners = vec![];
+        for addr in addrs {
+            listeners.push(hbb_common::tcp::new_listener(addr, true).await?);
+        }
+        listeners
+    };
+    let acceptor = if tls {
+        Some(direct_access::acceptor()?)
+    } else {
+        None
+    };
+    Ok((listeners, acceptor))
+}
+
 async fn direct_server(server: ServerPtr) {
-    let mut listener = None;
-    let mut port = 0;
+    let mut listeners = vec![];
+    let mut acceptor = None;
+    let mut options = Default::default();
+    let mut limiter = direct_access::RateLimiter::default();
     loop {
         let disabled = !option2bool(
             OPTION_DIRECT_SERVER,
             &Config::get_option(OPTION_DIRECT_SERVER),
         ) || option2bool("stop-service", &Config::get_option("stop-service"));
-        if !disabled && listener.is_none() {
-            port = get_direct_port();
-            match hbb_common::tcp::listen_any(port as _).await {
-                Ok(l) => {
-                    listener = Some(l);
+        if !disabled && listeners.is_empty() {
+            options = direct_server_options();
+            let (port, bind, tls, ..) = &options;
+            match direct_listen(*port, bind, *tls).await {
+                Ok((l, a)) => {
+                    listeners = l;
+                    acceptor = a;
                     log::info!(
-                        "Direct server listening on: {:?}",
-                        listener.as_ref().map(|l| l.local_addr())
+                        "Direct server listening on: {:?}{}",
+                        listeners.iter().map(|l| l.local_addr()).collect::<Vec<_>>(),
+                        if acceptor.is_some() { ", TLS" } else { "" }
                     );
                 }
                 Err(err) => {
@@ -749,38 +937,36 @@ async fn direct_server(server: ServerPtr) {
                         port,
                         err
                     );
-                    loop {
-                        if port != get_direct_port() {
-                            break;
-                        }
+                    let failed = Instant::now();
+                    while options == direct_server_options()
+                        && failed.elapsed() < DIRECT_LISTEN_RETRY
+                    {
                         sleep(1.).await;
                     }
                 }
             }
         }
-        if let Some(l) = listener.as_mut() {
-            if disabled || port != get_direct_port() {
+        if !listeners.is_empty() {
+            if disabled || options != direct_server_options() {
                 log::info!("Exit direct access listen");
-                listener = None;
+                listeners.clear();
                 continue;
             }
-            if let Ok(Ok((stream, addr))) = hbb_common::timeout(1000, l.accept()).await {
+            let accept = select_all(listeners.iter().map(|l| Box::pin(l.accept())));
+            if let Ok((Ok((stream, addr)), ..)) = hbb_common::timeout(1000, accept).await {
+                if !limiter.allow(addr.ip()) {
+                    log::warn!("Too many direct access attempts from {}", addr.ip());
+                    continue;
+                }
                 stream.set_nodelay(true).ok();
                 log::info!("direct access from {}", addr);
                 let local_addr = stream
                     .local_addr()
                     .unwrap_or(Config::get_any_listen_addr(true));
                 let server = server.clone();
+                let acceptor = acceptor.clone();
                 tokio::spawn(async move {
-                    allow_err!(
-                        crate::server::create_tcp_connection(
-                            server,
-                            hbb_common::Stream::from(stream, local_addr),
-                            addr,
-                            false,
-                        )
-                        .await
-                    );
+                    allow_err!(accept_direct(server, stream, addr, local_addr, acceptor).await);
                 });
             } else {
                 sleep(0.1).await;
@@ -791,9 +977,29 @@ async fn direct_server(server: ServerPtr) {
     }
 }
 
+// Nothing of the connection is sent before the TLS handshake and the access token.
+async fn accept_direct(
+    server: ServerPtr,
+    stream: TcpStream,
+    addr: SocketAddr,
+    local_addr: SocketAddr,
+    acceptor: Option<TlsAcceptor>,
+) -> ResultType<()> {
+    let mut stream = match acceptor {
+        Some(acceptor) => direct_access::accept(&acceptor, stream, local_addr).await?,
+        None => Stream::from(stream, local_addr),
+    };
+    let token = direct_access::token();
+    if !token.is_empty() {
+        direct_access::challenge(&mut stream, &token).await?;
+    }
+    crate::server::create_tcp_connection(server, stream, addr, false).await
+}
+
 enum Sink<'a> {
     Framed(&'a mut FramedSocket, &'a TargetAddr<'a>),
     Stream(&'a mut Stream),
+    Http(&'a http::Channel),
 }
 
 impl Sink<'_> {
@@ -801,6 +1007,7 @@ impl Sink<'_> {
         match self {
             Sink::Framed(socket, addr) => socket.send(msg, addr.to_owned()).await,
             Sink::Stream(stream) => stream.send(msg).await,
+            Sink::Http(channel) => channel.send(msg).await,
         }
     }
 }
@@ -809,29 +1016,54 @@ async fn start_ipv6(
     peer_addr_v6: SocketAddr,
     peer_addr_v4: SocketAddr,
     server: ServerPtr,
+    quic: bool,
 ) -> bytes::Bytes {
     crate::test_ipv6().await;
     if let Some((socket, local_addr_v6)) = crate::get_ipv6_socket().await {
         let server = server.clone();
         tokio::spawn(async move {
-            allow_err!(udp_nat_listen(socket.clone(), peer_addr_v6, peer_addr_v4, server).await);
+            allow_err!(
+                udp_nat_listen(socket.clone(), peer_addr_v6, peer_addr_v4, server, quic).await
+            );
         });
         return local_addr_v6;
     }
     Default::default()
 }
 
+// QUIC if the peer connects with it, kcp otherwise, as the first packet tells.
 async fn udp_nat_listen(
     socket: Arc<tokio::net::UdpSocket>,
     peer_addr: SocketAddr,
     peer_addr_v4: SocketAddr,
     server: ServerPtr,
+    quic: bool,
 ) -> ResultType<()> {
     let tm = Instant::now();
     let socket_cloned = socket.clone();
     let func = async {
+        // Unconnected for QUIC, the peer may migrate to another address.
+        let mut res = crate::punch_udp(socket.clone(), Some(peer_addr), true).await?;
+        if quic && res.as_deref().is_some_and(hbb_common::quic::is_initial) {
+            // The first packet of QUIC taken by the punching is sent again.
+            match QuicFramedStream::accept(socket.clone(), peer_addr, CONNECT_TIMEOUT).await {
+                Ok(stream) => {
+                    crate::server::create_tcp_connection(
+                        server,
+                        Stream::Quic(stream),
+                        peer_addr_v4,
+                        true,
+                    )
+                    .await?;
+                    return Ok(());
+                }
+                Err(err) => {
+                    log::debug!("Failed to accept QUIC stream, fall back to kcp: {}", err);
+                    res = None;
+                }
+            }
+        }
         socket.connect(peer_addr).await?;
-        let res = crate::punch_udp(socket.clone(), true).await?;
         let stream = crate::kcp_stream::KcpStream::accept(
             socket,
             Duration::from_millis(CONNECT_TIMEOUT as _),
@@ -843,7 +1075,7 @@ async fn udp_nat_listen(
     };
     func.await.map_err(|e: anyhow::Error| {
         anyhow::anyhow!(
-            "Stop listening on {:?} for remote {peer_addr} with KCP, {:?} elapsed: {e}",
+            "Stop listening on {:?} for remote {peer_addr}, {:?} elapsed: {e}",
             socket_cloned.local_addr(),
             tm.elapsed()
         )
@@ -0,0 +1,375 @@
+// The registration over https, for the networks letting only https out, e.g. through a proxy.
+//
+// The same `RendezvousMessage`s as over UDP and TCP, in base64, one per line:
+// `POST {url}/message` sends one, the answers of the server are in the body;
+// `GET {url}/events` receives the messages for this side, `PunchHole` and `RequestRelay` among
+// them, as server-sent events in `data:` or as the lines of a long-poll body, polled again
+// when it ends. Both carry the session in `X-Rendezvous-Session`, so the server knows the id
+// registered in it. The server sends a comment as heartbeat within `IDLE_TIMEOUT` on the events.
+//
+// It is used if UDP and TCP to the server have failed, the peers are answered by the relay
+// only then, which goes over websocket on 443 too. Both are tried again after `FALLBACK_TTL`,
+// the network may have changed.
+
+use crate::hbbs_http::create_http_client_async;
+use hbb_common::{
+    base64::{engine::general_purpose::STANDARD, Engine as _},
+    config::{keys::OPTION_RENDEZVOUS_HTTP_URL, Config, CONNECT_TIMEOUT, RENDEZVOUS_PORT},
+    log,
+    protobuf::Message as _,
+    rendezvous_proto::RendezvousMessage,
+    socket_client::{check_port, split_host_port},
+    tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+    ResultType,
+};
+use reqwest::header::{ACCEPT, CONTENT_TYPE};
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+const SESSION_HEADER: &str = "X-Rendezvous-Session";
+// ms, without any line on the events, heartbeats included.
+const IDLE_TIMEOUT: u64 = 90_000;
+const FALLBACK_TTL: Duration = Duration::from_secs(30 * 60);
+
+lazy_static::lazy_static! {
+    // The last failures of UDP and TCP by the rendezvous server.
+    static ref FAILURES: Mutex<HashMap<String, (Option<Instant>, Option<Instant>)>> =
+        Default::default();
+}
+
+fn with_failures<T>(host: &str, f: impl FnOnce(&mut (Option<Instant>, Option<Instant>)) -> T) -> T {
+    let mut failures = FAILURES.lock().unwrap();
+    f(failures
+        .entry(check_port(host, RENDEZVOUS_PORT))
+        .or_default())
+}
+
+fn recent(at: &mut Option<Instant>) -> bool {
+    match at {
+        Some(t) if t.elapsed() < FALLBACK_TTL => true,
+        _ => {
+            *at = None;
+            false
+        }
+    }
+}
+
+/// Record whether the registration to `host` over TCP or UDP got through.
+pub(super) fn set_failed(host: &str, tcp: bool, failed: bool) {
+    with_failures(host, |failures| {
+        let at = if tcp {
+            &mut failures.1
+        } else {
+            &mut failures.0
+        };
+        if failed {
+            if at.is_none() {
+                log::warn!(
+                    "Rendezvous to {} over {} failed, falling back to {}",
+                    host,
+                    if tcp { "TCP" } else { "UDP" },
+                    if tcp { "https" } else { "TCP" }
+                );
+            }
+            *at = Some(Instant::now());
+        } else {
+            *at = None;
+        }
+    })
+}
+
+// UDP and TCP are tried again if https has failed too, e.g. the server is down.
+pub(super) fn reset(host: &str) {
+    FAILURES
+        .lock()
+        .unwrap()
+        .remove(&check_port(host, RENDEZVOUS_PORT));
+}
+
+pub(super) fn udp_failed(host: &str) -> bool {
+    with_failures(host, |failures| recent(&mut failures.0))
+}
+
+/// Whether the registration to `host` goes over https.
+pub(super) fn is_fallback(host: &str) -> bool {
+    with_failures(host, |failures| recent(&mut failures.1))
+}
+
+/// The relay over websocket on 443, as the rendezvous, of `relay_server`.
+pub(super) fn relay_url(relay_server: &str) -> String {
+    let host = split_host_port(relay_server)
+        .map(|(host, _)| host)
+        .unwrap_or_else(|| relay_server.to_owned());
+    format!("wss://{}/ws/relay", host)
+}
+
+/// `rendezvous-http-url`, or /rendezvous on 443 of `host`.
+pub(super) fn url(host: &str) -> String {
+    let option = Config::get_option(OPTION_RENDEZVOUS_HTTP_URL);
+    let option = option.trim().trim_end_matches('/');
+    if !option.is_empty() {
+        return option.to_owned();
+    }
+    let host = split_host_port(host)
+        .map(|(host, _)| host)
+        .unwrap_or_else(|| host.to_owned());
+    format!("https://{}/rendezvous", host)
+}
+
+#[derive(Clone)]
+pub(super) struct Channel {
+    client: reqwest::Client,
+    url: String,
+    session: String,
+    tx: UnboundedSender<RendezvousMessage>,
+}
+
+impl Channel {
+    /// The messages of the server, the answers to `send` included, go to the receiver.
+    pub(super) async fn new(host: &str) -> (Self, UnboundedReceiver<RendezvousMessage>) {
+        Self::with_url(url(host)).await
+    }
+
+    async fn with_url(url: String) -> (Self, UnboundedReceiver<RendezvousMessage>) {
+        let (tx, rx) = unbounded_channel();
+        let channel = Self {
+            client: create_http_client_async(&url).await,
+            url,
+            session: uuid::Uuid::new_v4().to_string(),
+            tx,
+        };
+        (channel, rx)
+    }
+
+    pub(super) fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub(super) async fn send(&self, msg: &RendezvousMessage) -> ResultType<()> {
+        let body = STANDARD.encode(msg.write_to_bytes()?);
+        let request = self
+            .client
+            .post(format!("{}/message", self.url))
+            .header(SESSION_HEADER, &self.session)
+            .header(CONTENT_TYPE, "text/plain")
+            .body(body)
+            .send();
+        let resp = hbb_common::timeout(CONNECT_TIMEOUT, request)
+            .await??
+            .error_for_status()?;
+        self.read(resp).await
+    }
+
+    /// Polls the events until it fails.
+    pub(super) async fn poll(&self) -> ResultType<()> {
+        loop {
+            let request = self
+                .client
+                .get(format!("{}/events", self.url))
+                .header(SESSION_HEADER, &self.session)
+                .header(ACCEPT, "text/event-stream")
+                .send();
+            let resp = hbb_common::timeout(CONNECT_TIMEOUT, request)
+                .await??
+                .error_for_status()?;
+            self.read(resp).await?;
+        }
+    }
+
+    async fn read(&self, mut resp: reqwest::Response) -> ResultType<()> {
+        let mut lines = Lines::default();
+        while let Some(chunk) = hbb_common::timeout(IDLE_TIMEOUT, resp.chunk()).await?? {
+            for msg in lines.push(&chunk) {
+                self.tx.send(msg).ok();
+            }
+        }
+        if let Some(msg) = lines.finish() {
+            self.tx.send(msg).ok();
+        }
+        Ok(())
+    }
+}
+
+// The lines split across the chunks of a body.
+#[derive(Default)]
+struct Lines(Vec<u8>);
+
+impl Lines {
+    fn push(&mut self, chunk: &[u8]) -> Vec<RendezvousMessage> {
+        self.0.extend_from_slice(chunk);
+        let mut msgs = vec![];
+        while let Some(pos) = self.0.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.0.drain(..=pos).collect();
+            msgs.extend(parse_line(&line));
+        }
+        msgs
+    }
+
+    fn finish(&mut self) -> Option<RendezvousMessage> {
+        parse_line(&std::mem::take(&mut self.0))
+    }
+}
+
+// The other fields of the events, and the comments, have a colon base64 has not.
+fn parse_line(line: &[u8]) -> Option<RendezvousMessage> {
+    let line = std::str::from_utf8(line).ok()?.trim();
+    let data = match line.strip_prefix("data:") {
+        Some(data) => data.trim(),
+        None if line.contains(':') => return None,
+        None => line,
+    };
+    if data.is_empty() {
+        return None;
+    }
+    let bytes = STANDARD.decode(data).ok()?;
+    RendezvousMessage::parse_from_bytes(&bytes).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hbb_common::{rendezvous_proto::*, socket_client::connect_tcp_local};
+    use std::net::Ipv4Addr;
+
+    async fn next(rx: &mut UnboundedReceiver<RendezvousMessage>) -> rendezvous_message::Union {
+        hbb_common::timeout(3000, rx.recv())
+            .await
+            .unwrap()
+            .unwrap()
+            .union
+            .unwrap()
+    }
+
+    #[test]
+    fn test_lines() {
+        let mut msg = RendezvousMessage::new();
+        msg.set_punch_hole(PunchHole {
+            relay_server: "relay.example.com".to_owned(),
+            ..Default::default()
+        });
+        let line = STANDARD.encode(msg.write_to_bytes().unwrap());
+        let events = format!(
+            ": heartbeat\n\nevent: message\ndata: {}\r\n\n{}",
+            line, line
+        );
+        let (a, b) = events.as_bytes().split_at(20);
+        let mut lines = Lines::default();
+        assert!(lines.push(a).is_empty());
+        let msgs = lines.push(b);
+        assert_eq!(msgs.len(), 1);
+        assert_eq!(msgs[0].punch_hole().relay_server, "relay.example.com");
+        assert_eq!(lines.finish(), Some(msg));
+        assert_eq!(lines.finish(), None);
+        assert!(parse_line(b"data: not base64").is_none());
+    }
+
+    #[test]
+    fn test_failures() {
+        let (a, b) = ("a.example.com", "b.example.com:21116");
+        set_failed(a, true, true);
+        assert!(is_fallback("a.example.com:21116"));
+        assert!(!is_fallback(b));
+        set_failed(b, false, true);
+        assert!(udp_failed(b) && !is_fallback(b));
+        assert!(!udp_failed(a));
+        reset(a);
+        assert!(!is_fallback(a));
+        assert!(udp_failed(b));
+    }
+
+    #[test]
+    fn test_relay_url() {
+        assert_eq!(
+            relay_url("relay.example.com:21117"),
+            "wss://relay.example.com/ws/relay"
+        );
+        assert_eq!(
+            relay_url("relay.example.com"),
+            "wss://relay.example.com/ws/relay"
+        );
+        assert_eq!(
+            relay_url("[2001:db8::1]:21117"),
+            "wss://[2001:db8::1]/ws/relay"
+        );
+    }
+
+    // Against the http of the embedded server, the controlling side on tcp.
+    #[tokio::test]
+    async fn test_channel() {
+        let dir = std::env::temp_dir().join(format!("rendezvous_http_{}", std::process::id()));
+        let server =
+            crate::embedded_server::EmbeddedServer::start(&[Ipv4Addr::LOCALHOST.into()], 0, &dir)
+                .await
+                .unwrap();
+        let (channel, mut rx) = Channel::with_url(format!(
+            "http://127.0.0.1:{}/rendezvous",
+            server.http_port()
+        ))
+        .await;
+        let poll = channel.clone();
+        let events = tokio::spawn(async move { poll.poll().await });
+
+        let mut msg = RendezvousMessage::new();
+        msg.set_register_peer(RegisterPeer {
+            id: "http1".to_owned(),
+            ..Default::default()
+        });
+        channel.send(&msg).await.unwrap();
+        let rendezvous_message::Union::RegisterPeerResponse(_) = next(&mut rx).await else {
+            panic!("no register peer response");
+        };
+
+        // The request comes on the events, the answer goes back to the tcp of the requester.
+        let mut client = connect_tcp_local(format!("127.0.0.1:{}", server.ports().1), None, 3000)
+            .await
+            .unwrap();
+        let mut msg = RendezvousMessage::new();
+        msg.set_request_relay(RequestRelay {
+            id: "http1".to_owned(),
+            uuid: "uuid1".to_owned(),
+            ..Default::default()
+        });
+        client.send(&msg).await.unwrap();
+        let rendezvous_message::Union::RequestRelay(rr) = next(&mut rx).await else {
+            panic!("no request relay");
+        };
+        assert_eq!(rr.uuid, "uuid1");
+        let mut msg = RendezvousMessage::new();
+        msg.set_relay_response(RelayResponse {
+            socket_addr: rr.socket_addr,
+            uuid: rr.uuid,
+            ..Default::default()
+        });
+        channel.send(&msg).await.unwrap();
+        loop {
+            let bytes = client.next_timeout(3000).await.unwrap().unwrap();
+            match RendezvousMessage::parse_from_bytes(&bytes).unwrap().union {
+                Some(rendezvous_message::Union::KeyExchange(_)) => continue,
+                Some(rendezvous_message::Union::RelayResponse(rr)) => {
+                    assert_eq!(rr.uuid, "uuid1");
+                    break;
+                }
+                _ => panic!("no relay response"),
+            }
+        }
+        // The events are still polled.
+        assert!(!events.is_finished());
+        events.abort();
+    }
+
+    #[test]
+    fn test_url() {
+        assert_eq!(
+            url("rs.example.com:21116"),
+            "https://rs.example.com/rendezvous"
+        );
+        assert_eq!(url("rs.example.com"), "https://rs.example.com/rendezvous");
+        assert_eq!(
+            url("[2001:db8::1]:21116"),
+            "https://[2001:db8::1]/rendezvous"
+        );
+    }
+}
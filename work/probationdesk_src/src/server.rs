@@ -33,8 +33,13 @@ use video_service::VideoSource;
 use crate::ipc::Data;
 
 pub mod audio_service;
+pub mod bandwidth;
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+mod terminal_exec;
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 pub mod terminal_service;
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+mod inventory;
 cfg_if::cfg_if! {
 if #[cfg(not(target_os = "ios"))] {
 mod clipboard_service;
@@ -65,9 +70,11 @@ pub mod input_service {
 }
 
 mod connection;
+mod control_token;
 pub mod display_service;
 #[cfg(windows)]
 pub mod portable_service;
+mod screenshot;
 mod service;
 mod video_qos;
 pub mod video_service;
@@ -175,9 +182,14 @@ pub async fn create_tcp_connection(
     secure: bool,
 ) -> ResultType<()> {
     let mut stream = stream;
+    let strict = crate::common::is_strict_encryption();
     let id = server.write().unwrap().get_new_id();
     let (sk, pk) = Config::get_key_pair();
-    if secure && pk.len() == sign::PUBLICKEYBYTES && sk.len() == sign::SECRETKEYBYTES {
+    let secure = secure && pk.len() == sign::PUBLICKEYBYTES && sk.len() == sign::SECRETKEYBYTES;
+    if strict && !secure {
+        bail!("Handshake failed: connection without encryption from {} refused", addr);
+    }
+    if secure {
         let mut sk_ = [0u8; sign::SECRETKEYBYTES];
         sk_[..].copy_from_slice(&sk);
         let sk = sign::SecretKey(sk_);
@@ -188,6 +200,7 @@ pub async fn create_tcp_connection(
                 &IdPk {
                     id: Config::get_id(),
                     pk: Bytes::from(our_pk_b.0.to_vec()),
+                    key_exchange: tcp::KEY_EXCHANGE_FORWARD_SECRET,
                     ..Default::default()
                 }
                 .write_to_bytes()
@@ -203,7 +216,14 @@ pub async fn create_tcp_connection(
                 let bytes = res?;
                 if let Ok(msg_in) = Message::parse_from_bytes(&bytes) {
                     if let Some(message::Union::PublicKey(pk)) = msg_in.union {
-                        if pk.asymmetric_value.len() == box_::PUBLICKEYBYTES {
+                        if pk.key_exchange == tcp::KEY_EXCHANGE_FORWARD_SECRET {
+                            stream.set_encrypt(tcp::Encrypt::forward_secret(
+                                &our_sk_b,
+                                &our_pk_b,
+                                &pk.asymmetric_value,
+                                false,
+                            )?);
+                        } else if pk.asymmetric_value.len() == box_::PUBLICKEYBYTES {
                             stream.set_key(tcp::Encrypt::decode(
                                 &pk.symmetric_value,
                                 &pk.asymmetric_value,
@@ -212,9 +232,14 @@ pub async fn create_tcp_connection(
                         } else if pk.asymmetric_value.is_empty() {
                             Config::set_key_confirmed(false);
                             log::info!("Force to update pk");
+                            if strict {
+                                bail!("Handshake failed: peer refused encryption");
+                            }
                         } else {
                             bail!("Handshake failed: invalid public sign key length from peer");
                         }
+                    } else if strict {
+                        bail!("Handshake failed: invalid message type");
                     } else {
                         log::error!("Handshake failed: invalid message type");
                     }
@@ -261,10 +286,9 @@ pub async fn create_relay_connection(
     uuid: String,
     peer_addr: SocketAddr,
     secure: bool,
-    ipv4: bool,
 ) {
     if let Err(err) =
-        create_relay_connection_(server, relay_server, uuid.clone(), peer_addr, secure, ipv4).await
+        create_relay_connection_(server, relay_server, uuid.clone(), peer_addr, secure).await
     {
         log::error!(
             "Failed to create relay connection for {} with uuid {}: {}",
@@ -281,13 +305,11 @@ async fn create_relay_connection_(
     uuid: String,
     peer_addr: SocketAddr,
     secure: bool,
-    ipv4: bool,
 ) -> ResultType<()> {
-    let mut stream = socket_client::connect_tcp(
-        socket_client::ipv4_to_ipv6(crate::check_port(relay_server, RELAY_PORT), ipv4),
-        CONNECT_TIMEOUT,
-    )
-    .await?;
+    // Either family of the relay server, raced.
+    let mut stream =
+        socket_client::connect_tcp(crate::check_port(relay_server, RELAY_PORT), CONNECT_TIMEOUT)
+            .await?;
     let mut msg_out = RendezvousMessage::new();
     let licence_key = crate::get_key(true).await;
     msg_out.set_request_relay(RequestRelay {
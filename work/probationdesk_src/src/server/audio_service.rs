@@ -15,15 +15,25 @@
 use super::*;
 #[cfg(not(any(target_os = "linux", target_os = "android")))]
 use hbb_common::anyhow::anyhow;
-use magnum_opus::{Application::*, Channels::*, Encoder};
-use std::sync::atomic::{AtomicBool, Ordering};
+use magnum_opus::{Application::*, Channels, Channels::*, Encoder};
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicU32, Ordering};
 
 pub const NAME: &'static str = "audio";
 pub const AUDIO_DATA_SIZE_U8: usize = 960 * 4; // 10ms in 48000 stereo
+pub const CONFIG_AUDIO_SOURCES: &'static str = "audio-sources";
 static RESTARTING: AtomicBool = AtomicBool::new(false);
+static SEQ: AtomicU32 = AtomicU32::new(0);
+// The packet loss the encoder is set to, -1 for none.
+static ENCODER_LOSS: AtomicI32 = AtomicI32::new(-1);
 
 lazy_static::lazy_static! {
     static ref VOICE_CALL_INPUT_DEVICE: Arc::<Mutex::<Option<String>>> = Default::default();
+    // (source id, gain) selected by the controller, mixed instead of the default input.
+    static ref AUDIO_SOURCES: Arc::<Mutex::<Vec<(String, f32)>>> = Default::default();
+    // The selection of each connection, the latest one applies.
+    static ref AUDIO_SOURCES_CONNS: Arc::<Mutex::<Vec<(i32, Vec<(String, f32)>)>>> = Default::default();
+    // The audio loss in percent reported by each connection, see `TestDelay.audio_loss`.
+    static ref PACKET_LOSS: Arc::<Mutex::<HashMap<i32, u32>>> = Default::default();
 }
 
 #[cfg(not(any(target_os = "linux", target_os = "android")))]
@@ -58,6 +68,81 @@ pub fn set_voice_call_input_device(device: Option<String>, set_if_present: bool)
     restart();
 }
 
+/// Selects the sources of the connection, an empty selection drops it.
+pub fn set_audio_sources(conn_id: i32, sources: Vec<(String, f32)>) {
+    let mut conns = AUDIO_SOURCES_CONNS.lock().unwrap();
+    conns.retain(|(id, _)| *id != conn_id);
+    if !sources.is_empty() {
+        conns.push((conn_id, sources));
+    }
+    apply_audio_sources(&conns);
+}
+
+/// Drops the selection of the closed connection, the sources fall back to the selection of
+/// the connection selecting last, or to the default input if none is left.
+pub fn reset_audio_sources(conn_id: i32) {
+    let mut conns = AUDIO_SOURCES_CONNS.lock().unwrap();
+    if conns.iter().any(|(id, _)| *id == conn_id) {
+        conns.retain(|(id, _)| *id != conn_id);
+        apply_audio_sources(&conns);
+    }
+}
+
+fn apply_audio_sources(conns: &[(i32, Vec<(String, f32)>)]) {
+    let sources = conns.last().map(|(_, s)| s.clone()).unwrap_or_default();
+    if *AUDIO_SOURCES.lock().unwrap() == sources {
+        return;
+    }
+    *AUDIO_SOURCES.lock().unwrap() = sources;
+    restart();
+}
+
+/// The loss of the audio frames measured by the connection, the FEC data of the encoder
+/// follows the worst one.
+pub fn set_packet_loss(conn_id: i32, perc: u32) {
+    PACKET_LOSS.lock().unwrap().insert(conn_id, perc.min(100));
+}
+
+pub fn remove_packet_loss(conn_id: i32) {
+    PACKET_LOSS.lock().unwrap().remove(&conn_id);
+}
+
+fn new_encoder(sample_rate: u32, channels: Channels) -> ResultType<Encoder> {
+    let mut encoder = Encoder::new(sample_rate, channels, LowDelay)?;
+    // The lost frame is recovered from the next one, see `AudioHandler::handle_frame`.
+    encoder.set_inband_fec(true)?;
+    ENCODER_LOSS.store(-1, Ordering::Relaxed);
+    Ok(encoder)
+}
+
+// The more loss, the more bits of the FEC data, none without loss.
+fn update_packet_loss(encoder: &mut Encoder) {
+    let perc = PACKET_LOSS
+        .lock()
+        .unwrap()
+        .values()
+        .max()
+        .cloned()
+        .unwrap_or_default() as i32;
+    if ENCODER_LOSS.swap(perc, Ordering::Relaxed) != perc {
+        allow_err!(encoder.set_packet_loss_perc(perc));
+    }
+}
+
+// Query the audio sources from the user session, where `start_pa` runs.
+#[cfg(target_os = "linux")]
+pub async fn get_audio_sources() -> ResultType<Vec<AudioSource>> {
+    let mut stream = crate::ipc::connect(1000, "_pa_sources").await?;
+    stream
+        .send(&crate::ipc::Data::Config((
+            CONFIG_AUDIO_SOURCES.to_owned(),
+            None,
+        )))
+        .await?;
+    let data = timeout(3000, stream.next_raw()).await??;
+    Ok(AudioSources::parse_from_bytes(&data)?.sources)
+}
+
 #[inline]
 fn get_audio_input() -> String {
     VOICE_CALL_INPUT_DEVICE
@@ -100,16 +185,21 @@ mod pa_impl {
         unsafe {
             AUDIO_ZERO_COUNT = 0;
         }
-        let mut encoder = Encoder::new(crate::platform::PA_SAMPLE_RATE, Stereo, LowDelay)?;
+        let mut encoder = new_encoder(crate::platform::PA_SAMPLE_RATE, Stereo)?;
         #[cfg(target_os = "linux")]
-        allow_err!(
-            stream
-                .send(&crate::ipc::Data::Config((
-                    "audio-input".to_owned(),
-                    Some(super::get_audio_input())
-                )))
-                .await
-        );
+        {
+            // The voice call input device has priority over the selected sources.
+            let sources = AUDIO_SOURCES.lock().unwrap().clone();
+            let config = if sources.is_empty() || get_voice_call_input_device().is_some() {
+                ("audio-input".to_owned(), Some(super::get_audio_input()))
+            } else {
+                (
+                    CONFIG_AUDIO_SOURCES.to_owned(),
+                    serde_json::to_string(&sources).ok(),
+                )
+            };
+            allow_err!(stream.send(&crate::ipc::Data::Config(config)).await);
+        }
         #[cfg(target_os = "linux")]
         let zero_audio_frame: Vec<f32> = vec![0.; AUDIO_DATA_SIZE_U8 / 4];
         #[cfg(target_os = "android")]
@@ -404,7 +494,7 @@ mod cpal_impl {
             AUDIO_ZERO_COUNT = 0;
         }
         let device_channel = config.channels();
-        let mut encoder = Encoder::new(sample_rate, encode_channel, LowDelay)?;
+        let mut encoder = new_encoder(sample_rate, encode_channel)?;
         // https://www.opus-codec.org/docs/html_api/group__opusencoder.html#gace941e4ef26ed844879fde342ffbe546
         // https://chromium.googlesource.com/chromium/deps/opus/+/1.1.1/include/opus.h
         // Do not set `frame_size = sample_rate as usize / 100;`
@@ -459,6 +549,21 @@ fn create_format_msg(sample_rate: u32, channels: u16) -> Message {
     msg
 }
 
+// The sequence lets the controlling side tell the lost frames, and the timestamp of the capture
+// aligns the audio with the video, see `client::jitter_buffer`.
+fn new_frame(data: Vec<u8>) -> AudioFrame {
+    let mut seq = SEQ.fetch_add(1, Ordering::Relaxed).wrapping_add(1);
+    if seq == 0 {
+        seq = SEQ.fetch_add(1, Ordering::Relaxed).wrapping_add(1);
+    }
+    AudioFrame {
+        data: data.into(),
+        seq,
+        timestamp: hbb_common::get_time(),
+        ..Default::default()
+    }
+}
+
 // use AUDIO_ZERO_COUNT for the Noise(Zero) Gate Attack Time
 // every audio data length is set to 480
 // MAX_AUDIO_ZERO_COUNT=800 is similar as Gate Attack Time 3~5s(Linux) || 6~8s(Windows)
@@ -482,6 +587,7 @@ fn send_f32(data: &[f32], encoder: &mut Encoder, sp: &GenericService) {
             AUDIO_ZERO_COUNT += 1;
         }
     }
+    update_packet_loss(encoder);
     #[cfg(target_os = "android")]
     {
         // the permitted opus data size are 120, 240, 480, 960, 1920, and 2880
@@ -497,10 +603,7 @@ fn send_f32(data: &[f32], encoder: &mut Encoder, sp: &GenericService) {
                 {
                     Ok(data) => {
                         let mut msg_out = Message::new();
-                        msg_out.set_audio_frame(AudioFrame {
-                            data: data.into(),
-                            ..Default::default()
-                        });
+                        msg_out.set_audio_frame(new_frame(data));
                         sp.send(msg_out);
                     }
                     Err(_) => {}
@@ -516,10 +619,7 @@ fn send_f32(data: &[f32], encoder: &mut Encoder, sp: &GenericService) {
     match encoder.encode_vec_float(data, data.len() * 6) {
         Ok(data) => {
             let mut msg_out = Message::new();
-            msg_out.set_audio_frame(AudioFrame {
-                data: data.into(),
-                ..Default::default()
-            });
+            msg_out.set_audio_frame(new_frame(data));
             sp.send(msg_out);
         }
         Err(_) => {}
@@ -0,0 +1,460 @@
+// Bandwidth caps and traffic accounting of the connections.
+//
+// The upload and download are capped by the options, split equally between the authorized
+// connections sending, and by the limits of the connections set in the connection manager.
+// Within a connection, the capped upload is shared fairly by video, file transfer, clipboard and
+// port forwarding, each of the active ones gets an equal share: video by lowering its bitrate,
+// see `VideoQoS::user_bandwidth`, the others by waiting.
+// Audio and the control messages are small and latency sensitive, they are only counted.
+
+use hbb_common::{
+    config::{keys, Config},
+    message_proto::{message, Message},
+};
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+// The bucket holds the bytes of half a second, a burst above the cap.
+const BURST_SECS: f64 = 0.5;
+// A category is active if it has sent within this time.
+const ACTIVE_TIME: Duration = Duration::from_secs(2);
+/// The longest a connection waits for its budget at once, to keep it responsive.
+pub const MAX_WAIT: Duration = Duration::from_millis(500);
+
+// The number of the authorized connections sending, they share the global caps.
+static CONNS: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Category {
+    Video,
+    Audio,
+    File,
+    Clipboard,
+    PortForward,
+    Other,
+}
+
+const SHARED: [Category; 4] = [
+    Category::Video,
+    Category::File,
+    Category::Clipboard,
+    Category::PortForward,
+];
+
+impl Category {
+    pub fn of(msg: &Message) -> Self {
+        match &msg.union {
+            Some(message::Union::VideoFrame(_)) => Self::Video,
+            Some(message::Union::AudioFrame(_)) => Self::Audio,
+            Some(message::Union::FileResponse(_)) | Some(message::Union::FileAction(_)) => {
+                Self::File
+            }
+            Some(message::Union::Clipboard(_))
+            | Some(message::Union::MultiClipboards(_))
+            | Some(message::Union::Cliprdr(_)) => Self::Clipboard,
+            _ => Self::Other,
+        }
+    }
+
+    fn share_index(self) -> Option<usize> {
+        SHARED.iter().position(|c| *c == self)
+    }
+}
+
+/// Bytes by category.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Traffic {
+    pub video: u64,
+    pub audio: u64,
+    pub file: u64,
+    pub clipboard: u64,
+    pub port_forward: u64,
+    pub other: u64,
+}
+
+impl Traffic {
+    fn add(&mut self, category: Category, bytes: u64) {
+        let v = match category {
+            Category::Video => &mut self.video,
+            Category::Audio => &mut self.audio,
+            Category::File => &mut self.file,
+            Category::Clipboard => &mut self.clipboard,
+            Category::PortForward => &mut self.port_forward,
+            Category::Other => &mut self.other,
+        };
+        *v += bytes;
+    }
+
+    pub fn total(&self) -> u64 {
+        self.video + self.audio + self.file + self.clipboard + self.port_forward + self.other
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TrafficStats {
+    pub upload: Traffic,
+    pub download: Traffic,
+}
+
+// A token bucket in bytes, a large message may take it into debt.
+#[derive(Debug)]
+struct Bucket {
+    // Bytes per second, 0 for unlimited.
+    rate: u64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl Bucket {
+    fn new() -> Self {
+        Self {
+            rate: 0,
+            tokens: 0.,
+            last: Instant::now(),
+        }
+    }
+
+    fn set_rate(&mut self, rate: u64) {
+        if rate != self.rate {
+            self.refill();
+            self.rate = rate;
+            self.tokens = self.tokens.min(self.burst());
+        }
+    }
+
+    fn burst(&self) -> f64 {
+        self.rate as f64 * BURST_SECS
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.last = now;
+        self.tokens = (self.tokens + elapsed * self.rate as f64).min(self.burst());
+    }
+
+    fn take(&mut self, bytes: u64) {
+        if self.rate > 0 {
+            self.refill();
+            self.tokens -= bytes as f64;
+        }
+    }
+
+    // Until the debt is paid.
+    fn wait(&mut self) -> Duration {
+        if self.rate == 0 {
+            return Duration::ZERO;
+        }
+        self.refill();
+        if self.tokens >= 0. {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate as f64)
+        }
+    }
+}
+
+/// The traffic of a connection, counted and capped.
+#[derive(Debug)]
+pub struct Accounting {
+    stats: TrafficStats,
+    reported: TrafficStats,
+    // KB/s, set in the connection manager, 0 for the global caps only.
+    limit: (u32, u32),
+    upload: Bucket,
+    download: Bucket,
+    // The fair shares of the upload, by `SHARED`.
+    shares: [Bucket; SHARED.len()],
+    last_sent: [Option<Instant>; SHARED.len()],
+    authorized: bool,
+    // The last upload other than the control messages.
+    last_upload: Option<Instant>,
+    // Counted in `CONNS`.
+    sharing: bool,
+}
+
+impl Default for Accounting {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Accounting {
+    fn drop(&mut self) {
+        self.set_sharing(false);
+    }
+}
+
+// KB/s of the option, 0 for unlimited.
+fn get_limit_option(key: &str) -> u32 {
+    Config::get_option(key).trim().parse().unwrap_or(0)
+}
+
+// The smaller of the caps, 0 for unlimited.
+fn min_cap(a: u64, b: u64) -> u64 {
+    match (a, b) {
+        (0, b) => b,
+        (a, 0) => a,
+        (a, b) => a.min(b),
+    }
+}
+
+impl Accounting {
+    pub fn new() -> Self {
+        let mut accounting = Self {
+            stats: Default::default(),
+            reported: Default::default(),
+            limit: (0, 0),
+            upload: Bucket::new(),
+            download: Bucket::new(),
+            shares: [Bucket::new(), Bucket::new(), Bucket::new(), Bucket::new()],
+            last_sent: [None; SHARED.len()],
+            authorized: false,
+            last_upload: None,
+            sharing: false,
+        };
+        accounting.update();
+        accounting
+    }
+
+    /// The connection is logged in, it shares the global caps while it sends.
+    pub fn set_authorized(&mut self) {
+        self.authorized = true;
+        self.update();
+    }
+
+    fn set_sharing(&mut self, sharing: bool) {
+        if sharing != self.sharing {
+            self.sharing = sharing;
+            if sharing {
+                CONNS.fetch_add(1, Ordering::SeqCst);
+            } else {
+                CONNS.fetch_sub(1, Ordering::SeqCst);
+            }
+        }
+    }
+
+    /// The limits in KB/s set in the connection manager, 0 for the global caps only.
+    pub fn set_limit(&mut self, upload: u32, download: u32) {
+        self.limit = (upload, download);
+        self.update();
+    }
+
+    /// Applies the changes of the options and the connections, called every second.
+    pub fn update(&mut self) {
+        let sending = self.last_upload.is_some_and(|t| t.elapsed() < ACTIVE_TIME);
+        self.set_sharing(self.authorized && sending);
+        let conns = CONNS.load(Ordering::SeqCst).max(1) as u64;
+        let cap = |option: &str, limit: u32| {
+            let global = get_limit_option(option) as u64 * 1024 / conns;
+            min_cap(global, limit as u64 * 1024)
+        };
+        self.upload
+            .set_rate(cap(keys::OPTION_UPLOAD_LIMIT, self.limit.0));
+        self.download
+            .set_rate(cap(keys::OPTION_DOWNLOAD_LIMIT, self.limit.1));
+        self.update_shares();
+    }
+
+    fn update_shares(&mut self) {
+        let now = Instant::now();
+        let active = self
+            .last_sent
+            .iter()
+            .filter(|t| t.is_some_and(|t| now.duration_since(t) < ACTIVE_TIME))
+            .count()
+            .max(1) as u64;
+        let rate = self.upload.rate / active;
+        for share in self.shares.iter_mut() {
+            share.set_rate(rate);
+        }
+    }
+
+    pub fn on_upload(&mut self, category: Category, bytes: u64) {
+        self.stats.upload.add(category, bytes);
+        if category != Category::Other {
+            let newly_sending = !self.last_upload.is_some_and(|t| t.elapsed() < ACTIVE_TIME);
+            self.last_upload = Some(Instant::now());
+            if newly_sending && self.authorized {
+                self.update();
+            }
+        }
+        self.upload.take(bytes);
+        if let Some(i) = category.share_index() {
+            let newly_active = !self.last_sent[i].is_some_and(|t| t.elapsed() < ACTIVE_TIME);
+            self.last_sent[i] = Some(Instant::now());
+            if newly_active {
+                self.update_shares();
+            }
+            self.shares[i].take(bytes);
+        }
+    }
+
+    pub fn on_download(&mut self, category: Category, bytes: u64) {
+        self.stats.download.add(category, bytes);
+        self.download.take(bytes);
+    }
+
+    /// How long `category` should wait before sending more, zero if it may send now.
+    pub fn upload_wait(&mut self, category: Category) -> Duration {
+        let share = match category.share_index() {
+            Some(i) => self.shares[i].wait(),
+            None => Duration::ZERO,
+        };
+        share.max(self.upload.wait())
+    }
+
+    /// How long to stop reading from the peer, zero if not over the cap.
+    pub fn download_wait(&mut self) -> Duration {
+        self.download.wait()
+    }
+
+    /// The bitrate for video in kbps, `None` if unlimited.
+    pub fn video_budget_kbps(&self) -> Option<u32> {
+        let rate = self.shares[0].rate;
+        if rate > 0 {
+            Some((rate * 8 / 1000) as u32)
+        } else {
+            None
+        }
+    }
+
+    pub fn stats(&self) -> TrafficStats {
+        self.stats
+    }
+
+    /// The stats if they have changed since the last call, for the connection manager.
+    pub fn take_changed(&mut self) -> Option<TrafficStats> {
+        if self.stats == self.reported {
+            return None;
+        }
+        self.reported = self.stats;
+        Some(self.stats)
+    }
+}
+
+// The formats of a clipboard message, `None` for those of the file copy and paste, which are
+// all sent.
+fn clipboard_formats(msg: &Message) -> Option<Vec<(i32, &str)>> {
+    let clipboards = match &msg.union {
+        Some(message::Union::Clipboard(c)) => std::slice::from_ref(c),
+        Some(message::Union::MultiClipboards(mc)) => &mc.clipboards[..],
+        _ => return None,
+    };
+    let mut formats: Vec<_> = clipboards
+        .iter()
+        .map(|c| (c.format.value(), c.special_name.as_str()))
+        .collect();
+    formats.sort();
+    Some(formats)
+}
+
+/// Queues a clipboard waiting for the upload budget, in place of a pending one of the same
+/// formats, only the latest content is worth sending.
+pub fn push_clipboard(queue: &mut VecDeque<Arc<Message>>, msg: Arc<Message>) {
+    if let Some(formats) = clipboard_formats(&msg) {
+        queue.retain(|m| clipboard_formats(m).as_ref() != Some(&formats));
+    }
+    queue.push_back(msg);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hbb_common::message_proto::{Clipboard, ClipboardFormat, Cliprdr, MultiClipboards};
+
+    fn clipboard(format: ClipboardFormat, content: &str) -> Arc<Message> {
+        let mut msg = Message::new();
+        msg.set_clipboard(Clipboard {
+            content: content.as_bytes().to_vec().into(),
+            format: format.into(),
+            ..Default::default()
+        });
+        Arc::new(msg)
+    }
+
+    #[test]
+    fn test_push_clipboard() {
+        let mut queue = VecDeque::new();
+        push_clipboard(&mut queue, clipboard(ClipboardFormat::Text, "a"));
+        push_clipboard(&mut queue, clipboard(ClipboardFormat::ImagePng, "b"));
+        let mut cliprdr = Message::new();
+        cliprdr.set_cliprdr(Cliprdr::new());
+        push_clipboard(&mut queue, Arc::new(cliprdr.clone()));
+        push_clipboard(&mut queue, Arc::new(cliprdr));
+        push_clipboard(&mut queue, clipboard(ClipboardFormat::Text, "c"));
+        assert_eq!(queue.len(), 4);
+        assert_eq!(queue[3].clipboard().content.as_ref(), b"c");
+
+        let mut multi = Message::new();
+        multi.set_multi_clipboards(MultiClipboards {
+            clipboards: vec![queue[0].clipboard().clone(), queue[3].clipboard().clone()],
+            ..Default::default()
+        });
+        push_clipboard(&mut queue, Arc::new(multi.clone()));
+        push_clipboard(&mut queue, Arc::new(multi));
+        // The image and the text alone stay, the same two formats are replaced.
+        assert_eq!(queue.len(), 5);
+    }
+
+    #[test]
+    fn test_fair_share() {
+        let mut a = Accounting::new();
+        a.set_limit(100, 0);
+        let rate = a.upload.rate;
+        assert!(rate > 0 && rate <= 100 * 1024);
+        assert_eq!(a.download_wait(), Duration::ZERO);
+
+        // Alone, file transfer gets the whole cap.
+        a.on_upload(Category::File, 1024);
+        assert_eq!(a.shares[2].rate, rate);
+        // Video joins, both get half.
+        a.on_upload(Category::Video, 1024);
+        assert_eq!(a.shares[2].rate, rate / 2);
+        assert_eq!(a.video_budget_kbps(), Some((rate / 2 * 8 / 1000) as u32));
+
+        // Over the share, the file transfer waits, audio does not.
+        a.on_upload(Category::File, rate);
+        assert!(a.upload_wait(Category::File) > a.upload_wait(Category::Audio));
+        a.on_upload(Category::Audio, 100);
+        let stats = a.stats();
+        assert_eq!(stats.upload.file, rate + 1024);
+        assert_eq!(stats.upload.total(), rate + 1024 * 2 + 100);
+        assert_eq!(a.take_changed(), Some(stats));
+        assert_eq!(a.take_changed(), None);
+
+        a.set_limit(0, 0);
+        assert_eq!(a.upload_wait(Category::File), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_sharing() {
+        let conns = || CONNS.load(Ordering::SeqCst);
+        let before = conns();
+        let mut a = Accounting::new();
+        let mut b = Accounting::new();
+        assert_eq!(conns(), before);
+        // Sending before the login does not count.
+        a.on_upload(Category::Video, 1024);
+        assert_eq!(conns(), before);
+        a.set_authorized();
+        assert_eq!(conns(), before + 1);
+        // Nor logged in without sending, the control messages aside.
+        b.set_authorized();
+        b.on_upload(Category::Other, 100);
+        assert_eq!(conns(), before + 1);
+        b.on_upload(Category::File, 1024);
+        assert_eq!(conns(), before + 2);
+        drop(b);
+        assert_eq!(conns(), before + 1);
+        drop(a);
+        assert_eq!(conns(), before);
+    }
+}
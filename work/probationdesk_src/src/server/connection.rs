@@ -14,6 +14,7 @@ use crate::platform::WallPaperRemover;
 #[cfg(windows)]
 use crate::portable_service::client as portable_client;
 use crate::{
+    chat_history,
     client::{
         new_voice_call_request, new_voice_call_response, start_audio_thread, MediaData, MediaSender,
     },
@@ -50,6 +51,7 @@ use serde_json::{json, value::Value};
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 use std::sync::atomic::Ordering;
 use std::{
+    collections::VecDeque,
     num::NonZeroI64,
     path::PathBuf,
     sync::{atomic::AtomicI64, mpsc as std_mpsc},
@@ -208,6 +210,7 @@ pub struct Connection {
     server: super::ServerPtrWeak,
     hash: Hash,
     read_jobs: Vec<fs::TransferJob>,
+    chat_attachments: chat_history::Receiving,
     timer: crate::RustDeskInterval,
     file_timer: crate::RustDeskInterval,
     file_transfer: Option<(String, bool)>,
@@ -225,6 +228,7 @@ pub struct Connection {
     restart: bool,
     recording: bool,
     block_input: bool,
+    inventory: bool,
     last_test_delay: Option<Instant>,
     network_delay: u32,
     lock_after_session_end: bool,
@@ -293,6 +297,7 @@ pub struct Connection {
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
     terminal_user_token: Option<TerminalUserToken>,
     terminal_generic_service: Option<Box<GenericService>>,
+    traffic: super::bandwidth::Accounting,
 }
 
 impl ConnInner {
@@ -386,6 +391,7 @@ impl Connection {
             server,
             hash,
             read_jobs: Vec::new(),
+            chat_attachments: Default::default(),
             timer: crate::rustdesk_interval(time::interval(SEC30)),
             file_timer: crate::rustdesk_interval(time::interval(SEC30)),
             file_transfer: None,
@@ -403,6 +409,7 @@ impl Connection {
             restart: Connection::permission("enable-remote-restart"),
             recording: Connection::permission("enable-record-session"),
             block_input: Connection::permission("enable-block-input"),
+            inventory: Connection::permission(keys::OPTION_ENABLE_INVENTORY),
             last_test_delay: None,
             network_delay: 0,
             lock_after_session_end: false,
@@ -462,6 +469,7 @@ impl Connection {
             #[cfg(not(any(target_os = "android", target_os = "ios")))]
             terminal_user_token: None,
             terminal_generic_service: None,
+            traffic: super::bandwidth::Accounting::new(),
         };
         let addr = hbb_common::try_into_v4(addr);
         if !conn.on_open(addr).await {
@@ -497,6 +505,9 @@ impl Connection {
         if !conn.block_input {
             conn.send_permission(Permission::BlockInput, false).await;
         }
+        if !conn.inventory {
+            conn.send_permission(Permission::Inventory, false).await;
+        }
         let mut test_delay_timer =
             crate::rustdesk_interval(time::interval_at(Instant::now(), TEST_DELAY_TIMEOUT));
         let mut last_recv_time = Instant::now();
@@ -512,6 +523,7 @@ impl Connection {
         #[cfg(not(any(target_os = "android", target_os = "ios")))]
         std::thread::spawn(move || Self::handle_input(_rx_input, tx_cloned));
         let mut second_timer = crate::rustdesk_interval(time::interval(Duration::from_secs(1)));
+        let mut control_token_rx = super::control_token::subscribe();
 
         #[cfg(feature = "unix-file-copy-paste")]
         let rx_clip_holder;
@@ -534,8 +546,19 @@ impl Connection {
         {
             (_tx_clip, rx_clip) = mpsc::unbounded_channel::<i32>();
         }
+        let mut clipboard_queue: VecDeque<Arc<Message>> = VecDeque::new();
 
         loop {
+            // Over the caps, the peer is slowed down by not reading from it, and the clipboard
+            // waits in its queue, the rest goes on.
+            let read_wait = conn.traffic.download_wait().min(bandwidth::MAX_WAIT);
+            let clipboard_wait = if clipboard_queue.is_empty() {
+                Duration::ZERO
+            } else {
+                conn.traffic
+                    .upload_wait(bandwidth::Category::Clipboard)
+                    .min(bandwidth::MAX_WAIT)
+            };
             tokio::select! {
                 // biased; // video has higher priority // causing test_delay_timer failed while transferring big file
 
@@ -562,17 +585,44 @@ impl Connection {
                                 break;
                             }
                         }
-                        ipc::Data::ChatMessage{text} => {
+                        ipc::Data::ChatMessage{text, msg_id, timestamp, attachments} => {
+                            let (attachments, paths) = conn.outgoing_chat_attachments(attachments);
                             let mut misc = Misc::new();
                             misc.set_chat_message(ChatMessage {
                                 text,
+                                msg_id: msg_id.clone(),
+                                timestamp,
+                                attachments,
                                 ..Default::default()
                             });
                             let mut msg_out = Message::new();
                             msg_out.set_misc(misc);
                             conn.send(msg_out).await;
+                            for (mut job, msg_out) in chat_history::new_read_jobs(&msg_id, &paths) {
+                                conn.send(msg_out).await;
+                                job.is_remote = true;
+                                job.conn_id = conn.inner.id();
+                                conn.read_jobs.push(job);
+                                conn.file_timer = crate::rustdesk_interval(time::interval(MILLI1));
+                            }
                             conn.chat_unanswered = false;
                         }
+                        ipc::Data::ChatReceipt{msg_ids, read} => {
+                            let mut misc = Misc::new();
+                            misc.set_chat_receipt(ChatReceipt {
+                                msg_ids,
+                                status: if read {
+                                    chat_receipt::Status::Read
+                                } else {
+                                    chat_receipt::Status::Delivered
+                                }
+                                .into(),
+                                ..Default::default()
+                            });
+                            let mut msg_out = Message::new();
+                            msg_out.set_misc(misc);
+                            conn.send(msg_out).await;
+                        }
                         ipc::Data::SwitchPermission{name, enabled} => {
                             log::info!("Change permission {} -> {}", name, enabled);
                             if &name == "keyboard" {
@@ -642,6 +692,9 @@ impl Connection {
                             } else if &name == "block_input" {
                                 conn.block_input = enabled;
                                 conn.send_permission(Permission::BlockInput, enabled).await;
+                            } else if &name == "inventory" {
+                                conn.inventory = enabled;
+                                conn.send_permission(Permission::Inventory, enabled).await;
                             }
                         }
                         ipc::Data::RawMessage(bytes) => {
@@ -715,10 +768,18 @@ impl Connection {
                             let msg = new_voice_call_request(false);
                             conn.send(msg).await;
                         }
+                        ipc::Data::ControlToken { has_control, .. } => {
+                            log::info!("Control token of #{} set by cm: {}", id, has_control);
+                            super::control_token::set_by_cm(id, has_control);
+                        }
+                        ipc::Data::BandwidthLimit { upload, download } => {
+                            log::info!("Bandwidth limit of #{} set by cm: {} KB/s up, {} KB/s down", id, upload, download);
+                            conn.traffic.set_limit(upload, download);
+                        }
                         _ => {}
                     }
                 },
-                res = conn.stream.next() => {
+                res = conn.stream.next(), if read_wait.is_zero() => {
                     if let Some(res) = res {
                         match res {
                             Err(err) => {
@@ -729,6 +790,7 @@ impl Connection {
                                 last_recv_time = Instant::now();
                                 conn.session_last_recv_time.as_mut().map(|t| *t.lock().unwrap() = Instant::now());
                                 if let Ok(msg_in) = Message::parse_from_bytes(&bytes) {
+                                    conn.traffic.on_download(bandwidth::Category::of(&msg_in), bytes.len() as _);
                                     if !conn.on_message(msg_in).await {
                                         break;
                                     }
@@ -747,10 +809,29 @@ impl Connection {
                         break;
                     }
                 },
+                _ = sleep(read_wait.as_secs_f32()), if !read_wait.is_zero() => {}
+                _ = sleep(clipboard_wait.as_secs_f32()), if !clipboard_queue.is_empty() => {
+                    if clipboard_wait.is_zero() {
+                        if let Some(msg) = clipboard_queue.pop_front() {
+                            if let Err(err) = conn.stream.send(&msg as &Message).await {
+                                conn.on_close(&err.to_string(), false).await;
+                                break;
+                            }
+                            conn.traffic.on_upload(bandwidth::Category::Clipboard, msg.compute_size());
+                        }
+                    }
+                },
                 _ = conn.file_timer.tick() => {
                     if !conn.read_jobs.is_empty() {
+                        if !conn.traffic.upload_wait(bandwidth::Category::File).is_zero() {
+                            continue;
+                        }
                         conn.send_to_cm(ipc::Data::FileTransferLog(("transfer".to_string(), fs::serialize_transfer_jobs(&conn.read_jobs))));
-                        match fs::handle_read_jobs(&mut conn.read_jobs, &mut conn.stream).await {
+                        let transferred = conn.read_jobs_transferred();
+                        let res = fs::handle_read_jobs(&mut conn.read_jobs, &mut conn.stream).await;
+                        let bytes = conn.read_jobs_transferred().saturating_sub(transferred);
+                        conn.traffic.on_upload(bandwidth::Category::File, bytes);
+                        match res {
                             Ok(log) => {
                                 if !log.is_empty() {
                                     conn.send_to_cm(ipc::Data::FileTransferLog(("transfer".to_string(), log)));
@@ -780,6 +861,7 @@ impl Connection {
                         conn.on_close(&err.to_string(), false).await;
                         break;
                     }
+                    conn.traffic.on_upload(bandwidth::Category::Video, value.compute_size());
                 },
                 Some((instant, value)) = rx.recv() => {
                     let latency = instant.elapsed().as_millis() as i64;
@@ -836,11 +918,19 @@ impl Connection {
                         _ => {}
                     }
 
+                    let category = bandwidth::Category::of(&msg);
+                    if category == bandwidth::Category::Clipboard
+                        && (!clipboard_queue.is_empty() || !conn.traffic.upload_wait(category).is_zero())
+                    {
+                        bandwidth::push_clipboard(&mut clipboard_queue, msg);
+                        continue;
+                    }
                     let msg: &Message = &msg;
                     if let Err(err) = conn.stream.send(msg).await {
                         conn.on_close(&err.to_string(), false).await;
                         break;
                     }
+                    conn.traffic.on_upload(category, msg.compute_size());
                 },
                 Some(data) = rx_from_authed.recv() => {
                     match data {
@@ -855,9 +945,13 @@ impl Connection {
                         _ => {}
                     }
                 }
+                Ok(_) = control_token_rx.changed() => {
+                    conn.send_control_token_state().await;
+                }
                 _ = second_timer.tick() => {
                     #[cfg(windows)]
                     conn.portable_check();
+                    conn.update_traffic();
                     if let Some((instant, minute)) = conn.auto_disconnect_timer.as_ref() {
                         if instant.elapsed().as_secs() > minute * 60 {
                             conn.send_close_reason_no_retry("Connection failed due to inactivity").await;
@@ -877,10 +971,32 @@ impl Connection {
                     // The control end will jump out of the loop after receiving LoginResponse and will not reply to the TestDelay
                     if conn.last_test_delay.is_none() && !(conn.port_forward_socket.is_some() && conn.authorized) {
                         conn.last_test_delay = Some(Instant::now());
+                        let (target_bitrate, capture_stats) = {
+                            let qos = video_service::VIDEO_QOS.lock().unwrap();
+                            // Not to the unauthorized peers
+                            let capture_stats = if conn.authorized {
+                                qos.capture_stats(conn.video_source().service_name_prefix())
+                            } else {
+                                Default::default()
+                            };
+                            (qos.bitrate(), capture_stats)
+                        };
                         let mut msg_out = Message::new();
                         msg_out.set_test_delay(TestDelay{
                             last_delay: conn.network_delay,
-                            target_bitrate: video_service::VIDEO_QOS.lock().unwrap().bitrate(),
+                            target_bitrate,
+                            capture_stats: capture_stats
+                                .into_iter()
+                                .map(|(idx, s)| {
+                                    (idx, CaptureStats {
+                                        captured: s.captured as _,
+                                        idle: s.idle as _,
+                                        damage_ratio: s.damage_ratio,
+                                        encode_ms: s.encode_ms,
+                                        ..Default::default()
+                                    })
+                                })
+                                .collect(),
                             ..Default::default()
                         });
                         conn.send(msg_out.into()).await;
@@ -923,6 +1039,7 @@ impl Connection {
             conn.lr.my_id.clone(),
         );
         video_service::notify_video_frame_fetched(id, None);
+        super::control_token::remove(id);
         if conn.authorized {
             password::update_temporary_password();
         }
@@ -931,8 +1048,16 @@ impl Connection {
             raii::AuthedConnID::check_remove_session(conn.inner.id(), conn.session_key());
         }
 
+        let traffic = conn.traffic.stats();
+        log::info!(
+            "#{} traffic: {} bytes sent, {} bytes received",
+            id,
+            traffic.upload.total(),
+            traffic.download.total()
+        );
         conn.post_conn_audit(json!({
             "action": "close",
+            "traffic": traffic,
         }));
         if let Some(s) = conn.server.upgrade() {
             let mut s = s.write().unwrap();
@@ -1065,7 +1190,19 @@ impl Connection {
             log::info!("Running port forwarding loop");
             self.stream.set_raw();
             let mut hbbs_rx = crate::hbbs_http::sync::signal_receiver();
+            let mut traffic_timer =
+                crate::rustdesk_interval(time::interval(Duration::from_secs(1)));
             loop {
+                // Over the caps, each side is slowed down by not reading from it.
+                let forward_wait = self
+                    .traffic
+                    .upload_wait(bandwidth::Category::PortForward)
+                    .min(bandwidth::MAX_WAIT);
+                let read_wait = self.traffic.download_wait().min(bandwidth::MAX_WAIT);
+                let wake = [forward_wait, read_wait]
+                    .into_iter()
+                    .filter(|w| !w.is_zero())
+                    .min();
                 tokio::select! {
                     Some(data) = rx_from_cm.recv() => {
                         match data {
@@ -1076,25 +1213,37 @@ impl Connection {
                                 log::error!("Connection manager error: {e}");
                                 bail!("{e}");
                             }
+                            ipc::Data::BandwidthLimit { upload, download } => {
+                                self.traffic.set_limit(upload, download);
+                            }
                             _ => {}
                         }
                     }
-                    res = forward.next() => {
+                    res = forward.next(), if forward_wait.is_zero() => {
                         if let Some(res) = res {
                             last_recv_time = Instant::now();
-                            self.stream.send_bytes(res?.into()).await?;
+                            let bytes = res?;
+                            let len = bytes.len() as u64;
+                            self.stream.send_bytes(bytes.into()).await?;
+                            self.traffic.on_upload(bandwidth::Category::PortForward, len);
                         } else {
                             bail!("Forward reset by the peer");
                         }
                     },
-                    res = self.stream.next() => {
+                    res = self.stream.next(), if read_wait.is_zero() => {
                         if let Some(res) = res {
                             last_recv_time = Instant::now();
-                            timeout(SEND_TIMEOUT_OTHER, forward.send(res?)).await??;
+                            let bytes = res?;
+                            self.traffic.on_download(bandwidth::Category::PortForward, bytes.len() as _);
+                            timeout(SEND_TIMEOUT_OTHER, forward.send(bytes)).await??;
                         } else {
                             bail!("Stream reset by the peer");
                         }
                     },
+                    _ = sleep(wake.unwrap_or_default().as_secs_f32()), if wake.is_some() => {}
+                    _ = traffic_timer.tick() => {
+                        self.update_traffic();
+                    }
                     _ = self.timer.tick() => {
                         if last_recv_time.elapsed() >= H1 {
                             bail!("Timeout");
@@ -1324,6 +1473,7 @@ impl Connection {
             return;
         }
         self.authorized = true;
+        self.traffic.set_authorized();
         let (conn_type, auth_conn_type) = if self.file_transfer.is_some() {
             (1, AuthConnType::FileTransfer)
         } else if self.port_forward_socket.is_some() {
@@ -1496,6 +1646,8 @@ impl Connection {
             privacy_mode: privacy_mode::is_privacy_mode_supported(),
             #[cfg(not(any(target_os = "android", target_os = "ios")))]
             terminal,
+            // Without native injection, the pen would be a mouse only.
+            pen: input_service::is_pen_supported(),
             ..Default::default()
         })
         .into();
@@ -1658,6 +1810,10 @@ impl Connection {
                 if !self.audio_enabled() {
                     noperms.push(super::audio_service::NAME);
                 }
+                #[cfg(target_os = "linux")]
+                if self.audio_enabled() {
+                    self.send_audio_sources();
+                }
                 let mut s = s.write().unwrap();
                 #[cfg(not(any(target_os = "android", target_os = "ios")))]
                 let _h = try_start_record_cursor_pos();
@@ -1696,6 +1852,7 @@ impl Connection {
 
     fn on_remote_authorized(&self) {
         self.update_codec_on_login();
+        super::control_token::add(self.inner.id(), self.lr.my_name.clone());
         #[cfg(any(target_os = "windows", target_os = "linux"))]
         if config::option2bool(
             "allow-remove-wallpaper",
@@ -1716,6 +1873,36 @@ impl Connection {
         }
     }
 
+    fn handle_control_token_action(&self, action: ControlTokenAction) {
+        use super::control_token;
+        use control_token_action::Type;
+        let id = self.inner.id();
+        match action.type_.enum_value() {
+            Ok(Type::Request) => control_token::request(id),
+            Ok(Type::Release) => control_token::release(id),
+            Ok(Type::Grant) => control_token::grant(id, action.conn_id),
+            Ok(Type::Deny) => control_token::deny(id, action.conn_id),
+            Err(_) => {}
+        }
+    }
+
+    // Sent to the controllers and their cm on every change of the control token.
+    async fn send_control_token_state(&mut self) {
+        if !self.is_authed_remote_conn() {
+            return;
+        }
+        let state = super::control_token::get_state(self.inner.id());
+        self.send_to_cm(ipc::Data::ControlToken {
+            has_control: state.has_control,
+            requested: state.requested,
+        });
+        let mut misc = Misc::new();
+        misc.set_control_token_state(state);
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        self.send(msg_out).await;
+    }
+
     fn peer_keyboard_enabled(&self) -> bool {
         self.keyboard && !self.disable_keyboard
     }
@@ -1735,11 +1922,119 @@ impl Connection {
         self.audio && !self.disable_audio
     }
 
-    #[cfg(any(target_os = "windows", feature = "unix-file-copy-paste"))]
+    // Announce the audio sources so that the controller can pick some instead of the default one.
+    #[cfg(target_os = "linux")]
+    fn send_audio_sources(&self) {
+        let mut inner = self.inner.clone();
+        tokio::spawn(async move {
+            match super::audio_service::get_audio_sources().await {
+                Ok(sources) => {
+                    if sources.is_empty() {
+                        return;
+                    }
+                    let mut misc = Misc::new();
+                    misc.set_audio_sources(AudioSources {
+                        sources,
+                        ..Default::default()
+                    });
+                    let mut msg = Message::new();
+                    msg.set_misc(misc);
+                    inner.send(Arc::new(msg));
+                }
+                Err(err) => {
+                    log::debug!("Failed to get audio sources: {}", err);
+                }
+            }
+        });
+    }
+
+    fn send_inventory(&self, req: InventoryRequest) {
+        let mut inner = self.inner.clone();
+        let inventory_enabled = self.inventory;
+        std::thread::spawn(move || {
+            let inventory = if !inventory_enabled {
+                Inventory {
+                    error: "Inventory query is not allowed".to_owned(),
+                    ..Default::default()
+                }
+            } else {
+                #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                {
+                    super::inventory::get_inventory(&req)
+                }
+                #[cfg(any(target_os = "android", target_os = "ios"))]
+                {
+                    let _ = req;
+                    Inventory {
+                        error: "Inventory query is not supported on this platform".to_owned(),
+                        ..Default::default()
+                    }
+                }
+            };
+            let mut misc = Misc::new();
+            misc.set_inventory(inventory);
+            let mut msg = Message::new();
+            msg.set_misc(misc);
+            inner.send(Arc::new(msg));
+        });
+    }
+
     fn file_transfer_enabled(&self) -> bool {
         self.file && self.enable_file_transfer
     }
 
+    // The attachments to the peer by their local paths from cm, sent with the file permission
+    // only, returns them with the paths kept.
+    fn outgoing_chat_attachments(&self, paths: Vec<String>) -> (Vec<ChatAttachment>, Vec<String>) {
+        if paths.is_empty() {
+            return Default::default();
+        }
+        if !self.file_transfer_enabled()
+            || crate::get_builtin_option(keys::OPTION_ONE_WAY_FILE_TRANSFER) == "Y"
+        {
+            log::warn!("Chat attachments are not sent without the file permission");
+            return Default::default();
+        }
+        match chat_history::check_attachments(&paths) {
+            Ok(attachments) => (attachments, paths),
+            Err(e) => {
+                log::error!("Failed to send chat attachments: {}", e);
+                Default::default()
+            }
+        }
+    }
+
+    // An attachment of a chat message from the peer, written if the message announced it.
+    async fn receive_chat_attachment(&mut self, r: FileTransferReceiveRequest) {
+        if !self.file_transfer_enabled()
+            || !self
+                .chat_attachments
+                .add_job(r.id, &r.chat_msg_id, r.total_size)
+        {
+            self.send(fs::new_error(r.id, "The attachment is rejected", 0))
+                .await;
+            return;
+        }
+        self.send_fs(ipc::FS::NewChatWrite {
+            id: r.id,
+            msg_id: r.chat_msg_id,
+            name: r.path.clone(),
+            files: r
+                .files
+                .iter()
+                .map(|f| (f.name.clone(), f.modified_time))
+                .collect(),
+            total_size: r.total_size,
+            conn_id: self.inner.id(),
+        });
+        self.post_file_audit(
+            FileAuditType::RemoteReceive,
+            &r.path,
+            Self::get_files_for_audit(fs::JobType::Generic, r.files),
+            json!({}),
+        );
+    }
+
     #[cfg(feature = "unix-file-copy-paste")]
     fn can_sub_file_clipboard_service(&self) -> bool {
         self.clipboard_enabled()
@@ -1765,10 +2060,27 @@ impl Connection {
             restart: self.restart,
             recording: self.recording,
             block_input: self.block_input,
+            inventory: self.inventory,
             from_switch: self.from_switch,
         });
     }
 
+    fn read_jobs_transferred(&self) -> u64 {
+        self.read_jobs.iter().map(|j| j.transferred()).sum()
+    }
+
+    // Every second, applies the caps and reports the traffic to cm.
+    fn update_traffic(&mut self) {
+        self.traffic.update();
+        video_service::VIDEO_QOS
+            .lock()
+            .unwrap()
+            .user_bandwidth(self.inner.id(), self.traffic.video_budget_kbps());
+        if let Some(stats) = self.traffic.take_changed() {
+            self.send_to_cm(ipc::Data::Traffic(stats));
+        }
+    }
+
     #[inline]
     fn send_to_cm(&mut self, data: ipc::Data) {
         self.tx_to_cm.send(data).ok();
@@ -2291,6 +2603,7 @@ impl Connection {
                         .user_network_delay(self.inner.id(), new_delay);
                     self.network_delay = new_delay;
                 }
+                crate::audio_service::set_packet_loss(self.inner.id(), t.audio_loss);
             }
         } else if let Some(message::Union::SwitchSidesResponse(_s)) = msg.union {
             #[cfg(feature = "flutter")]
@@ -2321,6 +2634,16 @@ impl Connection {
             if self.port_forward_socket.is_some() {
                 return true;
             }
+            // The input of the controllers without the control token is dropped.
+            if matches!(
+                msg.union,
+                Some(message::Union::MouseEvent(_))
+                    | Some(message::Union::KeyEvent(_))
+                    | Some(message::Union::PointerDeviceEvent(_))
+            ) && !super::control_token::has_control(self.inner.id())
+            {
+                return true;
+            }
             match msg.union {
                 #[allow(unused_mut)]
                 Some(message::Union::MouseEvent(mut me)) => {
@@ -2630,6 +2953,12 @@ impl Connection {
                     }
                 }
                 Some(message::Union::FileAction(fa)) => {
+                    if let Some(file_action::Union::Receive(r)) = fa.union.as_ref() {
+                        if !r.chat_msg_id.is_empty() {
+                            self.receive_chat_attachment(r.clone()).await;
+                            return true;
+                        }
+                    }
                     let mut handle_fa = self.file_transfer.is_some();
                     if !handle_fa {
                         if let Some(file_action::Union::Send(s)) = fa.union.as_ref() {
@@ -2856,14 +3185,25 @@ impl Connection {
                 }
                 Some(message::Union::FileResponse(fr)) => match fr.union {
                     Some(file_response::Union::Block(block)) => {
-                        self.send_fs(ipc::FS::WriteBlock {
-                            id: block.id,
-                            file_num: block.file_num,
-                            data: block.data,
-                            compressed: block.compressed,
-                        });
+                        if self.chat_attachments.receive(&block) {
+                            self.send_fs(ipc::FS::WriteBlock {
+                                id: block.id,
+                                file_num: block.file_num,
+                                data: block.data,
+                                compressed: block.compressed,
+                            });
+                        } else {
+                            self.send_fs(ipc::FS::CancelWrite { id: block.id });
+                            self.send(fs::new_error(
+                                block.id,
+                                "The attachment exceeds its size",
+                                block.file_num,
+                            ))
+                            .await;
+                        }
                     }
                     Some(file_response::Union::Done(d)) => {
+                        self.chat_attachments.remove_job(d.id);
                         self.send_fs(ipc::FS::WriteDone {
                             id: d.id,
                             file_num: d.file_num,
@@ -2878,6 +3218,7 @@ impl Connection {
                         is_resume: d.is_resume,
                     }),
                     Some(file_response::Union::Error(e)) => {
+                        self.chat_attachments.remove_job(e.id);
                         self.send_fs(ipc::FS::WriteError {
                             id: e.id,
                             file_num: e.file_num,
@@ -2904,7 +3245,20 @@ impl Connection {
                         self.toggle_privacy_mode(t).await;
                     }
                     Some(misc::Union::ChatMessage(c)) => {
-                        self.send_to_cm(ipc::Data::ChatMessage { text: c.text });
+                        let attachments = if c.attachments.is_empty() {
+                            vec![]
+                        } else if self.file_transfer_enabled() {
+                            self.chat_attachments.expect(&c)
+                        } else {
+                            log::warn!("Chat attachments are rejected without the file permission");
+                            vec![]
+                        };
+                        self.send_to_cm(ipc::Data::ChatMessage {
+                            text: c.text,
+                            msg_id: c.msg_id,
+                            timestamp: c.timestamp,
+                            attachments,
+                        });
                         self.chat_unanswered = true;
                         self.update_auto_disconnect_timer();
                     }
@@ -2959,6 +3313,18 @@ impl Connection {
                         }
                         _ => {}
                     },
+                    Some(misc::Union::SelectAudioSources(select)) => {
+                        if self.audio_enabled() {
+                            crate::audio_service::set_audio_sources(
+                                self.inner.id(),
+                                select
+                                    .sources
+                                    .iter()
+                                    .map(|s| (s.id.clone(), s.gain.clamp(0., 2.)))
+                                    .collect(),
+                            );
+                        }
+                    }
                     Some(misc::Union::AudioFormat(format)) => {
                         if !self.disable_audio {
                             // Drop the audio sender previously.
@@ -3042,6 +3408,18 @@ impl Connection {
                             self.send(msg_out).await;
                         }
                     }
+                    Some(misc::Union::InventoryRequest(req)) => {
+                        self.send_inventory(req);
+                    }
+                    Some(misc::Union::ControlTokenAction(action)) => {
+                        self.handle_control_token_action(action);
+                    }
+                    Some(misc::Union::ChatReceipt(r)) => {
+                        self.send_to_cm(ipc::Data::ChatReceipt {
+                            msg_ids: r.msg_ids,
+                            read: r.status.enum_value() == Ok(chat_receipt::Status::Read),
+                        });
+                    }
                     _ => {}
                 },
                 Some(message::Union::AudioFrame(frame)) => {
@@ -3072,12 +3450,18 @@ impl Connection {
                 }
                 Some(message::Union::ScreenshotRequest(request)) => {
                     if let Some(tx) = self.inner.tx.clone() {
-                        crate::video_service::set_take_screenshot(
-                            request.display as _,
-                            request.sid.clone(),
-                            tx,
-                        );
-                        self.refresh_video_display(Some(request.display as usize));
+                        if request.all_displays {
+                            self.take_screenshot_of_all_displays(request, tx);
+                        } else {
+                            let display = request.display as usize;
+                            crate::video_service::set_take_screenshot(
+                                self.inner.id(),
+                                display,
+                                request,
+                                tx,
+                            );
+                            self.refresh_video_display(Some(display));
+                        }
                     }
                 }
                 Some(message::Union::TerminalAction(action)) => {
@@ -3258,6 +3642,44 @@ impl Connection {
         });
     }
 
+    fn take_screenshot_of_all_displays(&self, request: ScreenshotRequest, tx: Sender) {
+        let Some(server) = self.server.upgrade() else {
+            return;
+        };
+        let displays = display_service::get_sync_displays();
+        let names: Vec<String> = (0..displays.len())
+            .map(|idx| video_service::get_service_name(VideoSource::Monitor, idx))
+            .collect();
+        // The displays not being viewed are captured only for the screenshot.
+        // This subscriber keeps their video services running and drops the video frames.
+        let mut lock = server.write().unwrap();
+        let subscriber = ConnInner::new(lock.get_new_id(), None, None);
+        for (idx, name) in names.iter().enumerate() {
+            if !lock.contains(name) {
+                lock.add_service(Box::new(video_service::new(VideoSource::Monitor, idx)));
+            }
+            lock.subscribe(name, subscriber.clone(), true);
+        }
+        drop(lock);
+        let weak = self.server.clone();
+        let conn_id = self.inner.id();
+        video_service::set_take_screenshot_of_all_displays(
+            conn_id,
+            displays,
+            request,
+            tx,
+            move || {
+                if let Some(server) = weak.upgrade() {
+                    let mut lock = server.write().unwrap();
+                    for name in names.iter() {
+                        lock.subscribe(name, subscriber.clone(), false);
+                    }
+                }
+            },
+        );
+        self.refresh_video_display(None);
+    }
+
     async fn handle_switch_display(&mut self, s: SwitchDisplay) {
         let display_idx = s.display as usize;
         if self.display_idx != display_idx {
@@ -3719,6 +4141,14 @@ impl Connection {
                 self.update_terminal_persistence(q == BoolOption::Yes).await;
             }
         }
+        if let Ok(q) = o.text_lossless.enum_value() {
+            if q != BoolOption::NotSet {
+                video_service::VIDEO_QOS
+                    .lock()
+                    .unwrap()
+                    .user_text_lossless(self.inner.id(), q == BoolOption::Yes);
+            }
+        }
         #[cfg(not(any(target_os = "android", target_os = "ios")))]
         if let Ok(q) = o.show_my_cursor.enum_value() {
             if q != BoolOption::NotSet {
@@ -3896,6 +4326,12 @@ impl Connection {
         // We can add a (Vec<conn_id>, input device) to avoid this.
         // But it's not necessary now and we have to consider two audio services(client, server).
         crate::audio_service::set_voice_call_input_device(None, true);
+        // Unlike the voice call input above, the selected sources are of each connection.
+        crate::audio_service::reset_audio_sources(self.inner.id());
+        crate::audio_service::remove_packet_loss(self.inner.id());
+        crate::video_service::cancel_screenshots(self.inner.id());
+        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+        super::terminal_exec::cancel_all(self.inner.id());
         log::info!("#{} Connection closed: {}", self.inner.id(), reason);
         if lock && self.lock_after_session_end && self.keyboard {
             #[cfg(not(any(target_os = "android", target_os = "ios")))]
@@ -4199,6 +4635,17 @@ impl Connection {
             // unreacheable, but keep it for safety
             bail!("Terminal user token is not set.");
         };
+        match &action.union {
+            Some(terminal_action::Union::Exec(exec)) => {
+                self.handle_terminal_exec(exec.clone(), user_token).await;
+                return Ok(());
+            }
+            Some(terminal_action::Union::CancelExec(c)) => {
+                super::terminal_exec::cancel(self.inner.id(), c.exec_id);
+                return Ok(());
+            }
+            _ => {}
+        }
         let mut proxy = terminal_service::TerminalServiceProxy::new(
             self.terminal_service_id.clone(),
             Some(self.terminal_persistent),
@@ -4227,6 +4674,41 @@ impl Connection {
 
         Ok(())
     }
+
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    async fn handle_terminal_exec(&mut self, exec: ExecCommand, user_token: TerminalUserToken) {
+        if !Connection::permission(keys::OPTION_ENABLE_TERMINAL) {
+            let mut response = TerminalResponse::new();
+            response.set_exec_result(ExecResult {
+                exec_id: exec.exec_id,
+                exit_code: -1,
+                error: "No permission of terminal".to_owned(),
+                ..Default::default()
+            });
+            let mut msg_out = Message::new();
+            msg_out.set_terminal_response(response);
+            self.send(msg_out).await;
+            return;
+        }
+        log::info!("Exec {}: {}", exec.exec_id, exec.command);
+        self.post_conn_audit(json!({
+            "action": "exec",
+            "exec_id": exec.exec_id,
+            "command": exec.command,
+            "args": exec.args,
+            // The values may be secrets.
+            "env": exec.env.keys().collect::<Vec<_>>(),
+            "working_dir": exec.working_dir,
+            "run_as": exec.run_as,
+            "os_user": self.lr.os_login.username,
+        }));
+        super::terminal_exec::start(
+            self.inner.id(),
+            exec,
+            user_token.to_terminal_service_token(),
+            self.inner.clone(),
+        );
+    }
 }
 
 pub fn insert_switch_sides_uuid(id: String, uuid: uuid::Uuid) {
@@ -0,0 +1,195 @@
+// Floor control between the controllers of the same host.
+//
+// Only the holder of the token injects input, the others are view-only.
+// The first controller gets the token, the others request it from the holder,
+// or the connection manager grants it.
+
+use hbb_common::{
+    message_proto::{control_token_state::Requester, ControlTokenState},
+    tokio::sync::watch,
+};
+use std::sync::Mutex;
+
+#[derive(Default)]
+struct Token {
+    // (conn id, name) of the controllers, in the order of login.
+    controllers: Vec<(i32, String)>,
+    holder: Option<i32>,
+    requests: Vec<i32>,
+}
+
+impl Token {
+    fn is_controller(&self, conn_id: i32) -> bool {
+        self.controllers.iter().any(|(id, _)| *id == conn_id)
+    }
+
+    fn name(&self, conn_id: i32) -> String {
+        self.controllers
+            .iter()
+            .find(|(id, _)| *id == conn_id)
+            .map(|(_, name)| name.clone())
+            .unwrap_or_default()
+    }
+
+    // The token goes to the first requester, if any.
+    fn pass_on(&mut self) {
+        self.holder = if self.requests.is_empty() {
+            None
+        } else {
+            Some(self.requests.remove(0))
+        };
+    }
+
+    fn set_holder(&mut self, conn_id: i32) {
+        self.requests.retain(|id| *id != conn_id);
+        self.holder = Some(conn_id);
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref TOKEN: Mutex<Token> = Default::default();
+    // Bumped on every change, the connections send the new state to their peers and cm then.
+    static ref CHANGED: watch::Sender<u64> = watch::channel(0).0;
+}
+
+fn update(f: impl FnOnce(&mut Token) -> bool) {
+    let changed = f(&mut TOKEN.lock().unwrap());
+    if changed {
+        CHANGED.send_modify(|v| *v = v.wrapping_add(1));
+    }
+}
+
+pub fn subscribe() -> watch::Receiver<u64> {
+    CHANGED.subscribe()
+}
+
+/// Add an authorized controller, it gets the token if nobody has it.
+pub fn add(conn_id: i32, name: String) {
+    update(|t| {
+        if t.is_controller(conn_id) {
+            return false;
+        }
+        t.controllers.push((conn_id, name));
+        if t.holder.is_none() {
+            t.holder = Some(conn_id);
+        }
+        true
+    });
+}
+
+pub fn remove(conn_id: i32) {
+    update(|t| {
+        if !t.is_controller(conn_id) {
+            return false;
+        }
+        t.controllers.retain(|(id, _)| *id != conn_id);
+        t.requests.retain(|id| *id != conn_id);
+        if t.holder == Some(conn_id) {
+            t.pass_on();
+            // Better than nobody being able to control.
+            if t.holder.is_none() {
+                t.holder = t.controllers.first().map(|(id, _)| *id);
+            }
+        }
+        true
+    });
+}
+
+/// Whether the input of the connection is allowed.
+/// Connections which are not controllers, e.g. file transfer, are not affected.
+pub fn has_control(conn_id: i32) -> bool {
+    let t = TOKEN.lock().unwrap();
+    !t.is_controller(conn_id) || t.holder == Some(conn_id)
+}
+
+pub fn request(conn_id: i32) {
+    update(|t| {
+        if !t.is_controller(conn_id) || t.holder == Some(conn_id) {
+            return false;
+        }
+        if t.holder.is_none() {
+            t.set_holder(conn_id);
+        } else if !t.requests.contains(&conn_id) {
+            t.requests.push(conn_id);
+        } else {
+            return false;
+        }
+        true
+    });
+}
+
+/// Give the token up, or cancel the request.
+pub fn release(conn_id: i32) {
+    update(|t| {
+        if t.holder == Some(conn_id) {
+            t.pass_on();
+            true
+        } else if t.requests.contains(&conn_id) {
+            t.requests.retain(|id| *id != conn_id);
+            true
+        } else {
+            false
+        }
+    });
+}
+
+/// The holder `from` passes the token to `to`.
+pub fn grant(from: i32, to: i32) {
+    update(|t| {
+        if t.holder != Some(from) || !t.is_controller(to) {
+            return false;
+        }
+        t.set_holder(to);
+        true
+    });
+}
+
+/// The holder `from` rejects the request of `to`.
+pub fn deny(from: i32, to: i32) {
+    update(|t| {
+        if t.holder != Some(from) || !t.requests.contains(&to) {
+            return false;
+        }
+        t.requests.retain(|id| *id != to);
+        true
+    });
+}
+
+/// By the connection manager, give the token to `conn_id` or take it away.
+pub fn set_by_cm(conn_id: i32, has_control: bool) {
+    update(|t| {
+        if !t.is_controller(conn_id) || (t.holder == Some(conn_id)) == has_control {
+            return false;
+        }
+        if has_control {
+            t.set_holder(conn_id);
+        } else {
+            t.pass_on();
+        }
+        true
+    });
+}
+
+pub fn get_state(conn_id: i32) -> ControlTokenState {
+    let t = TOKEN.lock().unwrap();
+    let has_control = t.holder == Some(conn_id);
+    ControlTokenState {
+        has_control,
+        holder_name: t.holder.map(|id| t.name(id)).unwrap_or_default(),
+        controllers: t.controllers.len() as _,
+        requests: if has_control {
+            t.requests
+                .iter()
+                .map(|id| Requester {
+                    conn_id: *id,
+                    name: t.name(*id),
+                    ..Default::default()
+                })
+                .collect()
+        } else {
+            vec![]
+        },
+        requested: t.requests.contains(&conn_id),
+        ..Default::default()
+    }
+}
@@ -445,6 +445,11 @@ lazy_static::lazy_static! {
     static ref KEYS_DOWN: Arc<Mutex<HashMap<KeysDown, Instant>>> = Default::default();
     static ref LATEST_PEER_INPUT_CURSOR: Arc<Mutex<Input>> = Default::default();
     static ref LATEST_SYS_CURSOR_POS: Arc<Mutex<(Option<Instant>, (i32, i32))>> = Arc::new(Mutex::new((None, (INVALID_CURSOR_POS, INVALID_CURSOR_POS))));
+    static ref PEN_MOUSE_BUTTON: Mutex<i32> = Mutex::new(0);
+}
+#[cfg(target_os = "linux")]
+lazy_static::lazy_static! {
+    static ref UINPUT_PEN: Mutex<Option<super::uinput::client::UInputPen>> = Default::default();
 }
 static EXITING: AtomicBool = AtomicBool::new(false);
 
@@ -577,6 +582,14 @@ pub async fn setup_uinput(minx: i32, maxx: i32, miny: i32, maxy: i32) -> ResultT
     log::info!("UInput keyboard created");
     let mouse = super::uinput::client::UInputMouse::new().await?;
     log::info!("UInput mouse created");
+    // The pen is optional, pen events are simulated with the mouse without it.
+    match super::uinput::client::UInputPen::new().await {
+        Ok(pen) => {
+            *UINPUT_PEN.lock().unwrap() = Some(pen);
+            log::info!("UInput pen created");
+        }
+        Err(e) => log::warn!("Failed to create uinput pen: {}", e),
+    }
 
     ENIGO
         .lock()
@@ -985,10 +998,94 @@ pub fn handle_pointer_(evt: &PointerDeviceEvent, conn: i32) {
             }
             _ => {}
         },
+        Some(hbb_common::message_proto::pointer_device_event::Union::PenEvent(pen)) => {
+            handle_pen(pen, &evt.modifiers, conn);
+        }
         _ => {}
     }
 }
 
+fn handle_pen(pen: &PenEvent, modifiers: &[EnumOrUnknown<ControlKey>], conn: i32) {
+    #[cfg(windows)]
+    crate::platform::windows::try_change_desktop();
+    if inject_pen(pen) {
+        *PEN_MOUSE_BUTTON.lock().unwrap() = 0;
+        *LATEST_PEER_INPUT_CURSOR.lock().unwrap() = Input {
+            conn,
+            time: get_time(),
+            x: pen.x,
+            y: pen.y,
+        };
+        return;
+    }
+    let events = crate::input::pen_to_mouse(pen, &mut PEN_MOUSE_BUTTON.lock().unwrap());
+    for mut evt in events {
+        evt.modifiers = modifiers.to_vec();
+        handle_mouse_simulation_(&evt, conn);
+    }
+}
+
+/// Whether pen events are injected natively, the peer offers the pen input only then.
+#[cfg(windows)]
+pub fn is_pen_supported() -> bool {
+    crate::platform::win_pen::is_supported()
+}
+
+/// Whether pen events are injected natively, the peer offers the pen input only then.
+#[cfg(target_os = "linux")]
+pub fn is_pen_supported() -> bool {
+    UINPUT_PEN.lock().unwrap().is_some()
+}
+
+#[cfg(not(any(windows, target_os = "linux")))]
+pub fn is_pen_supported() -> bool {
+    false
+}
+
+#[cfg(windows)]
+fn inject_pen(pen: &PenEvent) -> bool {
+    if !crate::platform::win_pen::is_supported() {
+        return false;
+    }
+    match crate::platform::win_pen::inject(pen) {
+        Ok(()) => true,
+        Err(e) => {
+            log::debug!("Failed to inject pen event: {}", e);
+            false
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn inject_pen(pen: &PenEvent) -> bool {
+    let mut lock = UINPUT_PEN.lock().unwrap();
+    let Some(uinput_pen) = lock.as_mut() else {
+        return false;
+    };
+    let data = crate::ipc::DataPen {
+        x: pen.x,
+        y: pen.y,
+        pressure: pen.pressure,
+        tilt_x: pen.tilt_x,
+        tilt_y: pen.tilt_y,
+        buttons: pen.buttons,
+        eraser: pen.tool.enum_value() == Ok(hbb_common::message_proto::pen_event::Tool::Eraser),
+        in_range: pen.in_range,
+    };
+    match uinput_pen.send(data) {
+        Ok(()) => true,
+        Err(e) => {
+            log::debug!("Failed to send uinput pen event: {}", e);
+            false
+        }
+    }
+}
+
+#[cfg(not(any(windows, target_os = "linux")))]
+fn inject_pen(_pen: &PenEvent) -> bool {
+    false
+}
+
 pub fn handle_mouse_(
     evt: &MouseEvent,
     conn: i32,
@@ -0,0 +1,92 @@
+use hbb_common::{
+    message_proto::{inventory, Inventory, InventoryRequest},
+    sysinfo::System,
+};
+
+/// Collect the inventory of this device, this may block for a few seconds.
+pub fn get_inventory(req: &InventoryRequest) -> Inventory {
+    let mut system = System::new();
+    system.refresh_memory();
+    system.refresh_cpu();
+    system.refresh_disks_list();
+    // The cpu usage is computed from two refreshes.
+    std::thread::sleep(std::time::Duration::from_millis(200));
+    system.refresh_cpu();
+    let cpus = system.cpus();
+    let cpu_usage = if cpus.is_empty() {
+        0.
+    } else {
+        cpus.iter().map(|c| c.cpu_usage()).sum::<f32>() / cpus.len() as f32
+    };
+    let disks = system
+        .disks()
+        .iter()
+        .map(|d| inventory::Disk {
+            name: d.name().to_string_lossy().to_string(),
+            mount_point: d.mount_point().to_string_lossy().to_string(),
+            file_system: String::from_utf8_lossy(d.file_system()).to_string(),
+            total_space: d.total_space(),
+            available_space: d.available_space(),
+            ..Default::default()
+        })
+        .collect();
+    let network_interfaces = default_net::get_interfaces()
+        .into_iter()
+        .map(|i| inventory::NetworkInterface {
+            name: i.name,
+            mac: i.mac_addr.map(|m| m.address()).unwrap_or_default(),
+            ips: i
+                .ipv4
+                .iter()
+                .map(|x| format!("{}/{}", x.addr, x.prefix_len))
+                .chain(
+                    i.ipv6
+                        .iter()
+                        .map(|x| format!("{}/{}", x.addr, x.prefix_len)),
+                )
+                .collect(),
+            ..Default::default()
+        })
+        .collect();
+    let packages = if req.include_packages {
+        crate::platform::get_installed_packages()
+            .into_iter()
+            .map(|(name, version)| inventory::Package {
+                name,
+                version,
+                ..Default::default()
+            })
+            .collect()
+    } else {
+        vec![]
+    };
+    let users = crate::platform::get_logged_in_users()
+        .into_iter()
+        .map(|(name, session)| inventory::User {
+            name,
+            session,
+            ..Default::default()
+        })
+        .collect();
+    Inventory {
+        hostname: crate::common::hostname(),
+        os_name: system.long_os_version().unwrap_or_default(),
+        os_version: system.os_version().unwrap_or_default(),
+        kernel_version: system.kernel_version().unwrap_or_default(),
+        cpu_brand: cpus
+            .first()
+            .map(|c| c.brand().trim().to_owned())
+            .unwrap_or_default(),
+        cpu_cores: cpus.len() as _,
+        cpu_physical_cores: system.physical_core_count().unwrap_or_default() as _,
+        cpu_usage,
+        total_memory: system.total_memory(),
+        used_memory: system.used_memory(),
+        disks,
+        network_interfaces,
+        packages,
+        uptime: system.uptime(),
+        users,
+        ..Default::default()
+    }
+}
@@ -0,0 +1,303 @@
+// Post processing of the screenshots taken by the video services, see `video_service::set_take_screenshot`.
+
+use super::*;
+use hbb_common::tokio::time::Instant;
+
+const DEFAULT_QUALITY: u8 = 80;
+// The video services of the displays not being viewed need some time to start.
+const STITCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub rgba: Vec<u8>,
+}
+
+/// Send the response of `request`, the image is cropped and encoded as requested.
+pub fn send_response(request: &ScreenshotRequest, tx: &Sender, image: Result<Image, String>) {
+    let mut response = ScreenshotResponse::new();
+    response.sid = request.sid.clone();
+    match image.and_then(|image| process(image, request).map_err(|e| e.to_string())) {
+        Ok((data, format)) => {
+            response.data = data.into();
+            response.format = format.into();
+        }
+        Err(msg) => {
+            response.msg = msg;
+        }
+    }
+    let mut msg_out = Message::new();
+    msg_out.set_screenshot_response(response);
+    if let Err(e) = tx.send((Instant::now(), Arc::new(msg_out))) {
+        log::error!("Failed to send screenshot, {}", e);
+    }
+}
+
+fn process(image: Image, request: &ScreenshotRequest) -> ResultType<(Vec<u8>, ScreenshotFormat)> {
+    let image = match request.region.as_ref() {
+        Some(region) => crop(image, region)?,
+        None => image,
+    };
+    let format = request.format.enum_value().unwrap_or(ScreenshotFormat::Png);
+    encode(&image, format, request.quality)
+}
+
+fn crop(image: Image, region: &ScreenshotRegion) -> ResultType<Image> {
+    let x0 = (region.x.max(0) as usize).min(image.width);
+    let y0 = (region.y.max(0) as usize).min(image.height);
+    let x1 = ((region.x as i64 + region.width as i64).max(0) as usize).min(image.width);
+    let y1 = ((region.y as i64 + region.height as i64).max(0) as usize).min(image.height);
+    if x1 <= x0 || y1 <= y0 {
+        bail!("The region is out of the screen");
+    }
+    if x0 == 0 && y0 == 0 && x1 == image.width && y1 == image.height {
+        return Ok(image);
+    }
+    let mut rgba = Vec::with_capacity((x1 - x0) * (y1 - y0) * 4);
+    for y in y0..y1 {
+        let row = y * image.width * 4;
+        rgba.extend_from_slice(&image.rgba[row + x0 * 4..row + x1 * 4]);
+    }
+    Ok(Image {
+        width: x1 - x0,
+        height: y1 - y0,
+        rgba,
+    })
+}
+
+// The images are placed at their positions, the gaps between them are black.
+fn stitch(parts: Vec<(i32, i32, Image)>) -> Image {
+    let left = parts.iter().map(|(x, _, _)| *x).min().unwrap_or(0);
+    let top = parts.iter().map(|(_, y, _)| *y).min().unwrap_or(0);
+    let right = parts
+        .iter()
+        .map(|(x, _, i)| *x + i.width as i32)
+        .max()
+        .unwrap_or(0);
+    let bottom = parts
+        .iter()
+        .map(|(_, y, i)| *y + i.height as i32)
+        .max()
+        .unwrap_or(0);
+    let width = (right - left).max(0) as usize;
+    let height = (bottom - top).max(0) as usize;
+    let mut rgba = [0u8, 0, 0, 255].repeat(width * height);
+    for (x, y, image) in parts {
+        let (x, y) = ((x - left) as usize, (y - top) as usize);
+        let len = image.width * 4;
+        for row in 0..image.height {
+            let dst = ((y + row) * width + x) * 4;
+            rgba[dst..dst + len].copy_from_slice(&image.rgba[row * len..(row + 1) * len]);
+        }
+    }
+    Image {
+        width,
+        height,
+        rgba,
+    }
+}
+
+fn encode_png(image: &Image) -> ResultType<Vec<u8>> {
+    let mut png = Vec::new();
+    let mut encoder =
+        repng::Options::smallest(image.width as _, image.height as _).build(&mut png)?;
+    encoder.write(&image.rgba)?;
+    encoder.finish()?;
+    Ok(png)
+}
+
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+fn encode(
+    image: &Image,
+    format: ScreenshotFormat,
+    quality: u32,
+) -> ResultType<(Vec<u8>, ScreenshotFormat)> {
+    use image::{
+        codecs::{jpeg::JpegEncoder, webp::WebPEncoder},
+        ColorType, ImageEncoder,
+    };
+    let mut data = Vec::new();
+    match format {
+        ScreenshotFormat::Png => {
+            data = encode_png(image)?;
+        }
+        ScreenshotFormat::Jpeg => {
+            let quality = if quality == 0 {
+                DEFAULT_QUALITY
+            } else {
+                quality.min(100) as u8
+            };
+            let rgb: Vec<u8> = image
+                .rgba
+                .chunks_exact(4)
+                .flat_map(|p| [p[0], p[1], p[2]])
+                .collect();
+            JpegEncoder::new_with_quality(&mut data, quality).write_image(
+                &rgb,
+                image.width as _,
+                image.height as _,
+                ColorType::Rgb8,
+            )?;
+        }
+        ScreenshotFormat::WebP => {
+            WebPEncoder::new_lossless(&mut data).write_image(
+                &image.rgba,
+                image.width as _,
+                image.height as _,
+                ColorType::Rgba8,
+            )?;
+        }
+    }
+    Ok((data, format))
+}
+
+// Only png is supported, it is what the controlling side gets then.
+#[cfg(any(target_os = "android", target_os = "ios"))]
+fn encode(
+    image: &Image,
+    _format: ScreenshotFormat,
+    _quality: u32,
+) -> ResultType<(Vec<u8>, ScreenshotFormat)> {
+    Ok((encode_png(image)?, ScreenshotFormat::Png))
+}
+
+/// Collects the screenshots of all displays and stitches them in the layout of the displays.
+pub struct Stitcher {
+    request: ScreenshotRequest,
+    tx: Sender,
+    displays: Vec<DisplayInfo>,
+    state: Mutex<StitchState>,
+}
+
+struct StitchState {
+    parts: HashMap<usize, Image>,
+    // `None` if the response has been sent.
+    on_done: Option<Box<dyn FnOnce() + Send>>,
+}
+
+impl Stitcher {
+    /// `on_done` is called once the response is sent, on success, error or timeout.
+    pub fn new(
+        displays: Vec<DisplayInfo>,
+        request: ScreenshotRequest,
+        tx: Sender,
+        on_done: impl FnOnce() + Send + 'static,
+    ) -> Arc<Self> {
+        let stitcher = Arc::new(Self {
+            request,
+            tx,
+            displays,
+            state: Mutex::new(StitchState {
+                parts: HashMap::new(),
+                on_done: Some(Box::new(on_done)),
+            }),
+        });
+        if stitcher.displays.is_empty() {
+            stitcher.finish(Err("No displays".to_owned()));
+        } else {
+            let stitcher = stitcher.clone();
+            std::thread::spawn(move || {
+                std::thread::sleep(STITCH_TIMEOUT);
+                stitcher.finish(Err(
+                    "Failed to take screenshot, please try again later.".to_owned()
+                ));
+            });
+        }
+        stitcher
+    }
+
+    pub fn add(&self, display: usize, image: Result<Image, String>) {
+        let image = match image {
+            Ok(image) => image,
+            Err(e) => {
+                self.finish(Err(e));
+                return;
+            }
+        };
+        let mut state = self.state.lock().unwrap();
+        if state.on_done.is_none() {
+            return;
+        }
+        state.parts.insert(display, image);
+        if state.parts.len() < self.displays.len() {
+            return;
+        }
+        let parts = std::mem::take(&mut state.parts);
+        drop(state);
+        self.finish(Ok(self.layout(parts)));
+    }
+
+    // The positions of the displays may be in logical pixels, e.g. on macOS,
+    // they are scaled to the pixels of the images.
+    fn layout(&self, parts: HashMap<usize, Image>) -> Image {
+        let scale = parts
+            .iter()
+            .filter_map(|(idx, image)| {
+                let d = self.displays.get(*idx)?;
+                (d.width > 0).then(|| image.width as f64 / d.width as f64)
+            })
+            .fold(1.0, f64::max);
+        let parts = parts
+            .into_iter()
+            .filter_map(|(idx, image)| {
+                let d = self.displays.get(idx)?;
+                let x = (d.x as f64 * scale).round() as i32;
+                let y = (d.y as f64 * scale).round() as i32;
+                Some((x, y, image))
+            })
+            .collect();
+        stitch(parts)
+    }
+
+    fn finish(&self, image: Result<Image, String>) {
+        let Some(on_done) = self.state.lock().unwrap().on_done.take() else {
+            return;
+        };
+        on_done();
+        send_response(&self.request, &self.tx, image);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(width: usize, height: usize, value: u8) -> Image {
+        Image {
+            width,
+            height,
+            rgba: vec![value; width * height * 4],
+        }
+    }
+
+    #[test]
+    fn test_crop_and_stitch() {
+        let mut src = image(4, 3, 0);
+        for (i, p) in src.rgba.chunks_exact_mut(4).enumerate() {
+            p[0] = i as u8;
+        }
+        let region = ScreenshotRegion {
+            x: 1,
+            y: 1,
+            width: 10,
+            height: 1,
+            ..Default::default()
+        };
+        let cropped = crop(src, &region).unwrap();
+        assert_eq!((cropped.width, cropped.height), (3, 1));
+        let reds: Vec<u8> = cropped.rgba.chunks_exact(4).map(|p| p[0]).collect();
+        assert_eq!(reds, vec![5, 6, 7]);
+        let region = ScreenshotRegion {
+            x: 5,
+            width: 1,
+            height: 1,
+            ..Default::default()
+        };
+        assert!(crop(image(4, 3, 0), &region).is_err());
+
+        let stitched = stitch(vec![(-2, 0, image(2, 1, 1)), (0, 1, image(1, 2, 2))]);
+        assert_eq!((stitched.width, stitched.height), (3, 3));
+        let reds: Vec<u8> = stitched.rgba.chunks_exact(4).map(|p| p[0]).collect();
+        assert_eq!(reds, vec![1, 1, 0, 0, 0, 2, 0, 0, 2]);
+    }
+}
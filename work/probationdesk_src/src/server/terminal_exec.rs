@@ -0,0 +1,486 @@
+// Non-interactive command execution for terminal connections.
+//
+// Unlike the PTY terminals in `terminal_service`, the command gets no tty,
+// stdout and stderr are sent separately and the exit code is reported when the process ends.
+// The command runs in its own process group, or job object on Windows, so that the processes
+// it started are killed with it on timeout or cancel.
+
+use super::*;
+use crate::terminal_service::UserToken;
+use hbb_common::message_proto::exec_output;
+use std::{
+    collections::HashMap,
+    io::{Read, Write},
+    process::{Command, Stdio},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, RecvTimeoutError},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+const READ_BUF_SIZE: usize = 4096;
+const POLL_INTERVAL: Duration = Duration::from_millis(30);
+// Grandchildren may keep the pipes open after the command exits.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+lazy_static::lazy_static! {
+    // (conn id, exec id) -> cancel flag
+    static ref RUNNING: Mutex<HashMap<(i32, i32), Arc<AtomicBool>>> = Default::default();
+}
+
+type Pipes = (
+    Option<Box<dyn Write + Send>>,
+    Box<dyn Read + Send>,
+    Box<dyn Read + Send>,
+);
+
+enum Process {
+    #[cfg(not(target_os = "windows"))]
+    Child(std::process::Child),
+    #[cfg(target_os = "windows")]
+    Child(std::process::Child, crate::platform::windows::Job),
+    #[cfg(target_os = "windows")]
+    User(crate::platform::windows::PipedUserProcess),
+}
+
+impl Process {
+    fn try_wait(&mut self) -> ResultType<Option<i32>> {
+        match self {
+            #[cfg(not(target_os = "windows"))]
+            Process::Child(child) => Ok(child.try_wait()?.map(exit_code)),
+            #[cfg(target_os = "windows")]
+            Process::Child(child, _) => Ok(child.try_wait()?.map(exit_code)),
+            #[cfg(target_os = "windows")]
+            Process::User(process) => process.try_wait(),
+        }
+    }
+
+    // Not after `try_wait` returned the exit code, the group id may be reused then.
+    fn kill(&mut self) {
+        match self {
+            #[cfg(not(target_os = "windows"))]
+            Process::Child(child) => unsafe {
+                // The group id is the pid of the command, see `spawn`.
+                if hbb_common::libc::kill(-(child.id() as i32), hbb_common::libc::SIGKILL) != 0 {
+                    child.kill().ok();
+                }
+            },
+            #[cfg(target_os = "windows")]
+            Process::Child(child, job) => {
+                job.terminate();
+                child.kill().ok();
+            }
+            #[cfg(target_os = "windows")]
+            Process::User(process) => process.kill(),
+        }
+    }
+}
+
+fn exit_code(status: std::process::ExitStatus) -> i32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            // Like the shells do.
+            return 128 + signal;
+        }
+    }
+    status.code().unwrap_or(-1)
+}
+
+/// Run `exec` in a new thread, the output and the result are sent to `inner`.
+pub fn start(conn_id: i32, exec: ExecCommand, user_token: Option<UserToken>, inner: ConnInner) {
+    let cancel = Arc::new(AtomicBool::new(false));
+    RUNNING
+        .lock()
+        .unwrap()
+        .insert((conn_id, exec.exec_id), cancel.clone());
+    thread::spawn(move || {
+        let mut inner = inner;
+        let mut send = |response: TerminalResponse| {
+            let mut msg_out = Message::new();
+            msg_out.set_terminal_response(response);
+            inner.send(Arc::new(msg_out));
+        };
+        let result = match run(&exec, user_token, &cancel, &mut send) {
+            Ok(result) => result,
+            Err(err) => ExecResult {
+                exec_id: exec.exec_id,
+                exit_code: -1,
+                error: err.to_string(),
+                ..Default::default()
+            },
+        };
+        log::info!(
+            "Exec {} finished, exit code: {}, timed out: {}, canceled: {}",
+            exec.exec_id,
+            result.exit_code,
+            result.timed_out,
+            result.canceled
+        );
+        RUNNING.lock().unwrap().remove(&(conn_id, exec.exec_id));
+        let mut response = TerminalResponse::new();
+        response.set_exec_result(result);
+        send(response);
+    });
+}
+
+pub fn cancel(conn_id: i32, exec_id: i32) {
+    if let Some(cancel) = RUNNING.lock().unwrap().get(&(conn_id, exec_id)) {
+        cancel.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Kill the commands of a closed connection.
+pub fn cancel_all(conn_id: i32) {
+    for ((id, _), cancel) in RUNNING.lock().unwrap().iter() {
+        if *id == conn_id {
+            cancel.store(true, Ordering::SeqCst);
+        }
+    }
+}
+
+fn run(
+    exec: &ExecCommand,
+    user_token: Option<UserToken>,
+    cancel: &AtomicBool,
+    send: &mut impl FnMut(TerminalResponse),
+) -> ResultType<ExecResult> {
+    let (mut process, (stdin, stdout, stderr)) = spawn(exec, user_token)?;
+    // Dropping stdin closes it, so that commands reading it do not hang.
+    if let Some(mut stdin) = stdin {
+        if !exec.stdin.is_empty() {
+            allow_err!(stdin.write_all(&exec.stdin));
+        }
+    }
+    let (tx, rx) = mpsc::channel::<(exec_output::Stream, Vec<u8>)>();
+    for (stream, mut reader) in [
+        (exec_output::Stream::Stdout, stdout),
+        (exec_output::Stream::Stderr, stderr),
+    ] {
+        let tx = tx.clone();
+        thread::spawn(move || {
+            let mut buf = vec![0u8; READ_BUF_SIZE];
+            loop {
+                match reader.read(&mut buf) {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => {
+                        if tx.send((stream, buf[..n].to_vec())).is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+        });
+    }
+    drop(tx);
+
+    let mut send_output = |stream: exec_output::Stream, data: Vec<u8>| {
+        let mut response = TerminalResponse::new();
+        response.set_exec_output(ExecOutput {
+            exec_id: exec.exec_id,
+            stream: stream.into(),
+            data: data.into(),
+            ..Default::default()
+        });
+        send(response);
+    };
+    let deadline = (exec.timeout_secs > 0)
+        .then(|| Instant::now() + Duration::from_secs(exec.timeout_secs as _));
+    let mut timed_out = false;
+    let mut canceled = false;
+    let exit_code = loop {
+        match rx.recv_timeout(POLL_INTERVAL) {
+            Ok((stream, data)) => send_output(stream, data),
+            // Both pipes are closed, the process may still run.
+            Err(RecvTimeoutError::Disconnected) => thread::sleep(POLL_INTERVAL),
+            Err(RecvTimeoutError::Timeout) => {}
+        }
+        if let Some(code) = process.try_wait()? {
+            break code;
+        }
+        if timed_out || canceled {
+            continue;
+        }
+        if deadline.is_some_and(|d| Instant::now() >= d) {
+            timed_out = true;
+            process.kill();
+        } else if cancel.load(Ordering::SeqCst) {
+            canceled = true;
+            process.kill();
+        }
+    };
+    let drain_deadline = Instant::now() + DRAIN_TIMEOUT;
+    while let Ok((stream, data)) =
+        rx.recv_timeout(drain_deadline.saturating_duration_since(Instant::now()))
+    {
+        send_output(stream, data);
+    }
+    Ok(ExecResult {
+        exec_id: exec.exec_id,
+        exit_code,
+        timed_out,
+        canceled,
+        ..Default::default()
+    })
+}
+
+fn spawn(exec: &ExecCommand, _user_token: Option<UserToken>) -> ResultType<(Process, Pipes)> {
+    if exec.command.is_empty() {
+        bail!("Empty command");
+    }
+    #[cfg(target_os = "windows")]
+    if !exec.run_as.is_empty() {
+        bail!("Running as another user is not supported on Windows");
+    }
+    #[cfg(target_os = "windows")]
+    if let Some(token) = _user_token {
+        let mut process = crate::platform::windows::spawn_piped_as_user(
+            token,
+            &windows_command_line(exec),
+            &exec.env,
+            &exec.working_dir,
+        )?;
+        let (Some(stdout), Some(stderr)) = (process.stdout.take(), process.stderr.take()) else {
+            bail!("Failed to get the output pipes");
+        };
+        let stdin = process
+            .stdin
+            .take()
+            .map(|s| Box::new(s) as Box<dyn Write + Send>);
+        return Ok((
+            Process::User(process),
+            (stdin, Box::new(stdout), Box::new(stderr)),
+        ));
+    }
+
+    let mut cmd = if exec.args.is_empty() {
+        shell_command(&exec.command)
+    } else {
+        let mut cmd = Command::new(&exec.command);
+        cmd.args(&exec.args);
+        cmd
+    };
+    cmd.envs(&exec.env)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    if !exec.working_dir.is_empty() {
+        cmd.current_dir(&exec.working_dir);
+    }
+    #[cfg(not(target_os = "windows"))]
+    {
+        use std::os::unix::process::CommandExt;
+        cmd.process_group(0);
+        if !exec.run_as.is_empty() {
+            run_as(&mut cmd, &exec.run_as, exec.working_dir.is_empty())?;
+        }
+    }
+    #[cfg(target_os = "windows")]
+    {
+        use std::os::windows::process::CommandExt;
+        cmd.creation_flags(winapi::um::winbase::CREATE_NO_WINDOW);
+    }
+    let mut child = cmd.spawn()?;
+    // The processes started before the assignment, right after the spawn, are not in the job.
+    #[cfg(target_os = "windows")]
+    let job = {
+        use std::os::windows::io::AsRawHandle;
+        let job = crate::platform::windows::Job::new()?;
+        if let Err(err) = job.assign(child.as_raw_handle() as _) {
+            child.kill().ok();
+            return Err(err);
+        }
+        job
+    };
+    let (Some(stdout), Some(stderr)) = (child.stdout.take(), child.stderr.take()) else {
+        bail!("Failed to get the output pipes");
+    };
+    let stdin = child
+        .stdin
+        .take()
+        .map(|s| Box::new(s) as Box<dyn Write + Send>);
+    #[cfg(not(target_os = "windows"))]
+    let process = Process::Child(child);
+    #[cfg(target_os = "windows")]
+    let process = Process::Child(child, job);
+    Ok((process, (stdin, Box::new(stdout), Box::new(stderr))))
+}
+
+// The user, its groups and its environment, switched to in the forked process, the service runs
+// as root then.
+#[cfg(not(target_os = "windows"))]
+fn run_as(cmd: &mut Command, user: &str, home_dir: bool) -> ResultType<()> {
+    use hbb_common::libc;
+    use std::{
+        ffi::{CStr, CString},
+        os::unix::process::CommandExt,
+    };
+    #[cfg(target_os = "macos")]
+    type GroupId = libc::c_int;
+    #[cfg(not(target_os = "macos"))]
+    type GroupId = libc::gid_t;
+
+    let name = CString::new(user)?;
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buf = vec![0 as libc::c_char; 16384];
+    let mut found = std::ptr::null_mut();
+    let res = unsafe {
+        libc::getpwnam_r(
+            name.as_ptr(),
+            &mut passwd,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut found,
+        )
+    };
+    if res != 0 || found.is_null() {
+        bail!("No user {}", user);
+    }
+    let (uid, gid) = (passwd.pw_uid, passwd.pw_gid);
+    let home = unsafe { CStr::from_ptr(passwd.pw_dir) }
+        .to_string_lossy()
+        .into_owned();
+    cmd.env("HOME", &home)
+        .env("USER", user)
+        .env("LOGNAME", user);
+    if home_dir {
+        cmd.current_dir(&home);
+    }
+    if uid == unsafe { libc::geteuid() } {
+        return Ok(());
+    }
+    if unsafe { libc::geteuid() } != 0 {
+        bail!("Running as {} requires root", user);
+    }
+    let mut groups: Vec<GroupId> = vec![0; 64];
+    loop {
+        let mut n = groups.len() as libc::c_int;
+        let res =
+            unsafe { libc::getgrouplist(name.as_ptr(), gid as _, groups.as_mut_ptr(), &mut n) };
+        if res >= 0 {
+            groups.truncate(n as _);
+            break;
+        }
+        if groups.len() >= 65536 {
+            bail!("Failed to get the groups of {}", user);
+        }
+        groups.resize((n as usize).max(groups.len() * 2), 0);
+    }
+    // Only async-signal-safe calls after the fork, the groups are looked up before. The groups
+    // and the group go first, they can't be changed without root.
+    unsafe {
+        cmd.pre_exec(move || {
+            if libc::setgroups(groups.len() as _, groups.as_ptr() as *const libc::gid_t) != 0
+                || libc::setgid(gid) != 0
+                || libc::setuid(uid) != 0
+            {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "windows"))]
+fn shell_command(command: &str) -> Command {
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c").arg(command);
+    cmd
+}
+
+#[cfg(target_os = "windows")]
+fn comspec() -> String {
+    std::env::var("COMSPEC").unwrap_or_else(|_| "cmd.exe".to_owned())
+}
+
+#[cfg(target_os = "windows")]
+fn shell_command(command: &str) -> Command {
+    use std::os::windows::process::CommandExt;
+    let mut cmd = Command::new(comspec());
+    // `/S` strips the outer quotes only, so that the command is passed as it is.
+    cmd.raw_arg(format!("/S /C \"{}\"", command));
+    cmd
+}
+
+#[cfg(target_os = "windows")]
+fn windows_command_line(exec: &ExecCommand) -> String {
+    if exec.args.is_empty() {
+        return format!("\"{}\" /S /C \"{}\"", comspec(), exec.command);
+    }
+    std::iter::once(&exec.command)
+        .chain(exec.args.iter())
+        .map(|arg| quote_windows_arg(arg))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// https://learn.microsoft.com/en-us/cpp/c-language/parsing-c-command-line-arguments
+#[cfg(any(target_os = "windows", test))]
+fn quote_windows_arg(arg: &str) -> String {
+    if !arg.is_empty() && !arg.contains(&[' ', '\t', '"'][..]) {
+        return arg.to_owned();
+    }
+    let mut quoted = String::from("\"");
+    let mut backslashes = 0;
+    for c in arg.chars() {
+        match c {
+            '\\' => backslashes += 1,
+            '"' => {
+                quoted.push_str(&"\\".repeat(backslashes * 2 + 1));
+                backslashes = 0;
+            }
+            _ => {
+                quoted.push_str(&"\\".repeat(backslashes));
+                backslashes = 0;
+            }
+        }
+        if c != '\\' {
+            quoted.push(c);
+        }
+    }
+    quoted.push_str(&"\\".repeat(backslashes * 2));
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quote_windows_arg() {
+        assert_eq!(quote_windows_arg("abc"), "abc");
+        assert_eq!(quote_windows_arg(""), "\"\"");
+        assert_eq!(quote_windows_arg("a b"), "\"a b\"");
+        assert_eq!(quote_windows_arg("a\"b"), "\"a\\\"b\"");
+        assert_eq!(quote_windows_arg("C:\\a b\\"), "\"C:\\a b\\\\\"");
+        assert_eq!(quote_windows_arg("a\\\\\"b c"), "\"a\\\\\\\\\\\"b c\"");
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    #[test]
+    fn test_kill_group() {
+        let exec = ExecCommand {
+            exec_id: 1,
+            command: "sleep 30 & sleep 30".to_owned(),
+            timeout_secs: 1,
+            ..Default::default()
+        };
+        let start = Instant::now();
+        let result = run(&exec, None, &AtomicBool::new(false), &mut |_| {}).unwrap();
+        assert!(result.timed_out);
+        // The background sleep is killed too, its pipes are not waited for.
+        assert!(start.elapsed() < Duration::from_secs(1) + DRAIN_TIMEOUT);
+
+        let exec = ExecCommand {
+            command: "id -u".to_owned(),
+            run_as: "no-such-user".to_owned(),
+            ..exec
+        };
+        assert!(spawn(&exec, None).is_err());
+    }
+}
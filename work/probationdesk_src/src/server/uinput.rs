@@ -1,4 +1,4 @@
-use crate::ipc::{self, new_listener, Connection, Data, DataKeyboard, DataMouse};
+use crate::ipc::{self, new_listener, Connection, Data, DataKeyboard, DataMouse, DataPen};
 use enigo::{Key, KeyboardControllable, MouseButton, MouseControllable};
 use evdev::{
     uinput::{VirtualDevice, VirtualDeviceBuilder},
@@ -15,6 +15,7 @@ static IPC_REQUEST_TIMEOUT: u64 = 1000;
 static IPC_POSTFIX_KEYBOARD: &str = "_uinput_keyboard";
 static IPC_POSTFIX_MOUSE: &str = "_uinput_mouse";
 static IPC_POSTFIX_CONTROL: &str = "_uinput_control";
+static IPC_POSTFIX_PEN: &str = "_uinput_pen";
 
 pub mod client {
     use super::*;
@@ -161,6 +162,23 @@ pub mod client {
         }
     }
 
+    pub struct UInputPen {
+        conn: Connection,
+        rt: Runtime,
+    }
+
+    impl UInputPen {
+        pub async fn new() -> ResultType<Self> {
+            let conn = ipc::connect(IPC_CONN_TIMEOUT, IPC_POSTFIX_PEN).await?;
+            let rt = Runtime::new()?;
+            Ok(Self { conn, rt })
+        }
+
+        pub fn send(&mut self, pen: DataPen) -> ResultType<()> {
+            self.rt.block_on(self.conn.send(&Data::Pen(pen)))
+        }
+    }
+
     pub async fn set_resolution(minx: i32, maxx: i32, miny: i32, maxy: i32) -> ResultType<()> {
         let mut conn = ipc::connect(IPC_CONN_TIMEOUT, IPC_POSTFIX_CONTROL).await?;
         conn.send(&Data::Control(ipc::DataControl::Resolution {
@@ -680,6 +698,66 @@ pub mod service {
         });
     }
 
+    fn handle_pen(
+        pen: &mut Option<(mouce::UInputPenManager, ((i32, i32), (i32, i32)))>,
+        data: &DataPen,
+    ) {
+        log::trace!("handle_pen {:?}", &data);
+        let resolution = *RESOLUTION.lock().unwrap();
+        if resolution.0 .0 == resolution.0 .1 || resolution.1 .0 == resolution.1 .1 {
+            return;
+        }
+        // The pen device shares the absolute range of the mouse, recreate it if the displays changed.
+        if pen.as_ref().map(|(_, r)| *r != resolution).unwrap_or(true) {
+            *pen = None;
+            log::info!(
+                "Create uinput pen with rng_x: ({}, {}), rng_y: ({}, {})",
+                resolution.0 .0,
+                resolution.0 .1,
+                resolution.1 .0,
+                resolution.1 .1
+            );
+            match mouce::UInputPenManager::new(resolution.0, resolution.1) {
+                Ok(manager) => *pen = Some((manager, resolution)),
+                Err(e) => {
+                    log::error!("Failed to create pen, {}", e);
+                    return;
+                }
+            }
+        }
+        if let Some((manager, _)) = pen.as_mut() {
+            allow_err!(manager.update(data));
+        }
+    }
+
+    fn spawn_pen_handler(mut stream: ipc::Connection) {
+        tokio::spawn(async move {
+            let mut pen = None;
+            loop {
+                tokio::select! {
+                    res = stream.next() => {
+                        match res {
+                            Err(err) => {
+                                log::info!("UInput pen ipc connection closed: {}", err);
+                                break;
+                            }
+                            Ok(Some(data)) => {
+                                match data {
+                                    Data::Pen(data) => {
+                                        handle_pen(&mut pen, &data);
+                                    }
+                                    _ => {
+                                    }
+                                }
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+            }
+        });
+    }
+
     fn spawn_controller_handler(mut stream: ipc::Connection) {
         tokio::spawn(async move {
             loop {
@@ -758,6 +836,13 @@ pub mod service {
         start_service(IPC_POSTFIX_CONTROL, spawn_controller_handler).await;
     }
 
+    /// Start uinput pen service.
+    #[tokio::main(flavor = "current_thread")]
+    pub async fn start_service_pen() {
+        log::info!("start uinput pen service");
+        start_service(IPC_POSTFIX_PEN, spawn_pen_handler).await;
+    }
+
     pub fn stop_service_keyboard() {
         log::info!("stop uinput keyboard service");
     }
@@ -791,6 +876,7 @@ mod mouce {
     const UI_SET_KEYBIT: c_ulong = 1074025829;
     const UI_SET_RELBIT: c_ulong = 1074025830;
     const UI_SET_ABSBIT: c_ulong = 1074025831;
+    const UI_SET_PROPBIT: c_ulong = 1074025838;
     const UI_DEV_SETUP: c_ulong = 1079792899;
     const UI_DEV_CREATE: c_ulong = 21761;
     const UI_DEV_DESTROY: c_uint = 21762;
@@ -812,6 +898,15 @@ mod mouce {
     pub const BTN_FORWARD: c_int = 0x115;
     pub const BTN_BACK: c_int = 0x116;
     pub const BTN_TASK: c_int = 0x117;
+    pub const BTN_TOOL_PEN: c_int = 0x140;
+    pub const BTN_TOOL_RUBBER: c_int = 0x141;
+    pub const BTN_TOUCH: c_int = 0x14a;
+    pub const BTN_STYLUS: c_int = 0x14b;
+    pub const BTN_STYLUS2: c_int = 0x14c;
+    pub const ABS_PRESSURE: c_uint = 0x18;
+    pub const ABS_TILT_X: c_uint = 0x1a;
+    pub const ABS_TILT_Y: c_uint = 0x1b;
+    const INPUT_PROP_DIRECT: c_int = 0x01;
     const SYN_REPORT: c_int = 0x00;
     const EV_SYN: c_int = 0x00;
     const BUS_USB: c_ushort = 0x03;
@@ -889,6 +984,69 @@ mod mouce {
 
     const UINPUT_MAX_NAME_SIZE: usize = 80;
 
+    /// Write the given event to the uinput file
+    fn emit(uinput_file: &File, r#type: c_int, code: c_int, value: c_int) -> Result<()> {
+        let mut event = InputEvent {
+            time: TimeVal {
+                tv_sec: 0,
+                tv_usec: 0,
+            },
+            r#type: r#type as c_ushort,
+            code: code as c_ushort,
+            value,
+        };
+        let fd = uinput_file.as_raw_fd();
+
+        unsafe {
+            let count = size_of::<InputEvent>();
+            let written_bytes = write(fd, &mut event, count);
+            if written_bytes == -1 || written_bytes != count as c_long {
+                return Err(Error::new(
+                    ErrorKind::Other,
+                    format!("failed while trying to write to a file"),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn device_name(name: &str) -> [c_char; UINPUT_MAX_NAME_SIZE] {
+        let mut device_name = [0; UINPUT_MAX_NAME_SIZE];
+        for (dst, ch) in device_name
+            .iter_mut()
+            .zip(name.bytes().take(UINPUT_MAX_NAME_SIZE - 1))
+        {
+            *dst = ch as c_char;
+        }
+        device_name
+    }
+
+    unsafe fn abs_setup(
+        fd: c_int,
+        code: c_uint,
+        minimum: c_int,
+        maximum: c_int,
+        resolution: c_int,
+    ) {
+        ioctl(fd, UI_SET_ABSBIT, code);
+        ioctl(
+            fd,
+            UI_ABS_SETUP,
+            &UinputAbsSetup {
+                code: code as _,
+                absinfo: InputAbsinfo {
+                    value: 0,
+                    minimum,
+                    maximum,
+                    fuzz: 0,
+                    flat: 0,
+                    resolution,
+                },
+            },
+        );
+    }
+
     pub struct UInputMouseManager {
         uinput_file: File,
     }
@@ -991,29 +1149,7 @@ mod mouce {
 
         /// Write the given event to the uinput file
         fn emit(&self, r#type: c_int, code: c_int, value: c_int) -> Result<()> {
-            let mut event = InputEvent {
-                time: TimeVal {
-                    tv_sec: 0,
-                    tv_usec: 0,
-                },
-                r#type: r#type as c_ushort,
-                code: code as c_ushort,
-                value,
-            };
-            let fd = self.uinput_file.as_raw_fd();
-
-            unsafe {
-                let count = size_of::<InputEvent>();
-                let written_bytes = write(fd, &mut event, count);
-                if written_bytes == -1 || written_bytes != count as c_long {
-                    return Err(Error::new(
-                        ErrorKind::Other,
-                        format!("failed while trying to write to a file"),
-                    ));
-                }
-            }
-
-            Ok(())
+            emit(&self.uinput_file, r#type, code, value)
         }
 
         /// Syncronize the device
@@ -1110,4 +1246,130 @@ mod mouce {
             }
         }
     }
+
+    /// A tablet-like device, so that applications receive pressure and tilt.
+    pub struct UInputPenManager {
+        uinput_file: File,
+        tool: Option<c_int>,
+    }
+
+    impl UInputPenManager {
+        pub fn new(rng_x: (i32, i32), rng_y: (i32, i32)) -> Result<Self> {
+            let manager = UInputPenManager {
+                uinput_file: File::options()
+                    .write(true)
+                    .custom_flags(O_NONBLOCK)
+                    .open("/dev/uinput")?,
+                tool: None,
+            };
+            let fd = manager.uinput_file.as_raw_fd();
+            unsafe {
+                ioctl(fd, UI_SET_EVBIT, EV_KEY);
+                ioctl(fd, UI_SET_KEYBIT, BTN_TOOL_PEN);
+                ioctl(fd, UI_SET_KEYBIT, BTN_TOOL_RUBBER);
+                ioctl(fd, UI_SET_KEYBIT, BTN_TOUCH);
+                ioctl(fd, UI_SET_KEYBIT, BTN_STYLUS);
+                ioctl(fd, UI_SET_KEYBIT, BTN_STYLUS2);
+
+                ioctl(fd, UI_SET_EVBIT, EV_ABS);
+                abs_setup(fd, ABS_X, rng_x.0, rng_x.1, 1);
+                abs_setup(fd, ABS_Y, rng_y.0, rng_y.1, 1);
+                abs_setup(fd, ABS_PRESSURE, 0, crate::input::PEN_PRESSURE_MAX as _, 0);
+                abs_setup(
+                    fd,
+                    ABS_TILT_X,
+                    -crate::input::PEN_TILT_MAX,
+                    crate::input::PEN_TILT_MAX,
+                    0,
+                );
+                abs_setup(
+                    fd,
+                    ABS_TILT_Y,
+                    -crate::input::PEN_TILT_MAX,
+                    crate::input::PEN_TILT_MAX,
+                    0,
+                );
+
+                // The coordinates are mapped onto the screens, like a display tablet.
+                ioctl(fd, UI_SET_PROPBIT, INPUT_PROP_DIRECT);
+            }
+
+            let usetup = UInputSetup {
+                id: InputId {
+                    bustype: BUS_USB,
+                    vendor: 0x2222,
+                    product: 0x3334,
+                    version: 0,
+                },
+                name: device_name(&format!("{} UInput Pen", crate::get_app_name())),
+                ff_effects_max: 0,
+            };
+
+            unsafe {
+                ioctl(fd, UI_DEV_SETUP, &usetup);
+                ioctl(fd, UI_DEV_CREATE);
+            }
+
+            // See `UInputMouseManager::new()`.
+            thread::sleep(Duration::from_millis(300));
+
+            Ok(manager)
+        }
+
+        fn emit(&self, r#type: c_int, code: c_int, value: c_int) -> Result<()> {
+            emit(&self.uinput_file, r#type, code, value)
+        }
+
+        pub fn update(&mut self, pen: &super::DataPen) -> Result<()> {
+            let tool = if pen.eraser {
+                BTN_TOOL_RUBBER
+            } else {
+                BTN_TOOL_PEN
+            };
+            if let Some(old) = self.tool {
+                if !pen.in_range || old != tool {
+                    self.emit(EV_KEY, BTN_TOUCH, 0)?;
+                    self.emit(EV_KEY, BTN_STYLUS, 0)?;
+                    self.emit(EV_KEY, BTN_STYLUS2, 0)?;
+                    self.emit(EV_ABS, ABS_PRESSURE as c_int, 0)?;
+                    self.emit(EV_KEY, old, 0)?;
+                    self.emit(EV_SYN, SYN_REPORT, 0)?;
+                    self.tool = None;
+                }
+            }
+            if !pen.in_range {
+                return Ok(());
+            }
+            if self.tool.is_none() {
+                self.emit(EV_KEY, tool, 1)?;
+                self.tool = Some(tool);
+            }
+            self.emit(EV_ABS, ABS_X as c_int, pen.x)?;
+            self.emit(EV_ABS, ABS_Y as c_int, pen.y)?;
+            self.emit(EV_ABS, ABS_PRESSURE as c_int, pen.pressure as c_int)?;
+            self.emit(EV_ABS, ABS_TILT_X as c_int, pen.tilt_x)?;
+            self.emit(EV_ABS, ABS_TILT_Y as c_int, pen.tilt_y)?;
+            self.emit(EV_KEY, BTN_TOUCH, (pen.pressure > 0) as c_int)?;
+            self.emit(
+                EV_KEY,
+                BTN_STYLUS,
+                (pen.buttons & crate::input::PEN_BUTTON_BARREL != 0) as c_int,
+            )?;
+            self.emit(
+                EV_KEY,
+                BTN_STYLUS2,
+                (pen.buttons & crate::input::PEN_BUTTON_BARREL2 != 0) as c_int,
+            )?;
+            self.emit(EV_SYN, SYN_REPORT, 0)
+        }
+    }
+
+    impl Drop for UInputPenManager {
+        fn drop(&mut self) {
+            let fd = self.uinput_file.as_raw_fd();
+            unsafe {
+                ioctl(fd, UI_DEV_DESTROY as c_ulong);
+            }
+        }
+    }
 }
@@ -93,12 +93,24 @@ struct UserData {
     quality: Option<(i64, Quality)>, // (time, quality)
     delay: UserDelay,
     record: bool,
+    text_lossless: bool,
+    bandwidth: Option<u32>, // kbps for video, by the bandwidth cap
 }
 
 #[derive(Default, Debug, Clone)]
 struct DisplayData {
     send_counter: usize, // Number of times encode during period
     support_changing_quality: bool,
+    capture_stats: CaptureStats,
+}
+
+// Capture and encode statistics of one display, updated every second
+#[derive(Default, Debug, Clone, Copy)]
+pub struct CaptureStats {
+    pub captured: usize,   // Frames with changes
+    pub idle: usize,       // Frames skipped because nothing changed
+    pub damage_ratio: f32, // Average changed fraction of the captured frames
+    pub encode_ms: u32,    // Average encode time of the captured frames
 }
 
 // Main QoS controller structure
@@ -174,6 +186,12 @@ impl VideoQoS {
         }
     }
 
+    // Check if all users want static text sent as lossless tiles.
+    // Older clients can't decode the tiles, so one of them disables it for everyone.
+    pub fn text_lossless(&self) -> bool {
+        !self.users.is_empty() && self.users.iter().all(|u| u.1.text_lossless)
+    }
+
     // Check if variable bitrate encoding is supported and enabled
     pub fn in_vbr_state(&self) -> bool {
         self.abr_config && self.displays.iter().all(|e| e.1.support_changing_quality)
@@ -243,6 +261,19 @@ impl VideoQoS {
         }
     }
 
+    pub fn user_text_lossless(&mut self, id: i32, v: bool) {
+        if let Some(user) = self.users.get_mut(&id) {
+            user.text_lossless = v;
+        }
+    }
+
+    // The video share of the bandwidth cap of the user, None for unlimited
+    pub fn user_bandwidth(&mut self, id: i32, kbps: Option<u32>) {
+        if let Some(user) = self.users.get_mut(&id) {
+            user.bandwidth = kbps;
+        }
+    }
+
     pub fn user_network_delay(&mut self, id: i32, delay: u32) {
         let highest_fps = self.highest_fps();
         let target_ratio = self.latest_quality().ratio();
@@ -377,6 +408,22 @@ impl VideoQoS {
         }
     }
 
+    pub fn update_capture_stats(&mut self, video_service_name: &str, stats: CaptureStats) {
+        if let Some(display) = self.displays.get_mut(video_service_name) {
+            display.capture_stats = stats;
+        }
+    }
+
+    // The statistics of the displays of the video services named with the prefix, by the display indexes
+    pub fn capture_stats(&self, prefix: &str) -> HashMap<i32, CaptureStats> {
+        self.displays
+            .iter()
+            .filter_map(|(name, d)| {
+                Some((name.strip_prefix(prefix)?.parse().ok()?, d.capture_stats))
+            })
+            .collect()
+    }
+
     #[inline]
     fn highest_fps(&self) -> u32 {
         let user_fps = |u: &UserData| {
@@ -443,7 +490,7 @@ impl VideoQoS {
         };
 
         // Set minimum ratio based on quality mode
-        let min = match target_quality {
+        let mut min = match target_quality {
             Quality::Best => {
                 // For Best quality, ensure minimum 1Mbps for high resolution
                 let mut min = BR_BEST / 2.5;
@@ -466,7 +513,18 @@ impl VideoQoS {
             Quality::Low => BR_MIN_HIGH_RESOLUTION,
             Quality::Custom(_) => BR_MIN_HIGH_RESOLUTION,
         };
-        let max = target_ratio * MAX_BR_MULTIPLE;
+        let mut max = target_ratio * MAX_BR_MULTIPLE;
+
+        // Stay within the bandwidth cap, it overrides the minimum of the quality mode
+        let bandwidth = self.users.iter().filter_map(|u| u.1.bandwidth).min();
+        if let Some(bandwidth) = bandwidth {
+            if current_bitrate > 0 {
+                max = max
+                    .min(current_ratio * bandwidth as f32 / current_bitrate as f32)
+                    .max(BR_MIN_HIGH_RESOLUTION);
+                min = min.min(max);
+            }
+        }
 
         let mut v = current_ratio;
 
@@ -593,3 +651,122 @@ impl RttCalculator {
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hbb_common::netsim::{stream_pair, Link, NetConditions};
+
+    const ID: i32 = 1;
+
+    fn new_qos() -> VideoQoS {
+        let mut qos = VideoQoS::default();
+        qos.users.insert(ID, UserData::default());
+        // Past the cap of the new connections.
+        qos.new_user_instant = Instant::now() - Duration::from_secs(2);
+        qos
+    }
+
+    // The controlled side and the controlling side answering its TestDelay, through a link.
+    fn connect(conditions: NetConditions) -> (Stream, Link) {
+        let (mut controlled, mut controlling, link) =
+            stream_pair(conditions, "127.0.0.1:0".parse().unwrap());
+        tokio::spawn(async move {
+            while let Some(Ok(msg)) = controlling.next().await {
+                if controlling.send_bytes(msg.freeze()).await.is_err() {
+                    break;
+                }
+            }
+        });
+        (controlled, link)
+    }
+
+    // One TestDelay per second as in the connection, the ratio is adjusted every 3 seconds.
+    // While waiting for the response, the elapsed time is reported every second.
+    async fn run(qos: &mut VideoQoS, stream: &mut Stream, seconds: usize) {
+        for i in 0..seconds {
+            let start = tokio::time::Instant::now();
+            stream
+                .send_bytes(Bytes::from_static(b"delay"))
+                .await
+                .unwrap();
+            loop {
+                match tokio::time::timeout(Duration::from_secs(1), stream.next()).await {
+                    Ok(res) => {
+                        res.unwrap().unwrap();
+                        break;
+                    }
+                    Err(_) => qos.user_delay_response_elapsed(ID, start.elapsed().as_millis()),
+                }
+            }
+            qos.user_delay_response_elapsed(ID, 0);
+            qos.user_network_delay(ID, start.elapsed().as_millis() as u32);
+            if i % ADJUST_RATIO_INTERVAL == 0 {
+                qos.adjust_ratio(true);
+            }
+            tokio::time::sleep_until(start + Duration::from_secs(1)).await;
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_qos_scenarios() {
+        let mut qos = new_qos();
+        let (mut stream, link) = connect(NetConditions::lan());
+        run(&mut qos, &mut stream, 30).await;
+        assert_eq!(qos.fps(), FPS);
+        assert_eq!(qos.ratio(), BR_BALANCED);
+
+        // Congested, the jitter is much more than the round trip.
+        link.set_conditions(NetConditions {
+            latency: Duration::from_millis(50),
+            jitter: Duration::from_millis(600),
+            ..NetConditions::mobile()
+        });
+        run(&mut qos, &mut stream, 30).await;
+        assert!(qos.fps() <= 12, "fps: {}", qos.fps());
+        assert!(qos.ratio() < BR_BALANCED * 0.75, "ratio: {}", qos.ratio());
+
+        // A long but stable round trip is not congestion, once it is learned.
+        let mut qos = new_qos();
+        let (mut stream, _link) = connect(NetConditions::satellite());
+        run(&mut qos, &mut stream, 60).await;
+        assert_eq!(qos.fps(), FPS);
+        assert_eq!(qos.ratio(), BR_BALANCED);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_qos_outage() {
+        let mut qos = new_qos();
+        let (mut stream, link) = connect(NetConditions::lan());
+        run(&mut qos, &mut stream, 30).await;
+        assert_eq!(qos.fps(), FPS);
+
+        // No response for 3.5 seconds.
+        link.outage(Duration::from_millis(3500));
+        run(&mut qos, &mut stream, 1).await;
+        assert!(qos.fps() <= MIN_FPS + 1);
+
+        run(&mut qos, &mut stream, 30).await;
+        assert_eq!(qos.fps(), FPS);
+    }
+
+    #[test]
+    fn test_capture_stats() {
+        let mut qos = new_qos();
+        for name in ["monitor0", "monitor1", "camera0"] {
+            qos.new_display(name.to_owned());
+        }
+        let stats = CaptureStats {
+            captured: 3,
+            idle: 27,
+            damage_ratio: 0.1,
+            encode_ms: 4,
+        };
+        qos.update_capture_stats("monitor1", stats);
+        let monitors = qos.capture_stats("monitor");
+        assert_eq!(monitors.len(), 2);
+        assert_eq!(monitors[&1].idle, 27);
+        assert_eq!(monitors[&0].captured, 0);
+        assert_eq!(qos.capture_stats("camera").len(), 1);
+    }
+}
@@ -18,7 +18,13 @@
 // to-do:
 // https://slhck.info/video/2017/03/01/rate-control.html
 
-use super::{display_service::check_display_changed, service::ServiceTmpl, video_qos::VideoQoS, *};
+use super::{
+    display_service::check_display_changed,
+    screenshot::{send_response as send_screenshot_response, Image, Stitcher},
+    service::ServiceTmpl,
+    video_qos::{CaptureStats, VideoQoS},
+    *,
+};
 #[cfg(target_os = "linux")]
 use crate::common::SimpleCallOnReturn;
 #[cfg(target_os = "linux")]
@@ -48,6 +54,7 @@ use scrap::{
     aom::AomEncoderConfig,
     codec::{Encoder, EncoderCfg},
     record::{Recorder, RecorderContext},
+    text_tiles::TextTileEncoder,
     vpxcodec::{VpxEncoderConfig, VpxVideoCodecId},
     CodecFormat, Display, EncodeInput, TraitCapturer, TraitPixelBuffer,
 };
@@ -61,6 +68,8 @@ use std::{
 };
 
 pub const OPTION_REFRESH: &'static str = "refresh";
+// Only give ROI hints to the encoder if less than this fraction of the frame changed.
+const ROI_MAX_DAMAGE_RATIO: f32 = 0.5;
 
 lazy_static::lazy_static! {
     static ref FRAME_FETCHED_NOTIFIER: (UnboundedSender<(i32, Option<Instant>)>, Arc<TokioMutex<UnboundedReceiver<(i32, Option<Instant>)>>>) = {
@@ -70,13 +79,15 @@ lazy_static::lazy_static! {
     pub static ref VIDEO_QOS: Arc<Mutex<VideoQoS>> = Default::default();
     pub static ref IS_UAC_RUNNING: Arc<Mutex<bool>> = Default::default();
     pub static ref IS_FOREGROUND_WINDOW_ELEVATED: Arc<Mutex<bool>> = Default::default();
-    static ref SCREENSHOTS: Mutex<HashMap<usize, Screenshot>> = Default::default();
+    // By connection and display, each connection gets its own.
+    static ref SCREENSHOTS: Mutex<HashMap<(i32, usize), Screenshot>> = Default::default();
 }
 
 struct Screenshot {
-    sid: String,
+    request: ScreenshotRequest,
     tx: Sender,
     restore_vram: bool,
+    stitcher: Option<Arc<Stitcher>>,
 }
 
 #[inline]
@@ -134,6 +145,28 @@ impl VideoFrameController {
     }
 }
 
+#[derive(Default)]
+struct CaptureCounter {
+    captured: usize,
+    idle: usize,
+    damage_ratio_sum: f32,
+    encode_time: Duration,
+}
+
+impl CaptureCounter {
+    fn take_stats(&mut self) -> CaptureStats {
+        let captured = self.captured.max(1);
+        let stats = CaptureStats {
+            captured: self.captured,
+            idle: self.idle,
+            damage_ratio: self.damage_ratio_sum / captured as f32,
+            encode_ms: (self.encode_time.as_millis() / captured as u128) as _,
+        };
+        *self = Default::default();
+        stats
+    }
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum VideoSource {
     Monitor,
@@ -510,6 +543,7 @@ fn run(vs: VideoService) -> ResultType<()> {
         &Config::get_option("allow-auto-record-incoming"),
     );
     let client_record = video_qos.record();
+    let text_lossless = video_qos.text_lossless();
     drop(video_qos);
     let (mut encoder, encoder_cfg, codec_format, use_i444, recorder) = match setup_encoder(
         &c,
@@ -585,6 +619,12 @@ fn run(vs: VideoService) -> ResultType<()> {
     let capture_width = c.width;
     let capture_height = c.height;
     let (mut second_instant, mut send_counter) = (Instant::now(), 0);
+    let mut capture_counter = CaptureCounter::default();
+    let mut text_tiles = if text_lossless {
+        Some(TextTileEncoder::new())
+    } else {
+        None
+    };
 
     while sp.ok() {
         #[cfg(windows)]
@@ -595,6 +635,7 @@ fn run(vs: VideoService) -> ResultType<()> {
             &mut spf,
             client_record,
             &mut send_counter,
+            &mut capture_counter,
             &mut second_instant,
             &sp.name(),
         )?;
@@ -622,6 +663,11 @@ fn run(vs: VideoService) -> ResultType<()> {
             log::info!("switch due to i444 changed");
             bail!("SWITCH");
         }
+        // The new encoder starts with a key frame, which clears the tiles on the client.
+        if VIDEO_QOS.lock().unwrap().text_lossless() != text_lossless {
+            log::info!("switch due to text lossless changed");
+            bail!("SWITCH");
+        }
         #[cfg(all(windows, feature = "vram"))]
         if c.is_gdi() && encoder.input_texture() {
             log::info!("changed to gdi when using vram");
@@ -651,13 +697,15 @@ fn run(vs: VideoService) -> ResultType<()> {
 
         let time = now - start;
         let ms = (time.as_secs() * 1000 + time.subsec_millis() as u64) as i64;
+        // For the audio to be played in sync, see `AudioFrame.timestamp`.
+        let capture_time = hbb_common::get_time();
         let res = match c.frame(spf) {
             Ok(frame) => {
                 repeat_encode_counter = 0;
                 if frame.valid() {
-                    let screenshot = SCREENSHOTS.lock().unwrap().remove(&display_idx);
-                    if let Some(mut screenshot) = screenshot {
-                        let restore_vram = screenshot.restore_vram;
+                    let screenshots = take_screenshots(display_idx);
+                    if !screenshots.is_empty() {
+                        let restore_vram = screenshots.iter().any(|(_, s)| s.restore_vram);
                         let (msg, w, h, data) = match &frame {
                             scrap::Frame::PixelBuffer(f) => match get_rgba_from_pixelbuf(f) {
                                 Ok(rgba) => ("".to_owned(), f.width(), f.height(), rgba),
@@ -683,34 +731,78 @@ fn run(vs: VideoService) -> ResultType<()> {
                                 } else {
                                     #[cfg(all(windows, feature = "vram"))]
                                     VRamEncoder::set_not_use(sp.name(), true);
-                                    screenshot.restore_vram = true;
-                                    SCREENSHOTS.lock().unwrap().insert(display_idx, screenshot);
+                                    let mut lock = SCREENSHOTS.lock().unwrap();
+                                    for (conn_id, mut screenshot) in screenshots {
+                                        screenshot.restore_vram = true;
+                                        lock.insert((conn_id, display_idx), screenshot);
+                                    }
+                                    drop(lock);
                                     _raii.try_vram = false;
                                     bail!("SWITCH");
                                 }
                             }
                         };
                         std::thread::spawn(move || {
-                            handle_screenshot(screenshot, msg, w, h, data);
+                            for (_, screenshot) in screenshots {
+                                let (msg, data) = (msg.clone(), data.clone());
+                                handle_screenshot(display_idx, screenshot, msg, w, h, data);
+                            }
                         });
                         if restore_vram {
                             bail!("SWITCH");
                         }
                     }
 
+                    let text_frame = match (text_tiles.as_mut(), &frame) {
+                        (Some(text_tiles), scrap::Frame::PixelBuffer(f)) => {
+                            let stride = f.stride().get(0).cloned().unwrap_or_default();
+                            text_tiles.on_frame(f.data(), f.width(), f.height(), stride, f.pixfmt())
+                        }
+                        _ => false,
+                    };
                     let frame = frame.to(encoder.yuvfmt(), &mut yuv, &mut mid_data)?;
+                    let damage = c.damage();
+                    if text_frame {
+                        send_text_tiles(
+                            display_idx,
+                            &sp,
+                            text_tiles
+                                .as_mut()
+                                .and_then(|t| t.on_damage(damage.as_deref())),
+                        );
+                    }
+                    let damage_ratio = damage
+                        .as_ref()
+                        .map(|d| scrap::damage::damage_ratio(d, capture_width, capture_height))
+                        .unwrap_or(1.0);
+                    let roi: &[scrap::DamageRect] = match &damage {
+                        Some(d) if damage_ratio < ROI_MAX_DAMAGE_RATIO => d,
+                        _ => &[],
+                    };
+                    allow_err!(encoder.set_roi(roi));
+                    let encode_begin = Instant::now();
                     let send_conn_ids = handle_one_frame(
                         display_idx,
                         &sp,
                         frame,
                         ms,
+                        capture_time,
                         &mut encoder,
                         recorder.clone(),
                         &mut encode_fail_counter,
                         &mut first_frame,
                         capture_width,
                         capture_height,
+                        text_tiles.as_mut(),
                     )?;
+                    send_text_tiles(
+                        display_idx,
+                        &sp,
+                        text_tiles.as_mut().and_then(|t| t.promote()),
+                    );
+                    capture_counter.captured += 1;
+                    capture_counter.damage_ratio_sum += damage_ratio;
+                    capture_counter.encode_time += encode_begin.elapsed();
                     frame_controller.set_send(now, send_conn_ids);
                     send_counter += 1;
                 }
@@ -729,6 +821,7 @@ fn run(vs: VideoService) -> ResultType<()> {
 
         match res {
             Err(ref e) if e.kind() == WouldBlock => {
+                capture_counter.idle += 1;
                 #[cfg(windows)]
                 if try_gdi > 0 && !c.is_gdi() {
                     if try_gdi > 3 {
@@ -763,17 +856,24 @@ fn run(vs: VideoService) -> ResultType<()> {
                             &sp,
                             EncodeInput::YUV(&yuv),
                             ms,
+                            capture_time,
                             &mut encoder,
                             recorder.clone(),
                             &mut encode_fail_counter,
                             &mut first_frame,
                             capture_width,
                             capture_height,
+                            text_tiles.as_mut(),
                         )?;
                         frame_controller.set_send(now, send_conn_ids);
                         send_counter += 1;
                     }
                 }
+                send_text_tiles(
+                    display_idx,
+                    &sp,
+                    text_tiles.as_mut().and_then(|t| t.promote()),
+                );
             }
             Err(err) => {
                 // This check may be redundant, but it is better to be safe.
@@ -1061,12 +1161,14 @@ fn handle_one_frame(
     sp: &GenericService,
     frame: EncodeInput,
     ms: i64,
+    capture_time: i64,
     encoder: &mut Encoder,
     recorder: Arc<Mutex<Option<Recorder>>>,
     encode_fail_counter: &mut usize,
     first_frame: &mut bool,
     width: usize,
     height: usize,
+    text_tiles: Option<&mut TextTileEncoder>,
 ) -> ResultType<HashSet<i32>> {
     sp.snapshot(|sps| {
         // so that new sub and old sub share the same encoder after switch
@@ -1084,6 +1186,12 @@ fn handle_one_frame(
         Ok(mut vf) => {
             *encode_fail_counter = 0;
             vf.display = display as _;
+            vf.timestamp = capture_time;
+            if let (Some(text_tiles), Some(frame)) = (text_tiles, vf.union.as_ref()) {
+                if scrap::codec::contains_key_frame(frame) {
+                    text_tiles.on_key_frame();
+                }
+            }
             let mut msg = Message::new();
             msg.set_video_frame(vf);
             recorder
@@ -1127,6 +1235,16 @@ fn handle_one_frame(
     Ok(send_conn_ids)
 }
 
+// Sent on the video channel to keep the order with the encoded frames.
+fn send_text_tiles(display: usize, sp: &GenericService, vf: Option<VideoFrame>) {
+    if let Some(mut vf) = vf {
+        vf.display = display as _;
+        let mut msg = Message::new();
+        msg.set_video_frame(vf);
+        sp.send_video_frame(msg);
+    }
+}
+
 #[inline]
 pub fn refresh() {
     #[cfg(target_os = "android")]
@@ -1243,6 +1361,7 @@ fn check_qos(
     spf: &mut Duration,
     client_record: bool,
     send_counter: &mut usize,
+    capture_counter: &mut CaptureCounter,
     second_instant: &mut Instant,
     name: &str,
 ) -> ResultType<()> {
@@ -1269,22 +1388,78 @@ fn check_qos(
         *second_instant = Instant::now();
         video_qos.update_display_data(&name, *send_counter);
         *send_counter = 0;
+        video_qos.update_capture_stats(&name, capture_counter.take_stats());
     }
     drop(video_qos);
     Ok(())
 }
 
-pub fn set_take_screenshot(display_idx: usize, sid: String, tx: Sender) {
+pub fn set_take_screenshot(
+    conn_id: i32,
+    display_idx: usize,
+    request: ScreenshotRequest,
+    tx: Sender,
+) {
     SCREENSHOTS.lock().unwrap().insert(
-        display_idx,
+        (conn_id, display_idx),
         Screenshot {
-            sid,
+            request,
             tx,
             restore_vram: false,
+            stitcher: None,
         },
     );
 }
 
+// `on_done` is called when the screenshot is sent or failed,
+// to stop the video services which are only running for it.
+pub fn set_take_screenshot_of_all_displays(
+    conn_id: i32,
+    displays: Vec<DisplayInfo>,
+    request: ScreenshotRequest,
+    tx: Sender,
+    on_done: impl FnOnce() + Send + 'static,
+) {
+    let count = displays.len();
+    let stitcher = Stitcher::new(displays, request.clone(), tx.clone(), on_done);
+    let mut lock = SCREENSHOTS.lock().unwrap();
+    for display_idx in 0..count {
+        lock.insert(
+            (conn_id, display_idx),
+            Screenshot {
+                request: request.clone(),
+                tx: tx.clone(),
+                restore_vram: false,
+                stitcher: Some(stitcher.clone()),
+            },
+        );
+    }
+}
+
+// The screenshots of all connections waiting for the display.
+fn take_screenshots(display_idx: usize) -> Vec<(i32, Screenshot)> {
+    let mut lock = SCREENSHOTS.lock().unwrap();
+    let conn_ids: Vec<i32> = lock
+        .keys()
+        .filter(|(_, idx)| *idx == display_idx)
+        .map(|(conn_id, _)| *conn_id)
+        .collect();
+    conn_ids
+        .into_iter()
+        .filter_map(|conn_id| {
+            let screenshot = lock.remove(&(conn_id, display_idx))?;
+            Some((conn_id, screenshot))
+        })
+        .collect()
+}
+
+pub fn cancel_screenshots(conn_id: i32) {
+    SCREENSHOTS
+        .lock()
+        .unwrap()
+        .retain(|(id, _), _| *id != conn_id);
+}
+
 // We need to this function, because the `stride` may be larger than `width * 4`.
 fn get_rgba_from_pixelbuf<'a>(pixbuf: &scrap::PixelBuffer<'a>) -> ResultType<Vec<u8>> {
     let w = pixbuf.width();
@@ -1311,39 +1486,27 @@ fn get_rgba_from_pixelbuf<'a>(pixbuf: &scrap::PixelBuffer<'a>) -> ResultType<Vec
     }
 }
 
-fn handle_screenshot(screenshot: Screenshot, msg: String, w: usize, h: usize, data: Vec<u8>) {
-    let mut response = ScreenshotResponse::new();
-    response.sid = screenshot.sid;
-    if msg.is_empty() {
-        if data.is_empty() {
-            response.msg = "Failed to take screenshot, please try again later.".to_owned();
-        } else {
-            fn encode_png(width: usize, height: usize, rgba: Vec<u8>) -> ResultType<Vec<u8>> {
-                let mut png = Vec::new();
-                let mut encoder =
-                    repng::Options::smallest(width as _, height as _).build(&mut png)?;
-                encoder.write(&rgba)?;
-                encoder.finish()?;
-                Ok(png)
-            }
-            match encode_png(w as _, h as _, data) {
-                Ok(png) => {
-                    response.data = png.into();
-                }
-                Err(e) => {
-                    response.msg = format!("Error encoding png: {}", e);
-                }
-            }
-        }
+fn handle_screenshot(
+    display_idx: usize,
+    screenshot: Screenshot,
+    msg: String,
+    w: usize,
+    h: usize,
+    data: Vec<u8>,
+) {
+    let image = if !msg.is_empty() {
+        Err(msg)
+    } else if data.is_empty() {
+        Err("Failed to take screenshot, please try again later.".to_owned())
     } else {
-        response.msg = msg;
-    }
-    let mut msg_out = Message::new();
-    msg_out.set_screenshot_response(response);
-    if let Err(e) = screenshot
-        .tx
-        .send((hbb_common::tokio::time::Instant::now(), Arc::new(msg_out)))
-    {
-        log::error!("Failed to send screenshot, {}", e);
+        Ok(Image {
+            width: w,
+            height: h,
+            rgba: data,
+        })
+    };
+    match screenshot.stitcher {
+        Some(stitcher) => stitcher.add(display_idx, image),
+        None => send_screenshot_response(&screenshot.request, &screenshot.tx, image),
     }
 }
@@ -90,6 +90,10 @@ impl TraitCapturer for CapturerPtr {
     fn frame<'a>(&'a mut self, timeout: std::time::Duration) -> std::io::Result<Frame<'a>> {
         unsafe { (*self.0).frame(timeout) }
     }
+
+    fn damage(&self) -> Option<Vec<scrap::DamageRect>> {
+        unsafe { (*self.0).damage() }
+    }
 }
 
 struct CapDisplayInfo {
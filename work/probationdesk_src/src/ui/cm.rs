@@ -47,10 +47,12 @@ impl InvokeUiCM for SciterHandler {
         }
     }
 
-    fn new_message(&self, id: i32, text: String) {
-        self.call("newMessage", &make_args!(id, text));
+    fn new_message(&self, id: i32, record: &crate::chat_history::ChatRecord) {
+        self.call("newMessage", &make_args!(id, record.text.clone()));
     }
 
+    fn chat_receipt(&self, _id: i32, _msg_ids: Vec<String>, _read: bool) {}
+
     fn change_theme(&self, _dark: String) {
         // TODO
     }
@@ -71,6 +73,10 @@ impl InvokeUiCM for SciterHandler {
     }
 
     fn file_transfer_log(&self, _action: &str, _log: &str) {}
+
+    fn update_control_token(&self, _client: &crate::ui_cm_interface::Client) {}
+
+    fn update_traffic(&self, _client: &crate::ui_cm_interface::Client) {}
 }
 
 impl SciterHandler {
@@ -1,3 +1,6 @@
+#[cfg(not(any(target_os = "ios")))]
+use crate::chat_history;
+use crate::chat_history::ChatRecord;
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 use crate::ipc::Connection;
 #[cfg(not(any(target_os = "ios")))]
@@ -22,11 +25,12 @@ use hbb_common::{
         task::spawn_blocking,
     },
 };
+#[cfg(not(any(target_os = "ios")))]
+use hbb_common::{bail, ResultType};
 #[cfg(target_os = "windows")]
 use hbb_common::{
     config::{keys::*, option2bool},
     tokio::sync::Mutex as TokioMutex,
-    ResultType,
 };
 use serde_derive::Serialize;
 #[cfg(any(target_os = "android", target_os = "ios", feature = "flutter"))]
@@ -60,9 +64,18 @@ pub struct Client {
     pub restart: bool,
     pub recording: bool,
     pub block_input: bool,
+    pub inventory: bool,
     pub from_switch: bool,
     pub in_voice_call: bool,
     pub incoming_voice_call: bool,
+    // Floor control between the controllers.
+    pub has_control: bool,
+    pub control_requested: bool,
+    #[cfg(not(any(target_os = "ios")))]
+    pub traffic: crate::server::bandwidth::TrafficStats,
+    // KB/s, 0 for the global caps only.
+    pub upload_limit: u32,
+    pub download_limit: u32,
     #[serde(skip)]
     #[cfg(not(any(target_os = "ios")))]
     tx: UnboundedSender<Data>,
@@ -99,7 +112,9 @@ pub trait InvokeUiCM: Send + Clone + 'static + Sized {
 
     fn remove_connection(&self, id: i32, close: bool);
 
-    fn new_message(&self, id: i32, text: String);
+    fn new_message(&self, id: i32, record: &ChatRecord);
+
+    fn chat_receipt(&self, id: i32, msg_ids: Vec<String>, read: bool);
 
     fn change_theme(&self, dark: String);
 
@@ -110,6 +125,10 @@ pub trait InvokeUiCM: Send + Clone + 'static + Sized {
     fn update_voice_call_state(&self, client: &Client);
 
     fn file_transfer_log(&self, action: &str, log: &str);
+
+    fn update_control_token(&self, client: &Client);
+
+    fn update_traffic(&self, client: &Client);
 }
 
 impl<T: InvokeUiCM> Deref for ConnectionManager<T> {
@@ -144,6 +163,7 @@ impl<T: InvokeUiCM> ConnectionManager<T> {
         restart: bool,
         recording: bool,
         block_input: bool,
+        inventory: bool,
         from_switch: bool,
         #[cfg(not(any(target_os = "ios")))] tx: mpsc::UnboundedSender<Data>,
     ) {
@@ -164,11 +184,18 @@ impl<T: InvokeUiCM> ConnectionManager<T> {
             restart,
             recording,
             block_input,
+            inventory,
             from_switch,
             #[cfg(not(any(target_os = "ios")))]
             tx,
             in_voice_call: false,
             incoming_voice_call: false,
+            has_control: false,
+            control_requested: false,
+            #[cfg(not(any(target_os = "ios")))]
+            traffic: Default::default(),
+            upload_limit: 0,
+            download_limit: 0,
         };
         CLIENTS
             .write()
@@ -255,6 +282,65 @@ impl<T: InvokeUiCM> ConnectionManager<T> {
             self.ui_handler.update_voice_call_state(client);
         }
     }
+
+    #[cfg(not(target_os = "ios"))]
+    fn control_token_changed(&self, id: i32, has_control: bool, requested: bool) {
+        if let Some(client) = CLIENTS.write().unwrap().get_mut(&id) {
+            client.has_control = has_control;
+            client.control_requested = requested;
+            self.ui_handler.update_control_token(client);
+        }
+    }
+
+    #[cfg(not(target_os = "ios"))]
+    fn traffic_changed(&self, id: i32, traffic: crate::server::bandwidth::TrafficStats) {
+        if let Some(client) = CLIENTS.write().unwrap().get_mut(&id) {
+            client.traffic = traffic;
+            self.ui_handler.update_traffic(client);
+        }
+    }
+
+    #[cfg(not(target_os = "ios"))]
+    fn on_chat_message(
+        &self,
+        id: i32,
+        text: String,
+        msg_id: String,
+        timestamp: i64,
+        attachments: Vec<String>,
+    ) {
+        let Some(peer_id) = peer_id(id) else {
+            return;
+        };
+        let receipt = !msg_id.is_empty();
+        let record = chat_history::add_incoming(&peer_id, msg_id, timestamp, text, attachments);
+        if receipt {
+            send_chat_receipt(id, vec![record.msg_id.clone()], false);
+        }
+        self.ui_handler.new_message(id, &record);
+    }
+
+    #[cfg(not(target_os = "ios"))]
+    fn on_chat_receipt(&self, id: i32, msg_ids: Vec<String>, read: bool) {
+        if let Some(peer_id) = peer_id(id) {
+            chat_history::add_receipt(&peer_id, msg_ids.clone(), receipt(read));
+        }
+        self.ui_handler.chat_receipt(id, msg_ids, read);
+    }
+}
+
+#[cfg(not(target_os = "ios"))]
+fn peer_id(id: i32) -> Option<String> {
+    CLIENTS.read().unwrap().get(&id).map(|c| c.peer_id.clone())
+}
+
+#[cfg(not(target_os = "ios"))]
+fn receipt(read: bool) -> chat_history::Receipt {
+    if read {
+        chat_history::Receipt::Read
+    } else {
+        chat_history::Receipt::Delivered
+    }
 }
 
 #[inline]
@@ -296,9 +382,40 @@ pub fn remove(id: i32) {
 #[inline]
 #[cfg(not(any(target_os = "ios")))]
 pub fn send_chat(id: i32, text: String) {
+    allow_err!(send_chat_with_attachments(id, text, &[]));
+}
+
+/// The attachments are sent from the local `paths` by the connection, if it has the file
+/// permission, see `chat_history::MAX_ATTACHMENTS_SIZE`.
+#[cfg(not(any(target_os = "ios")))]
+pub fn send_chat_with_attachments(
+    id: i32,
+    text: String,
+    paths: &[String],
+) -> ResultType<ChatRecord> {
     let clients = CLIENTS.read().unwrap();
-    if let Some(client) = clients.get(&id) {
-        allow_err!(client.tx.send(Data::ChatMessage { text }));
+    let Some(client) = clients.get(&id) else {
+        bail!("The connection is closed");
+    };
+    chat_history::check_attachments(paths)?;
+    let record = chat_history::add_outgoing(&client.peer_id, text, paths.to_vec());
+    allow_err!(client.tx.send(Data::ChatMessage {
+        text: record.text.clone(),
+        msg_id: record.msg_id.clone(),
+        timestamp: record.timestamp,
+        attachments: paths.to_vec(),
+    }));
+    Ok(record)
+}
+
+#[cfg(not(any(target_os = "ios")))]
+pub fn send_chat_receipt(id: i32, msg_ids: Vec<String>, read: bool) {
+    if msg_ids.is_empty() {
+        return;
+    }
+    if let Some(client) = CLIENTS.read().unwrap().get(&id) {
+        chat_history::add_receipt(&client.peer_id, msg_ids.clone(), receipt(read));
+        allow_err!(client.tx.send(Data::ChatReceipt { msg_ids, read }));
     }
 }
 
@@ -409,9 +526,9 @@ impl<T: InvokeUiCM> IpcTaskRunner<T> {
                         }
                         Ok(Some(data)) => {
                             match data {
-                                Data::Login{id, is_file_transfer, is_view_camera, is_terminal, port_forward, peer_id, name, authorized, keyboard, clipboard, audio, file, file_transfer_enabled: _file_transfer_enabled, restart, recording, block_input, from_switch} => {
+                                Data::Login{id, is_file_transfer, is_view_camera, is_terminal, port_forward, peer_id, name, authorized, keyboard, clipboard, audio, file, file_transfer_enabled: _file_transfer_enabled, restart, recording, block_input, inventory, from_switch} => {
                                     log::debug!("conn_id: {}", id);
-                                    self.cm.add_connection(id, is_file_transfer, is_view_camera, is_terminal, port_forward, peer_id, name, authorized, keyboard, clipboard, audio, file, restart, recording, block_input, from_switch, self.tx.clone());
+                                    self.cm.add_connection(id, is_file_transfer, is_view_camera, is_terminal, port_forward, peer_id, name, authorized, keyboard, clipboard, audio, file, restart, recording, block_input, inventory, from_switch, self.tx.clone());
                                     self.conn_id = id;
                                     #[cfg(target_os = "windows")]
                                     {
@@ -436,8 +553,11 @@ impl<T: InvokeUiCM> IpcTaskRunner<T> {
                                 Data::ClickTime(ms) => {
                                     CLICK_TIME.store(ms, Ordering::SeqCst);
                                 }
-                                Data::ChatMessage { text } => {
-                                    self.cm.new_message(self.conn_id, text);
+                                Data::ChatMessage { text, msg_id, timestamp, attachments } => {
+                                    self.cm.on_chat_message(self.conn_id, text, msg_id, timestamp, attachments);
+                                }
+                                Data::ChatReceipt { msg_ids, read } => {
+                                    self.cm.on_chat_receipt(self.conn_id, msg_ids, read);
                                 }
                                 Data::FS(mut fs) => {
                                     if let ipc::FS::WriteBlock { id, file_num, data: _, compressed } = fs {
@@ -502,6 +622,12 @@ impl<T: InvokeUiCM> IpcTaskRunner<T> {
                                 Data::CloseVoiceCall(reason) => {
                                     self.cm.voice_call_closed(self.conn_id, reason.as_str());
                                 }
+                                Data::ControlToken { has_control, requested } => {
+                                    self.cm.control_token_changed(self.conn_id, has_control, requested);
+                                }
+                                Data::Traffic(traffic) => {
+                                    self.cm.traffic_changed(self.conn_id, traffic);
+                                }
                                 #[cfg(target_os = "windows")]
                                 Data::ClipboardNonFile(_) => {
                                     match crate::clipboard::check_clipboard_cm() {
@@ -692,6 +818,7 @@ pub async fn start_listen<T: InvokeUiCM>(
                 restart,
                 recording,
                 block_input,
+                inventory,
                 from_switch,
                 ..
             }) => {
@@ -712,12 +839,21 @@ pub async fn start_listen<T: InvokeUiCM>(
                     restart,
                     recording,
                     block_input,
+                    inventory,
                     from_switch,
                     tx.clone(),
                 );
             }
-            Some(Data::ChatMessage { text }) => {
-                cm.new_message(current_id, text);
+            Some(Data::ChatMessage {
+                text,
+                msg_id,
+                timestamp,
+                attachments,
+            }) => {
+                cm.on_chat_message(current_id, text, msg_id, timestamp, attachments);
+            }
+            Some(Data::ChatReceipt { msg_ids, read }) => {
+                cm.on_chat_receipt(current_id, msg_ids, read);
             }
             Some(Data::FS(fs)) => {
                 handle_fs(fs, &mut write_jobs, &tx, None).await;
@@ -734,6 +870,15 @@ pub async fn start_listen<T: InvokeUiCM>(
             Some(Data::CloseVoiceCall(reason)) => {
                 cm.voice_call_closed(current_id, reason.as_str());
             }
+            Some(Data::ControlToken {
+                has_control,
+                requested,
+            }) => {
+                cm.control_token_changed(current_id, has_control, requested);
+            }
+            Some(Data::Traffic(traffic)) => {
+                cm.traffic_changed(current_id, traffic);
+            }
             None => {
                 break;
             }
@@ -813,6 +958,42 @@ async fn handle_fs(
             job.conn_id = conn_id;
             write_jobs.push(job);
         }
+        ipc::FS::NewChatWrite {
+            id,
+            msg_id,
+            name,
+            mut files,
+            total_size,
+            conn_id,
+        } => {
+            let Some(path) =
+                peer_id(conn_id).and_then(|p| chat_history::attachment_path(&p, &msg_id, &name))
+            else {
+                send_raw(fs::new_error(id, "Invalid attachment", 0), tx);
+                return;
+            };
+            let mut job = fs::TransferJob::new_write(
+                id,
+                fs::JobType::Generic,
+                "".to_string(),
+                fs::DataSource::FilePath(path),
+                0,
+                false,
+                false,
+                files
+                    .drain(..)
+                    .map(|f| FileEntry {
+                        name: f.0,
+                        modified_time: f.1,
+                        ..Default::default()
+                    })
+                    .collect(),
+                false,
+            );
+            job.total_size = total_size;
+            job.conn_id = conn_id;
+            write_jobs.push(job);
+        }
         ipc::FS::CancelWrite { id } => {
             if let Some(job) = fs::remove_job(id, write_jobs) {
                 job.remove_download_file();
@@ -1099,6 +1280,29 @@ pub fn close_voice_call(id: i32) {
     };
 }
 
+/// Give the control token to the connection, or take it away.
+#[inline]
+pub fn set_control_token(id: i32, has_control: bool) {
+    if let Some(client) = CLIENTS.read().unwrap().get(&id) {
+        #[cfg(not(any(target_os = "ios")))]
+        allow_err!(client.tx.send(Data::ControlToken {
+            has_control,
+            requested: false,
+        }));
+    };
+}
+
+/// Cap the bandwidth of the connection in KB/s, 0 for the global caps only.
+#[inline]
+pub fn set_bandwidth_limit(id: i32, upload: u32, download: u32) {
+    if let Some(client) = CLIENTS.write().unwrap().get_mut(&id) {
+        client.upload_limit = upload;
+        client.download_limit = download;
+        #[cfg(not(any(target_os = "ios")))]
+        allow_err!(client.tx.send(Data::BandwidthLimit { upload, download }));
+    };
+}
+
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 pub fn quit_cm() {
     // in case of std::process::exit not work
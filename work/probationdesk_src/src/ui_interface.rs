@@ -265,6 +265,14 @@ pub fn forget_password(id: String) {
     c.store(&id);
 }
 
+/// Forget the pinned key of the peer, its current key is trusted on the next connection.
+#[inline]
+pub fn retrust_peer_key(id: String) {
+    let mut c = PeerConfig::load(&id);
+    c.trusted_pk.clear();
+    c.store(&id);
+}
+
 #[inline]
 pub fn get_peer_option(id: String, name: String) -> String {
     let c = PeerConfig::load(&id);
@@ -10,8 +10,8 @@ use hbb_common::config::keys;
 #[cfg(not(feature = "flutter"))]
 use hbb_common::fs;
 use hbb_common::{
-    allow_err,
-    config::{Config, LocalConfig, PeerConfig},
+    allow_err, bail,
+    config::{Config, InputMacro, LocalConfig, PeerConfig},
     get_version_number, log,
     message_proto::*,
     rendezvous_proto::ConnType,
@@ -20,7 +20,7 @@ use hbb_common::{
         sync::mpsc,
         time::{Duration as TokioDuration, Instant},
     },
-    whoami, Stream,
+    whoami, ResultType, Stream,
 };
 use rdev::{Event, EventType::*, KeyCode};
 #[cfg(all(feature = "vram", feature = "flutter"))]
@@ -29,12 +29,16 @@ use std::{
     collections::HashMap,
     ops::{Deref, DerefMut},
     str::FromStr,
-    sync::{Arc, Mutex, RwLock},
+    sync::{mpsc::RecvTimeoutError, Arc, Mutex, RwLock},
     time::SystemTime,
 };
 use uuid::Uuid;
 
+use crate::chat_history::{self, ChatRecord};
+use crate::client::input_macro::{self, InputMacroState};
 use crate::client::io_loop::Remote;
+use crate::client::jitter_buffer::AvSync;
+use crate::client::screenshot::{self, ScreenshotInterval};
 use crate::client::{
     check_if_retry, handle_hash, handle_login_error, handle_login_from_ui, handle_test_delay,
     input_os_password, send_mouse, send_pointer_device_event, FileManager, Key, LoginConfigHandler,
@@ -61,6 +65,11 @@ pub struct Session<T: InvokeUiSession> {
     pub last_change_display: Arc<Mutex<ChangeDisplayRecord>>,
     pub connection_round_state: Arc<Mutex<ConnectionRoundState>>,
     pub printer_names: Arc<RwLock<HashMap<i32, String>>>,
+    pub input_macro: Arc<Mutex<InputMacroState>>,
+    // The mouse button held down by the pen if the peer doesn't support pen events.
+    pub pen_mouse_button: Arc<Mutex<i32>>,
+    pub screenshot_interval: Arc<Mutex<Option<ScreenshotInterval>>>,
+    pub av_sync: Arc<AvSync>,
 }
 
 #[derive(Clone)]
@@ -421,6 +430,49 @@ impl<T: InvokeUiSession> Session<T> {
         self.send(Data::Message(msg_out));
     }
 
+    pub fn request_inventory(&self, include_packages: bool) {
+        let mut misc = Misc::new();
+        misc.set_inventory_request(InventoryRequest {
+            include_packages,
+            ..Default::default()
+        });
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        self.send(Data::Message(msg_out));
+    }
+
+    /// Request, release, grant or deny the control token, `conn_id` is the requester for grant and deny.
+    pub fn control_token_action(&self, action: control_token_action::Type, conn_id: i32) {
+        let mut misc = Misc::new();
+        misc.set_control_token_action(ControlTokenAction {
+            type_: action.into(),
+            conn_id,
+            ..Default::default()
+        });
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        self.send(Data::Message(msg_out));
+    }
+
+    // Empty to capture the default audio source of the peer again.
+    pub fn select_audio_sources(&self, sources: Vec<(String, f32)>) {
+        let mut misc = Misc::new();
+        misc.set_select_audio_sources(SelectAudioSources {
+            sources: sources
+                .into_iter()
+                .map(|(id, gain)| AudioSourceGain {
+                    id,
+                    gain,
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        });
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        self.send(Data::Message(msg_out));
+    }
+
     #[cfg(not(feature = "flutter"))]
     pub fn refresh_video(&self, _display: i32) {
         self.send(Data::Message(LoginConfigHandler::refresh()));
@@ -435,7 +487,84 @@ impl<T: InvokeUiSession> Session<T> {
     }
 
     pub fn take_screenshot(&self, display: i32, sid: String) {
-        self.send(Data::TakeScreenshot((display, sid)));
+        self.send(Data::TakeScreenshot(ScreenshotRequest {
+            display,
+            sid,
+            ..Default::default()
+        }));
+    }
+
+    /// `options` is a json object, see `screenshot::parse_request`.
+    pub fn take_screenshot_with_options(
+        &self,
+        display: i32,
+        sid: String,
+        options: &str,
+    ) -> ResultType<()> {
+        let request = screenshot::parse_request(display, sid, options)?;
+        self.send(Data::TakeScreenshot(request));
+        Ok(())
+    }
+
+    /// Take a screenshot every `interval_secs` seconds and save it to `folder`,
+    /// until it is stopped or the session is closed.
+    pub fn start_screenshot_interval(
+        &self,
+        display: i32,
+        options: &str,
+        interval_secs: u64,
+        folder: String,
+    ) -> ResultType<()> {
+        if interval_secs == 0 {
+            bail!("Invalid interval");
+        }
+        let request =
+            screenshot::parse_request(display, screenshot::INTERVAL_SID.to_owned(), options)?;
+        std::fs::create_dir_all(&folder)?;
+        let (interval, stop) = ScreenshotInterval::new(folder);
+        // The previous one is stopped by dropping it.
+        *self.screenshot_interval.lock().unwrap() = Some(interval);
+        let sender = self.sender.clone();
+        std::thread::spawn(move || {
+            let interval = std::time::Duration::from_secs(interval_secs);
+            loop {
+                let sent = sender
+                    .read()
+                    .unwrap()
+                    .as_ref()
+                    .is_some_and(|s| s.send(Data::TakeScreenshot(request.clone())).is_ok());
+                // Disconnected if stopped.
+                if !sent || stop.recv_timeout(interval) != Err(RecvTimeoutError::Timeout) {
+                    break;
+                }
+            }
+        });
+        Ok(())
+    }
+
+    pub fn stop_screenshot_interval(&self) {
+        self.screenshot_interval.lock().unwrap().take();
+    }
+
+    pub fn save_interval_screenshot(&self, response: ScreenshotResponse) {
+        let Some(folder) = self
+            .screenshot_interval
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|i| i.folder.clone())
+        else {
+            return;
+        };
+        if !response.msg.is_empty() {
+            log::warn!("Failed to take screenshot, {}", response.msg);
+            return;
+        }
+        let format = response.format.enum_value_or_default();
+        match screenshot::save_to_folder(&folder, &self.get_id(), &response.data, format) {
+            Ok(path) => log::debug!("Screenshot saved to {}", path.display()),
+            Err(e) => log::error!("Failed to save screenshot to {}, {}", folder, e),
+        }
     }
 
     pub fn is_recording(&self) -> bool {
@@ -749,10 +878,132 @@ impl<T: InvokeUiSession> Session<T> {
         self.send(Data::Message(msg_out));
     }
 
+    pub fn start_input_macro_recording(&self) {
+        self.input_macro.lock().unwrap().start_recording();
+    }
+
+    /// Save the recording as `name`, or discard it if `name` is empty.
+    pub fn stop_input_macro_recording(&self, name: String) -> ResultType<()> {
+        let Some(steps) = self.input_macro.lock().unwrap().stop_recording() else {
+            bail!("Not recording");
+        };
+        if name.is_empty() {
+            return Ok(());
+        }
+        if steps.is_empty() {
+            bail!("Empty macro");
+        }
+        InputMacro { name, steps }.store()
+    }
+
+    pub fn insert_input_macro_text(&self, text: String) {
+        self.input_macro.lock().unwrap().add_text(text);
+    }
+
+    pub fn replay_input_macro(&self, name: String, speed: f64, variables: HashMap<String, String>) {
+        let Some(recorded) = InputMacro::load(&name) else {
+            log::error!("Input macro {} not found", name);
+            return;
+        };
+        let Some(abort) = self.input_macro.lock().unwrap().start_replay() else {
+            log::warn!("Input macro is being replayed");
+            return;
+        };
+        let session = self.clone();
+        std::thread::spawn(move || {
+            log::info!("Replay input macro {}, speed: {}", name, speed);
+            let completed = input_macro::replay(
+                &recorded.steps,
+                speed,
+                &variables,
+                &abort,
+                || {
+                    session
+                        .lc
+                        .read()
+                        .unwrap()
+                        .peer_info
+                        .as_ref()
+                        .map(|pi| pi.displays.clone())
+                        .unwrap_or_default()
+                },
+                |msg| {
+                    // Bypass `Interface::send`, the replayed input is not user input.
+                    if let Some(sender) = session.sender.read().unwrap().as_ref() {
+                        sender.send(Data::Message(msg)).ok();
+                    }
+                },
+            );
+            session.input_macro.lock().unwrap().end_replay(&abort);
+            log::info!("Input macro {} replayed, completed: {}", name, completed);
+            session.input_macro_replayed(&name, completed);
+        });
+    }
+
+    pub fn abort_input_macro(&self) {
+        self.input_macro.lock().unwrap().abort_replay();
+    }
+
+    // The buttons, wheel and keys of the user abort the replay, any input is recorded when
+    // recording.
+    fn on_input_macro_event(&self, msg: &Message) {
+        let mut input_macro = self.input_macro.lock().unwrap();
+        if input_macro::aborts_replay(msg) {
+            input_macro.abort_replay();
+        }
+        if input_macro.is_recording() {
+            let displays = self
+                .lc
+                .read()
+                .unwrap()
+                .peer_info
+                .as_ref()
+                .map(|pi| pi.displays.clone())
+                .unwrap_or_default();
+            input_macro.record(msg, &displays);
+        }
+    }
+
     pub fn send_chat(&self, text: String) {
+        allow_err!(self.send_chat_with_attachments(text, &[]));
+    }
+
+    /// The attachments are sent from the local `paths` after the message, the peer writes them
+    /// only with the file permission, see `chat_history::MAX_ATTACHMENTS_SIZE`.
+    pub fn send_chat_with_attachments(
+        &self,
+        text: String,
+        paths: &[String],
+    ) -> ResultType<ChatRecord> {
+        let attachments = chat_history::check_attachments(paths)?;
+        let record = chat_history::add_outgoing(&self.get_id(), text, paths.to_vec());
+        let mut misc = Misc::new();
+        misc.set_chat_message(chat_history::to_message(&record, attachments));
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        self.send(Data::Message(msg_out));
+        if !paths.is_empty() {
+            self.send(Data::SendChatAttachments((
+                record.msg_id.clone(),
+                paths.to_vec(),
+            )));
+        }
+        Ok(record)
+    }
+
+    pub fn send_chat_receipt(&self, msg_ids: Vec<String>, receipt: chat_history::Receipt) {
+        if msg_ids.is_empty() {
+            return;
+        }
+        chat_history::add_receipt(&self.get_id(), msg_ids.clone(), receipt);
         let mut misc = Misc::new();
-        misc.set_chat_message(ChatMessage {
-            text,
+        misc.set_chat_receipt(ChatReceipt {
+            msg_ids,
+            status: match receipt {
+                chat_history::Receipt::Delivered => chat_receipt::Status::Delivered,
+                chat_history::Receipt::Read => chat_receipt::Status::Read,
+            }
+            .into(),
             ..Default::default()
         });
         let mut msg_out = Message::new();
@@ -799,6 +1050,43 @@ impl<T: InvokeUiSession> Session<T> {
         self.send(Data::Message(msg_out));
     }
 
+    pub fn exec_command(
+        &self,
+        exec_id: i32,
+        command: String,
+        args: Vec<String>,
+        env: HashMap<String, String>,
+        working_dir: String,
+        timeout_secs: u32,
+        run_as: String,
+    ) {
+        let mut action = TerminalAction::new();
+        action.set_exec(ExecCommand {
+            exec_id,
+            command,
+            args,
+            env,
+            working_dir,
+            timeout_secs,
+            run_as,
+            ..Default::default()
+        });
+        let mut msg_out = Message::new();
+        msg_out.set_terminal_action(action);
+        self.send(Data::Message(msg_out));
+    }
+
+    pub fn cancel_exec(&self, exec_id: i32) {
+        let mut action = TerminalAction::new();
+        action.set_cancel_exec(CancelExec {
+            exec_id,
+            ..Default::default()
+        });
+        let mut msg_out = Message::new();
+        msg_out.set_terminal_action(action);
+        self.send(Data::Message(msg_out));
+    }
+
     pub fn close_terminal(&self, terminal_id: i32) {
         let mut action = TerminalAction::new();
         action.set_close(CloseTerminal {
@@ -1168,6 +1456,19 @@ impl<T: InvokeUiSession> Session<T> {
         send_pointer_device_event(evt, alt, ctrl, shift, command, self);
     }
 
+    pub fn send_pen_event(&self, pen: PenEvent, alt: bool, ctrl: bool, shift: bool, command: bool) {
+        if self.lc.read().unwrap().is_pen_supported() {
+            let mut evt = PointerDeviceEvent::new();
+            evt.set_pen_event(pen);
+            send_pointer_device_event(evt, alt, ctrl, shift, command, self);
+            return;
+        }
+        let events = crate::input::pen_to_mouse(&pen, &mut self.pen_mouse_button.lock().unwrap());
+        for evt in events {
+            self.send_mouse(evt.mask, evt.x, evt.y, alt, ctrl, shift, command);
+        }
+    }
+
     #[inline]
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
     fn is_scroll_reverse_mode(&self) -> bool {
@@ -1369,6 +1670,7 @@ impl<T: InvokeUiSession> Session<T> {
     }
 
     pub fn close(&self) {
+        self.stop_screenshot_interval();
         self.send(Data::Close);
     }
 
@@ -1665,6 +1967,14 @@ pub trait InvokeUiSession: Send + Sync + Clone + 'static + Sized + Default {
     fn is_multi_ui_session(&self) -> bool;
     fn update_record_status(&self, start: bool);
     fn update_empty_dirs(&self, _res: ReadEmptyDirsResponse) {}
+    fn set_audio_sources(&self, _sources: Vec<AudioSource>) {}
+    fn handle_inventory(&self, _inventory: Inventory) {}
+    fn update_control_token(&self, _state: ControlTokenState) {}
+    fn new_chat_message(&self, record: ChatRecord) {
+        self.new_message(record.text);
+    }
+    fn chat_receipt(&self, _msg_ids: Vec<String>, _read: bool) {}
+    fn input_macro_replayed(&self, _name: &str, _completed: bool) {}
     fn printer_request(&self, id: i32, path: String);
     fn handle_screenshot_resp(&self, sid: String, msg: String);
     fn handle_terminal_response(&self, response: TerminalResponse);
@@ -1693,6 +2003,14 @@ impl<T: InvokeUiSession> Interface for Session<T> {
     }
 
     fn send(&self, data: Data) {
+        if let Data::Message(msg) = &data {
+            if matches!(
+                msg.union,
+                Some(message::Union::KeyEvent(_)) | Some(message::Union::MouseEvent(_))
+            ) {
+                self.on_input_macro_event(msg);
+            }
+        }
         if let Some(sender) = self.sender.read().unwrap().as_ref() {
             sender.send(data).ok();
         }
@@ -1817,13 +2135,15 @@ impl<T: InvokeUiSession> Interface for Session<T> {
         .await;
     }
 
-    async fn handle_test_delay(&self, t: TestDelay, peer: &mut Stream) {
+    async fn handle_test_delay(&self, mut t: TestDelay, peer: &mut Stream) {
         if !t.from_client {
             self.update_quality_status(QualityStatus {
                 delay: Some(t.last_delay as _),
                 target_bitrate: Some(t.target_bitrate as _),
+                capture: t.capture_stats.clone(),
                 ..Default::default()
             });
+            t.audio_loss = self.av_sync.take_loss_perc();
             handle_test_delay(t, peer).await;
         }
     }